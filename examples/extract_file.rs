@@ -13,7 +13,7 @@ use std::path::Path;
 use tar::Archive;
 
 fn main() {
-    let first_arg = args_os().skip(1).next().unwrap();
+    let first_arg = args_os().nth(1).unwrap();
     let filename = Path::new(&first_arg);
     let mut ar = Archive::new(stdin());
     for file in ar.entries().unwrap() {
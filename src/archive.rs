@@ -1,18 +1,33 @@
 use std::cell::{Cell, RefCell};
 use std::cmp;
+use std::collections::BTreeSet;
 use std::convert::TryFrom;
 use std::fs;
 use std::io::prelude::*;
 use std::io::{self, SeekFrom};
 use std::marker;
-use std::path::Path;
+use std::ops::Range;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use crate::entry::{EntryFields, EntryIo};
+use crate::builder::pad_zeroes;
+use crate::cancel::{check_deadline, CancellationToken, Cancelled};
+use crate::digest::{Digest, DigestAlgorithm, Hasher};
+use crate::entry::{representable, CaseFolding, EntryFields, EntryIo};
 use crate::error::TarError;
+use crate::filesystem::FileSystem;
 use crate::header::BLOCK_SIZE;
+use crate::manifest::{ManifestFormat, ManifestMismatch, ManifestVerifier, ManifestWriter};
 use crate::other;
 use crate::pax::*;
-use crate::{Entry, GnuExtSparseHeader, GnuSparseHeader, Header};
+use crate::progress::ProgressObserver;
+use crate::throttle::RateLimiter;
+use crate::{
+    ArchiveFormat, ArchiveMetadata, CaseCollisionPolicy, Entry, EntryMetadata, GnuExtSparseHeader,
+    GnuSparseHeader, Header, NonUtf8PathPolicy, NormalizationForm, OverwritePolicy, OwnerMap,
+    SymlinkFallback, SyncPolicy, UnpackJournal,
+};
 
 /// A top-level representation of an archive file.
 ///
@@ -24,21 +39,220 @@ pub struct Archive<R: ?Sized + Read> {
 pub struct ArchiveInner<R: ?Sized> {
     pos: Cell<u64>,
     mask: u32,
+    // The process umask, when applying it
+    umask: Option<u32>,
     unpack_xattrs: bool,
+    unpack_selinux: bool,
+    unpack_mac_metadata: bool,
+    unpack_windows_attributes: bool,
+    unpack_devices: bool,
     preserve_permissions: bool,
     preserve_ownerships: bool,
+    numeric_owner: bool,
+    owner_map: Option<Arc<OwnerMap>>,
     preserve_mtime: bool,
-    overwrite: bool,
+    preserve_atime: bool,
+    overwrite: OverwritePolicy,
     ignore_zeros: bool,
+    parse_profile: ParseProfile,
+    skip_apple_double: bool,
+    incremental: bool,
+    strip_components: usize,
+    path_transform: Option<PathTransform>,
+    allow_external_symlinks: bool,
+    copy_external_symlinks: bool,
+    case_collisions: CaseCollisionPolicy,
+    case_folding: RefCell<CaseFolding>,
+    windows_names: bool,
+    normalization: Option<NormalizationForm>,
+    non_utf8_paths: NonUtf8PathPolicy,
+    symlink_fallback: SymlinkFallback,
+    atomic_unpack: bool,
+    sync: SyncPolicy,
+    preallocate_threshold: Option<u64>,
+    unpack_sparse: bool,
+    digest: Option<DigestAlgorithm>,
+    // The whole archive, when it is mapped into memory by `from_mmap` or
+    // already in memory with `from_bytes`
+    mapped: Option<Arc<dyn AsRef<[u8]> + Send + Sync>>,
+    read_buf_size: usize,
+    read_buf: RefCell<ReadBuffer>,
+    pool: BufferPool,
+    // Set for the duration of `unpack_with_cancel`
+    cancel: Option<CancellationToken>,
+    timeout: Option<Duration>,
+    entry_timeout: Option<Duration>,
+    // When the current unpack times out, set for its duration
+    deadline: Option<Instant>,
+    throttle: RefCell<Option<RateLimiter>>,
+    progress: Option<Progress>,
     obj: RefCell<R>,
 }
 
+/// Byte buffers an archive reuses from one entry to the next, rather than
+/// allocating new ones for each.
+#[derive(Default)]
+pub(crate) struct BufferPool {
+    free: RefCell<Vec<Vec<u8>>>,
+}
+
+impl BufferPool {
+    /// Buffers with more capacity than this are freed rather than kept.
+    const MAX_CAPACITY: usize = 1 << 20;
+    const MAX_BUFFERS: usize = 8;
+
+    /// Takes a buffer from the pool, or a new one if none are free. The
+    /// buffer still holds whatever it was last used for.
+    pub(crate) fn take(&self) -> Vec<u8> {
+        self.free.borrow_mut().pop().unwrap_or_default()
+    }
+
+    /// Returns `buf` to the pool.
+    pub(crate) fn give(&self, buf: Vec<u8>) {
+        let mut free = self.free.borrow_mut();
+        if buf.capacity() <= Self::MAX_CAPACITY && free.len() < Self::MAX_BUFFERS {
+            free.push(buf);
+        }
+    }
+}
+
+/// Data read ahead from the underlying reader, of which `data[start..end]`
+/// is yet to be consumed.
+#[derive(Default)]
+struct ReadBuffer {
+    data: Vec<u8>,
+    start: usize,
+    end: usize,
+}
+
+/// Rewrites entry paths when unpacking, as set by `Archive::set_path_transform`.
+pub(crate) type PathTransform = Arc<dyn Fn(&Path) -> Option<PathBuf> + Send + Sync>;
+
+pub(crate) type Progress = RefCell<Box<dyn ProgressObserver + Send>>;
+
+/// Which irregularities in headers are tolerated when reading an archive, as
+/// set by `Archive::set_parse_profile`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ParseProfile {
+    /// Accept only well-formed archives: numeric fields must be octal
+    /// digits with nothing but spaces before them and spaces or nuls after
+    /// them, or in base-256, checksums must be of the header's bytes as
+    /// unsigned, and the archive must end with an end-of-archive marker.
+    Strict,
+    /// Accept what GNU tar does, such as checksums of the header's bytes as
+    /// signed, which some historical tars computed, and archives which just
+    /// end after their last entry. Numeric fields which don't parse are only
+    /// reported once read. This is the default.
+    GnuCompatible,
+    /// Accept anything `GnuCompatible` does, and also numeric fields with
+    /// other junk after their digits, which are read as the octal digits
+    /// they start with, and archives cut off in the padding after their
+    /// last entry or partway through their end-of-archive marker.
+    Permissive,
+}
+
+/// A summary of what `Archive::unpack_with_report` unpacked.
+#[derive(Debug, Default)]
+pub struct UnpackReport {
+    entries: u64,
+    digests: Vec<(PathBuf, Digest)>,
+}
+
+impl UnpackReport {
+    /// Returns the number of entries unpacked.
+    pub fn entries(&self) -> u64 {
+        self.entries
+    }
+
+    /// Returns the path in the archive and content digest of each regular
+    /// file unpacked, in archive order, if the archive was configured to
+    /// compute digests with `Archive::set_digest_algorithm`.
+    pub fn digests(&self) -> &[(PathBuf, Digest)] {
+        &self.digests
+    }
+
+    /// Returns the digest of the last regular file unpacked from the archive
+    /// path `path`.
+    pub fn digest<P: AsRef<Path>>(&self, path: P) -> Option<&Digest> {
+        let path = path.as_ref();
+        self.digests
+            .iter()
+            .rev()
+            .find(|(p, _)| p == path)
+            .map(|(_, digest)| digest)
+    }
+}
+
+/// A summary of what `Archive::repair` fixed.
+#[derive(Debug, Default)]
+pub struct RepairReport {
+    entries: u64,
+    fixed_checksums: u64,
+    fixed_padding: u64,
+    truncated: bool,
+    added_trailer: bool,
+}
+
+impl RepairReport {
+    /// Returns the number of entries copied, not counting GNU long name or
+    /// PAX headers.
+    pub fn entries(&self) -> u64 {
+        self.entries
+    }
+
+    /// Returns the number of headers whose checksum was recomputed.
+    pub fn fixed_checksums(&self) -> u64 {
+        self.fixed_checksums
+    }
+
+    /// Returns the number of entries whose padding wasn't zeros.
+    pub fn fixed_padding(&self) -> u64 {
+        self.fixed_padding
+    }
+
+    /// Returns whether the archive was cut off partway through its last
+    /// entry, whose size was shrunk or whose header was dropped.
+    pub fn truncated(&self) -> bool {
+        self.truncated
+    }
+
+    /// Returns whether the archive's end-of-archive marker was missing or
+    /// incomplete.
+    pub fn added_trailer(&self) -> bool {
+        self.added_trailer
+    }
+
+    /// Returns whether anything was fixed.
+    pub fn repaired(&self) -> bool {
+        self.fixed_checksums > 0 || self.fixed_padding > 0 || self.truncated || self.added_trailer
+    }
+}
+
 /// An iterator over the entries of an archive.
 pub struct Entries<'a, R: 'a + Read> {
     fields: EntriesFields<'a>,
     _ignored: marker::PhantomData<&'a Archive<R>>,
 }
 
+/// An iterator over the entries of an archive, passing over damage, as
+/// returned by `Archive::entries_salvage`.
+pub struct SalvageEntries<'a, R: 'a + Read> {
+    fields: EntriesFields<'a>,
+    // An entry found after damage, returned once the damage is reported
+    pending: Option<Entry<'a, io::Empty>>,
+    _ignored: marker::PhantomData<&'a Archive<R>>,
+}
+
+/// An item returned by `SalvageEntries`.
+#[allow(clippy::large_enum_variant)]
+pub enum Salvaged<'a, R: 'a + Read> {
+    /// An entry read intact.
+    Entry(Entry<'a, R>),
+    /// A range of bytes of the archive, from its start, which were passed
+    /// over because they didn't hold a valid header or entry.
+    Skipped(Range<u64>),
+}
+
 trait SeekRead: Read + Seek {}
 impl<R: Read + Seek> SeekRead for R {}
 
@@ -46,8 +260,16 @@ struct EntriesFields<'a> {
     archive: &'a Archive<dyn Read + 'a>,
     seekable_archive: Option<&'a Archive<dyn SeekRead + 'a>>,
     next: u64,
+    // Bytes of padding just before `next`, after the last entry's contents
+    padding: u64,
     done: bool,
     raw: bool,
+    // Whether to read past bad headers, and the ranges passed over
+    salvage: bool,
+    skipped: Vec<Range<u64>>,
+    // The records of the global PAX headers read so far, and where they are
+    global_pax: GlobalPax,
+    global_headers: Vec<Range<u64>>,
 }
 
 impl<R: Read> Archive<R> {
@@ -56,12 +278,48 @@ impl<R: Read> Archive<R> {
         Archive {
             inner: ArchiveInner {
                 mask: u32::MIN,
+                umask: None,
                 unpack_xattrs: false,
+                unpack_selinux: false,
+                unpack_mac_metadata: false,
+                unpack_windows_attributes: false,
+                unpack_devices: false,
                 preserve_permissions: false,
                 preserve_ownerships: false,
+                numeric_owner: true,
+                owner_map: None,
                 preserve_mtime: true,
-                overwrite: true,
+                preserve_atime: false,
+                overwrite: OverwritePolicy::Overwrite,
                 ignore_zeros: false,
+                parse_profile: ParseProfile::GnuCompatible,
+                skip_apple_double: false,
+                incremental: false,
+                strip_components: 0,
+                path_transform: None,
+                allow_external_symlinks: false,
+                copy_external_symlinks: false,
+                case_collisions: CaseCollisionPolicy::Ignore,
+                case_folding: RefCell::default(),
+                windows_names: cfg!(windows),
+                normalization: None,
+                non_utf8_paths: NonUtf8PathPolicy::Error,
+                symlink_fallback: SymlinkFallback::Error,
+                atomic_unpack: false,
+                sync: SyncPolicy::None,
+                preallocate_threshold: None,
+                unpack_sparse: false,
+                digest: None,
+                mapped: None,
+                read_buf_size: 0,
+                read_buf: RefCell::default(),
+                pool: BufferPool::default(),
+                cancel: None,
+                timeout: None,
+                entry_timeout: None,
+                deadline: None,
+                throttle: RefCell::new(None),
+                progress: None,
                 obj: RefCell::new(obj),
                 pos: Cell::new(0),
             },
@@ -69,6 +327,9 @@ impl<R: Read> Archive<R> {
     }
 
     /// Unwrap this archive, returning the underlying object.
+    ///
+    /// With `set_read_buffer_size`, anything read ahead into the buffer is
+    /// lost, so the object may be further along than the archive has read.
     pub fn into_inner(self) -> R {
         self.inner.obj.into_inner()
     }
@@ -79,14 +340,103 @@ impl<R: Read> Archive<R> {
     /// sequence. If entries are processed out of sequence (from what the
     /// iterator returns), then the contents read for each entry may be
     /// corrupted.
-    pub fn entries(&mut self) -> io::Result<Entries<R>> {
+    pub fn entries(&mut self) -> io::Result<Entries<'_, R>> {
         let me: &mut Archive<dyn Read> = self;
         me._entries(None).map(|fields| Entries {
-            fields: fields,
+            fields,
+            _ignored: marker::PhantomData,
+        })
+    }
+
+    /// Construct an iterator over the entries in this archive which carries
+    /// on past damage, for recovering what can be from a corrupt archive.
+    ///
+    /// When a header's checksum or fields are bad, the following blocks are
+    /// read one at a time until one holds a valid header, and iteration
+    /// resumes from there. Each run of bytes passed over like this, along
+    /// with any entry which couldn't be parsed, is reported as a
+    /// `Salvaged::Skipped` range before the entry after it. Running out of
+    /// data, or the underlying reader failing, still ends iteration with an
+    /// error.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use std::fs::File;
+    /// use tar::{Archive, Salvaged};
+    ///
+    /// let mut ar = Archive::new(File::open("damaged.tar").unwrap());
+    /// for item in ar.entries_salvage().unwrap() {
+    ///     match item.unwrap() {
+    ///         Salvaged::Entry(entry) => println!("{}", entry.path().unwrap().display()),
+    ///         Salvaged::Skipped(range) => println!("skipped bytes {:?}", range),
+    ///     }
+    /// }
+    /// ```
+    pub fn entries_salvage(&mut self) -> io::Result<SalvageEntries<'_, R>> {
+        let me: &mut Archive<dyn Read> = self;
+        me._entries(None).map(|fields| SalvageEntries {
+            fields: EntriesFields {
+                salvage: true,
+                ..fields
+            },
+            pending: None,
             _ignored: marker::PhantomData,
         })
     }
 
+    /// Lists the metadata of every entry in this archive, in one pass over
+    /// it.
+    ///
+    /// Paths and link names are taken from any GNU long name or PAX headers,
+    /// as with `entries`, and the contents of entries are skipped over
+    /// without being read.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use std::fs::File;
+    /// use tar::Archive;
+    ///
+    /// let mut ar = Archive::new(File::open("foo.tar").unwrap());
+    /// for entry in ar.list().unwrap() {
+    ///     println!("{} ({} bytes)", entry.path.display(), entry.size);
+    /// }
+    /// ```
+    pub fn list(&mut self) -> io::Result<Vec<EntryMetadata>> {
+        self.entries()?
+            .map(|entry| EntryMetadata::from_entry(&entry?))
+            .collect()
+    }
+
+    /// Reads through this archive to find the dialect of tar it is written
+    /// in, or `None` if it has no entries.
+    ///
+    /// When entries are written in different dialects, the most capable is
+    /// reported: an unrecognized one over PAX, over GNU, over UStar, over V7.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use std::fs::File;
+    /// use tar::{Archive, ArchiveFormat};
+    ///
+    /// let mut ar = Archive::new(File::open("foo.tar").unwrap());
+    /// if ar.detect_format().unwrap() == Some(ArchiveFormat::V7) {
+    ///     eprintln!("warning: foo.tar has no owner names");
+    /// }
+    /// ```
+    pub fn detect_format(&mut self) -> io::Result<Option<ArchiveFormat>> {
+        let mut detected: Option<ArchiveFormat> = None;
+        for entry in self.entries()? {
+            let format = entry?.format();
+            if detected.is_none_or(|detected| format.rank() > detected.rank()) {
+                detected = Some(format);
+            }
+        }
+        Ok(detected)
+    }
+
     /// Unpacks the contents tarball into the specified `dst`.
     ///
     /// This function will iterate over the entire contents of this tarball,
@@ -108,7 +458,284 @@ impl<R: Read> Archive<R> {
     /// ```
     pub fn unpack<P: AsRef<Path>>(&mut self, dst: P) -> io::Result<()> {
         let me: &mut Archive<dyn Read> = self;
-        me._unpack(dst.as_ref())
+        me._unpack(dst.as_ref(), &mut UnpackReport::default(), None)
+    }
+
+    /// Unpacks the contents tarball into the specified `dst` like `unpack`,
+    /// returning a report of what was unpacked.
+    ///
+    /// With `set_digest_algorithm`, the report includes the digest of each
+    /// regular file, computed as it is written out so the archive isn't read
+    /// twice to verify it.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # #[cfg(feature = "sha2")] {
+    /// use std::fs::File;
+    /// use tar::{Archive, DigestAlgorithm};
+    ///
+    /// let mut ar = Archive::new(File::open("foo.tar").unwrap());
+    /// ar.set_digest_algorithm(Some(DigestAlgorithm::Sha256));
+    /// let report = ar.unpack_with_report("foo").unwrap();
+    /// for (path, digest) in report.digests() {
+    ///     println!("{}  {}", digest, path.display());
+    /// }
+    /// # }
+    /// ```
+    pub fn unpack_with_report<P: AsRef<Path>>(&mut self, dst: P) -> io::Result<UnpackReport> {
+        let me: &mut Archive<dyn Read> = self;
+        let mut report = UnpackReport::default();
+        me._unpack(dst.as_ref(), &mut report, None)?;
+        Ok(report)
+    }
+
+    /// Unpacks the contents tarball into the specified `dst` like `unpack`,
+    /// keeping a journal of finished files at `journal` so an interrupted
+    /// unpack can be resumed.
+    ///
+    /// If `journal` exists, it is taken to be left from an earlier unpack of
+    /// the same archive into the same `dst`, and regular files it records are
+    /// skipped as long as they still match what was recorded. The journal is
+    /// removed once the whole archive has been unpacked. See `UnpackJournal`
+    /// for what it records.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use std::fs::File;
+    /// use tar::Archive;
+    ///
+    /// let mut ar = Archive::new(File::open("foo.tar").unwrap());
+    /// ar.unpack_resumable("foo", "foo.journal").unwrap();
+    /// ```
+    pub fn unpack_resumable<P: AsRef<Path>, J: AsRef<Path>>(
+        &mut self,
+        dst: P,
+        journal: J,
+    ) -> io::Result<UnpackReport> {
+        let me: &mut Archive<dyn Read> = self;
+        let mut journal = UnpackJournal::open(journal)?;
+        let mut report = UnpackReport::default();
+        me._unpack(dst.as_ref(), &mut report, Some(&mut journal))?;
+        journal.finish()?;
+        Ok(report)
+    }
+
+    /// Unpacks the contents tarball into the specified `dst` like `unpack`,
+    /// stopping as soon as `token` is cancelled.
+    ///
+    /// The token is checked before each entry and as the contents of regular
+    /// files are written out. When it is cancelled, the entries already
+    /// unpacked are left complete, the file being written is removed, and
+    /// the directories unpacked so far don't get their permissions and
+    /// times set. A `Cancelled` error is then returned, in place of any other
+    /// error cancelling caused.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use std::fs::File;
+    /// use std::thread;
+    /// use std::time::Duration;
+    /// use tar::{Archive, CancellationToken};
+    ///
+    /// let token = CancellationToken::new();
+    /// let canceller = token.clone();
+    /// thread::spawn(move || {
+    ///     thread::sleep(Duration::from_secs(10));
+    ///     canceller.cancel();
+    /// });
+    /// let mut ar = Archive::new(File::open("foo.tar").unwrap());
+    /// if let Err(e) = ar.unpack_with_cancel("foo", &token) {
+    ///     println!("stopped: {}", e);
+    /// }
+    /// ```
+    pub fn unpack_with_cancel<P: AsRef<Path>>(
+        &mut self,
+        dst: P,
+        token: &CancellationToken,
+    ) -> io::Result<()> {
+        let me: &mut Archive<dyn Read> = self;
+        me._unpack_with_cancel(dst.as_ref(), token)
+    }
+
+    /// Unpacks only the entries under `prefix` in the archive into `dst`
+    /// like `unpack`, re-rooted so that `prefix` itself is `dst`.
+    ///
+    /// The prefix is matched a whole component at a time, ignoring leading
+    /// `/` and `.` components, so `"docs"` matches `./docs/index.html` but
+    /// not `docs-old/index.html`. Any path transform and stripped components
+    /// apply to what is left of the path after the prefix. Hard links to
+    /// entries outside of `prefix` fail, as their targets aren't unpacked.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use std::fs::File;
+    /// use tar::Archive;
+    ///
+    /// // Unpacks `project/docs/index.html` to `docs/index.html`
+    /// let mut ar = Archive::new(File::open("project.tar").unwrap());
+    /// ar.unpack_prefix("docs", "project/docs/").unwrap();
+    /// ```
+    pub fn unpack_prefix<P: AsRef<Path>, Q: AsRef<Path>>(
+        &mut self,
+        dst: P,
+        prefix: Q,
+    ) -> io::Result<()> {
+        let me: &mut Archive<dyn Read> = self;
+        me._unpack_prefix(dst.as_ref(), prefix.as_ref())
+    }
+
+    /// Unpacks the contents tarball into the specified `dst` like `unpack`,
+    /// writing regular files out through io_uring.
+    ///
+    /// Many small files are written at once, which speeds up unpacking
+    /// archives of lots of them. Files over a megabyte and other kinds of
+    /// entries are unpacked as usual, as is everything if the archive is set
+    /// to preserve ownerships or extended attributes, or to unpack
+    /// atomically, sync or preallocate. The archive itself is still read
+    /// through its reader.
+    ///
+    /// An error is returned if the kernel doesn't support io_uring.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use std::fs::File;
+    /// use tar::Archive;
+    ///
+    /// let mut ar = Archive::new(File::open("foo.tar").unwrap());
+    /// ar.unpack_uring("foo").unwrap();
+    /// ```
+    #[cfg(all(target_os = "linux", feature = "uring"))]
+    pub fn unpack_uring<P: AsRef<Path>>(&mut self, dst: P) -> io::Result<()> {
+        let me: &mut Archive<dyn Read> = self;
+        me._unpack_uring(dst.as_ref())
+    }
+
+    /// Unpacks the contents of this archive into `fs`, a `FileSystem` which
+    /// could be an in-memory tree or anything else, rather than directly onto
+    /// the local filesystem.
+    ///
+    /// Entries are placed as `unpack` places them, skipping those with a `..`
+    /// in their path and taking the archive's path transform and stripped
+    /// components into account. Directories, regular files, symlinks and
+    /// hard links are unpacked; device files and FIFOs are skipped. The
+    /// archive's other unpacking options are left to `fs`.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use std::fs::File;
+    /// use tar::{Archive, OsFileSystem};
+    ///
+    /// let mut ar = Archive::new(File::open("foo.tar").unwrap());
+    /// ar.unpack_into(&mut OsFileSystem::new("foo")).unwrap();
+    /// ```
+    pub fn unpack_into(&mut self, fs: &mut dyn FileSystem) -> io::Result<()> {
+        let me: &mut Archive<dyn Read> = self;
+        me._unpack_into(fs)
+    }
+
+    /// Streams the contents of entries in this archive to writers given by
+    /// `sink`, rather than unpacking them onto a filesystem.
+    ///
+    /// `sink` is called with each entry in turn and returns a writer for its
+    /// contents, or `None` to skip it. The writer is flushed and dropped once
+    /// the contents have been written to it, so entries can be uploaded
+    /// somewhere or filtered without writing them out to files first.
+    /// Entries without contents, such as directories and symlinks, are
+    /// passed to `sink` too, and anything returned for them is written
+    /// nothing.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use std::fs::File;
+    /// use tar::Archive;
+    ///
+    /// let mut ar = Archive::new(File::open("foo.tar").unwrap());
+    /// ar.unpack_with_sinks(|entry| {
+    ///     let path = entry.path().ok()?;
+    ///     if path.extension()? != "txt" {
+    ///         return None;
+    ///     }
+    ///     File::create(path.file_name()?).ok()
+    /// })
+    /// .unwrap();
+    /// ```
+    pub fn unpack_with_sinks<W, F>(&mut self, mut sink: F) -> io::Result<()>
+    where
+        W: Write,
+        F: FnMut(&Entry<'_, R>) -> Option<W>,
+    {
+        for entry in self.entries()? {
+            let mut entry =
+                entry.map_err(|e| TarError::new("failed to iterate over archive", e))?;
+            let mut dst = match sink(&entry) {
+                Some(dst) => dst,
+                None => continue,
+            };
+            io::copy(&mut entry, &mut dst)
+                .and_then(|_| dst.flush())
+                .map_err(|e| {
+                    let path = String::from_utf8_lossy(&entry.path_bytes()).into_owned();
+                    TarError::new(format!("failed to unpack `{}`", path), e)
+                })?;
+        }
+        Ok(())
+    }
+
+    /// Writes a manifest of every member of this archive to `dst`, in the
+    /// given format.
+    ///
+    /// The contents of regular files are read to hash them, and hard links
+    /// are listed with the contents of the file they link to.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use std::fs::File;
+    /// use tar::{Archive, ManifestFormat};
+    ///
+    /// let mut ar = Archive::new(File::open("foo.tar").unwrap());
+    /// let dst = File::create("foo.mtree").unwrap();
+    /// ar.write_manifest(ManifestFormat::Mtree, dst).unwrap();
+    /// ```
+    pub fn write_manifest<W: Write>(
+        &mut self,
+        format: ManifestFormat,
+        mut dst: W,
+    ) -> io::Result<()> {
+        let me: &mut Archive<dyn Read> = self;
+        me._write_manifest(format, &mut dst)
+    }
+
+    /// Checks every member of this archive against a manifest written by
+    /// `write_manifest`, returning how the two differ.
+    ///
+    /// Both manifest formats are accepted, and told apart by whether the
+    /// first line is `#mtree`. Only what the manifest records is checked, so
+    /// a `sha256sum` listing checks the contents of regular files and ignores
+    /// other members. An empty list means the archive matches.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use std::fs::File;
+    /// use tar::Archive;
+    ///
+    /// let mut ar = Archive::new(File::open("foo.tar").unwrap());
+    /// let manifest = File::open("foo.mtree").unwrap();
+    /// for mismatch in ar.verify_manifest(manifest).unwrap() {
+    ///     println!("{}", mismatch);
+    /// }
+    /// ```
+    pub fn verify_manifest<M: Read>(&mut self, manifest: M) -> io::Result<Vec<ManifestMismatch>> {
+        let me: &mut Archive<dyn Read> = self;
+        me._verify_manifest(&mut io::BufReader::new(manifest))
     }
 
     /// Set the mask of the permission bits when unpacking this entry.
@@ -125,46 +752,363 @@ impl<R: Read> Archive<R> {
         self.inner.mask = mask;
     }
 
-    /// Indicate whether extended file attributes (xattrs on Unix) are preserved
-    /// when unpacking this archive.
+    /// Indicate whether the process umask is applied to the permissions of
+    /// unpacked files when they aren't preserved with
+    /// `set_preserve_permissions`, as GNU tar does for users other than root.
+    ///
+    /// With a umask of `0o022`, for example, files archived with mode `0o666`
+    /// are unpacked with mode `0o644` and directories archived with `0o777`
+    /// with `0o755`. The umask is combined with any mask given to `set_mask`,
+    /// and is read when this is called.
+    ///
+    /// This defaults to false and is only implemented on Unix.
+    pub fn set_apply_umask(&mut self, apply: bool) {
+        self.inner.umask = apply.then(process_umask);
+    }
+
+    /// Indicate whether extended file attributes (xattrs on Unix) are preserved
+    /// when unpacking this archive.
+    ///
+    /// This flag is disabled by default and is currently only implemented on
+    /// Unix using xattr support. This may eventually be implemented for
+    /// Windows, however, if other archive implementations are found which do
+    /// this as well.
+    pub fn set_unpack_xattrs(&mut self, unpack_xattrs: bool) {
+        self.inner.unpack_xattrs = unpack_xattrs;
+    }
+
+    /// Indicate whether SELinux contexts are restored when unpacking this
+    /// archive, from `security.selinux` extended attributes or GNU tar's
+    /// `RHT.security.selinux` records.
+    ///
+    /// SELinux contexts are only restored with this flag, not with
+    /// `set_unpack_xattrs`, since setting them needs a system with SELinux
+    /// enabled and usually privileges. This flag is disabled by default and
+    /// is only implemented on Unix using xattr support.
+    pub fn set_unpack_selinux(&mut self, unpack_selinux: bool) {
+        self.inner.unpack_selinux = unpack_selinux;
+    }
+
+    /// Indicate whether macOS metadata, the `com.apple.*` extended attributes
+    /// holding things like Finder info and resource forks, is restored when
+    /// unpacking this archive.
+    ///
+    /// This metadata is only restored with this flag, not with
+    /// `set_unpack_xattrs`. This flag is disabled by default and is only
+    /// implemented on Unix using xattr support.
+    pub fn set_unpack_mac_metadata(&mut self, unpack_mac_metadata: bool) {
+        self.inner.unpack_mac_metadata = unpack_mac_metadata;
+    }
+
+    /// Indicate whether the readonly, hidden and system attributes of files
+    /// are restored from `SCHILY.fflags` records when unpacking this archive,
+    /// as written by `Builder::windows_attributes` and libarchive.
+    ///
+    /// This flag is disabled by default and is only implemented on Windows.
+    pub fn set_unpack_windows_attributes(&mut self, unpack_windows_attributes: bool) {
+        self.inner.unpack_windows_attributes = unpack_windows_attributes;
+    }
+
+    /// Indicate whether character devices, block devices and FIFOs are
+    /// created as such when unpacking this archive, using the device numbers
+    /// recorded for them, as is needed to unpack a root filesystem image.
+    ///
+    /// Without this flag they are unpacked as empty regular files. Creating
+    /// devices usually needs privileges. This flag is disabled by default and
+    /// is only implemented on Unix.
+    pub fn set_unpack_devices(&mut self, unpack_devices: bool) {
+        self.inner.unpack_devices = unpack_devices;
+    }
+
+    /// Indicate whether extended permissions (like suid on Unix) are preserved
+    /// when unpacking this entry.
+    ///
+    /// This flag is disabled by default and is currently only implemented on
+    /// Unix.
+    pub fn set_preserve_permissions(&mut self, preserve: bool) {
+        self.inner.preserve_permissions = preserve;
+    }
+
+    /// Indicate whether numeric ownership ids (like uid and gid on Unix)
+    /// are preserved when unpacking this entry.
+    ///
+    /// This flag is disabled by default and is currently only implemented on
+    /// Unix.
+    pub fn set_preserve_ownerships(&mut self, preserve: bool) {
+        self.inner.preserve_ownerships = preserve;
+    }
+
+    /// Indicate whether preserved ownerships use the numeric uid and gid of
+    /// each entry as is, rather than first looking up the entry's user and
+    /// group names on this system like `tar` does by default.
+    ///
+    /// The names are those returned by `Entry::username` and
+    /// `Entry::groupname`, so long ones stored in PAX records are used too.
+    /// Names that don't exist locally fall back to the numeric ids. This flag
+    /// is enabled by default, and name lookups are only implemented on Unix.
+    pub fn set_numeric_owner(&mut self, numeric: bool) {
+        self.inner.numeric_owner = numeric;
+    }
+
+    /// Sets translations of the users and groups owning entries, applied to
+    /// preserved ownerships before any name lookups, or `None` to restore
+    /// owners as stored.
+    ///
+    /// This is for restoring an archive on a system whose accounts have
+    /// different ids than the one it was made on, as with GNU tar's
+    /// `--owner-map` and `--group-map`.
+    pub fn set_owner_map(&mut self, map: Option<OwnerMap>) {
+        self.inner.owner_map = map.map(Arc::new);
+    }
+
+    /// Indicate whether files and symlinks should be overwritten on extraction.
+    ///
+    /// This is a shorthand for `set_overwrite_policy` with
+    /// `OverwritePolicy::Overwrite` or `OverwritePolicy::Error`.
+    pub fn set_overwrite(&mut self, overwrite: bool) {
+        self.inner.overwrite = if overwrite {
+            OverwritePolicy::Overwrite
+        } else {
+            OverwritePolicy::Error
+        };
+    }
+
+    /// Indicate what happens when unpacking an entry whose destination
+    /// already exists.
+    ///
+    /// Existing directories are always reused. This defaults to
+    /// `OverwritePolicy::Overwrite`.
+    pub fn set_overwrite_policy(&mut self, policy: OverwritePolicy) {
+        self.inner.overwrite = policy;
+    }
+
+    /// Indicate whether access time information is preserved when unpacking
+    /// this entry.
+    ///
+    /// This flag is enabled by default.
+    pub fn set_preserve_mtime(&mut self, preserve: bool) {
+        self.inner.preserve_mtime = preserve;
+    }
+
+    /// Indicate whether the access times stored in `atime` PAX records or GNU
+    /// headers are restored when unpacking files, rather than being set to
+    /// their modification times.
+    ///
+    /// This flag is disabled by default.
+    pub fn set_preserve_atime(&mut self, preserve: bool) {
+        self.inner.preserve_atime = preserve;
+    }
+
+    /// Strip the given number of leading path components from each entry's
+    /// path when unpacking it, like GNU `tar --strip-components`.
+    ///
+    /// Entries with no components left after stripping are skipped, and the
+    /// targets of hard links are stripped the same way. This defaults to 0.
+    pub fn set_strip_components(&mut self, count: usize) {
+        self.inner.strip_components = count;
+    }
+
+    /// Rewrite the path of each entry when unpacking it.
+    ///
+    /// The function is given an entry's path and returns the path to unpack
+    /// it at, or `None` to skip the entry. It is also applied to the targets
+    /// of hard links, and runs before any `set_strip_components` stripping.
+    /// The rewritten path is subject to the same checks as one read from the
+    /// archive, so it cannot be used to unpack outside of the destination.
+    pub fn set_path_transform<F>(&mut self, transform: F)
+    where
+        F: Fn(&Path) -> Option<PathBuf> + Send + Sync + 'static,
+    {
+        self.inner.path_transform = Some(Arc::new(transform));
+    }
+
+    /// Indicate whether `unpack` and `Entry::unpack_in` may create symlinks
+    /// whose targets are absolute or lead outside of the destination.
+    ///
+    /// Targets are resolved against the symlinks already unpacked, so a chain
+    /// of links can't be used to escape either. When disallowed such entries
+    /// are an error, unless `set_copy_external_symlinks` is enabled. Files are
+    /// never written through a symlink leading outside of the destination
+    /// either way. This flag is disabled by default, and has no effect on
+    /// `Entry::unpack`.
+    pub fn set_allow_external_symlinks(&mut self, allow: bool) {
+        self.inner.allow_external_symlinks = allow;
+    }
+
+    /// Indicate whether symlinks disallowed by `set_allow_external_symlinks`
+    /// are unpacked as copies of the files they point to rather than being an
+    /// error.
+    ///
+    /// The copy is made when the entry is unpacked, so the target must
+    /// already exist and be a regular file. This flag is disabled by default.
+    pub fn set_copy_external_symlinks(&mut self, copy: bool) {
+        self.inner.copy_external_symlinks = copy;
+    }
+
+    /// Indicate what happens when unpacking an entry whose path differs only
+    /// in case from one already unpacked by this archive.
+    ///
+    /// Paths are compared after stripping components and applying any path
+    /// transform. This defaults to `CaseCollisionPolicy::Ignore`.
+    pub fn set_case_collision_policy(&mut self, policy: CaseCollisionPolicy) {
+        self.inner.case_collisions = policy;
+    }
+
+    /// Indicate whether entry paths are rewritten into names Windows can
+    /// create when unpacking.
+    ///
+    /// Characters Windows doesn't allow in names, such as `:` or `?`, are
+    /// replaced with `_`, trailing dots and spaces are removed, and `_` is
+    /// appended to the stem of reserved device names like `CON` or `nul.txt`.
+    /// The targets of hard links are rewritten the same way. This flag is
+    /// enabled by default on Windows, where paths too long for the usual
+    /// limit are also unpacked through their `\\?\` form.
+    pub fn set_windows_names(&mut self, windows_names: bool) {
+        self.inner.windows_names = windows_names;
+    }
+
+    /// Converts entry paths to a Unicode normalization form when unpacking,
+    /// so that archives made on macOS, whose names are usually decomposed,
+    /// don't unpack beside the composed names of existing files on Linux as
+    /// files which look the same.
+    ///
+    /// The targets of links and the names in GNU dump directories are
+    /// converted too. Paths which aren't valid UTF-8 are left as they are.
+    /// This defaults to `None`, unpacking names as they are stored.
+    pub fn set_normalize_paths(&mut self, form: Option<NormalizationForm>) {
+        self.inner.normalization = form;
+    }
+
+    /// Indicate what happens to entries whose path or link name isn't valid
+    /// UTF-8 on platforms where paths must be Unicode, such as Windows.
+    ///
+    /// The policy applies to `Entry::path` and `Entry::link_name` as well as
+    /// to unpacking, so that entries are listed under the same names they
+    /// are unpacked at. It has no effect on Unix, where paths can be any
+    /// bytes. This defaults to `NonUtf8PathPolicy::Error`.
+    pub fn set_non_utf8_path_policy(&mut self, policy: NonUtf8PathPolicy) {
+        self.inner.non_utf8_paths = policy;
+    }
+
+    /// Indicate what happens when a symlink entry can't be unpacked because
+    /// creating the symlink fails, such as on Windows without the privilege
+    /// to.
+    ///
+    /// A symlink which fails because something already exists at its path
+    /// under `OverwritePolicy::Error` is always an error. This defaults to
+    /// `SymlinkFallback::Error`.
+    pub fn set_symlink_fallback(&mut self, fallback: SymlinkFallback) {
+        self.inner.symlink_fallback = fallback;
+    }
+
+    /// Indicate whether each regular file is written to a temporary name in
+    /// its destination directory and then renamed into place when unpacking,
+    /// so that nothing reading the destination ever sees a partially written
+    /// file, and an existing file is replaced in one step.
+    ///
+    /// The temporary file is removed if unpacking the entry fails. This
+    /// defaults to false.
+    pub fn set_atomic_unpack(&mut self, atomic: bool) {
+        self.inner.atomic_unpack = atomic;
+    }
+
+    /// Indicate how much of what is unpacked is synced to stable storage
+    /// before unpacking returns, for tools that must not report a restore as
+    /// done until it would survive a crash.
+    ///
+    /// Files are synced as each is written, before being renamed into place
+    /// with `set_atomic_unpack`. With `SyncPolicy::DataAndDirs` the
+    /// directories are synced once by `unpack` after everything is written,
+    /// and only on Unix, where that is what makes new names durable. This
+    /// defaults to `SyncPolicy::None`.
+    pub fn set_sync_on_unpack(&mut self, policy: SyncPolicy) {
+        self.inner.sync = policy;
+    }
+
+    /// Preallocate regular files of at least `threshold` bytes when unpacking
+    /// them, and hint to the OS that they are written sequentially and won't
+    /// be read back soon.
+    ///
+    /// This keeps large files from fragmenting on disk, and keeps unpacking
+    /// them from pushing everything else out of the page cache. Space is
+    /// reserved with `fallocate` on Linux, and by setting the file's length
+    /// elsewhere, so a file whose unpacking fails partway through may be left
+    /// at its full length. Sparse files are never preallocated, nor are any
+    /// files with `set_unpack_sparse`. This defaults to `None`.
+    pub fn set_preallocate_threshold(&mut self, threshold: Option<u64>) {
+        self.inner.preallocate_threshold = threshold;
+    }
+
+    /// Indicate whether blocks of zeros in the data of regular files are left
+    /// as holes when unpacking this archive, rather than written out.
+    ///
+    /// The gaps recorded in the maps of sparse entries are always left as
+    /// holes; this also makes sparse files of entries archived with their
+    /// zeros, such as VM images archived without `Builder::sparse`, on
+    /// filesystems which support them. Zeros are looked for in blocks of
+    /// 4 KiB, the block size of most filesystems. This flag is disabled by
+    /// default.
+    pub fn set_unpack_sparse(&mut self, unpack_sparse: bool) {
+        self.inner.unpack_sparse = unpack_sparse;
+    }
+
+    /// Fail unpacking with a `TimedOut` error once it has taken longer than
+    /// `timeout` altogether.
+    ///
+    /// The time is checked between entries and as regular files are written
+    /// out, so a read from the underlying reader which never returns still
+    /// hangs; give sources which can stall, such as sockets, a read timeout
+    /// of their own. This defaults to `None`.
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.inner.timeout = timeout;
+    }
+
+    /// Fail reading or unpacking an entry with a `TimedOut` error once more
+    /// than `timeout` has passed since its header was read.
     ///
-    /// This flag is disabled by default and is currently only implemented on
-    /// Unix using xattr support. This may eventually be implemented for
-    /// Windows, however, if other archive implementations are found which do
-    /// this as well.
-    pub fn set_unpack_xattrs(&mut self, unpack_xattrs: bool) {
-        self.inner.unpack_xattrs = unpack_xattrs;
+    /// This applies to entries however they are read, checking the time
+    /// before each read of their contents. This defaults to `None`.
+    pub fn set_entry_timeout(&mut self, timeout: Option<Duration>) {
+        self.inner.entry_timeout = timeout;
     }
 
-    /// Indicate whether extended permissions (like suid on Unix) are preserved
-    /// when unpacking this entry.
+    /// Limit reading from the underlying reader to `rate` bytes a second,
+    /// sleeping as needed, so that unpacking a large archive doesn't
+    /// saturate a disk or network link shared with other work.
     ///
-    /// This flag is disabled by default and is currently only implemented on
-    /// Unix.
-    pub fn set_preserve_permissions(&mut self, preserve: bool) {
-        self.inner.preserve_permissions = preserve;
+    /// Short bursts of up to a quarter of a second's worth are let through
+    /// at full speed. This defaults to `None`, reading as fast as possible.
+    pub fn set_max_bytes_per_sec(&mut self, rate: Option<u64>) {
+        *self.inner.throttle.get_mut() = rate.map(RateLimiter::new);
     }
 
-    /// Indicate whether numeric ownership ids (like uid and gid on Unix)
-    /// are preserved when unpacking this entry.
+    /// Report the progress of unpacking to `observer`.
     ///
-    /// This flag is disabled by default and is currently only implemented on
-    /// Unix.
-    pub fn set_preserve_ownerships(&mut self, preserve: bool) {
-        self.inner.preserve_ownerships = preserve;
+    /// Each entry unpacked is reported as it starts and finishes, along with
+    /// the bytes of its contents as they are written out, and the archive is
+    /// reported finished once it has all been unpacked successfully.
+    pub fn set_progress_observer<P: ProgressObserver + Send + 'static>(&mut self, observer: P) {
+        self.inner.progress = Some(RefCell::new(Box::new(observer)));
     }
 
-    /// Indicate whether files and symlinks should be overwritten on extraction.
-    pub fn set_overwrite(&mut self, overwrite: bool) {
-        self.inner.overwrite = overwrite;
+    /// Compute a digest of the contents of each regular file as it is read or
+    /// unpacked, available from `Entry::digest` and in the report returned by
+    /// `unpack_with_report`.
+    ///
+    /// This defaults to `None`, computing no digests.
+    pub fn set_digest_algorithm(&mut self, algorithm: Option<DigestAlgorithm>) {
+        self.inner.digest = algorithm;
     }
 
-    /// Indicate whether access time information is preserved when unpacking
-    /// this entry.
+    /// Read from the underlying object in chunks of at least `size` bytes,
+    /// buffering what isn't needed yet.
     ///
-    /// This flag is enabled by default.
-    pub fn set_preserve_mtime(&mut self, preserve: bool) {
-        self.inner.preserve_mtime = preserve;
+    /// Headers are otherwise read 512 bytes at a time, which is slow when
+    /// each read is costly, such as from a network filesystem or a pipe.
+    /// Reads at least as large as the buffer bypass it. This defaults to 0,
+    /// reading nothing ahead.
+    pub fn set_read_buffer_size(&mut self, size: usize) {
+        self.inner.read_buf_size = size;
     }
 
     /// Ignore zeroed headers, which would otherwise indicate to the archive that it has no more
@@ -174,6 +1118,121 @@ impl<R: Read> Archive<R> {
     pub fn set_ignore_zeros(&mut self, ignore_zeros: bool) {
         self.inner.ignore_zeros = ignore_zeros;
     }
+
+    /// Set which irregularities in headers are tolerated while reading this
+    /// archive, `ParseProfile::GnuCompatible` by default.
+    pub fn set_parse_profile(&mut self, profile: ParseProfile) {
+        self.inner.parse_profile = profile;
+    }
+
+    /// Skip the AppleDouble files, named `._` followed by the name of the file
+    /// they belong to, which BSD tar on macOS adds to hold metadata that
+    /// other systems can't store, leaving them out of `entries` and of what
+    /// is unpacked.
+    ///
+    /// This defaults to false.
+    pub fn set_skip_apple_double(&mut self, skip: bool) {
+        self.inner.skip_apple_double = skip;
+    }
+
+    /// Unpack GNU incremental backups as a chain: for each dump directory
+    /// entry, whatever is in the directory on disk but not listed in the
+    /// entry is removed, so that files deleted between backups are deleted
+    /// again when the backups are unpacked in order. Directories reached
+    /// through a symlink, or lying outside of the destination, aren't pruned.
+    ///
+    /// This defaults to false, when dump directories are unpacked like any
+    /// other directory.
+    pub fn set_incremental(&mut self, incremental: bool) {
+        self.inner.incremental = incremental;
+    }
+}
+
+#[cfg(feature = "encrypt")]
+impl<R: Read> Archive<crate::DecryptingReader<R>> {
+    /// Create a new archive reading from `obj`, an archive encrypted with age
+    /// to one of `identities`.
+    ///
+    /// The age header is read straight away, so an error is returned here if
+    /// `obj` isn't encrypted to any of `identities`. Entries whose contents
+    /// fail to authenticate return an error when read rather than any of the
+    /// tampered data.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use std::fs::{self, File};
+    /// use tar::{Archive, Identity};
+    ///
+    /// let identities = Identity::from_file(&fs::read_to_string("key.txt").unwrap()).unwrap();
+    /// let file = File::open("foo.tar.age").unwrap();
+    /// let mut ar = Archive::with_decryption(file, &identities).unwrap();
+    /// ar.unpack("foo").unwrap();
+    /// ```
+    pub fn with_decryption(obj: R, identities: &[crate::Identity]) -> io::Result<Self> {
+        Ok(Archive::new(crate::DecryptingReader::new(obj, identities)?))
+    }
+}
+
+#[cfg(feature = "mmap")]
+impl Archive<crate::MmapReader> {
+    /// Create a new archive reading from the file at `path`, mapped into
+    /// memory.
+    ///
+    /// Reading from the mapping takes no system calls, and the contents of
+    /// entries can be borrowed straight from it with `Entry::mapped_data`.
+    /// Iterate with `entries_with_seek` to skip over contents without
+    /// touching them. See `MmapReader::open` for what happens if the file is
+    /// truncated while it is mapped.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use tar::Archive;
+    ///
+    /// let mut ar = Archive::from_mmap("foo.tar").unwrap();
+    /// for entry in ar.entries_with_seek().unwrap() {
+    ///     let entry = entry.unwrap();
+    ///     let data = entry.mapped_data().unwrap();
+    ///     println!("{}: {} bytes", entry.path().unwrap().display(), data.len());
+    /// }
+    /// ```
+    pub fn from_mmap<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let reader = crate::MmapReader::open(path)?;
+        let mapping = reader.mapping();
+        let mut archive = Archive::new(reader);
+        archive.inner.mapped = Some(mapping);
+        Ok(archive)
+    }
+}
+
+#[cfg(feature = "bytes")]
+impl Archive<io::Cursor<bytes::Bytes>> {
+    /// Create a new archive reading from `data`, an archive already in memory.
+    ///
+    /// As with `from_mmap`, the contents of entries can be borrowed straight
+    /// from `data` with `Entry::mapped_data`, or shared with
+    /// `Entry::read_all_bytes` without being copied.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use bytes::Bytes;
+    /// use tar::Archive;
+    ///
+    /// # let data = Bytes::new();
+    /// let mut ar = Archive::from_bytes(data);
+    /// for entry in ar.entries_with_seek().unwrap() {
+    ///     let mut entry = entry.unwrap();
+    ///     let contents: Bytes = entry.read_all_bytes().unwrap();
+    ///     println!("{}: {} bytes", entry.path().unwrap().display(), contents.len());
+    /// }
+    /// ```
+    pub fn from_bytes(data: bytes::Bytes) -> Self {
+        let mut archive = Archive::new(io::Cursor::new(data.clone()));
+        archive.inner.mapped = Some(Arc::new(data));
+        archive
+    }
 }
 
 impl<R: Seek + Read> Archive<R> {
@@ -184,14 +1243,224 @@ impl<R: Seek + Read> Archive<R> {
     /// sequence. If entries are processed out of sequence (from what the
     /// iterator returns), then the contents read for each entry may be
     /// corrupted.
-    pub fn entries_with_seek(&mut self) -> io::Result<Entries<R>> {
+    pub fn entries_with_seek(&mut self) -> io::Result<Entries<'_, R>> {
         let me: &Archive<dyn Read> = self;
         let me_seekable: &Archive<dyn SeekRead> = self;
         me._entries(Some(me_seekable)).map(|fields| Entries {
-            fields: fields,
+            fields,
             _ignored: marker::PhantomData,
         })
     }
+
+    /// Checks that `signature` was made by `key` over this whole archive,
+    /// returning an error of kind `InvalidData` if not.
+    ///
+    /// This must be called before reading any entries, which it leaves the
+    /// archive ready for by seeking back to the start.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use std::fs::{self, File};
+    /// use tar::{Archive, PublicKey, Signature};
+    ///
+    /// let key = PublicKey::from_minisign(&fs::read_to_string("tar.pub").unwrap()).unwrap();
+    /// let sig = fs::read_to_string("foo.tar.minisig").unwrap();
+    /// let sig = Signature::from_minisign(&sig).unwrap();
+    ///
+    /// let mut ar = Archive::new(File::open("foo.tar").unwrap());
+    /// ar.verify_signature(&key, &sig).unwrap();
+    /// ar.unpack("foo").unwrap();
+    /// ```
+    #[cfg(feature = "sign")]
+    pub fn verify_signature(
+        &mut self,
+        key: &crate::PublicKey,
+        signature: &crate::Signature,
+    ) -> io::Result<()> {
+        if self.inner.pos.get() != 0 {
+            return Err(other(
+                "cannot verify the signature unless archive is at position 0",
+            ));
+        }
+        let obj = self.inner.obj.get_mut();
+        obj.seek(SeekFrom::Start(0))?;
+        let result = key.verify(&mut *obj, signature);
+        obj.seek(SeekFrom::Start(0))?;
+        result
+    }
+
+    /// Writes a copy of this archive to `dst`, leaving out every member for
+    /// which `keep` returns `false`.
+    ///
+    /// Kept members are copied byte for byte along with any GNU long name or
+    /// PAX headers describing them, and the copy is terminated with an
+    /// end-of-archive marker. Global PAX headers are always copied, as they
+    /// describe the members after them too. Returns the number of members
+    /// left out.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use std::fs::File;
+    /// use tar::Archive;
+    ///
+    /// let mut ar = Archive::new(File::open("foo.tar").unwrap());
+    /// let dst = File::create("bar.tar").unwrap();
+    /// ar.rewrite(dst, |entry| &*entry.path_bytes() != b"secret.txt").unwrap();
+    /// ```
+    pub fn rewrite<W, F>(&mut self, mut dst: W, mut keep: F) -> io::Result<u64>
+    where
+        W: Write,
+        F: FnMut(&Entry<'_, R>) -> bool,
+    {
+        let mut ranges = Vec::new();
+        let mut removed = 0;
+        {
+            let mut entries = self.entries_with_seek()?;
+            // Global headers are read along with the member after them, so
+            // those before a member left out are copied on their own
+            let mut globals = 0;
+            loop {
+                let start = entries.end_position();
+                let entry = match entries.next() {
+                    Some(entry) => entry?,
+                    None => break,
+                };
+                if keep(&entry) {
+                    ranges.push((start, entries.end_position()));
+                } else {
+                    removed += 1;
+                    let headers = &entries.global_headers()[globals..];
+                    ranges.extend(headers.iter().map(|range| (range.start, range.end)));
+                }
+                globals = entries.global_headers().len();
+            }
+            let headers = &entries.global_headers()[globals..];
+            ranges.extend(headers.iter().map(|range| (range.start, range.end)));
+        }
+
+        let obj = self.inner.obj.get_mut();
+        for (start, end) in ranges {
+            obj.seek(SeekFrom::Start(start))?;
+            let copied = io::copy(&mut obj.take(end - start), &mut dst)?;
+            if copied != end - start {
+                return Err(other("unexpected EOF while copying archive member"));
+            }
+        }
+        dst.write_all(&[0; BLOCK_SIZE as usize * 2])?;
+        Ok(removed)
+    }
+
+    /// Writes a copy of this archive to `dst` with the problems which can be
+    /// worked out from the rest of it fixed.
+    ///
+    /// Header checksums which aren't the sum of the header's bytes as
+    /// unsigned are recomputed, padding after an entry's contents which
+    /// isn't zeros is zeroed, and the copy is always terminated with a
+    /// complete end-of-archive marker. When the archive is cut off partway
+    /// through the contents of its last entry, that entry's size is shrunk
+    /// to what is there, and a header cut off partway through is dropped.
+    /// Everything else is copied byte for byte.
+    ///
+    /// Headers are only found by following the sizes of the entries before
+    /// them, so a damaged size field can't be repaired; for that see
+    /// `entries_salvage`.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use std::fs::File;
+    /// use tar::Archive;
+    ///
+    /// let mut ar = Archive::new(File::open("damaged.tar").unwrap());
+    /// let report = ar.repair(File::create("repaired.tar").unwrap()).unwrap();
+    /// println!("fixed {} checksums", report.fixed_checksums());
+    /// ```
+    pub fn repair<W: Write>(&mut self, mut dst: W) -> io::Result<RepairReport> {
+        if self.inner.pos.get() != 0 {
+            return Err(other("cannot repair an archive unless it is at position 0"));
+        }
+        let obj = self.inner.obj.get_mut();
+        let len = obj.seek(SeekFrom::End(0))?;
+        obj.seek(SeekFrom::Start(0))?;
+
+        let mut report = RepairReport::default();
+        let mut header = Header::new_old();
+        let mut pax_size = None;
+        let mut pos = 0;
+        loop {
+            if len - pos < BLOCK_SIZE {
+                report.truncated |= pos < len;
+                report.added_trailer = true;
+                break;
+            }
+            obj.read_exact(header.as_mut_bytes())?;
+            pos += BLOCK_SIZE;
+            if header.as_bytes().iter().all(|b| *b == 0) {
+                let mut block = [0; BLOCK_SIZE as usize];
+                report.added_trailer = len - pos < BLOCK_SIZE || {
+                    obj.read_exact(&mut block)?;
+                    block.iter().any(|b| *b != 0)
+                };
+                break;
+            }
+
+            let header_pos = pos - BLOCK_SIZE;
+            let mut size = header.entry_size().map_err(|e| {
+                TarError::new(
+                    format!("cannot repair the header at offset {}", header_pos),
+                    e,
+                )
+            })?;
+            let kind = header.entry_type();
+            let extension =
+                kind.is_pax_local_extensions() || kind.is_gnu_longname() || kind.is_gnu_longlink();
+            if size == 0 && !extension {
+                size = pax_size.take().unwrap_or(0);
+            }
+            let present = cmp::min(size, len - pos);
+            let cksum_ok = header.cksum().ok() == Some(header.calculate_cksum());
+            if !cksum_ok {
+                report.fixed_checksums += 1;
+            }
+            if present < size {
+                header.set_size(present);
+                report.truncated = true;
+            }
+            if !cksum_ok || present < size {
+                header.set_cksum();
+            }
+            dst.write_all(header.as_bytes())?;
+
+            let mut data = obj.take(present);
+            if kind.is_pax_local_extensions() {
+                let mut records = Vec::new();
+                data.read_to_end(&mut records)?;
+                pax_size = pax_extensions_value(&records, PAX_SIZE);
+                dst.write_all(&records)?;
+            } else {
+                io::copy(&mut data, &mut dst)?;
+            }
+            pos += present;
+            if !extension {
+                pax_size = None;
+                report.entries += 1;
+            }
+
+            let padding = (BLOCK_SIZE - present % BLOCK_SIZE) % BLOCK_SIZE;
+            let padding = cmp::min(padding, len - pos);
+            let mut block = [0; BLOCK_SIZE as usize];
+            obj.read_exact(&mut block[..padding as usize])?;
+            if block.iter().any(|b| *b != 0) {
+                report.fixed_padding += 1;
+            }
+            pos += padding;
+            pad_zeroes(&mut dst, present)?;
+        }
+        dst.write_all(&[0; BLOCK_SIZE as usize * 2])?;
+        Ok(report)
+    }
 }
 
 impl Archive<dyn Read + '_> {
@@ -210,13 +1479,114 @@ impl Archive<dyn Read + '_> {
             seekable_archive,
             done: false,
             next: 0,
+            padding: 0,
             raw: false,
+            salvage: false,
+            skipped: Vec::new(),
+            global_pax: GlobalPax::default(),
+            global_headers: Vec::new(),
         })
     }
 
-    fn _unpack(&mut self, dst: &Path) -> io::Result<()> {
+    fn _write_manifest(&mut self, format: ManifestFormat, dst: &mut dyn Write) -> io::Result<()> {
+        let mut manifest = ManifestWriter::new(format, dst)?;
+        for entry in self._entries(None)? {
+            manifest.add(&mut entry?)?;
+        }
+        dst.flush()
+    }
+
+    fn _verify_manifest(
+        &mut self,
+        manifest: &mut dyn BufRead,
+    ) -> io::Result<Vec<ManifestMismatch>> {
+        let mut verifier = ManifestVerifier::new(manifest)?;
+        for entry in self._entries(None)? {
+            verifier.check(&mut entry?)?;
+        }
+        verifier.finish()
+    }
+
+    #[cfg(all(target_os = "linux", feature = "uring"))]
+    fn _unpack_uring(&mut self, dst: &Path) -> io::Result<()> {
+        let inner = &self.inner;
+        if inner.preserve_ownerships
+            || inner.unpack_xattrs
+            || inner.unpack_selinux
+            || inner.unpack_mac_metadata
+            || inner.unpack_devices
+            || inner.atomic_unpack
+            || inner.sync != SyncPolicy::None
+            || inner.preallocate_threshold.is_some()
+            || inner.unpack_sparse
+        {
+            return self._unpack(dst, &mut UnpackReport::default(), None);
+        }
+        crate::uring::unpack(self._entries(None)?, dst)
+    }
+
+    fn _unpack_into(&mut self, fs: &mut dyn FileSystem) -> io::Result<()> {
+        crate::filesystem::unpack(self._entries(None)?, fs)
+    }
+
+    fn _unpack_with_cancel(&mut self, dst: &Path, token: &CancellationToken) -> io::Result<()> {
+        self.inner.cancel = Some(token.clone());
+        let result = self._unpack(dst, &mut UnpackReport::default(), None);
+        self.inner.cancel = None;
+        match result {
+            Err(_) if token.is_cancelled() => Err(Cancelled.into()),
+            result => result,
+        }
+    }
+
+    fn _unpack_prefix(&mut self, dst: &Path, prefix: &Path) -> io::Result<()> {
+        let prefix = prefix.to_path_buf();
+        let transform = self.inner.path_transform.take();
+        let then = transform.clone();
+        self.inner.path_transform = Some(Arc::new(move |path: &Path| {
+            let rest = strip_member_prefix(path, &prefix)?;
+            match &then {
+                Some(transform) => transform(&rest),
+                None => Some(rest),
+            }
+        }));
+        let result = self._unpack(dst, &mut UnpackReport::default(), None);
+        self.inner.path_transform = transform;
+        result
+    }
+
+    fn _unpack(
+        &mut self,
+        dst: &Path,
+        report: &mut UnpackReport,
+        journal: Option<&mut UnpackJournal>,
+    ) -> io::Result<()> {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::info_span!("unpack", dst = %dst.display()).entered();
+        let start = Instant::now();
+        self.inner.deadline = self.inner.timeout.map(|timeout| start + timeout);
+        let result = self._unpack_entries(dst, report, journal);
+        self.inner.deadline = None;
+        #[cfg(feature = "tracing")]
+        match &result {
+            Ok(()) => tracing::info!(
+                entries = report.entries,
+                elapsed = ?start.elapsed(),
+                "unpacked archive"
+            ),
+            Err(e) => tracing::warn!(error = %e, "failed to unpack archive"),
+        }
+        result
+    }
+
+    fn _unpack_entries(
+        &mut self,
+        dst: &Path,
+        report: &mut UnpackReport,
+        mut journal: Option<&mut UnpackJournal>,
+    ) -> io::Result<()> {
         if dst.symlink_metadata().is_err() {
-            fs::create_dir_all(&dst)
+            fs::create_dir_all(dst)
                 .map_err(|e| TarError::new(format!("failed to create `{}`", dst.display()), e))?;
         }
 
@@ -231,12 +1601,42 @@ impl Archive<dyn Read + '_> {
         // descendants), to ensure that directory permissions do not interfer with descendant
         // extraction.
         let mut directories = Vec::new();
+        // The directories to sync at the end, under `SyncPolicy::DataAndDirs`
+        let mut to_sync = BTreeSet::new();
         for entry in self._entries(None)? {
+            if let Some(token) = &self.inner.cancel {
+                token.check()?;
+            }
+            check_deadline(self.inner.deadline)?;
             let mut file = entry.map_err(|e| TarError::new("failed to iterate over archive", e))?;
-            if file.header().entry_type() == crate::EntryType::Directory {
+            if self.inner.sync == SyncPolicy::DataAndDirs {
+                if let Some(path) = file.destination_in(dst)? {
+                    to_sync.extend(
+                        path.ancestors()
+                            .skip(1)
+                            .take_while(|dir| dir.starts_with(dst))
+                            .map(Path::to_path_buf),
+                    );
+                }
+            }
+            let kind = file.header().entry_type();
+            if kind.is_dir() {
+                file.prepare_dir_in(dst)?;
                 directories.push(file);
-            } else {
-                file.unpack_in(dst)?;
+            } else if let Some(true) = journal
+                .as_deref()
+                .map(|journal| journal.is_complete(&file, dst))
+                .transpose()?
+            {
+                report.entries += 1;
+            } else if unpack_observed(&mut file, dst, self.inner.progress.as_ref())? {
+                report.entries += 1;
+                if let Some(digest) = file.digest() {
+                    report.digests.push((file.path()?.into_owned(), digest));
+                }
+                if let Some(journal) = journal.as_deref_mut() {
+                    journal.record(&file)?;
+                }
             }
         }
 
@@ -249,9 +1649,21 @@ impl Archive<dyn Read + '_> {
         // [0]: <https://github.com/alexcrichton/tar-rs/issues/242>
         directories.sort_by(|a, b| b.path_bytes().cmp(&a.path_bytes()));
         for mut dir in directories {
-            dir.unpack_in(dst)?;
+            if unpack_observed(&mut dir, dst, self.inner.progress.as_ref())? {
+                report.entries += 1;
+            }
         }
 
+        // Deeper directories sort later, and are synced before their parents
+        #[cfg(unix)]
+        for dir in to_sync.iter().rev() {
+            let synced = fs::File::open(dir).and_then(|dir| dir.sync_all());
+            synced.map_err(|e| TarError::new(format!("failed to sync `{}`", dir.display()), e))?;
+        }
+
+        if let Some(progress) = &self.inner.progress {
+            progress.borrow_mut().archive_finished();
+        }
         Ok(())
     }
 }
@@ -264,14 +1676,71 @@ impl<'a, R: Read> Entries<'a, R> {
     /// or long link archive members. Raw iteration is disabled by default.
     pub fn raw(self, raw: bool) -> Entries<'a, R> {
         Entries {
-            fields: EntriesFields {
-                raw: raw,
-                ..self.fields
-            },
+            fields: EntriesFields { raw, ..self.fields },
             _ignored: marker::PhantomData,
         }
     }
 }
+impl<R: Read> Entries<'_, R> {
+    /// Offset of the header following the last entry read, which is the end
+    /// of the archive's entries once iteration has finished.
+    pub(crate) fn end_position(&self) -> u64 {
+        self.fields.next
+    }
+
+    /// Returns where the global PAX headers read so far are in the archive.
+    pub(crate) fn global_headers(&self) -> &[Range<u64>] {
+        &self.fields.global_headers
+    }
+
+    /// Returns the metadata describing the archive, from the global PAX
+    /// headers read so far, as written by `Builder::append_archive_metadata`.
+    ///
+    /// Such headers are usually at the start of the archive, so this is
+    /// complete once the first entry has been read.
+    pub fn archive_metadata(&self) -> ArchiveMetadata {
+        ArchiveMetadata::from_records(self.fields.global_pax.records())
+    }
+}
+
+impl<'a, R: Read> Iterator for SalvageEntries<'a, R> {
+    type Item = io::Result<Salvaged<'a, R>>;
+
+    fn next(&mut self) -> Option<io::Result<Salvaged<'a, R>>> {
+        if !self.fields.skipped.is_empty() {
+            return Some(Ok(Salvaged::Skipped(self.fields.skipped.remove(0))));
+        }
+        if let Some(entry) = self.pending.take() {
+            return Some(Ok(Salvaged::Entry(EntryFields::from(entry).into_entry())));
+        }
+        if self.fields.done {
+            return None;
+        }
+        let start = self.fields.next;
+        match self.fields.next_entry() {
+            Ok(entry) => {
+                self.pending = entry;
+                if self.pending.is_none() {
+                    self.fields.done = true;
+                }
+            }
+            // A member was read past but couldn't be made into an entry, so
+            // carry on after it
+            Err(_) if self.fields.next > start => {
+                let end = self.fields.skipped.iter().map(|range| range.end);
+                let end = end.fold(self.fields.next, cmp::max);
+                self.fields.skipped.clear();
+                self.fields.skipped.push(start..end);
+            }
+            Err(e) => {
+                self.fields.done = true;
+                return Some(Err(e));
+            }
+        }
+        self.next()
+    }
+}
+
 impl<'a, R: Read> Iterator for Entries<'a, R> {
     type Item = io::Result<Entry<'a, R>>;
 
@@ -287,43 +1756,86 @@ impl<'a> EntriesFields<'a> {
         &mut self,
         pax_extensions: Option<&[u8]>,
     ) -> io::Result<Option<Entry<'a, io::Empty>>> {
+        let profile = self.archive.inner.parse_profile;
         let mut header = Header::new_old();
-        let mut header_pos = self.next;
+        let mut header_pos;
+        // Where the blocks without a valid header being passed over when
+        // salvaging start
+        let mut damaged = None;
         loop {
-            // Seek to the start of the next header in the archive
-            let delta = self.next - self.archive.inner.pos.get();
-            self.skip(delta)?;
+            let mut zeros = false;
+            loop {
+                // Seek to the start of the next header in the archive. Some tools
+                // cut off the end of the last entry's padding along with the
+                // end-of-archive marker.
+                let delta = self.next - self.archive.inner.pos.get();
+                match self.skip(delta) {
+                    Err(e)
+                        if profile == ParseProfile::Permissive
+                            && e.kind() == io::ErrorKind::UnexpectedEof
+                            && self.next - self.archive.inner.pos.get() <= self.padding =>
+                    {
+                        return Ok(None);
+                    }
+                    result => result?,
+                }
 
-            // EOF is an indicator that we are at the end of the archive.
-            if !try_read_all(&mut &self.archive.inner, header.as_mut_bytes())? {
-                return Ok(None);
-            }
+                // EOF is an indicator that we are at the end of the archive, as
+                // is a partial end-of-archive marker when parsing permissively.
+                // Damage running up to the end is passed over when salvaging.
+                header.as_mut_bytes().fill(0);
+                let read = match try_read_all(&mut &self.archive.inner, header.as_mut_bytes()) {
+                    Err(e)
+                        if e.kind() == io::ErrorKind::UnexpectedEof
+                            && (damaged.is_some()
+                                || profile == ParseProfile::Permissive
+                                    && header.as_bytes().iter().all(|b| *b == 0)) =>
+                    {
+                        false
+                    }
+                    result => result?,
+                };
+                if !read {
+                    if let Some(start) = damaged {
+                        self.skipped.push(start..self.archive.inner.pos.get());
+                    } else if profile == ParseProfile::Strict && !zeros {
+                        return Err(other("archive ended without an end-of-archive marker"));
+                    }
+                    return Ok(None);
+                }
+
+                // If a header is not all zeros, we have another valid header.
+                // Otherwise, check if we are ignoring zeros and continue, or break as if this is the
+                // end of the archive. Zeros amid damage are passed over with it.
+                if !header.as_bytes().iter().all(|i| *i == 0) {
+                    self.next += BLOCK_SIZE;
+                    break;
+                }
 
-            // If a header is not all zeros, we have another valid header.
-            // Otherwise, check if we are ignoring zeros and continue, or break as if this is the
-            // end of the archive.
-            if !header.as_bytes().iter().all(|i| *i == 0) {
+                if !self.archive.inner.ignore_zeros && damaged.is_none() {
+                    return Ok(None);
+                }
+                zeros = true;
                 self.next += BLOCK_SIZE;
-                break;
             }
+            header_pos = self.next - BLOCK_SIZE;
 
-            if !self.archive.inner.ignore_zeros {
-                return Ok(None);
+            match check_header(&mut header, profile) {
+                Ok(()) => break,
+                Err(_) if self.salvage => {
+                    damaged.get_or_insert(header_pos);
+                }
+                Err(e) => return Err(e),
             }
-            self.next += BLOCK_SIZE;
-            header_pos = self.next;
-        }
-
-        // Make sure the checksum is ok
-        let sum = header.as_bytes()[..148]
-            .iter()
-            .chain(&header.as_bytes()[156..])
-            .fold(0, |a, b| a + (*b as u32))
-            + 8 * 32;
-        let cksum = header.cksum()?;
-        if sum != cksum {
-            return Err(other("archive header checksum mismatch"));
         }
+        // Extensions from before damage don't describe what's after it
+        let pax_extensions = match damaged {
+            Some(start) => {
+                self.skipped.push(start..header_pos);
+                None
+            }
+            None => pax_extensions,
+        };
 
         let mut pax_size: Option<u64> = None;
         if let Some(pax_extensions_ref) = &pax_extensions {
@@ -346,24 +1858,67 @@ impl<'a> EntriesFields<'a> {
             }
         }
         let ret = EntryFields {
-            size: size,
-            header_pos: header_pos,
-            file_pos: file_pos,
+            size,
+            header_pos,
+            file_pos,
             data: vec![EntryIo::Data((&self.archive.inner).take(size))],
-            header: header,
+            header,
             long_pathname: None,
             long_linkname: None,
             pax_extensions: None,
-            mask: self.archive.inner.mask,
+            local_pax_len: 0,
+            mask: match self.archive.inner.umask {
+                Some(umask) if !self.archive.inner.preserve_permissions => {
+                    self.archive.inner.mask | umask
+                }
+                _ => self.archive.inner.mask,
+            },
             unpack_xattrs: self.archive.inner.unpack_xattrs,
+            unpack_selinux: self.archive.inner.unpack_selinux,
+            unpack_mac_metadata: self.archive.inner.unpack_mac_metadata,
+            unpack_windows_attributes: self.archive.inner.unpack_windows_attributes,
+            unpack_devices: self.archive.inner.unpack_devices,
             preserve_permissions: self.archive.inner.preserve_permissions,
             preserve_mtime: self.archive.inner.preserve_mtime,
+            preserve_atime: self.archive.inner.preserve_atime,
             overwrite: self.archive.inner.overwrite,
+            strip_components: self.archive.inner.strip_components,
+            path_transform: self.archive.inner.path_transform.clone(),
+            allow_external_symlinks: self.archive.inner.allow_external_symlinks,
+            copy_external_symlinks: self.archive.inner.copy_external_symlinks,
+            case_folding: match self.archive.inner.case_collisions {
+                CaseCollisionPolicy::Ignore => None,
+                policy => Some((policy, &self.archive.inner.case_folding)),
+            },
+            windows_names: self.archive.inner.windows_names,
+            normalization: self.archive.inner.normalization,
+            non_utf8_paths: self.archive.inner.non_utf8_paths,
+            symlink_fallback: self.archive.inner.symlink_fallback,
+            atomic_unpack: self.archive.inner.atomic_unpack,
+            sync: self.archive.inner.sync,
+            preallocate_threshold: self.archive.inner.preallocate_threshold,
+            unpack_sparse: self.archive.inner.unpack_sparse,
+            incremental: self.archive.inner.incremental,
             preserve_ownerships: self.archive.inner.preserve_ownerships,
+            numeric_owner: self.archive.inner.numeric_owner,
+            owner_map: self.archive.inner.owner_map.clone(),
+            digest: None,
+            mapped: self.archive.inner.mapped.as_ref(),
+            pool: &self.archive.inner.pool,
+            cancel: self.archive.inner.cancel.as_ref(),
+            progress: self.archive.inner.progress.as_ref(),
+            deadline: earliest(
+                self.archive.inner.deadline,
+                self.archive
+                    .inner
+                    .entry_timeout
+                    .map(|timeout| Instant::now() + timeout),
+            ),
         };
 
         // Store where the next entry is, rounding up by 512 bytes (the size of
         // a header);
+        self.padding = BLOCK_SIZE.wrapping_sub(size) % BLOCK_SIZE;
         let size = size
             .checked_add(BLOCK_SIZE - 1)
             .ok_or_else(|| other("size overflow"))?;
@@ -386,6 +1941,7 @@ impl<'a> EntriesFields<'a> {
         let mut processed = 0;
         loop {
             processed += 1;
+            let skipped = self.skipped.len();
             let entry = match self.next_entry_raw(pax_extensions.as_deref())? {
                 Some(entry) => entry,
                 None if processed > 1 => {
@@ -396,6 +1952,11 @@ impl<'a> EntriesFields<'a> {
                 }
                 None => return Ok(None),
             };
+            if self.skipped.len() > skipped {
+                gnu_longname = None;
+                gnu_longlink = None;
+                pax_extensions = None;
+            }
 
             let is_recognized_header =
                 entry.header().as_gnu().is_some() || entry.header().as_ustar().is_some();
@@ -433,11 +1994,48 @@ impl<'a> EntriesFields<'a> {
                 continue;
             }
 
+            // Global headers describe every member after them rather than
+            // being members themselves
+            if is_recognized_header && entry.header().entry_type().is_pax_global_extensions() {
+                let mut fields = EntryFields::from(entry);
+                self.global_headers.push(fields.header_pos..self.next);
+                let records = fields.read_all()?;
+                self.global_pax.update(&records)?;
+                processed -= 1;
+                continue;
+            }
+
             let mut fields = EntryFields::from(entry);
             fields.long_pathname = gnu_longname;
             fields.long_linkname = gnu_longlink;
-            fields.pax_extensions = pax_extensions;
+            fields.local_pax_len = pax_extensions.as_ref().map_or(0, Vec::len);
+            fields.pax_extensions = self.global_pax.apply(pax_extensions)?;
+            let unrepresentable = self.archive.inner.non_utf8_paths == NonUtf8PathPolicy::Skip
+                && !(representable(&fields.path_bytes())
+                    && fields
+                        .link_name_bytes()
+                        .is_none_or(|name| representable(name.as_ref())));
+            if unrepresentable
+                || self.archive.inner.skip_apple_double && is_apple_double(&fields.path_bytes())
+            {
+                gnu_longname = None;
+                gnu_longlink = None;
+                pax_extensions = None;
+                processed = 0;
+                continue;
+            }
             self.parse_sparse_header(&mut fields)?;
+            let kind = fields.header.entry_type();
+            if kind.is_file() || kind.is_contiguous() || kind.is_gnu_sparse() {
+                fields.digest = self.archive.inner.digest.map(Hasher::new);
+            }
+            #[cfg(feature = "tracing")]
+            tracing::trace!(
+                path = %String::from_utf8_lossy(&fields.path_bytes()),
+                size = fields.size,
+                position = fields.header_pos,
+                "read entry"
+            );
             return Ok(Some(fields.into_entry()));
         }
     }
@@ -484,7 +2082,7 @@ impl<'a> EntriesFields<'a> {
                 }
                 let off = block.offset()?;
                 let len = block.length()?;
-                if len != 0 && (size - remaining) % BLOCK_SIZE != 0 {
+                if len != 0 && !(size - remaining).is_multiple_of(BLOCK_SIZE) {
                     return Err(other(
                         "previous block in sparse file was not \
                          aligned to 512-byte boundary",
@@ -551,15 +2149,20 @@ impl<'a> EntriesFields<'a> {
             );
             (&seekable_archive.inner).seek(pos)?;
         } else {
-            let mut buf = [0u8; 4096 * 8];
+            let mut buf = self.archive.inner.pool.take();
+            buf.resize(4096 * 8, 0);
             while amt > 0 {
                 let n = cmp::min(amt, buf.len() as u64);
                 let n = (&self.archive.inner).read(&mut buf[..n as usize])?;
                 if n == 0 {
-                    return Err(other("unexpected EOF during skip"));
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "unexpected EOF during skip",
+                    ));
                 }
                 amt -= n as u64;
             }
+            self.archive.inner.pool.give(buf);
         }
         Ok(())
     }
@@ -587,22 +2190,104 @@ impl<'a> Iterator for EntriesFields<'a> {
     }
 }
 
-impl<'a, R: ?Sized + Read> Read for &'a ArchiveInner<R> {
+impl<R: ?Sized + Read> Read for &ArchiveInner<R> {
     fn read(&mut self, into: &mut [u8]) -> io::Result<usize> {
-        let i = self.obj.borrow_mut().read(into)?;
+        let mut buf = self.read_buf.borrow_mut();
+        if buf.start == buf.end {
+            if into.len() >= self.read_buf_size {
+                let i = self.obj.borrow_mut().read(into)?;
+                if let Some(limiter) = &mut *self.throttle.borrow_mut() {
+                    limiter.consume(i);
+                }
+                self.pos.set(self.pos.get() + i as u64);
+                return Ok(i);
+            }
+            let size = self.read_buf_size;
+            buf.data.resize(size, 0);
+            buf.end = self.obj.borrow_mut().read(&mut buf.data)?;
+            buf.start = 0;
+            if let Some(limiter) = &mut *self.throttle.borrow_mut() {
+                limiter.consume(buf.end);
+            }
+        }
+        let i = cmp::min(into.len(), buf.end - buf.start);
+        into[..i].copy_from_slice(&buf.data[buf.start..buf.start + i]);
+        buf.start += i;
         self.pos.set(self.pos.get() + i as u64);
         Ok(i)
     }
 }
 
-impl<'a, R: ?Sized + Seek> Seek for &'a ArchiveInner<R> {
+impl<R: ?Sized + Seek> Seek for &ArchiveInner<R> {
     fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let mut buf = self.read_buf.borrow_mut();
+        let buffered = (buf.end - buf.start) as i64;
+        let pos = match pos {
+            // Skipping over what is already buffered needs no seek
+            SeekFrom::Current(n) if (0..=buffered).contains(&n) => {
+                buf.start += n as usize;
+                self.pos.set(self.pos.get() + n as u64);
+                return Ok(self.pos.get());
+            }
+            SeekFrom::Current(n) => SeekFrom::Current(
+                n.checked_sub(buffered)
+                    .ok_or_else(|| other("seek position out of bounds"))?,
+            ),
+            pos => pos,
+        };
+        buf.start = 0;
+        buf.end = 0;
         let pos = self.obj.borrow_mut().seek(pos)?;
         self.pos.set(pos);
         Ok(pos)
     }
 }
 
+/// Checks that `header` is one which can be parsed with `profile`, fixing up
+/// its numeric fields when parsing permissively.
+fn check_header(header: &mut Header, profile: ParseProfile) -> io::Result<()> {
+    let cksum = header.stored_cksum(profile == ParseProfile::Permissive)?;
+    if header.calculate_cksum() != cksum
+        && (profile == ParseProfile::Strict || header.calculate_signed_cksum() != i64::from(cksum))
+    {
+        return Err(other("archive header checksum mismatch"));
+    }
+    match profile {
+        ParseProfile::Strict => header.check_numeric_fields()?,
+        ParseProfile::GnuCompatible => {}
+        ParseProfile::Permissive => header.repair_numeric_fields(),
+    }
+    header.entry_size().map(drop)
+}
+
+/// Returns the umask of this process.
+#[cfg(unix)]
+fn process_umask() -> u32 {
+    // Linux reports it without changing it, which would be visible to other
+    // threads creating files meanwhile
+    if let Ok(status) = std::fs::read_to_string("/proc/self/status") {
+        let umask = status
+            .lines()
+            .find_map(|line| line.strip_prefix("Umask:"))
+            .and_then(|umask| u32::from_str_radix(umask.trim(), 8).ok());
+        if let Some(umask) = umask {
+            return umask;
+        }
+    }
+    // Otherwise it can only be read by setting it, so set the most
+    // restrictive one meanwhile
+    unsafe {
+        let umask = libc::umask(0o077);
+        libc::umask(umask);
+        umask as u32
+    }
+}
+
+#[cfg(not(unix))]
+fn process_umask() -> u32 {
+    0
+}
+
 /// Try to fill the buffer from the reader.
 ///
 /// If the reader reaches its end before filling the buffer at all, returns `false`.
@@ -616,10 +2301,79 @@ fn try_read_all<R: Read>(r: &mut R, buf: &mut [u8]) -> io::Result<bool> {
                     return Ok(false);
                 }
 
-                return Err(other("failed to read entire block"));
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "failed to read entire block",
+                ));
             }
             n => read += n,
         }
     }
     Ok(true)
 }
+
+/// Unpacks `entry` into `dst` like `Entry::unpack_in`, telling `progress` as
+/// it starts and finishes.
+fn unpack_observed<R: Read>(
+    entry: &mut Entry<'_, R>,
+    dst: &Path,
+    progress: Option<&Progress>,
+) -> io::Result<bool> {
+    #[cfg(feature = "tracing")]
+    let _span = tracing::debug_span!(
+        "unpack_entry",
+        path = %String::from_utf8_lossy(&entry.path_bytes()),
+        size = entry.size()
+    )
+    .entered();
+    #[cfg(feature = "tracing")]
+    let start = Instant::now();
+    let path = match progress {
+        Some(progress) => {
+            let path = entry.path()?.into_owned();
+            progress.borrow_mut().entry_started(&path, entry.size());
+            Some(path)
+        }
+        None => None,
+    };
+    let unpacked = entry.unpack_in(dst)?;
+    #[cfg(feature = "tracing")]
+    tracing::debug!(unpacked, elapsed = ?start.elapsed(), "unpacked entry");
+    if let (Some(progress), Some(path)) = (progress, path) {
+        progress.borrow_mut().entry_finished(&path);
+    }
+    Ok(unpacked)
+}
+
+/// Returns the earlier of two optional deadlines.
+fn earliest(a: Option<Instant>, b: Option<Instant>) -> Option<Instant> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(cmp::min(a, b)),
+        (a, b) => a.or(b),
+    }
+}
+
+/// Returns what is left of `path` after `prefix`, comparing whole components
+/// and ignoring `/` and `.` ones, or `None` if it isn't under `prefix`.
+fn strip_member_prefix(path: &Path, prefix: &Path) -> Option<PathBuf> {
+    let significant =
+        |part: &Component<'_>| !matches!(part, Component::RootDir | Component::CurDir);
+    let mut parts = path.components().filter(significant);
+    for part in prefix.components().filter(significant) {
+        if parts.next() != Some(part) {
+            return None;
+        }
+    }
+    Some(parts.collect())
+}
+
+/// Whether `path` names an AppleDouble file, `._` followed by the name of the
+/// file whose metadata it holds.
+fn is_apple_double(path: &[u8]) -> bool {
+    let path = path.strip_suffix(b"/").unwrap_or(path);
+    let name = match path.iter().rposition(|b| *b == b'/') {
+        Some(i) => &path[i + 1..],
+        None => path,
+    };
+    name.starts_with(b"._") && name.len() > 2
+}
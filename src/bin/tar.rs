@@ -1,12 +1,34 @@
-use clap::{Parser};
-use std::path::PathBuf;
-use tar::{Archive, Builder};
-use std::fs::File;
-use std::io::{self, Read, Write};
+use bzip2::read::MultiBzDecoder;
+use bzip2::write::BzEncoder;
+use clap::{Parser, ValueEnum};
+use flate2::read::MultiGzDecoder;
 use flate2::write::GzEncoder;
-use flate2::read::GzDecoder;
-use flate2::Compression;
-use indicatif::{ProgressBar, ProgressStyle};
+use globset::{Glob, GlobMatcher, GlobSet, GlobSetBuilder};
+use ignore::WalkBuilder;
+use indicatif::{HumanBytes, ProgressBar, ProgressStyle};
+use regex::{Regex, RegexBuilder};
+use std::cell::Cell;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::ops::RangeInclusive;
+use std::path::{Component, Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+#[cfg(feature = "http")]
+use tar::HttpReader;
+use tar::{
+    Archive, Builder, CaseCollisionPolicy, DigestAlgorithm, Entry, EntryType, FileSnapshot,
+    ManifestFormat, MultiVolumeReader, MultiVolumeWriter, NonUtf8PathPolicy, NormalizationForm,
+    OverwritePolicy, OwnerMap, Snapshot, SymlinkFallback, SyncPolicy, UnpackJournal,
+};
+#[cfg(feature = "encrypt")]
+use tar::{DecryptingReader, EncryptingWriter, Identity, Recipient};
+#[cfg(feature = "sign")]
+use tar::{PublicKey, SecretKey, Signature, SigningWriter};
+use xz2::read::XzDecoder;
+use xz2::write::XzEncoder;
 
 #[derive(Parser)]
 #[command(name = "tar")]
@@ -24,106 +46,3081 @@ struct Cli {
     #[arg(short = 'x', group = "mode")]
     extract: bool,
 
-    /// Enable gzip compression
-    #[arg(short = 'z')]
+    /// List the contents of an archive
+    #[arg(short = 't', long = "list", group = "mode")]
+    list: bool,
+
+    /// Append files to the end of an existing archive
+    #[arg(short = 'r', long = "append", group = "mode")]
+    append: bool,
+
+    /// Append only files newer than their copy in an existing archive
+    #[arg(short = 'u', long = "update", group = "mode")]
+    update: bool,
+
+    /// Check an archive for corrupt headers, truncated data and a missing
+    /// end-of-archive marker
+    #[arg(long = "verify", group = "mode")]
+    verify: bool,
+
+    /// Compare an archive against the files in the `-o` directory (default
+    /// the current directory), reporting anything that differs
+    #[arg(short = 'd', long = "diff", visible_alias = "compare", group = "mode")]
+    diff: bool,
+
+    /// Report the number of entries, sizes and compression ratio of an
+    /// archive without extracting it
+    #[arg(long = "stats", group = "mode")]
+    stats: bool,
+
+    /// Print a manifest of every member of an archive, or write it to the
+    /// `-o` file
+    #[arg(long = "manifest", group = "mode")]
+    manifest: bool,
+
+    /// Check every member of an archive against MANIFEST, as written by
+    /// `--manifest` in either format, reporting anything that doesn't match
+    #[arg(long = "check-manifest", value_name = "MANIFEST", group = "mode")]
+    check_manifest: Option<PathBuf>,
+
+    /// Format written by `--manifest`
+    #[arg(long = "manifest-format", value_enum, default_value_t = ManifestKind::Mtree)]
+    manifest_format: ManifestKind,
+
+    /// Sign an existing archive with `--secret-key`, writing a minisign
+    /// signature to `ARCHIVE.minisig` (or the `--signature` file)
+    #[arg(long = "sign", group = "mode", requires = "secret_key")]
+    sign: bool,
+
+    /// Generate a new signing key pair, writing it to the `--secret-key` and
+    /// `--public-key` files
+    #[arg(
+        long = "generate-key",
+        group = "mode",
+        requires_all = ["secret_key", "public_key"]
+    )]
+    generate_key: bool,
+
+    /// Delete members from an archive, in place unless `-o` is given
+    #[arg(long = "delete", group = "mode")]
+    delete: bool,
+
+    /// Copy an archive to the `-o` file with bad header checksums, padding
+    /// and end-of-archive marker fixed, and a truncated last member shortened
+    #[arg(long = "repair", group = "mode")]
+    repair: bool,
+
+    /// Pack the inputs into as few archives of at most SIZE bytes each (with
+    /// an optional K, M, G or T suffix) as will hold them, never splitting a
+    /// file, written to the `-o` directory as `000.tar`, `001.tar`, ... along
+    /// with an `index.tsv` of which archive holds each member
+    #[arg(
+        long = "pack",
+        value_name = "SIZE",
+        value_parser = parse_size,
+        group = "mode",
+        conflicts_with_all = ["split_size", "compress", "gzip", "listed_incremental", "incremental_state", "encrypt", "secret_key"]
+    )]
+    pack: Option<u64>,
+
+    /// Print the entries processed, bytes read and written, compression ratio
+    /// and elapsed time once create or extract finishes
+    #[arg(long = "totals")]
+    totals: bool,
+
+    /// Don't show a progress bar (it is also hidden when stderr isn't a
+    /// terminal)
+    #[arg(long = "no-progress")]
+    no_progress: bool,
+
+    /// Report progress every N records (10240 bytes each) of the archive
+    /// read or written, 10 if N is omitted
+    #[arg(
+        long = "checkpoint",
+        value_name = "N",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "10",
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    checkpoint: Option<u64>,
+
+    /// What to do at each checkpoint: `echo` prints a line to stderr, and
+    /// `exec=COMMAND` runs COMMAND through the shell with the checkpoint
+    /// number in `$TAR_CHECKPOINT`
+    #[arg(
+        long = "checkpoint-action",
+        value_name = "ACTION",
+        default_value = "echo",
+        value_parser = CheckpointAction::parse
+    )]
+    checkpoint_action: CheckpointAction,
+
+    /// Compress the archive with gzip; short for `--compress gzip`
+    #[arg(short = 'z', conflicts_with = "compress")]
     gzip: bool,
 
-    /// Output location (file for create, directory for extract)
-    #[arg(short = 'o')]
-    output: PathBuf,
+    /// Compression to use; when creating, defaults to the one matching the
+    /// output file's extension, and when reading it is detected from the
+    /// archive's contents
+    #[arg(long = "compress", value_enum, value_name = "ALGORITHM")]
+    compress: Option<Codec>,
+
+    /// Split the created archive into parts of at most SIZE bytes (with an
+    /// optional K, M, G or T suffix), named `ARCHIVE.000`, `ARCHIVE.001`, ...;
+    /// such parts are recombined automatically when reading
+    #[arg(
+        long = "split-size",
+        visible_alias = "tape-length",
+        value_name = "SIZE",
+        value_parser = parse_size
+    )]
+    split_size: Option<u64>,
+
+    /// Number of 512-byte blocks in each record: a created archive is padded
+    /// with zeros to a whole number of records, and archives are read a
+    /// record at a time, as tape drives need
+    #[arg(
+        short = 'b',
+        long = "blocking-factor",
+        value_name = "N",
+        value_parser = clap::value_parser!(u32).range(1..)
+    )]
+    blocking_factor: Option<u32>,
+
+    /// Make a GNU incremental backup, archiving only what changed since the
+    /// backup recorded in the snapshot FILE, which is then updated (or
+    /// created, making a full backup); when extracting, restore such a
+    /// backup, deleting files deleted since the last one (FILE is ignored)
+    #[arg(
+        short = 'g',
+        long = "listed-incremental",
+        value_name = "FILE",
+        conflicts_with_all = ["append", "update", "exclude", "exclude_ignore", "transform", "totals"]
+    )]
+    listed_incremental: Option<PathBuf>,
+
+    /// Archive only the files whose size, modification time or inode changed
+    /// since the state recorded in FILE, which is then updated (or created,
+    /// archiving everything); the archive is an ordinary one
+    #[arg(
+        long = "incremental-state",
+        value_name = "FILE",
+        conflicts_with_all = ["listed_incremental", "append", "update", "exclude", "exclude_ignore", "transform", "totals"]
+    )]
+    incremental_state: Option<PathBuf>,
+
+    /// Compression level (gzip/xz 0-9, bzip2 1-9, zstd 1-22)
+    #[arg(long = "level", value_name = "N")]
+    level: Option<u32>,
+
+    /// Exclude files matching PATTERN when creating an archive (may be given
+    /// multiple times)
+    #[arg(long = "exclude", value_name = "PATTERN")]
+    exclude: Vec<String>,
+
+    /// Read exclusion patterns from FILE (e.g. `.tarignore`) in each
+    /// directory being archived, using `.gitignore` syntax
+    #[arg(long = "exclude-ignore", value_name = "FILE")]
+    exclude_ignore: Vec<String>,
+
+    /// Write the contents of extracted files to stdout instead of creating
+    /// them on disk
+    #[arg(short = 'O', long = "to-stdout", requires = "extract")]
+    to_stdout: bool,
+
+    /// Instead of creating extracted files, run COMMAND through the shell
+    /// for each one with its contents on stdin and its name, mode, size,
+    /// mtime and owner in `TAR_*` environment variables
+    #[arg(
+        long = "to-command",
+        value_name = "COMMAND",
+        requires = "extract",
+        conflicts_with = "to_stdout"
+    )]
+    to_command: Option<String>,
+
+    /// Rename members with a sed-style `s/REGEX/REPLACEMENT/[gi]` expression
+    /// when creating or extracting, where REGEX uses extended syntax and
+    /// REPLACEMENT may refer to `&` and `\1`..`\9` (may be given multiple
+    /// times)
+    #[arg(long = "transform", visible_alias = "xform", value_name = "EXPR")]
+    transform: Vec<String>,
+
+    /// Replace existing files when extracting (the default)
+    #[arg(long = "overwrite", group = "existing")]
+    overwrite: bool,
+
+    /// Fail when extracting over an existing file
+    #[arg(short = 'k', long = "keep-old-files", group = "existing")]
+    keep_old_files: bool,
+
+    /// Don't replace existing files that are newer than their archived copy
+    #[arg(long = "keep-newer-files", group = "existing")]
+    keep_newer_files: bool,
+
+    /// Silently skip members whose destination already exists
+    #[arg(
+        long = "skip-existing",
+        visible_alias = "skip-old-files",
+        group = "existing"
+    )]
+    skip_existing: bool,
+
+    /// Restore the permissions stored in the archive, including setuid and
+    /// sticky bits, when extracting; otherwise the umask is applied to them
+    /// unless running as root
+    #[arg(short = 'p', long = "preserve-permissions", alias = "same-permissions")]
+    preserve_permissions: bool,
+
+    /// Restore the owner stored in the archive when extracting (the default
+    /// when running as root)
+    #[arg(long = "same-owner", conflicts_with = "no_same_owner")]
+    same_owner: bool,
+
+    /// Extract files as owned by the current user, even when running as root
+    #[arg(long = "no-same-owner")]
+    no_same_owner: bool,
+
+    /// Store only the numeric uid/gid of owners when creating, and restore
+    /// owners from them rather than the stored user and group names when
+    /// extracting
+    #[arg(long = "numeric-owner")]
+    numeric_owner: bool,
+
+    /// Restore the users listed in FILE as other users when extracting, one
+    /// `OLD NEW` pair per line, where OLD is a name or `+UID` and NEW is
+    /// `+UID`, `NAME:UID` or a local user name
+    #[arg(long = "owner-map", value_name = "FILE", requires = "extract")]
+    owner_map: Option<PathBuf>,
+
+    /// Restore the groups listed in FILE as other groups when extracting, in
+    /// the same format as `--owner-map`
+    #[arg(long = "group-map", value_name = "FILE", requires = "extract")]
+    group_map: Option<PathBuf>,
+
+    /// Don't restore modification times; extracted files get the current time
+    #[arg(short = 'm', long = "touch")]
+    touch: bool,
+
+    /// Store extended attributes when creating, and restore them when
+    /// extracting
+    #[arg(long = "xattrs")]
+    xattrs: bool,
+
+    /// Store POSIX ACLs when creating, as extended attributes which
+    /// `--xattrs` restores (requires the `acl` feature)
+    #[arg(long = "acls")]
+    acls: bool,
+
+    /// Store SELinux contexts when creating, and restore them when extracting
+    #[arg(long = "selinux")]
+    selinux: bool,
+
+    /// Store macOS metadata (`com.apple.*` extended attributes) when
+    /// creating, and restore it when extracting
+    #[arg(long = "mac-metadata")]
+    mac_metadata: bool,
+
+    /// Leave out the `._` AppleDouble files BSD tar on macOS adds to archives
+    /// when extracting or listing
+    #[arg(long = "skip-apple-double")]
+    skip_apple_double: bool,
+
+    /// What to do with member names which aren't valid UTF-8 when listing or
+    /// extracting on Windows, where names must be Unicode
+    #[arg(long = "non-utf8-names", value_enum)]
+    non_utf8_names: Option<NonUtf8Names>,
+
+    /// Create device nodes and FIFOs when extracting rather than empty
+    /// files, as is the default when run as root
+    #[arg(long = "devices")]
+    devices: bool,
+
+    /// Store the readonly, hidden and system attributes of files when
+    /// creating on Windows, and restore them when extracting there
+    #[arg(long = "windows-attributes")]
+    windows_attributes: bool,
+
+    /// Store modification, access and status change times to the nanosecond
+    /// when creating
+    #[arg(long = "extended-times")]
+    extended_times: bool,
+
+    /// Restore the access times stored in the archive when extracting, rather
+    /// than setting them to the modification times
+    #[arg(long = "preserve-atime", requires = "extract")]
+    preserve_atime: bool,
+
+    /// Extract each file under a temporary name and rename it into place, so
+    /// partially written files are never seen
+    #[arg(long = "atomic", requires = "extract")]
+    atomic: bool,
+
+    /// Sync extracted files, or with `dirs` also the directories they are in,
+    /// to stable storage before finishing
+    #[arg(long = "sync", value_name = "WHAT", requires = "extract")]
+    sync: Option<SyncArg>,
+
+    /// Preallocate extracted files of at least SIZE bytes (with an optional
+    /// K, M, G or T suffix), and keep them out of the page cache
+    #[arg(
+        long = "preallocate",
+        value_name = "SIZE",
+        value_parser = parse_size,
+        requires = "extract"
+    )]
+    preallocate: Option<u64>,
+
+    /// Leave blocks of zeros in extracted files as holes rather than writing
+    /// them, making sparse files
+    #[arg(long = "sparse", requires = "extract")]
+    sparse: bool,
+
+    /// Keep a journal of extracted files in JOURNAL, and skip files it
+    /// records as already extracted when resuming an interrupted extraction
+    #[arg(long = "resume", value_name = "JOURNAL", requires = "extract")]
+    resume: Option<PathBuf>,
+
+    /// Print what extracting would create, overwrite or reject without
+    /// writing anything
+    #[arg(long = "dry-run", requires = "extract")]
+    dry_run: bool,
+
+    /// Strip N leading path components from member names when extracting
+    #[arg(long = "strip-components", value_name = "N", default_value_t = 0)]
+    strip_components: usize,
+
+    /// Extract symlinks whose targets are absolute or lead outside of the
+    /// output directory
+    #[arg(long = "allow-external-symlinks", requires = "extract")]
+    allow_external_symlinks: bool,
+
+    /// Extract symlinks leading outside of the output directory as copies of
+    /// the files they point to
+    #[arg(
+        long = "copy-external-symlinks",
+        requires = "extract",
+        conflicts_with = "allow_external_symlinks"
+    )]
+    copy_external_symlinks: bool,
+
+    /// Store every name of a hard-linked file with its own copy of the
+    /// contents, rather than as hard links to the first name archived
+    #[arg(long = "hard-dereference")]
+    hard_dereference: bool,
+
+    /// Store symlinks as symlinks rather than the files they point to, and
+    /// don't descend into symlinked directories
+    #[arg(long = "no-dereference")]
+    no_dereference: bool,
+
+    /// Store files whose contents are identical to one already archived as
+    /// hard links to it, even if they aren't linked on disk (requires the
+    /// `sha2` feature)
+    #[arg(long = "deduplicate")]
+    deduplicate: bool,
+
+    /// What to do with members whose names differ only in case from one
+    /// already extracted, which would overwrite each other on a
+    /// case-insensitive filesystem
+    #[arg(long = "case-collisions", value_enum, requires = "extract")]
+    case_collisions: Option<CaseCollisions>,
+
+    /// What to do with symlinks which can't be created, such as on Windows
+    /// without the privilege to
+    #[arg(long = "symlink-fallback", value_enum, requires = "extract")]
+    symlink_fallback: Option<SymlinkFallbackArg>,
+
+    /// Convert member names to a Unicode normalization form when creating
+    /// and extracting, so names from macOS and Linux don't end up as
+    /// different files which look the same (requires the
+    /// `unicode-normalization` feature)
+    #[arg(long = "normalize-names", value_enum)]
+    normalize_names: Option<NormalizationArg>,
+
+    /// Unencrypted minisign secret key to sign archives with; the signature of
+    /// a created, appended or updated archive is written to
+    /// `ARCHIVE.minisig` (requires the `sign` feature)
+    #[arg(long = "secret-key", value_name = "FILE")]
+    secret_key: Option<PathBuf>,
+
+    /// Minisign public key to check an archive's signature with before
+    /// reading it (requires the `sign` feature)
+    #[arg(long = "public-key", value_name = "FILE", conflicts_with = "create")]
+    public_key: Option<PathBuf>,
+
+    /// Signature file to write with `--sign`, or to check with
+    /// `--public-key`, instead of `ARCHIVE.minisig`
+    #[arg(long = "signature", value_name = "FILE")]
+    signature: Option<PathBuf>,
+
+    /// Encrypt a created archive with age to RECIPIENT, an `age1...` public
+    /// key; may be given more than once (requires the `encrypt` feature)
+    #[arg(long = "encrypt", value_name = "RECIPIENT", requires = "create")]
+    encrypt: Vec<String>,
+
+    /// age identity file to decrypt an encrypted archive with; may be given
+    /// more than once (requires the `encrypt` feature)
+    #[arg(long = "identity", value_name = "FILE")]
+    identity: Vec<PathBuf>,
+
+    /// Output format used when listing an archive
+    #[arg(long = "format", value_enum, default_value_t = ListFormat::Text)]
+    format: ListFormat,
+
+    /// List paths separated by NUL bytes, for `xargs -0`; short for
+    /// `--format print0`
+    #[arg(long = "print0", conflicts_with_all = ["format", "porcelain"])]
+    print0: bool,
+
+    /// List NUL-delimited records in a stable format for scripts; short for
+    /// `--format porcelain`
+    #[arg(long = "porcelain", conflicts_with = "format")]
+    porcelain: bool,
+
+    /// Report the type of each regular file's contents when listing, as a
+    /// media type like `image/png` detected from its first bytes
+    #[arg(long = "content-type", requires = "list")]
+    content_type: bool,
+
+    /// Output location (file for create, directory for extract and pack, the archive
+    /// to grow for append and update, a new archive for delete and repair);
+    /// `-` writes the created or repaired archive to stdout
+    #[arg(short = 'o', required_unless_present_any = ["list", "delete", "to_stdout", "to_command", "verify", "diff", "stats", "manifest", "check_manifest", "sign", "generate_key"])]
+    output: Option<PathBuf>,
+
+    /// Inputs (files/directories to archive for create; for extract and list
+    /// the archive, optionally followed by the members or globs to select);
+    /// `-` reads the archive from stdin, and an `http://` or `https://` URL
+    /// downloads it as it is read
+    #[arg(required_unless_present_any = ["files_from", "generate_key"])]
+    inputs: Vec<PathBuf>,
+
+    /// Read further inputs (or member names) from FILE, one per line; `-`
+    /// reads them from stdin
+    #[arg(short = 'T', long = "files-from", value_name = "FILE")]
+    files_from: Option<PathBuf>,
+
+    /// Names read with `-T` are separated by NUL bytes rather than newlines,
+    /// as written by `find -print0`
+    #[arg(long = "null", requires = "files_from")]
+    null: bool,
+}
+
+impl Cli {
+    /// The format to list archives in.
+    fn list_format(&self) -> ListFormat {
+        if self.print0 {
+            ListFormat::Print0
+        } else if self.porcelain {
+            ListFormat::Porcelain
+        } else {
+            self.format
+        }
+    }
+
+    /// The compression explicitly asked for, if any.
+    fn codec(&self) -> Option<Codec> {
+        self.compress.or(self.gzip.then_some(Codec::Gzip))
+    }
+
+    /// The digest `--deduplicate` compares file contents with.
+    fn dedup_algorithm(&self) -> io::Result<Option<DigestAlgorithm>> {
+        if !self.deduplicate {
+            return Ok(None);
+        }
+        #[cfg(feature = "sha2")]
+        return Ok(Some(DigestAlgorithm::Sha256));
+        #[cfg(not(feature = "sha2"))]
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "--deduplicate requires tar to be built with the `sha2` feature",
+        ))
+    }
+
+    /// What `--non-utf8-names` asks for.
+    fn non_utf8_path_policy(&self) -> NonUtf8PathPolicy {
+        match self.non_utf8_names {
+            None | Some(NonUtf8Names::Error) => NonUtf8PathPolicy::Error,
+            Some(NonUtf8Names::Lossy) => NonUtf8PathPolicy::LossyReplace,
+            Some(NonUtf8Names::Percent) => NonUtf8PathPolicy::PercentEncode,
+            Some(NonUtf8Names::Skip) => NonUtf8PathPolicy::Skip,
+        }
+    }
+
+    /// The normalization form `--normalize-names` asks for, which needs the
+    /// `unicode-normalization` feature.
+    #[cfg_attr(not(feature = "unicode-normalization"), allow(unused_variables))]
+    fn normalization(&self) -> io::Result<Option<NormalizationForm>> {
+        let form = match self.normalize_names {
+            Some(form) => form,
+            None => return Ok(None),
+        };
+        #[cfg(feature = "unicode-normalization")]
+        return Ok(Some(match form {
+            NormalizationArg::Nfc => NormalizationForm::Nfc,
+            NormalizationArg::Nfd => NormalizationForm::Nfd,
+        }));
+        #[cfg(not(feature = "unicode-normalization"))]
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "--normalize-names requires tar to be built with the `unicode-normalization` feature",
+        ))
+    }
+
+    /// The translations of owners read from the `--owner-map` and
+    /// `--group-map` files, if either was given.
+    fn owner_map(&self) -> io::Result<Option<OwnerMap>> {
+        if self.owner_map.is_none() && self.group_map.is_none() {
+            return Ok(None);
+        }
+        let mut map = OwnerMap::new();
+        if let Some(path) = &self.owner_map {
+            map.read_users(File::open(path)?)
+                .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))?;
+        }
+        if let Some(path) = &self.group_map {
+            map.read_groups(File::open(path)?)
+                .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))?;
+        }
+        Ok(Some(map))
+    }
+
+    /// Whether `--acls` asks for POSIX ACLs to be stored, which needs the
+    /// `acl` feature.
+    fn store_acls(&self) -> io::Result<bool> {
+        if self.acls && !cfg!(feature = "acl") {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "--acls requires tar to be built with the `acl` feature",
+            ));
+        }
+        Ok(self.acls)
+    }
+
+    /// Wraps the archive stream to run the `--checkpoint` action as it is
+    /// read or written.
+    fn checkpoints<T>(&self, inner: T, verb: &'static str) -> Checkpoints<T> {
+        Checkpoints {
+            inner,
+            every: self.checkpoint.map(|n| n.saturating_mul(RECORD_SIZE)),
+            action: self.checkpoint_action.clone(),
+            verb,
+            bytes: 0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum CaseCollisions {
+    /// Fail with an error
+    Error,
+    /// Extract the later member with a `.~N~` suffix
+    Rename,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum SymlinkFallbackArg {
+    /// Fail with an error
+    Error,
+    /// Leave the symlink out
+    Skip,
+    /// Extract a copy of what the symlink points to
+    Copy,
+    /// Create a junction for symlinks to directories (Windows only)
+    Junction,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum NonUtf8Names {
+    /// Fail with an error
+    Error,
+    /// Replace the invalid bytes with U+FFFD
+    Lossy,
+    /// Replace each invalid byte with `%` and its value in hex
+    Percent,
+    /// Leave the member out
+    Skip,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum NormalizationArg {
+    /// Composed characters, as Linux tools usually write names
+    Nfc,
+    /// Decomposed characters, as macOS stores names
+    Nfd,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum SyncArg {
+    /// Sync the contents of each extracted file
+    Data,
+    /// Sync extracted files and the directories they are in
+    Dirs,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum ListFormat {
+    /// One path per line (with `-v`, an `ls -l` style line)
+    Text,
+    /// A JSON array with one object per member
+    Json,
+    /// Comma separated values with a header row
+    Csv,
+    /// Each path followed by a NUL byte, like `find -print0`
+    Print0,
+    /// For each member its type, octal mode, uid, gid, size, mtime, path and
+    /// link name (empty if none), and with `--content-type` its content
+    /// type, each followed by a NUL byte
+    Porcelain,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum ManifestKind {
+    /// An mtree(8) specification
+    Mtree,
+    /// A listing of regular files that `sha256sum -c` can check
+    #[cfg(feature = "sha2")]
+    #[value(name = "sha256sum")]
+    Sha256Sum,
+}
+
+/// A compression algorithm for the archive stream.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum Codec {
+    None,
+    Gzip,
+    Zstd,
+    Xz,
+    Bzip2,
+}
+
+impl Codec {
+    /// Picks the codec conventionally used for files named like `path`.
+    fn from_extension(path: &Path) -> Codec {
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        // An encrypted `foo.tar.gz.age` is compressed before it's encrypted.
+        let name = name.strip_suffix(".age").unwrap_or(&name);
+        let ext = name.rsplit('.').next().unwrap_or_default();
+        match ext {
+            "gz" | "tgz" => Codec::Gzip,
+            "zst" | "tzst" => Codec::Zstd,
+            "xz" | "txz" => Codec::Xz,
+            "bz2" | "tbz" | "tbz2" => Codec::Bzip2,
+            _ => Codec::None,
+        }
+    }
+
+    /// Identifies the codec of a stream from its first bytes.
+    fn sniff(magic: &[u8]) -> Codec {
+        if magic.starts_with(&[0x1f, 0x8b]) {
+            Codec::Gzip
+        } else if magic.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Codec::Zstd
+        } else if magic.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0]) {
+            Codec::Xz
+        } else if magic.starts_with(b"BZh") {
+            Codec::Bzip2
+        } else {
+            Codec::None
+        }
+    }
+
+    fn levels(self) -> RangeInclusive<u32> {
+        match self {
+            Codec::None => 0..=0,
+            Codec::Gzip | Codec::Xz => 0..=9,
+            Codec::Zstd => 1..=22,
+            Codec::Bzip2 => 1..=9,
+        }
+    }
+
+    fn default_level(self) -> u32 {
+        match self {
+            Codec::None => 0,
+            Codec::Gzip => 6,
+            Codec::Zstd => 3,
+            Codec::Xz => 6,
+            Codec::Bzip2 => 9,
+        }
+    }
+
+    /// Wraps `reader` to decompress it.
+    fn decoder<'a>(self, reader: Box<dyn BufRead + 'a>) -> io::Result<Box<dyn Read + 'a>> {
+        Ok(match self {
+            Codec::None => reader,
+            Codec::Gzip => Box::new(MultiGzDecoder::new(reader)),
+            Codec::Zstd => Box::new(zstd::Decoder::with_buffer(reader)?),
+            Codec::Xz => Box::new(XzDecoder::new_multi_decoder(reader)),
+            Codec::Bzip2 => Box::new(MultiBzDecoder::new(reader)),
+        })
+    }
+}
+
+/// The output of create, compressed with the chosen codec.
+enum CompressedWriter<W: Write> {
+    None(W),
+    Gzip(GzEncoder<W>),
+    Zstd(zstd::Encoder<'static, W>),
+    Xz(XzEncoder<W>),
+    Bzip2(BzEncoder<W>),
+}
+
+impl<W: Write> CompressedWriter<W> {
+    fn new(writer: W, codec: Codec, level: Option<u32>) -> io::Result<Self> {
+        let level = level.unwrap_or(codec.default_level());
+        if codec != Codec::None && !codec.levels().contains(&level) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "{:?} compression level must be between {} and {}",
+                    codec,
+                    codec.levels().start(),
+                    codec.levels().end()
+                ),
+            ));
+        }
+        Ok(match codec {
+            Codec::None => CompressedWriter::None(writer),
+            Codec::Gzip => {
+                CompressedWriter::Gzip(GzEncoder::new(writer, flate2::Compression::new(level)))
+            }
+            Codec::Zstd => CompressedWriter::Zstd(zstd::Encoder::new(writer, level as i32)?),
+            Codec::Xz => CompressedWriter::Xz(XzEncoder::new(writer, level)),
+            Codec::Bzip2 => {
+                CompressedWriter::Bzip2(BzEncoder::new(writer, bzip2::Compression::new(level)))
+            }
+        })
+    }
+
+    /// Writes out the end of the compressed stream.
+    fn finish(self) -> io::Result<W> {
+        match self {
+            CompressedWriter::None(w) => Ok(w),
+            CompressedWriter::Gzip(w) => w.finish(),
+            CompressedWriter::Zstd(w) => w.finish(),
+            CompressedWriter::Xz(w) => w.finish(),
+            CompressedWriter::Bzip2(w) => w.finish(),
+        }
+    }
+
+    fn get_mut(&mut self) -> &mut dyn Write {
+        match self {
+            CompressedWriter::None(w) => w,
+            CompressedWriter::Gzip(w) => w,
+            CompressedWriter::Zstd(w) => w,
+            CompressedWriter::Xz(w) => w,
+            CompressedWriter::Bzip2(w) => w,
+        }
+    }
+}
+
+impl<W: Write> Write for CompressedWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.get_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.get_mut().flush()
+    }
+}
+
+/// Metadata of a single archive member, as reported by `--list`.
+struct Member {
+    path: String,
+    kind: &'static str,
+    size: u64,
+    mode: u32,
+    uid: u64,
+    gid: u64,
+    mtime: u64,
+    link_name: Option<String>,
+    content_type: Option<&'static str>,
+}
+
+impl Member {
+    fn from_entry<R: Read>(entry: &Entry<R>) -> io::Result<Member> {
+        let header = entry.header();
+        Ok(Member {
+            path: entry.path()?.to_string_lossy().into_owned(),
+            kind: entry_type_name(header.entry_type()),
+            size: entry.size(),
+            mode: header.mode()?,
+            uid: header.uid()?,
+            gid: header.gid()?,
+            mtime: header.mtime()?,
+            link_name: entry
+                .link_name()?
+                .map(|name| name.to_string_lossy().into_owned()),
+            content_type: None,
+        })
+    }
+}
+
+fn entry_type_name(kind: EntryType) -> &'static str {
+    match kind {
+        EntryType::Regular | EntryType::Continuous | EntryType::GNUSparse => "file",
+        EntryType::Link => "hardlink",
+        EntryType::Symlink => "symlink",
+        EntryType::Char => "char",
+        EntryType::Block => "block",
+        EntryType::Directory | EntryType::GNUDumpDir => "directory",
+        EntryType::Fifo => "fifo",
+        _ => "other",
+    }
+}
+
+fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+fn list_archive<R: Read>(
+    archive: &mut Archive<R>,
+    selection: &mut MemberSelection,
+    format: ListFormat,
+    verbose: bool,
+    content_types: bool,
+    out: &mut dyn Write,
+) -> io::Result<()> {
+    match format {
+        ListFormat::Json => write!(out, "[")?,
+        ListFormat::Csv => {
+            write!(out, "path,type,size,mode,uid,gid,mtime,linkname")?;
+            if content_types {
+                write!(out, ",content_type")?;
+            }
+            writeln!(out)?;
+        }
+        ListFormat::Text | ListFormat::Print0 | ListFormat::Porcelain => {}
+    }
+    let mut listed = 0;
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        if !selection.matches(&entry.path_bytes()) {
+            continue;
+        }
+        let mut member = Member::from_entry(&entry)?;
+        if content_types {
+            member.content_type = entry.content_type()?;
+        }
+        listed += 1;
+        match format {
+            ListFormat::Text if verbose => {
+                write!(
+                    out,
+                    "{:<9} {:o} {}/{} {:>10} {} ",
+                    member.kind, member.mode, member.uid, member.gid, member.size, member.mtime,
+                )?;
+                if content_types {
+                    write!(out, "{:<24} ", member.content_type.unwrap_or("-"))?;
+                }
+                write!(out, "{}", member.path)?;
+                match member.link_name {
+                    Some(link_name) => writeln!(out, " -> {}", link_name)?,
+                    None => writeln!(out)?,
+                }
+            }
+            ListFormat::Text if content_types => writeln!(
+                out,
+                "{}: {}",
+                member.path,
+                member.content_type.unwrap_or(member.kind)
+            )?,
+            ListFormat::Text => writeln!(out, "{}", member.path)?,
+            // The raw names, as these are for scripts which may need to
+            // handle any bytes in them
+            ListFormat::Print0 => {
+                out.write_all(&entry.path_bytes())?;
+                out.write_all(b"\0")?;
+            }
+            ListFormat::Porcelain => {
+                write!(
+                    out,
+                    "{}\0{:o}\0{}\0{}\0{}\0{}\0",
+                    member.kind, member.mode, member.uid, member.gid, member.size, member.mtime
+                )?;
+                out.write_all(&entry.path_bytes())?;
+                out.write_all(b"\0")?;
+                out.write_all(&entry.link_name_bytes().unwrap_or_default())?;
+                out.write_all(b"\0")?;
+                if content_types {
+                    write!(out, "{}\0", member.content_type.unwrap_or(""))?;
+                }
+            }
+            ListFormat::Json => {
+                if listed > 1 {
+                    write!(out, ",")?;
+                }
+                write!(
+                    out,
+                    "\n  {{\"path\":{},\"type\":\"{}\",\"size\":{},\"mode\":{},\
+                     \"uid\":{},\"gid\":{},\"mtime\":{},\"linkname\":{}",
+                    json_string(&member.path),
+                    member.kind,
+                    member.size,
+                    member.mode,
+                    member.uid,
+                    member.gid,
+                    member.mtime,
+                    member
+                        .link_name
+                        .as_deref()
+                        .map_or_else(|| "null".to_string(), json_string)
+                )?;
+                if content_types {
+                    write!(
+                        out,
+                        ",\"content_type\":{}",
+                        member
+                            .content_type
+                            .map_or_else(|| "null".to_string(), json_string)
+                    )?;
+                }
+                write!(out, "}}")?;
+            }
+            ListFormat::Csv => {
+                write!(
+                    out,
+                    "{},{},{},{},{},{},{},{}",
+                    csv_field(&member.path),
+                    member.kind,
+                    member.size,
+                    member.mode,
+                    member.uid,
+                    member.gid,
+                    member.mtime,
+                    csv_field(member.link_name.as_deref().unwrap_or(""))
+                )?;
+                if content_types {
+                    write!(out, ",{}", member.content_type.unwrap_or(""))?;
+                }
+                writeln!(out)?;
+            }
+        }
+    }
+    if format == ListFormat::Json {
+        writeln!(out, "\n]")?;
+    }
+    out.flush()?;
+    selection.check_all_matched()
+}
+
+/// Unpacks the selected members of `archive` into `dst`.
+///
+/// This mirrors `Archive::unpack`, including deferring directories until
+/// the end so restrictive permissions don't prevent extracting their
+/// contents.
+fn extract_archive<R: Read>(
+    archive: &mut Archive<R>,
+    selection: &mut MemberSelection,
+    dst: &Path,
+    sync_dirs: bool,
+    mut journal: Option<&mut UnpackJournal>,
+    totals: &mut Totals,
+) -> io::Result<()> {
+    if dst.symlink_metadata().is_err() {
+        fs::create_dir_all(dst)?;
+    }
+    let dst = &dst.canonicalize().unwrap_or(dst.to_path_buf());
+
+    let mut directories = Vec::new();
+    // The directories to sync once everything is written, for `--sync dirs`
+    let mut to_sync = BTreeSet::new();
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        if !selection.matches(&entry.path_bytes()) {
+            continue;
+        }
+        if sync_dirs {
+            if let Some(path) = entry.destination_in(dst)? {
+                to_sync.extend(
+                    path.ancestors()
+                        .skip(1)
+                        .take_while(|dir| dir.starts_with(dst))
+                        .map(Path::to_path_buf),
+                );
+            }
+        }
+        totals.entries += 1;
+        if entry.header().entry_type().is_file() {
+            totals.content_bytes += entry.size();
+        }
+        if entry.header().entry_type().is_dir() {
+            entry.prepare_dir_in(dst)?;
+            directories.push(entry);
+        } else if let Some(journal) = journal.as_deref_mut() {
+            if !journal.is_complete(&entry, dst)? && entry.unpack_in(dst)? {
+                journal.record(&entry)?;
+            }
+        } else {
+            entry.unpack_in(dst)?;
+        }
+    }
+    directories.sort_by(|a, b| b.path_bytes().cmp(&a.path_bytes()));
+    for mut dir in directories {
+        dir.unpack_in(dst)?;
+    }
+    #[cfg(unix)]
+    for dir in to_sync.iter().rev() {
+        File::open(dir)?.sync_all()?;
+    }
+    selection.check_all_matched()
+}
+
+/// Reports what `extract_archive` would do with each selected member without
+/// writing anything, flagging members whose path would escape `dst`.
+fn dry_run_extract<R: Read>(
+    archive: &mut Archive<R>,
+    selection: &mut MemberSelection,
+    dst: &Path,
+    policy: OverwritePolicy,
+    out: &mut dyn Write,
+) -> io::Result<()> {
+    let canon_dst = dst.canonicalize().unwrap_or(dst.to_path_buf());
+    // Symlinks created by earlier members, which later members could be
+    // written through
+    let mut symlinks: HashMap<PathBuf, PathBuf> = HashMap::new();
+    for entry in archive.entries()? {
+        let entry = entry?;
+        if !selection.matches(&entry.path_bytes()) {
+            continue;
+        }
+        let name = String::from_utf8_lossy(&entry.path_bytes()).into_owned();
+        let file_dst = match entry.destination_in(&canon_dst)? {
+            Some(file_dst) if file_dst != canon_dst => file_dst,
+            Some(_) => continue,
+            None if Path::new(&name)
+                .components()
+                .any(|c| c == Component::ParentDir) =>
+            {
+                writeln!(out, "reject {}: path contains `..`", name)?;
+                continue;
+            }
+            None => {
+                writeln!(out, "skip {}: removed by --transform", name)?;
+                continue;
+            }
+        };
+        let shown = file_dst.strip_prefix(&canon_dst).unwrap_or(&file_dst);
+
+        if let Some(via) = escaping_ancestor(&file_dst, &canon_dst, &symlinks) {
+            writeln!(
+                out,
+                "reject {}: would be written outside the destination through `{}`",
+                name,
+                via.strip_prefix(&canon_dst).unwrap_or(&via).display()
+            )?;
+            continue;
+        }
+        let kind = entry.header().entry_type();
+        if kind.is_symlink() {
+            if let Some(target) = entry.link_name()? {
+                let resolved = normalize_lexically(&file_dst.parent().unwrap().join(&target));
+                if !resolved.starts_with(&canon_dst) {
+                    writeln!(
+                        out,
+                        "warning {}: symlink points outside the destination ({})",
+                        name,
+                        target.display()
+                    )?;
+                }
+                symlinks.insert(file_dst.clone(), target.into_owned());
+            }
+        }
+
+        let action = match fs::symlink_metadata(&file_dst) {
+            Err(_) => "create",
+            Ok(existing) if existing.is_dir() && kind.is_dir() => "keep existing",
+            Ok(existing) => match policy {
+                OverwritePolicy::Overwrite => "overwrite",
+                OverwritePolicy::Error => "fail on existing",
+                OverwritePolicy::Skip => "skip existing",
+                OverwritePolicy::KeepNewer => {
+                    let on_disk = existing
+                        .modified()
+                        .ok()
+                        .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+                        .map_or(0, |d| d.as_secs());
+                    if on_disk < entry.header().mtime()? {
+                        "overwrite"
+                    } else {
+                        "keep newer"
+                    }
+                }
+            },
+        };
+        writeln!(out, "{} {}", action, shown.display())?;
+    }
+    selection.check_all_matched()
+}
+
+/// Finds a symlink, either on disk or created earlier in the dry run, that
+/// would lead `path` out of `dst`.
+fn escaping_ancestor(
+    path: &Path,
+    dst: &Path,
+    symlinks: &HashMap<PathBuf, PathBuf>,
+) -> Option<PathBuf> {
+    for ancestor in path.ancestors().skip(1) {
+        if ancestor == dst || !ancestor.starts_with(dst) {
+            break;
+        }
+        let target = match symlinks.get(ancestor) {
+            Some(target) => normalize_lexically(&ancestor.parent()?.join(target)),
+            None => match fs::symlink_metadata(ancestor) {
+                Ok(md) if md.file_type().is_symlink() => ancestor.canonicalize().ok()?,
+                _ => continue,
+            },
+        };
+        if !target.starts_with(dst) {
+            return Some(ancestor.to_path_buf());
+        }
+    }
+    None
+}
+
+/// Resolves `.` and `..` components without consulting the filesystem.
+fn normalize_lexically(path: &Path) -> PathBuf {
+    let mut out = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                out.pop();
+            }
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// Writes the contents of the selected regular files to `out`, one after
+/// another, like `tar -xO`.
+fn cat_archive<R: Read>(
+    archive: &mut Archive<R>,
+    selection: &mut MemberSelection,
+    out: &mut dyn Write,
+) -> io::Result<()> {
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        if !entry.header().entry_type().is_file() || !selection.matches(&entry.path_bytes()) {
+            continue;
+        }
+        io::copy(&mut entry, out)?;
+    }
+    out.flush()?;
+    selection.check_all_matched()
+}
+
+/// Runs `command` for each selected regular file with its contents on stdin,
+/// like `tar -x --to-command`. Failing commands are reported and turn into an
+/// error once the whole archive has been processed.
+fn pipe_archive<R: Read>(
+    archive: &mut Archive<R>,
+    selection: &mut MemberSelection,
+    input: &Path,
+    command: &str,
+    verbose: bool,
+) -> io::Result<()> {
+    let mut failures = 0;
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        if !entry.header().entry_type().is_file() || !selection.matches(&entry.path_bytes()) {
+            continue;
+        }
+        let name = entry.path()?.into_owned();
+        if verbose {
+            eprintln!("{}", name.display());
+        }
+        let header = entry.header();
+        let mut child = Command::new("sh")
+            .arg("-c")
+            .arg(command)
+            .env("TAR_ARCHIVE", input)
+            .env("TAR_FILETYPE", "f")
+            .env("TAR_FILENAME", &name)
+            .env("TAR_REALNAME", &name)
+            .env("TAR_MODE", format!("{:04o}", header.mode()? & 0o7777))
+            .env("TAR_SIZE", entry.size().to_string())
+            .env("TAR_MTIME", header.mtime()?.to_string())
+            .env("TAR_UID", header.uid()?.to_string())
+            .env("TAR_GID", header.gid()?.to_string())
+            .env("TAR_UNAME", entry.username().ok().flatten().unwrap_or(""))
+            .env("TAR_GNAME", entry.groupname().ok().flatten().unwrap_or(""))
+            .stdin(Stdio::piped())
+            .spawn()?;
+        let mut stdin = child.stdin.take().unwrap();
+        match io::copy(&mut entry, &mut stdin) {
+            // A command that doesn't read all of its input isn't an error;
+            // the rest of the member is skipped along with it.
+            Err(e) if e.kind() == io::ErrorKind::BrokenPipe => {}
+            result => {
+                result?;
+            }
+        }
+        drop(stdin);
+        let status = child.wait()?;
+        if !status.success() {
+            eprintln!("tar: {}: command {}", name.display(), status);
+            failures += 1;
+        }
+    }
+    selection.check_all_matched()?;
+    if failures > 0 {
+        return Err(io::Error::other(format!(
+            "the command failed for {} member(s)",
+            failures
+        )));
+    }
+    Ok(())
+}
+
+/// A reader that counts the bytes read through it, so the position reached
+/// in the archive can be checked while its entries are borrowed.
+struct CountingReader<R> {
+    inner: R,
+    count: Rc<Cell<u64>>,
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.count.set(self.count.get() + n as u64);
+        Ok(n)
+    }
+}
+
+/// A writer that counts the bytes written through it.
+struct CountingWriter<W> {
+    inner: W,
+    count: Rc<Cell<u64>>,
+}
+
+impl<W: Write> Write for CountingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.count.set(self.count.get() + n as u64);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// The size of a record, the unit `--checkpoint` counts in.
+const RECORD_SIZE: u64 = 20 * 512;
+
+#[derive(Clone)]
+enum CheckpointAction {
+    Echo,
+    Exec(String),
+}
+
+impl CheckpointAction {
+    fn parse(s: &str) -> Result<CheckpointAction, String> {
+        match s.split_once('=') {
+            None if s == "echo" => Ok(CheckpointAction::Echo),
+            Some(("exec", command)) if !command.is_empty() => {
+                Ok(CheckpointAction::Exec(command.to_string()))
+            }
+            _ => Err(format!(
+                "unknown checkpoint action `{}` (expected `echo` or `exec=COMMAND`)",
+                s
+            )),
+        }
+    }
+
+    fn run(&self, verb: &str, checkpoint: u64) -> io::Result<()> {
+        match self {
+            CheckpointAction::Echo => {
+                eprintln!("tar: {} checkpoint {}", verb, checkpoint);
+            }
+            // Like GNU tar, the command's exit status is ignored.
+            CheckpointAction::Exec(command) => {
+                Command::new("sh")
+                    .arg("-c")
+                    .arg(command)
+                    .env("TAR_CHECKPOINT", checkpoint.to_string())
+                    .status()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A reader or writer over the archive stream that runs the checkpoint
+/// action every time another `every` bytes pass through it.
+struct Checkpoints<T> {
+    inner: T,
+    every: Option<u64>,
+    action: CheckpointAction,
+    verb: &'static str,
+    bytes: u64,
+}
+
+impl<T> Checkpoints<T> {
+    fn advance(&mut self, n: usize) -> io::Result<()> {
+        let before = self.bytes;
+        self.bytes += n as u64;
+        if let Some(every) = self.every {
+            for checkpoint in before / every + 1..=self.bytes / every {
+                self.action
+                    .run(self.verb, checkpoint * every / RECORD_SIZE)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for Checkpoints<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.advance(n)?;
+        Ok(n)
+    }
+}
+
+impl<W: Write> Write for Checkpoints<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.advance(n)?;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// The summary printed by `--totals` and `--stats`.
+#[derive(Default)]
+struct Totals {
+    /// Members added, extracted or found
+    entries: u64,
+    /// Size of the regular files' contents
+    content_bytes: u64,
+    /// Size of the uncompressed tar stream
+    archive_bytes: u64,
+    /// Size of the archive file, which differs from `archive_bytes` when it
+    /// is compressed
+    stored_bytes: u64,
+}
+
+impl Totals {
+    fn print(&self, elapsed: Duration, out: &mut dyn Write) -> io::Result<()> {
+        writeln!(out, "Entries: {}", self.entries)?;
+        writeln!(
+            out,
+            "Content: {} bytes ({})",
+            self.content_bytes,
+            HumanBytes(self.content_bytes)
+        )?;
+        writeln!(
+            out,
+            "Archive: {} bytes ({})",
+            self.archive_bytes,
+            HumanBytes(self.archive_bytes)
+        )?;
+        writeln!(
+            out,
+            "Stored: {} bytes ({})",
+            self.stored_bytes,
+            HumanBytes(self.stored_bytes)
+        )?;
+        if self.stored_bytes > 0 {
+            let ratio = self.archive_bytes as f64 / self.stored_bytes as f64;
+            writeln!(out, "Compression ratio: {:.2}", ratio)?;
+        }
+        writeln!(out, "Elapsed: {:.2?}", elapsed)
+    }
+}
+
+/// Reads through a whole archive to total up its members without
+/// extracting them.
+fn archive_stats(reader: Box<dyn Read>) -> io::Result<Totals> {
+    let count = Rc::new(Cell::new(0));
+    let mut archive = Archive::new(CountingReader {
+        inner: reader,
+        count: count.clone(),
+    });
+    let mut totals = Totals::default();
+    for entry in archive.entries()? {
+        let entry = entry?;
+        totals.entries += 1;
+        if entry.header().entry_type().is_file() {
+            totals.content_bytes += entry.size();
+        }
+    }
+    // Count any padding after the end-of-archive marker too
+    io::copy(&mut archive.into_inner(), &mut io::sink())?;
+    totals.archive_bytes = count.get();
+    Ok(totals)
+}
+
+/// Reads every entry of an archive, writing a line to `out` for each problem
+/// found, and fails if there were any.
+fn verify_archive(reader: Box<dyn Read>, verbose: bool, out: &mut dyn Write) -> io::Result<()> {
+    const BLOCK_SIZE: u64 = 512;
+
+    let count = Rc::new(Cell::new(0));
+    let mut archive = Archive::new(CountingReader {
+        inner: reader,
+        count: count.clone(),
+    });
+    let mut problems = 0;
+    let mut report = |name: &str, problem: &dyn std::fmt::Display| {
+        problems += 1;
+        writeln!(out, "{}: {}", name, problem)
+    };
+
+    // Offset just past the data of the last entry that was read
+    let mut end = 0;
+    let mut last = None;
+    let mut complete = true;
+    for entry in archive.entries()? {
+        let mut entry = match entry {
+            Ok(entry) => entry,
+            Err(e) => {
+                let name = match &last {
+                    Some(name) => format!("header after {}", name),
+                    None => "first header".to_string(),
+                };
+                report(&name, &e)?;
+                complete = false;
+                break;
+            }
+        };
+        let name = String::from_utf8_lossy(&entry.path_bytes()).into_owned();
+        let header = entry.header();
+        if let Err(e) = header.mode() {
+            report(&name, &e)?;
+        }
+        if let Err(e) = header.uid().and(header.gid()) {
+            report(&name, &e)?;
+        }
+        if let Err(e) = header.mtime() {
+            report(&name, &e)?;
+        }
+        if let Err(e) = entry.link_name() {
+            report(&name, &e)?;
+        }
+        let expected = entry.size();
+        match io::copy(&mut entry, &mut io::sink()) {
+            Ok(n) if n != expected => report(
+                &name,
+                &format!("truncated data: expected {} bytes, found {}", expected, n),
+            )?,
+            Ok(_) => {}
+            Err(e) => report(&name, &e)?,
+        }
+        if verbose {
+            eprintln!("Verified: {}", name);
+        }
+        end = count.get().div_ceil(BLOCK_SIZE) * BLOCK_SIZE;
+        last = Some(name);
+    }
+
+    if complete {
+        // Finishing the iteration consumes the first end-of-archive block
+        let mut reader = archive.into_inner();
+        let mut block = Vec::new();
+        if count.get() <= end {
+            report("archive", &"missing end-of-archive marker")?;
+        } else {
+            (&mut reader).take(BLOCK_SIZE).read_to_end(&mut block)?;
+            if block.len() as u64 != BLOCK_SIZE || block.iter().any(|b| *b != 0) {
+                report("archive", &"incomplete end-of-archive marker")?;
+            }
+        }
+    }
+
+    if problems > 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{} problem(s) found in archive", problems),
+        ));
+    }
+    Ok(())
+}
+
+/// Compares the selected members against the files under `dir`, writing a
+/// line to `out` for each difference and, when every member is selected,
+/// for each file under `dir` that isn't in the archive.
+fn diff_archive<R: Read>(
+    archive: &mut Archive<R>,
+    selection: &mut MemberSelection,
+    dir: &Path,
+    out: &mut dyn Write,
+) -> io::Result<()> {
+    let mut differences = 0;
+    let mut members = HashSet::new();
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let name = normalize_member(&String::from_utf8_lossy(&entry.path_bytes()));
+        // Parent directories are implied by their contents even when the
+        // archive has no entry of their own
+        let mut parent = name.as_str();
+        while let Some((dir, _)) = parent.rsplit_once('/') {
+            members.insert(dir.to_string());
+            parent = dir;
+        }
+        members.insert(name.clone());
+        if name.is_empty() || !selection.matches(&entry.path_bytes()) {
+            continue;
+        }
+        if name.split('/').any(|c| c == "..") {
+            writeln!(out, "{}: unsafe path, not compared", name)?;
+            differences += 1;
+            continue;
+        }
+        for difference in entry.compare_to(dir.join(&name))? {
+            writeln!(out, "{}: {}", name, difference)?;
+            differences += 1;
+        }
+    }
+    selection.check_all_matched()?;
+
+    if selection.is_empty() {
+        let walk = WalkBuilder::new(dir)
+            .standard_filters(false)
+            .sort_by_file_name(|a, b| a.cmp(b))
+            .build();
+        for entry in walk {
+            let entry = entry.map_err(|e| io::Error::other(e.to_string()))?;
+            let rel = entry.path().strip_prefix(dir).unwrap_or(entry.path());
+            let name = normalize_member(&rel.to_string_lossy().replace('\\', "/"));
+            if !name.is_empty() && !members.contains(&name) {
+                writeln!(out, "{}: not in archive", name)?;
+                differences += 1;
+            }
+        }
+    }
+
+    if differences > 0 {
+        return Err(io::Error::other(format!(
+            "{} difference(s) found",
+            differences
+        )));
+    }
+    Ok(())
+}
+
+/// The members named on the command line for extract and list.
+///
+/// A pattern containing glob metacharacters is matched against the whole
+/// member path; otherwise it selects the member with that path and, for a
+/// directory, everything below it. No patterns selects every member.
+struct MemberSelection {
+    patterns: Vec<MemberPattern>,
+}
+
+struct MemberPattern {
+    text: String,
+    glob: Option<GlobMatcher>,
+    matched: bool,
+}
+
+impl MemberSelection {
+    fn new(patterns: &[PathBuf]) -> io::Result<MemberSelection> {
+        let patterns = patterns
+            .iter()
+            .map(|pattern| {
+                let text = pattern.to_string_lossy().into_owned();
+                let glob = if text.contains(['*', '?', '[', '{']) {
+                    let glob = Glob::new(&text)
+                        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?;
+                    Some(glob.compile_matcher())
+                } else {
+                    None
+                };
+                Ok(MemberPattern {
+                    text,
+                    glob,
+                    matched: false,
+                })
+            })
+            .collect::<io::Result<_>>()?;
+        Ok(MemberSelection { patterns })
+    }
+
+    fn is_empty(&self) -> bool {
+        self.patterns.is_empty()
+    }
+
+    fn matches(&mut self, path: &[u8]) -> bool {
+        if self.patterns.is_empty() {
+            return true;
+        }
+        let path = normalize_member(&String::from_utf8_lossy(path));
+        let mut any = false;
+        for pattern in &mut self.patterns {
+            let hit = match &pattern.glob {
+                Some(glob) => glob.is_match(&path),
+                None => {
+                    let literal = normalize_member(&pattern.text);
+                    path == literal
+                        || path
+                            .strip_prefix(&literal)
+                            .is_some_and(|rest| rest.starts_with('/'))
+                }
+            };
+            pattern.matched |= hit;
+            any |= hit;
+        }
+        any
+    }
+
+    fn check_all_matched(&self) -> io::Result<()> {
+        let missing = self
+            .patterns
+            .iter()
+            .filter(|p| !p.matched)
+            .map(|p| p.text.as_str())
+            .collect::<Vec<_>>();
+        if missing.is_empty() {
+            Ok(())
+        } else {
+            Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("not found in archive: {}", missing.join(", ")),
+            ))
+        }
+    }
+}
+
+/// Strips leading `./` and `/` as well as trailing `/` so that member names
+/// compare equal however they were spelled in the archive.
+fn normalize_member(path: &str) -> String {
+    path.split('/')
+        .filter(|c| !c.is_empty() && *c != ".")
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Whether `path` is the conventional `-` placeholder for stdin/stdout.
+fn is_stdio(path: &Path) -> bool {
+    path == Path::new("-")
+}
+
+/// Opens the archive at `path` for reading, decompressing it if needed.
+///
+/// `pb` advances as the archive file itself is read, so for a compressed
+/// archive it tracks the compressed bytes.
+fn open_archive(path: &Path, cli: &Cli, pb: &ProgressBar) -> io::Result<Box<dyn Read>> {
+    let stored = open_stored(path, pb)?;
+    let mut reader: Box<dyn BufRead> = match cli.blocking_factor {
+        Some(factor) => Box::new(BufReader::with_capacity(factor as usize * 512, stored)),
+        None => Box::new(BufReader::new(stored)),
+    };
+    if reader.fill_buf()?.starts_with(AGE_MAGIC) {
+        if cli.verbose {
+            eprintln!("Detected age encryption");
+        }
+        reader = Box::new(BufReader::new(decrypt_archive(reader, cli)?));
+    }
+    // Extensions aren't available when reading from stdin, so go by the
+    // magic number unless told otherwise.
+    let codec = match cli.codec() {
+        Some(codec) => codec,
+        None => Codec::sniff(reader.fill_buf()?),
+    };
+    if cli.verbose && codec != Codec::None {
+        eprintln!("Detected {:?} compression", codec);
+    }
+    codec.decoder(reader)
+}
+
+/// The stored bytes of the archive at `path`, joining the parts of a split
+/// archive, without decompressing them.
+fn open_stored(path: &Path, pb: &ProgressBar) -> io::Result<Box<dyn Read>> {
+    if is_stdio(path) {
+        Ok(Box::new(pb.wrap_read(io::stdin())))
+    } else if let Some(url) = remote_url(path) {
+        open_remote(url, pb)
+    } else if let Some(parts) = split_parts(path)? {
+        let mut total = 0;
+        for part in &parts {
+            total += fs::metadata(part)?.len();
+        }
+        set_progress_total(pb, Some(total));
+        let mut parts = parts.into_iter();
+        Ok(Box::new(pb.wrap_read(MultiVolumeReader::new(move |_| {
+            parts.next().map(File::open).transpose()
+        }))))
+    } else {
+        let file = File::open(path)?;
+        set_progress_total(pb, Some(file.metadata()?.len()));
+        Ok(Box::new(pb.wrap_read(file)))
+    }
+}
+
+/// The URL of an archive given as an `http://` or `https://` URL rather than
+/// a path.
+fn remote_url(path: &Path) -> Option<&str> {
+    path.to_str()
+        .filter(|p| p.starts_with("http://") || p.starts_with("https://"))
+}
 
-    /// Input (file/directory to archive for create, archive for extract)
-    input: PathBuf,
+/// Downloads the archive at `url` as it is read.
+#[cfg(feature = "http")]
+fn open_remote(url: &str, pb: &ProgressBar) -> io::Result<Box<dyn Read>> {
+    let reader = HttpReader::open(url)?;
+    set_progress_total(pb, Some(reader.len()));
+    Ok(Box::new(pb.wrap_read(reader)))
 }
 
-struct CompressedWriter<W: Write> {
-    inner: GzEncoder<W>,
+#[cfg(not(feature = "http"))]
+fn open_remote(url: &str, _pb: &ProgressBar) -> io::Result<Box<dyn Read>> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!("cannot read {}: built without the http feature", url),
+    ))
 }
 
-impl<W: Write> CompressedWriter<W> {
-    fn new(writer: W) -> Self {
-        CompressedWriter {
-            inner: GzEncoder::new(writer, Compression::default())
+/// Where a created archive is written, hashed on the way if it is signed.
+enum Output {
+    Plain(Box<dyn Write>),
+    #[cfg(feature = "sign")]
+    Signed(Box<SigningWriter<Box<dyn Write>>>, Box<SecretKey>),
+}
+
+impl Output {
+    #[cfg_attr(not(feature = "sign"), allow(unused_variables))]
+    fn new(inner: Box<dyn Write>, cli: &Cli, path: &Path) -> io::Result<Output> {
+        #[cfg(feature = "sign")]
+        if let Some(key) = &cli.secret_key {
+            if is_stdio(path) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "cannot sign an archive written to stdout",
+                ));
+            }
+            let key = SecretKey::from_minisign(&fs::read_to_string(key)?)?;
+            return Ok(Output::Signed(
+                Box::new(SigningWriter::new(inner)),
+                Box::new(key),
+            ));
+        }
+        Ok(Output::Plain(inner))
+    }
+
+    /// Flushes the archive, writing its signature next to `path` if signed.
+    #[cfg_attr(not(feature = "sign"), allow(unused_variables))]
+    fn finish(self, path: &Path) -> io::Result<()> {
+        match self {
+            Output::Plain(mut inner) => inner.flush(),
+            #[cfg(feature = "sign")]
+            Output::Signed(writer, key) => {
+                let (_, signature) = writer.finish(&key, &trusted_comment(path))?;
+                fs::write(signature_path(path), signature.to_minisign())
+            }
         }
     }
 }
 
-impl<W: Write> Write for CompressedWriter<W> {
+impl Write for Output {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        self.inner.write(buf)
+        match self {
+            Output::Plain(inner) => inner.write(buf),
+            #[cfg(feature = "sign")]
+            Output::Signed(inner, _) => inner.write(buf),
+        }
     }
 
     fn flush(&mut self) -> io::Result<()> {
-        self.inner.flush()
+        match self {
+            Output::Plain(inner) => inner.flush(),
+            #[cfg(feature = "sign")]
+            Output::Signed(inner, _) => inner.flush(),
+        }
+    }
+}
+
+/// The default signature file of the archive at `path`.
+#[cfg(feature = "sign")]
+fn signature_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".minisig");
+    PathBuf::from(name)
+}
+
+/// The trusted comment of an archive's signature, in the format minisign
+/// uses for files signed with `-H`.
+#[cfg(feature = "sign")]
+fn trusted_comment(path: &Path) -> String {
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    let name = path.file_name().unwrap_or(path.as_os_str());
+    format!("timestamp:{}\tfile:{}\thashed", now, name.to_string_lossy())
+}
+
+/// Signs the archive at `path` with the `--secret-key`, writing the signature
+/// to `--signature` or `ARCHIVE.minisig`.
+#[cfg(feature = "sign")]
+fn sign_archive(cli: &Cli, path: &Path) -> io::Result<()> {
+    let key = cli.secret_key.as_deref().unwrap();
+    let key = SecretKey::from_minisign(&fs::read_to_string(key)?)?;
+    let signature = key.sign(
+        open_stored(path, &ProgressBar::hidden())?,
+        &trusted_comment(path),
+    )?;
+    let dst = cli
+        .signature
+        .clone()
+        .unwrap_or_else(|| signature_path(path));
+    fs::write(&dst, signature.to_minisign())?;
+    if cli.verbose {
+        eprintln!("Signature written to {}", dst.display());
+    }
+    Ok(())
+}
+
+/// Checks the signature of the archive at `path` against the `--public-key`.
+#[cfg(feature = "sign")]
+fn check_signature(cli: &Cli, path: &Path) -> io::Result<()> {
+    if is_stdio(path) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "cannot check the signature of an archive read from stdin",
+        ));
+    }
+    let key = cli.public_key.as_deref().unwrap();
+    let key = PublicKey::from_minisign(&fs::read_to_string(key)?)?;
+    let sig = cli
+        .signature
+        .clone()
+        .unwrap_or_else(|| signature_path(path));
+    let signature =
+        Signature::from_minisign(&fs::read_to_string(&sig).map_err(|e| {
+            io::Error::new(e.kind(), format!("cannot read {}: {}", sig.display(), e))
+        })?)?;
+    key.verify(open_stored(path, &ProgressBar::hidden())?, &signature)?;
+    if cli.verbose {
+        eprintln!("Good signature: {}", signature.trusted_comment());
+    }
+    Ok(())
+}
+
+#[cfg(feature = "sign")]
+fn generate_key(cli: &Cli) -> io::Result<()> {
+    let key = SecretKey::generate()?;
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let secret = cli.secret_key.as_deref().unwrap();
+    options
+        .open(secret)?
+        .write_all(key.to_minisign().as_bytes())?;
+    fs::write(
+        cli.public_key.as_deref().unwrap(),
+        key.public_key().to_minisign(),
+    )
+}
+
+#[cfg(not(feature = "sign"))]
+fn sign_archive(_cli: &Cli, _path: &Path) -> io::Result<()> {
+    Err(signing_unsupported())
+}
+
+#[cfg(not(feature = "sign"))]
+fn check_signature(_cli: &Cli, _path: &Path) -> io::Result<()> {
+    Err(signing_unsupported())
+}
+
+#[cfg(not(feature = "sign"))]
+fn generate_key(_cli: &Cli) -> io::Result<()> {
+    Err(signing_unsupported())
+}
+
+#[cfg(not(feature = "sign"))]
+fn signing_unsupported() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        "signing requires tar to be built with the `sign` feature",
+    )
+}
+
+/// The first line of an archive encrypted with age.
+const AGE_MAGIC: &[u8] = b"age-encryption.org/v1\n";
+
+/// The compressed archive on its way to `Output`, encrypted if `--encrypt`
+/// was given.
+enum Sealed<W: Write> {
+    Plain(W),
+    #[cfg(feature = "encrypt")]
+    Encrypted(Box<EncryptingWriter<W>>),
+}
+
+impl<W: Write> Sealed<W> {
+    fn new(inner: W, cli: &Cli) -> io::Result<Sealed<W>> {
+        if cli.encrypt.is_empty() {
+            return Ok(Sealed::Plain(inner));
+        }
+        #[cfg(feature = "encrypt")]
+        {
+            let recipients = cli
+                .encrypt
+                .iter()
+                .map(|r| r.parse())
+                .collect::<io::Result<Vec<Recipient>>>()?;
+            Ok(Sealed::Encrypted(Box::new(EncryptingWriter::new(
+                inner,
+                &recipients,
+            )?)))
+        }
+        #[cfg(not(feature = "encrypt"))]
+        Err(encryption_unsupported())
+    }
+
+    fn finish(self) -> io::Result<W> {
+        match self {
+            Sealed::Plain(inner) => Ok(inner),
+            #[cfg(feature = "encrypt")]
+            Sealed::Encrypted(inner) => inner.finish(),
+        }
+    }
+}
+
+impl<W: Write> Write for Sealed<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Sealed::Plain(inner) => inner.write(buf),
+            #[cfg(feature = "encrypt")]
+            Sealed::Encrypted(inner) => inner.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Sealed::Plain(inner) => inner.flush(),
+            #[cfg(feature = "encrypt")]
+            Sealed::Encrypted(inner) => inner.flush(),
+        }
+    }
+}
+
+/// Decrypts an archive encrypted with age using the `--identity` files.
+#[cfg(feature = "encrypt")]
+fn decrypt_archive(reader: Box<dyn BufRead>, cli: &Cli) -> io::Result<Box<dyn Read>> {
+    if cli.identity.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "the archive is encrypted; give an --identity to decrypt it with",
+        ));
+    }
+    let mut identities = Vec::new();
+    for file in &cli.identity {
+        let text = fs::read_to_string(file).map_err(|e| {
+            io::Error::new(e.kind(), format!("cannot read {}: {}", file.display(), e))
+        })?;
+        identities.extend(Identity::from_file(&text)?);
+    }
+    Ok(Box::new(DecryptingReader::new(reader, &identities)?))
+}
+
+#[cfg(not(feature = "encrypt"))]
+fn decrypt_archive(_reader: Box<dyn BufRead>, _cli: &Cli) -> io::Result<Box<dyn Read>> {
+    Err(encryption_unsupported())
+}
+
+#[cfg(not(feature = "encrypt"))]
+fn encryption_unsupported() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        "encryption requires tar to be built with the `encrypt` feature",
+    )
+}
+
+fn create_output(path: &Path) -> io::Result<Box<dyn Write>> {
+    if is_stdio(path) {
+        Ok(Box::new(io::stdout()))
+    } else {
+        Ok(Box::new(File::create(path)?))
+    }
+}
+
+/// Parses a size like `512`, `64K` or `1G` (powers of 1024).
+fn parse_size(s: &str) -> Result<u64, String> {
+    let digits = s.trim_end_matches(|c: char| c.is_ascii_alphabetic());
+    let unit = s[digits.len()..].to_ascii_uppercase();
+    let shift = match unit.trim_end_matches("IB").trim_end_matches('B') {
+        "" => 0,
+        "K" => 10,
+        "M" => 20,
+        "G" => 30,
+        "T" => 40,
+        _ => return Err(format!("unknown size unit `{}`", unit)),
+    };
+    let n: u64 = digits
+        .parse()
+        .map_err(|_| format!("invalid size `{}`", s))?;
+    match n.checked_mul(1 << shift) {
+        Some(0) | None => Err(format!("invalid size `{}`", s)),
+        Some(size) => Ok(size),
+    }
+}
+
+/// The name of the `index`th part of a split archive.
+fn part_path(path: &Path, index: usize) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{:03}", index));
+    PathBuf::from(name)
+}
+
+/// Finds the parts of a split archive, given either its base name or the
+/// name of its first part. Returns `None` if `path` isn't a split archive.
+fn split_parts(path: &Path) -> io::Result<Option<Vec<PathBuf>>> {
+    let base = match path.to_str().and_then(|p| p.strip_suffix(".000")) {
+        Some(base) => PathBuf::from(base),
+        None if !path.exists() => path.to_path_buf(),
+        None => return Ok(None),
+    };
+    let parts = (0..)
+        .map(|i| part_path(&base, i))
+        .take_while(|part| part.exists())
+        .collect::<Vec<_>>();
+    Ok(if parts.is_empty() { None } else { Some(parts) })
+}
+
+/// Opens an existing archive for modifying it, which is only possible for
+/// uncompressed archive files.
+fn open_for_modify(path: &Path, codec: Option<Codec>, write: bool) -> io::Result<File> {
+    if is_stdio(path) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "cannot modify an archive on stdin/stdout",
+        ));
+    }
+    let mut file = fs::OpenOptions::new().read(true).write(write).open(path)?;
+    let mut magic = Vec::new();
+    (&mut file).take(6).read_to_end(&mut magic)?;
+    if AGE_MAGIC.starts_with(&magic) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "cannot modify an encrypted archive",
+        ));
+    }
+    let codec = codec.unwrap_or(Codec::sniff(&magic));
+    if codec != Codec::None {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "cannot modify a compressed archive",
+        ));
+    }
+    file.seek(SeekFrom::Start(0))?;
+    Ok(file)
+}
+
+/// Removes the selected members from the archive at `input`, writing the
+/// result to `output` or, without one, replacing the archive itself.
+fn delete_members(
+    input: &Path,
+    selection: &mut MemberSelection,
+    output: Option<&Path>,
+    codec: Option<Codec>,
+    verbose: bool,
+) -> io::Result<()> {
+    if selection.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "no members given to delete",
+        ));
+    }
+    let mut archive = Archive::new(open_for_modify(input, codec, false)?);
+    // Without an output the copy goes to a sibling file which then replaces
+    // the archive, so a failure never leaves it half-written.
+    let tmp = match output {
+        Some(_) => None,
+        None => {
+            let mut name = std::ffi::OsString::from(".");
+            name.push(input.file_name().unwrap_or_default());
+            name.push(".tmp");
+            Some(input.with_file_name(name))
+        }
+    };
+    let dst = tmp.as_deref().or(output).unwrap();
+    let result = archive.rewrite(create_output(dst)?, |entry| {
+        let delete = selection.matches(&entry.path_bytes());
+        if delete && verbose {
+            eprintln!("Deleting: {}", String::from_utf8_lossy(&entry.path_bytes()));
+        }
+        !delete
+    });
+    if let Err(e) = result.and_then(|_| selection.check_all_matched()) {
+        if let Some(tmp) = &tmp {
+            let _ = fs::remove_file(tmp);
+        }
+        return Err(e);
+    }
+    if let Some(tmp) = &tmp {
+        fs::rename(tmp, input)?;
+    }
+    Ok(())
+}
+
+/// Reads the snapshot file of an incremental backup, or starts a full backup
+/// if there isn't one yet.
+fn read_snapshot(path: &Path) -> io::Result<Snapshot> {
+    match File::open(path) {
+        Ok(file) => Snapshot::read(BufReader::new(file)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Snapshot::new()),
+        Err(e) => Err(e),
+    }
+}
+
+/// Appends the changes to `inputs` since the backup recorded in `snapshot`,
+/// with every directory given archived as a dump directory.
+fn append_listed_incremental<W: Write>(
+    builder: &mut Builder<W>,
+    inputs: &[PathBuf],
+    snapshot: &mut Snapshot,
+    verbose: bool,
+    pb: &ProgressBar,
+) -> io::Result<()> {
+    for input in inputs {
+        let name = archive_name(input);
+        if verbose {
+            pb.suspend(|| eprintln!("{}", name.display()));
+        }
+        if fs::metadata(input)?.is_dir() {
+            builder.append_dir_incremental(&name, input, snapshot)?;
+        } else {
+            builder.append_path_with_name(input, &name)?;
+        }
+    }
+    Ok(())
+}
+
+fn read_file_snapshot(path: &Path) -> io::Result<FileSnapshot> {
+    match File::open(path) {
+        Ok(file) => FileSnapshot::read(BufReader::new(file)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(FileSnapshot::new()),
+        Err(e) => Err(e),
+    }
+}
+
+/// Appends what changed in `inputs` since `previous` was taken, returning
+/// the state of everything found.
+fn append_changed<W: Write>(
+    builder: &mut Builder<W>,
+    inputs: &[PathBuf],
+    previous: &FileSnapshot,
+    verbose: bool,
+    pb: &ProgressBar,
+) -> io::Result<FileSnapshot> {
+    let mut state = FileSnapshot::new();
+    for input in inputs {
+        let name = archive_name(input);
+        if verbose {
+            pb.suspend(|| eprintln!("{}", name.display()));
+        }
+        state.merge(builder.append_incremental(&name, input, previous)?);
+    }
+    Ok(state)
+}
+
+/// Returns the name under which `path` is stored in a created archive.
+///
+/// Like GNU tar, the path is kept as given on the command line except for
+/// leading `/` and `..` components, which would make the member unsafe to
+/// extract.
+fn archive_name(path: &Path) -> PathBuf {
+    let components = path.components().collect::<Vec<_>>();
+    let start = components
+        .iter()
+        .rposition(|c| {
+            matches!(
+                c,
+                Component::Prefix(..) | Component::RootDir | Component::ParentDir
+            )
+        })
+        .map_or(0, |i| i + 1);
+    components[start..]
+        .iter()
+        .filter(|c| matches!(c, Component::Normal(..)))
+        .collect()
+}
+
+fn build_excludes(patterns: &[String]) -> io::Result<GlobSet> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        let glob = Glob::new(pattern)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?;
+        builder.add(glob);
+    }
+    builder
+        .build()
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))
+}
+
+/// Whether an `--exclude` pattern matches the member `name`, either as a whole
+/// or by its final component (so `--exclude target` skips every `target`).
+fn is_excluded(excludes: &GlobSet, name: &Path) -> bool {
+    excludes.is_match(name) || name.file_name().is_some_and(|n| excludes.is_match(n))
+}
+
+/// How the inputs of create, append and update are added to the archive.
+struct InputOptions<'a> {
+    excludes: GlobSet,
+    ignore_files: &'a [String],
+    transforms: &'a [Transform],
+    follow_links: bool,
+    update: bool,
+    verbose: bool,
+}
+
+/// A file found on disk by `collect_input`, to be added to the archive.
+struct InputFile {
+    path: PathBuf,
+    name: PathBuf,
+    size: u64,
+}
+
+/// Finds `input` and, for a directory, everything below it that should go
+/// into the archive, skipping anything excluded by a pattern or an ignore
+/// file.
+fn collect_input(
+    input: &Path,
+    options: &InputOptions,
+    files: &mut Vec<InputFile>,
+) -> io::Result<()> {
+    let base = archive_name(input);
+    // The walker only filters what it finds below `input`, so check the
+    // input itself here.
+    if is_excluded(&options.excludes, &base) {
+        return Ok(());
+    }
+    let mut walk = WalkBuilder::new(input);
+    walk.standard_filters(false)
+        .follow_links(options.follow_links)
+        .sort_by_file_name(|a, b| a.cmp(b));
+    for name in options.ignore_files {
+        walk.add_custom_ignore_filename(name);
+    }
+    let filter_excludes = options.excludes.clone();
+    let filter_input = input.to_path_buf();
+    let filter_base = base.clone();
+    walk.filter_entry(move |entry| {
+        let rel = entry
+            .path()
+            .strip_prefix(&filter_input)
+            .unwrap_or(entry.path());
+        !is_excluded(&filter_excludes, &filter_base.join(rel))
+    });
+
+    for entry in walk.build() {
+        let entry = entry.map_err(|e| io::Error::other(e.to_string()))?;
+        let name = match entry.path().strip_prefix(input) {
+            Ok(rel) if !rel.as_os_str().is_empty() => base.join(rel),
+            _ => base.clone(),
+        };
+        let name = match options.transforms {
+            [] => name,
+            transforms => PathBuf::from(apply_transforms(transforms, &name.to_string_lossy())),
+        };
+        // Archiving `.` yields an empty name for the root itself
+        if name.as_os_str().is_empty() {
+            continue;
+        }
+        let size = match entry.metadata() {
+            Ok(md) if md.is_file() => md.len(),
+            _ => 0,
+        };
+        files.push(InputFile {
+            path: entry.into_path(),
+            name,
+            size,
+        });
+    }
+    Ok(())
+}
+
+/// Adds the files found by `collect_input` to the archive, advancing `pb` by
+/// their sizes.
+fn append_inputs<W: Write>(
+    builder: &mut Builder<W>,
+    files: &[InputFile],
+    options: &InputOptions,
+    pb: &ProgressBar,
+) -> io::Result<()> {
+    for file in files {
+        if options.update {
+            if builder.update_path_with_name(&file.path, &file.name)? && options.verbose {
+                pb.suspend(|| eprintln!("Updating: {}", file.path.display()));
+            }
+        } else {
+            if options.verbose {
+                pb.suspend(|| eprintln!("Adding: {}", file.path.display()));
+            }
+            builder.append_path_with_name(&file.path, &file.name)?;
+        }
+        pb.inc(file.size);
+    }
+    Ok(())
+}
+
+/// The bytes `file` takes up in an archive: its header, a GNU long name
+/// header if its name needs one, and its contents padded to whole blocks.
+/// Extended attributes and other PAX records aren't counted.
+fn packed_size(file: &InputFile) -> u64 {
+    let blocks = |len: u64| len.div_ceil(512) * 512;
+    // Directory names are stored with a trailing slash
+    let name_len = file.name.as_os_str().len() as u64 + 1;
+    let long_name = if name_len > 100 {
+        512 + blocks(name_len)
+    } else {
+        0
+    };
+    512 + long_name + blocks(file.size)
+}
+
+/// Applies the options for how inputs are stored to `builder`.
+fn configure_builder<W: Write>(cli: &Cli, builder: &mut Builder<W>) -> io::Result<()> {
+    builder.follow_symlinks(!cli.no_dereference);
+    builder.xattrs(cli.xattrs);
+    builder.acls(cli.store_acls()?);
+    builder.selinux(cli.selinux);
+    builder.mac_metadata(cli.mac_metadata);
+    builder.windows_attributes(cli.windows_attributes);
+    builder.extended_times(cli.extended_times);
+    builder.owner_names(!cli.numeric_owner);
+    builder.detect_hardlinks(!cli.hard_dereference);
+    builder.deduplicate(cli.dedup_algorithm()?);
+    builder.normalize_paths(cli.normalization()?);
+    if let Some(factor) = cli.blocking_factor {
+        builder.blocking_factor(factor);
+    }
+    Ok(())
+}
+
+/// Writes the files found by `collect_input` into archives of at most `size`
+/// bytes in `dir`, along with an index of which holds each member, returning
+/// the bytes written to all of them.
+///
+/// Files are packed first-fit in decreasing order of size, then archived in
+/// the order they were found within each archive. A file too large to fit
+/// in any archive gets one to itself.
+fn pack_inputs(
+    cli: &Cli,
+    files: &[InputFile],
+    size: u64,
+    dir: &Path,
+    options: &InputOptions,
+    pb: &ProgressBar,
+) -> io::Result<u64> {
+    let record = u64::from(cli.blocking_factor.unwrap_or(1)) * 512;
+    // An archive's size once its end-of-archive marker is added and it is
+    // padded to whole records
+    let archive_size = |used: u64| (used + 1024).div_ceil(record) * record;
+
+    let mut order = (0..files.len()).collect::<Vec<_>>();
+    order.sort_by_key(|&i| std::cmp::Reverse(packed_size(&files[i])));
+    // The bytes used by each archive and the indices of the files in it
+    let mut bins: Vec<(u64, Vec<usize>)> = Vec::new();
+    for i in order {
+        let needed = packed_size(&files[i]);
+        match bins
+            .iter_mut()
+            .find(|(used, _)| archive_size(used + needed) <= size)
+        {
+            Some((used, members)) => {
+                *used += needed;
+                members.push(i);
+            }
+            None => {
+                if archive_size(needed) > size {
+                    pb.suspend(|| {
+                        eprintln!(
+                            "Warning: {} is larger than the pack size on its own",
+                            files[i].path.display()
+                        )
+                    });
+                }
+                bins.push((needed, vec![i]));
+            }
+        }
+    }
+
+    fs::create_dir_all(dir)?;
+    let mut index = BufWriter::new(File::create(dir.join("index.tsv"))?);
+    let mut total = 0;
+    for (n, (_, mut members)) in bins.into_iter().enumerate() {
+        members.sort_unstable();
+        let name = format!("{:03}.tar", n);
+        let written = Rc::new(Cell::new(0));
+        let mut builder = Builder::new(CountingWriter {
+            inner: BufWriter::new(File::create(dir.join(&name))?),
+            count: written.clone(),
+        });
+        configure_builder(cli, &mut builder)?;
+        let members = members.into_iter().map(|i| &files[i]).collect::<Vec<_>>();
+        for file in &members {
+            if options.verbose {
+                pb.suspend(|| eprintln!("Adding: {} to {}", file.path.display(), name));
+            }
+            builder.append_path_with_name(&file.path, &file.name)?;
+            writeln!(index, "{}\t{}", name, file.name.display())?;
+            pb.inc(file.size);
+        }
+        builder.into_inner()?.inner.flush()?;
+        if written.get() > size && members.len() > 1 {
+            pb.suspend(|| {
+                eprintln!(
+                    "Warning: {} came to {} bytes with its extended headers",
+                    name,
+                    written.get()
+                )
+            });
+        }
+        total += written.get();
+    }
+    index.flush()?;
+    Ok(total)
+}
+
+/// A sed-style `s/regex/replacement/flags` expression given to `--transform`.
+struct Transform {
+    regex: Regex,
+    replacement: String,
+    global: bool,
+}
+
+impl Transform {
+    fn parse(expr: &str) -> io::Result<Transform> {
+        let invalid = |msg: &str| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid transform `{}`: {}", expr, msg),
+            )
+        };
+        let mut chars = expr.chars();
+        if chars.next() != Some('s') {
+            return Err(invalid("expected an `s` command"));
+        }
+        let delim = chars.next().ok_or_else(|| invalid("missing delimiter"))?;
+
+        // Split on unescaped delimiters, unescaping them along the way
+        let mut parts = vec![String::new()];
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => match chars.next() {
+                    Some(next) if next == delim => parts.last_mut().unwrap().push(next),
+                    Some(next) => {
+                        parts.last_mut().unwrap().push('\\');
+                        parts.last_mut().unwrap().push(next);
+                    }
+                    None => return Err(invalid("trailing backslash")),
+                },
+                c if c == delim => parts.push(String::new()),
+                c => parts.last_mut().unwrap().push(c),
+            }
+        }
+        let [pattern, replacement, flags] = <[String; 3]>::try_from(parts)
+            .map_err(|_| invalid("expected s/regex/replacement/flags"))?;
+
+        let mut builder = RegexBuilder::new(&pattern);
+        let mut global = false;
+        for flag in flags.chars() {
+            match flag {
+                'g' => global = true,
+                'i' => {
+                    builder.case_insensitive(true);
+                }
+                _ => return Err(invalid(&format!("unknown flag `{}`", flag))),
+            }
+        }
+        let regex = builder.build().map_err(|e| invalid(&e.to_string()))?;
+        Ok(Transform {
+            regex,
+            replacement: sed_replacement(&replacement),
+            global,
+        })
+    }
+
+    fn apply(&self, name: &str) -> String {
+        let limit = if self.global { 0 } else { 1 };
+        self.regex
+            .replacen(name, limit, self.replacement.as_str())
+            .into_owned()
+    }
+}
+
+/// Converts a sed replacement, where `&` is the whole match and `\N` a
+/// group, into the syntax used by the regex crate.
+fn sed_replacement(replacement: &str) -> String {
+    let mut out = String::new();
+    let mut chars = replacement.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '&' => out.push_str("${0}"),
+            '$' => out.push_str("$$"),
+            '\\' => match chars.next() {
+                Some(d) if d.is_ascii_digit() => {
+                    out.push_str("${");
+                    out.push(d);
+                    out.push('}');
+                }
+                Some('$') => out.push_str("$$"),
+                Some(other) => out.push(other),
+                None => out.push('\\'),
+            },
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// Runs `name` through every transform in order.
+fn apply_transforms(transforms: &[Transform], name: &str) -> String {
+    transforms
+        .iter()
+        .fold(name.to_string(), |name, transform| transform.apply(&name))
+}
+
+/// Splits the positional arguments of extract/list into the archive and the
+/// member patterns that follow it.
+fn archive_and_members(inputs: &[PathBuf]) -> (&Path, &[PathBuf]) {
+    let (archive, members) = inputs.split_first().expect("inputs are required");
+    (archive, members)
+}
+
+/// Reads the names listed in `path` for `-T`, separated by newlines or, with
+/// `null`, by NUL bytes. Empty names are skipped.
+fn read_file_list(path: &Path, null: bool) -> io::Result<Vec<PathBuf>> {
+    let mut data = Vec::new();
+    if is_stdio(path) {
+        io::stdin().lock().read_to_end(&mut data)?;
+    } else {
+        File::open(path)?.read_to_end(&mut data)?;
     }
+    let separator = if null { b'\0' } else { b'\n' };
+    data.split(|b| *b == separator)
+        .filter(|name| !name.is_empty())
+        .map(bytes_to_path)
+        .collect()
+}
+
+#[cfg(unix)]
+fn bytes_to_path(bytes: &[u8]) -> io::Result<PathBuf> {
+    use std::os::unix::ffi::OsStrExt;
+    Ok(PathBuf::from(std::ffi::OsStr::from_bytes(bytes)))
+}
+
+#[cfg(not(unix))]
+fn bytes_to_path(bytes: &[u8]) -> io::Result<PathBuf> {
+    std::str::from_utf8(bytes)
+        .map(|s| PathBuf::from(s.trim_end_matches('\r')))
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "file name is not valid UTF-8"))
 }
 
-fn create_progress_bar(msg: &str) -> ProgressBar {
+/// Creates the progress bar shown on stderr, or a hidden one when `enabled`
+/// is false. The bar counts bytes towards `total`, or just shows the bytes
+/// processed so far when the total isn't known.
+fn create_progress_bar(msg: &str, total: Option<u64>, enabled: bool) -> ProgressBar {
+    if !enabled {
+        return ProgressBar::hidden();
+    }
     let pb = ProgressBar::new_spinner();
-    pb.set_style(
-        ProgressStyle::default_spinner()
-            .template("{spinner:.green} [{elapsed_precise}] {msg}: {pos}")
-            .unwrap()
-    );
+    set_progress_total(&pb, total);
     pb.set_message(msg.to_string());
     pb
 }
 
+fn set_progress_total(pb: &ProgressBar, total: Option<u64>) {
+    let style = match total {
+        Some(total) => {
+            pb.set_length(total);
+            ProgressStyle::default_bar().template(
+                "{spinner:.green} [{elapsed_precise}] {msg} [{bar:30.cyan/blue}] \
+                 {bytes}/{total_bytes} ({bytes_per_sec}, ETA {eta})",
+            )
+        }
+        None => ProgressStyle::default_spinner()
+            .template("{spinner:.green} [{elapsed_precise}] {msg}: {bytes} ({bytes_per_sec})"),
+    };
+    pb.set_style(style.unwrap().progress_chars("=> "));
+}
+
+/// Whether we run as the superuser, which GNU tar takes to mean owners
+/// should be restored by default.
+#[cfg(unix)]
+fn is_root() -> bool {
+    unsafe { libc::geteuid() == 0 }
+}
+
+#[cfg(not(unix))]
+fn is_root() -> bool {
+    false
+}
+
 fn handle_error(err: std::io::Error) -> ! {
     eprintln!("Error: {}", err);
     std::process::exit(1);
 }
 
 fn run() -> std::io::Result<()> {
-    let cli = Cli::parse();
+    let mut cli = Cli::parse();
+    if let Some(list) = &cli.files_from {
+        let creating = cli.create || cli.append || cli.update;
+        if !creating && cli.inputs.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "the archive must be given before the names read with -T",
+            ));
+        }
+        let names = read_file_list(list, cli.null)?;
+        cli.inputs.extend(names);
+    }
+    let transforms = cli
+        .transform
+        .iter()
+        .map(|expr| Transform::parse(expr))
+        .collect::<io::Result<Vec<_>>>()?;
 
-    if cli.create {
-        let pb = create_progress_bar("Creating archive");
-        let file = File::create(&cli.output)?;
-        let writer: Box<dyn Write> = if cli.gzip {
-            if cli.verbose {
-                println!("Using gzip compression");
+    let reading = !(cli.create || cli.append || cli.update || cli.sign || cli.generate_key);
+    if reading && cli.public_key.is_some() {
+        check_signature(&cli, archive_and_members(&cli.inputs).0)?;
+    }
+
+    let start = Instant::now();
+    let mut totals = Totals::default();
+    if cli.generate_key {
+        generate_key(&cli)?;
+    } else if cli.sign {
+        let (input, members) = archive_and_members(&cli.inputs);
+        if !members.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "a signature covers the whole archive and takes no member names",
+            ));
+        }
+        sign_archive(&cli, input)?;
+    } else if cli.create {
+        let output = cli.output.as_deref().unwrap();
+        let stored = Rc::new(Cell::new(0));
+        let out = match cli.split_size {
+            Some(_) if is_stdio(output) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "cannot split an archive written to stdout",
+                ));
             }
-            Box::new(CompressedWriter::new(file))
-        } else {
-            Box::new(file)
+            Some(part_size) => {
+                // Keep parts on block boundaries, as the library requires.
+                let part_size = part_size - part_size % 512;
+                let base = output.to_path_buf();
+                Box::new(MultiVolumeWriter::new(part_size, move |i| {
+                    File::create(part_path(&base, i))
+                })?)
+            }
+            None => create_output(output)?,
         };
-        let mut builder = Builder::new(writer);
+        #[cfg(not(feature = "sign"))]
+        if cli.secret_key.is_some() {
+            return Err(signing_unsupported());
+        }
+        let file = CountingWriter {
+            inner: Output::new(out, &cli, output)?,
+            count: stored.clone(),
+        };
+        let codec = cli.codec().unwrap_or(Codec::from_extension(output));
+        if cli.verbose && codec != Codec::None {
+            eprintln!("Using {:?} compression", codec);
+        }
+        let writer = CompressedWriter::new(Sealed::new(file, &cli)?, codec, cli.level)?;
+        let written = Rc::new(Cell::new(0));
+        let mut builder = Builder::new(cli.checkpoints(
+            CountingWriter {
+                inner: writer,
+                count: written.clone(),
+            },
+            "Write",
+        ));
+        configure_builder(&cli, &mut builder)?;
 
-        if cli.input.is_dir() {
-            if cli.verbose {
-                println!("Adding directory: {}", cli.input.display());
-            }
-            // Use the directory name itself as the base path
-            let base_name = cli.input.file_name().unwrap_or_default();
-            builder.append_dir_all(base_name, &cli.input)?;
+        let mut snapshot = match &cli.listed_incremental {
+            Some(path) => Some(read_snapshot(path)?),
+            None => None,
+        };
+        let previous_state = match &cli.incremental_state {
+            Some(path) => Some(read_file_snapshot(path)?),
+            None => None,
+        };
+        let mut state = None;
+        let (entries, total, pb) = if let Some(snapshot) = &mut snapshot {
+            let pb = create_progress_bar("Creating archive", None, !cli.no_progress);
+            append_listed_incremental(&mut builder, &cli.inputs, snapshot, cli.verbose, &pb)?;
+            (0, 0, pb)
+        } else if let Some(previous) = &previous_state {
+            let pb = create_progress_bar("Creating archive", None, !cli.no_progress);
+            state = Some(append_changed(
+                &mut builder,
+                &cli.inputs,
+                previous,
+                cli.verbose,
+                &pb,
+            )?);
+            (0, 0, pb)
         } else {
-            if cli.verbose {
-                println!("Adding file: {}", cli.input.display());
+            let options = InputOptions {
+                excludes: build_excludes(&cli.exclude)?,
+                ignore_files: &cli.exclude_ignore,
+                transforms: &transforms,
+                follow_links: !cli.no_dereference,
+                update: false,
+                verbose: cli.verbose,
+            };
+            let mut files = Vec::new();
+            for input in &cli.inputs {
+                collect_input(input, &options, &mut files)?;
             }
-            builder.append_path(&cli.input)?;
+            let total = files.iter().map(|f| f.size).sum();
+            let pb = create_progress_bar("Creating archive", Some(total), !cli.no_progress);
+            append_inputs(&mut builder, &files, &options, &pb)?;
+            (files.len() as u64, total, pb)
+        };
+        let file = builder.into_inner()?.inner.inner.finish()?.finish()?;
+        file.inner.finish(output)?;
+        // The snapshot only records the backup once it is safely written
+        if let (Some(path), Some(snapshot)) = (&cli.listed_incremental, &snapshot) {
+            snapshot.write(BufWriter::new(File::create(path)?))?;
+        }
+        if let (Some(path), Some(state)) = (&cli.incremental_state, &state) {
+            state.write(BufWriter::new(File::create(path)?))?;
         }
-        builder.finish()?;
         pb.finish_with_message("Archive created successfully");
+        totals = Totals {
+            entries,
+            content_bytes: total,
+            archive_bytes: written.get(),
+            stored_bytes: stored.get(),
+        };
+    } else if let Some(size) = cli.pack {
+        let dir = cli.output.as_deref().unwrap();
+        let options = InputOptions {
+            excludes: build_excludes(&cli.exclude)?,
+            ignore_files: &cli.exclude_ignore,
+            transforms: &transforms,
+            follow_links: !cli.no_dereference,
+            update: false,
+            verbose: cli.verbose,
+        };
+        let mut files = Vec::new();
+        for input in &cli.inputs {
+            collect_input(input, &options, &mut files)?;
+        }
+        let total = files.iter().map(|f| f.size).sum();
+        let pb = create_progress_bar("Packing archives", Some(total), !cli.no_progress);
+        let written = pack_inputs(&cli, &files, size, dir, &options, &pb)?;
+        pb.finish_with_message("Archives packed successfully");
+        totals = Totals {
+            entries: files.len() as u64,
+            content_bytes: total,
+            archive_bytes: written,
+            stored_bytes: written,
+        };
+    } else if cli.append || cli.update {
+        let output = cli.output.as_deref().unwrap();
+        let mut builder = Builder::append_to(open_for_modify(output, cli.codec(), true)?)?;
+        configure_builder(&cli, &mut builder)?;
+
+        let options = InputOptions {
+            excludes: build_excludes(&cli.exclude)?,
+            ignore_files: &cli.exclude_ignore,
+            transforms: &transforms,
+            follow_links: !cli.no_dereference,
+            update: cli.update,
+            verbose: cli.verbose,
+        };
+        let mut files = Vec::new();
+        for input in &cli.inputs {
+            collect_input(input, &options, &mut files)?;
+        }
+        let total = files.iter().map(|f| f.size).sum();
+        let pb = create_progress_bar("Updating archive", Some(total), !cli.no_progress);
+        append_inputs(&mut builder, &files, &options, &pb)?;
+        builder.finish()?;
+        pb.finish_with_message("Archive updated successfully");
+        if cli.secret_key.is_some() {
+            sign_archive(&cli, output)?;
+        }
+    } else if cli.extract && cli.to_stdout {
+        let (input, members) = archive_and_members(&cli.inputs);
+        let mut selection = MemberSelection::new(members)?;
+        let reader = open_archive(input, &cli, &ProgressBar::hidden())?;
+        let mut archive = Archive::new(cli.checkpoints(reader, "Read"));
+        archive.set_skip_apple_double(cli.skip_apple_double);
+        archive.set_non_utf8_path_policy(cli.non_utf8_path_policy());
+        let stdout = io::stdout();
+        cat_archive(&mut archive, &mut selection, &mut stdout.lock())?;
+    } else if let (true, Some(command)) = (cli.extract, &cli.to_command) {
+        let (input, members) = archive_and_members(&cli.inputs);
+        let mut selection = MemberSelection::new(members)?;
+        let reader = open_archive(input, &cli, &ProgressBar::hidden())?;
+        let mut archive = Archive::new(cli.checkpoints(reader, "Read"));
+        archive.set_skip_apple_double(cli.skip_apple_double);
+        archive.set_non_utf8_path_policy(cli.non_utf8_path_policy());
+        pipe_archive(&mut archive, &mut selection, input, command, cli.verbose)?;
     } else if cli.extract {
-        let pb = create_progress_bar("Extracting archive");
-        let file = File::open(&cli.input)?;
-        let reader: Box<dyn Read> = if cli.input.extension().map_or(false, |ext| ext == "gz") {
+        let (input, members) = archive_and_members(&cli.inputs);
+        let mut selection = MemberSelection::new(members)?;
+        let output = cli.output.as_deref().unwrap();
+        let show_progress = !cli.no_progress && !cli.dry_run;
+        let pb = create_progress_bar("Extracting archive", None, show_progress);
+        let count = Rc::new(Cell::new(0));
+        let mut archive = Archive::new(CountingReader {
+            inner: cli.checkpoints(open_archive(input, &cli, &pb)?, "Read"),
+            count: count.clone(),
+        });
+        let policy = if cli.keep_old_files {
+            OverwritePolicy::Error
+        } else if cli.keep_newer_files {
+            OverwritePolicy::KeepNewer
+        } else if cli.skip_existing {
+            OverwritePolicy::Skip
+        } else {
+            OverwritePolicy::Overwrite
+        };
+        archive.set_strip_components(cli.strip_components);
+        archive.set_skip_apple_double(cli.skip_apple_double);
+        archive.set_non_utf8_path_policy(cli.non_utf8_path_policy());
+        archive.set_overwrite_policy(policy);
+        archive.set_atomic_unpack(cli.atomic);
+        archive.set_preallocate_threshold(cli.preallocate);
+        archive.set_unpack_sparse(cli.sparse);
+        archive.set_incremental(cli.listed_incremental.is_some());
+        archive.set_sync_on_unpack(match cli.sync {
+            None => SyncPolicy::None,
+            Some(SyncArg::Data) => SyncPolicy::Data,
+            Some(SyncArg::Dirs) => SyncPolicy::DataAndDirs,
+        });
+        archive.set_allow_external_symlinks(cli.allow_external_symlinks);
+        archive.set_copy_external_symlinks(cli.copy_external_symlinks);
+        archive.set_case_collision_policy(match cli.case_collisions {
+            None => CaseCollisionPolicy::Ignore,
+            Some(CaseCollisions::Error) => CaseCollisionPolicy::Error,
+            Some(CaseCollisions::Rename) => CaseCollisionPolicy::Rename,
+        });
+        archive.set_symlink_fallback(match cli.symlink_fallback {
+            None | Some(SymlinkFallbackArg::Error) => SymlinkFallback::Error,
+            Some(SymlinkFallbackArg::Skip) => SymlinkFallback::Skip,
+            Some(SymlinkFallbackArg::Copy) => SymlinkFallback::CopyTarget,
+            Some(SymlinkFallbackArg::Junction) => SymlinkFallback::CreateJunctionForDirs,
+        });
+        archive.set_normalize_paths(cli.normalization()?);
+        archive.set_preserve_permissions(cli.preserve_permissions);
+        archive.set_apply_umask(!is_root());
+        archive.set_preserve_ownerships(cli.same_owner || is_root() && !cli.no_same_owner);
+        archive.set_numeric_owner(cli.numeric_owner);
+        archive.set_owner_map(cli.owner_map()?);
+        archive.set_preserve_mtime(!cli.touch);
+        archive.set_preserve_atime(cli.preserve_atime);
+        archive.set_unpack_xattrs(cli.xattrs);
+        archive.set_unpack_selinux(cli.selinux);
+        archive.set_unpack_mac_metadata(cli.mac_metadata);
+        archive.set_unpack_windows_attributes(cli.windows_attributes);
+        archive.set_unpack_devices(cli.devices || is_root());
+        if !transforms.is_empty() {
+            archive.set_path_transform(move |path| {
+                let name = apply_transforms(&transforms, &path.to_string_lossy());
+                (!name.is_empty()).then(|| PathBuf::from(name))
+            });
+        }
+        if cli.dry_run {
+            let stdout = io::stdout();
+            dry_run_extract(
+                &mut archive,
+                &mut selection,
+                output,
+                policy,
+                &mut stdout.lock(),
+            )?;
+        } else {
             if cli.verbose {
-                println!("Detected gzip compression");
+                pb.suspend(|| eprintln!("Extracting to: {}", output.display()));
             }
-            Box::new(GzDecoder::new(file))
-        } else {
-            Box::new(file)
+            let sync_dirs = cli.sync == Some(SyncArg::Dirs);
+            let mut journal = cli.resume.as_ref().map(UnpackJournal::open).transpose()?;
+            extract_archive(
+                &mut archive,
+                &mut selection,
+                output,
+                sync_dirs,
+                journal.as_mut(),
+                &mut totals,
+            )?;
+            if let Some(journal) = journal {
+                journal.finish()?;
+            }
+            io::copy(&mut archive.into_inner(), &mut io::sink())?;
+            pb.finish_with_message("Archive extracted successfully");
+            totals.archive_bytes = count.get();
+            // The progress bar follows the archive file as it is read
+            totals.stored_bytes = pb.position();
+        }
+    } else if cli.diff {
+        let (input, members) = archive_and_members(&cli.inputs);
+        let mut selection = MemberSelection::new(members)?;
+        let dir = cli.output.as_deref().unwrap_or(Path::new("."));
+        let reader = open_archive(input, &cli, &ProgressBar::hidden())?;
+        let mut archive = Archive::new(cli.checkpoints(reader, "Read"));
+        let stdout = io::stdout();
+        diff_archive(&mut archive, &mut selection, dir, &mut stdout.lock())?;
+    } else if cli.stats {
+        let (input, members) = archive_and_members(&cli.inputs);
+        if !members.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "stats covers the whole archive and takes no member names",
+            ));
+        }
+        let pb = ProgressBar::hidden();
+        let mut totals = archive_stats(open_archive(input, &cli, &pb)?)?;
+        totals.stored_bytes = pb.position();
+        let stdout = io::stdout();
+        totals.print(start.elapsed(), &mut stdout.lock())?;
+    } else if cli.verify {
+        let (input, members) = archive_and_members(&cli.inputs);
+        if !members.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "verify checks the whole archive and takes no member names",
+            ));
+        }
+        let reader = open_archive(input, &cli, &ProgressBar::hidden())?;
+        let stdout = io::stdout();
+        verify_archive(reader, cli.verbose, &mut stdout.lock())?;
+    } else if cli.manifest {
+        let (input, members) = archive_and_members(&cli.inputs);
+        if !members.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "a manifest covers the whole archive and takes no member names",
+            ));
+        }
+        let reader = open_archive(input, &cli, &ProgressBar::hidden())?;
+        let mut archive = Archive::new(cli.checkpoints(reader, "Read"));
+        let format = match cli.manifest_format {
+            ManifestKind::Mtree => ManifestFormat::Mtree,
+            #[cfg(feature = "sha2")]
+            ManifestKind::Sha256Sum => ManifestFormat::Sha256Sums,
         };
-        let mut archive = Archive::new(reader);
+        let out = create_output(cli.output.as_deref().unwrap_or(Path::new("-")))?;
+        archive.write_manifest(format, BufWriter::new(out))?;
+    } else if let Some(manifest) = &cli.check_manifest {
+        let (input, members) = archive_and_members(&cli.inputs);
+        if !members.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "a manifest covers the whole archive and takes no member names",
+            ));
+        }
+        let manifest = File::open(manifest)?;
+        let reader = open_archive(input, &cli, &ProgressBar::hidden())?;
+        let mut archive = Archive::new(cli.checkpoints(reader, "Read"));
+        let mismatches = archive.verify_manifest(manifest)?;
+        let stdout = io::stdout();
+        let mut out = stdout.lock();
+        for mismatch in &mismatches {
+            writeln!(out, "{}", mismatch)?;
+        }
+        if !mismatches.is_empty() {
+            return Err(io::Error::other(format!(
+                "archive doesn't match the manifest ({} mismatches)",
+                mismatches.len()
+            )));
+        }
         if cli.verbose {
-            println!("Extracting to: {}", cli.output.display());
+            eprintln!("Archive matches the manifest");
+        }
+    } else if cli.delete {
+        let (input, members) = archive_and_members(&cli.inputs);
+        let mut selection = MemberSelection::new(members)?;
+        delete_members(
+            input,
+            &mut selection,
+            cli.output.as_deref(),
+            cli.codec(),
+            cli.verbose,
+        )?;
+    } else if cli.repair {
+        let (input, members) = archive_and_members(&cli.inputs);
+        if !members.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "repair covers the whole archive and takes no member names",
+            ));
+        }
+        let mut archive = Archive::new(open_for_modify(input, cli.codec(), false)?);
+        let mut out = BufWriter::new(create_output(cli.output.as_deref().unwrap())?);
+        let report = archive.repair(&mut out)?;
+        out.flush()?;
+        if report.fixed_checksums() > 0 {
+            eprintln!("Fixed {} header checksum(s)", report.fixed_checksums());
+        }
+        if report.fixed_padding() > 0 {
+            eprintln!("Zeroed the padding of {} member(s)", report.fixed_padding());
         }
-        archive.unpack(&cli.output)?;
-        pb.finish_with_message("Archive extracted successfully");
+        if report.truncated() {
+            eprintln!("Shortened the truncated last member");
+        }
+        if report.added_trailer() {
+            eprintln!("Added the missing end-of-archive marker");
+        }
+        if cli.verbose && !report.repaired() {
+            eprintln!("Nothing to repair in {} member(s)", report.entries());
+        }
+    } else if cli.list {
+        let (input, members) = archive_and_members(&cli.inputs);
+        let mut selection = MemberSelection::new(members)?;
+        let reader = open_archive(input, &cli, &ProgressBar::hidden())?;
+        let mut archive = Archive::new(cli.checkpoints(reader, "Read"));
+        archive.set_skip_apple_double(cli.skip_apple_double);
+        archive.set_non_utf8_path_policy(cli.non_utf8_path_policy());
+        let stdout = io::stdout();
+        list_archive(
+            &mut archive,
+            &mut selection,
+            cli.list_format(),
+            cli.verbose,
+            cli.content_type,
+            &mut stdout.lock(),
+        )?;
     }
 
+    if cli.totals
+        && (cli.create
+            || cli.pack.is_some()
+            || cli.extract && !cli.to_stdout && cli.to_command.is_none() && !cli.dry_run)
+    {
+        totals.print(start.elapsed(), &mut io::stderr())?;
+    }
     Ok(())
 }
 
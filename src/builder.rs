@@ -1,12 +1,27 @@
-use rayon;
+use std::borrow::Cow;
+use std::collections::{hash_map, HashMap, HashSet};
+use std::env;
 use std::fs;
-use std::io::{self, Read, Seek, Write};
-use std::path::Path;
+use std::io::{self, ErrorKind, IoSlice, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
 use std::str;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
+use filetime::FileTime;
+
+use crate::digest::{Digest, DigestAlgorithm, Hasher};
+use crate::file_snapshot::FileState;
 use crate::header::{path2bytes, Header, HeaderMode, BLOCK_SIZE, GNU_SPARSE_HEADERS_COUNT};
-use crate::{other, EntryType, GnuExtSparseHeader};
+use crate::incremental::SnapshotDir;
+use crate::normalize::{normalize_path, NormalizationForm};
+use crate::pax::{format_pax_time, PAX_ATIME, PAX_CTIME, PAX_GNAME, PAX_MTIME, PAX_UNAME};
+use crate::progress::ProgressObserver;
+use crate::throttle::{RateLimiter, Throttled};
+use crate::{other, Archive, Entry, EntryType, FileSnapshot, GnuExtSparseHeader, Snapshot};
+#[cfg(feature = "encrypt")]
+use crate::{EncryptingWriter, Recipient};
 
 /// A structure for building archives
 ///
@@ -16,6 +31,12 @@ pub struct Builder<W: Write> {
     options: BuilderOptions,
     finished: bool,
     obj: Option<W>,
+    archived: HashMap<Vec<u8>, u64>,
+    links: Links,
+    throttle: Option<RateLimiter>,
+    // Bytes of the archive written so far, for padding it to whole records
+    written: u64,
+    progress: Option<Box<dyn ProgressObserver + Send>>,
 }
 
 #[derive(Clone, Copy)]
@@ -24,6 +45,44 @@ struct BuilderOptions {
     follow: bool,
     sparse: bool,
     thread: Option<usize>,
+    hardlinks: bool,
+    dedup: Option<DigestAlgorithm>,
+    xattrs: bool,
+    acls: bool,
+    selinux: bool,
+    mac_metadata: bool,
+    windows_attributes: bool,
+    extended_times: bool,
+    owner_names: bool,
+    spool_threshold: usize,
+    blocking_factor: u32,
+    normalization: Option<NormalizationForm>,
+}
+
+/// Which of the members with the same path to keep when merging archives
+/// with `Builder::append_merged`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MergePolicy {
+    /// Keep the member from the earliest archive.
+    KeepFirst,
+    /// Keep the member from the latest archive, as unpacking the archives
+    /// over each other would.
+    KeepLast,
+    /// Keep the member with the latest modification time, or the later one
+    /// if they were modified at the same time.
+    KeepNewestMtime,
+    /// Fail with an error.
+    Error,
+}
+
+/// The names files were first archived under, for adding their later names
+/// or copies as hard links.
+#[derive(Default)]
+struct Links {
+    /// Files with several hard links, keyed by device and inode number.
+    inodes: HashMap<(u64, u64), PathBuf>,
+    /// Files deduplicated by contents, keyed by size and digest.
+    contents: HashMap<(u64, Digest), PathBuf>,
 }
 
 impl<W: Write> Builder<W> {
@@ -37,9 +96,26 @@ impl<W: Write> Builder<W> {
                 follow: true,
                 sparse: true,
                 thread: None,
+                hardlinks: false,
+                dedup: None,
+                xattrs: false,
+                acls: false,
+                selinux: false,
+                mac_metadata: false,
+                windows_attributes: false,
+                extended_times: false,
+                owner_names: false,
+                spool_threshold: 8 << 20,
+                blocking_factor: 1,
+                normalization: None,
             },
             finished: false,
             obj: Some(obj),
+            archived: HashMap::new(),
+            links: Links::default(),
+            throttle: None,
+            written: 0,
+            progress: None,
         }
     }
 
@@ -66,12 +142,166 @@ impl<W: Write> Builder<W> {
         self.options.sparse = sparse;
     }
 
+    /// Archive files with several hard links once, adding a hard link entry to
+    /// the first name a file was archived under for each of its other names,
+    /// like GNU `tar` does by default. Defaults to false.
+    ///
+    /// Links are found by device and inode number among the files added by
+    /// path, by `append_file` and by `append_dir_all`, so only on Unix.
+    pub fn detect_hardlinks(&mut self, detect: bool) {
+        self.options.hardlinks = detect;
+    }
+
+    /// Archive files whose contents are identical to a file archived before
+    /// as hard links to it, even if they aren't linked on disk. Defaults to
+    /// `None`, archiving every file in full.
+    ///
+    /// Contents are compared by their size and digest with `algorithm`, so
+    /// each file is read twice. Empty files are never deduplicated. Note that
+    /// when unpacked, every name of a deduplicated file shares the
+    /// permissions and timestamps of the first. This applies to the same
+    /// files as `detect_hardlinks`.
+    pub fn deduplicate(&mut self, algorithm: Option<DigestAlgorithm>) {
+        self.options.dedup = algorithm;
+    }
+
+    /// Store the extended attributes of the files added by path, by
+    /// `append_file` and by the directory methods as `SCHILY.xattr.*` PAX
+    /// records, which `Archive::set_unpack_xattrs` restores. Defaults to
+    /// false.
+    ///
+    /// This is only supported on Unix with the `xattr` feature, and does
+    /// nothing otherwise. POSIX ACLs, SELinux contexts and macOS metadata are
+    /// left out unless `acls`, `selinux` or `mac_metadata` is also set.
+    pub fn xattrs(&mut self, xattrs: bool) {
+        self.options.xattrs = xattrs;
+    }
+
+    /// Store the POSIX ACLs of the files added like `xattrs` does, as the
+    /// `system.posix_acl_access` and `system.posix_acl_default` extended
+    /// attributes they are kept in on Linux. Defaults to false.
+    ///
+    /// This is only supported on Unix with the `acl` feature, and does
+    /// nothing otherwise.
+    pub fn acls(&mut self, acls: bool) {
+        self.options.acls = acls;
+    }
+
+    /// Store the SELinux context of the files added like `xattrs` does, as
+    /// the `security.selinux` extended attribute, which
+    /// `Archive::set_unpack_selinux` restores. Defaults to false.
+    ///
+    /// This is only supported on Unix with the `xattr` feature, and does
+    /// nothing otherwise.
+    pub fn selinux(&mut self, selinux: bool) {
+        self.options.selinux = selinux;
+    }
+
+    /// Store the macOS metadata of the files added like `xattrs` does, as the
+    /// `com.apple.*` extended attributes holding things like Finder info and
+    /// resource forks, which `Archive::set_unpack_mac_metadata` restores.
+    /// Defaults to false.
+    ///
+    /// This is only supported on Unix with the `xattr` feature, and does
+    /// nothing otherwise.
+    pub fn mac_metadata(&mut self, mac_metadata: bool) {
+        self.options.mac_metadata = mac_metadata;
+    }
+
+    /// Store the readonly, hidden and system attributes of the files added
+    /// like `xattrs` does, in a `SCHILY.fflags` PAX record as libarchive
+    /// does, which `Archive::set_unpack_windows_attributes` restores.
+    /// Defaults to false.
+    ///
+    /// This is only supported on Windows, and does nothing otherwise.
+    pub fn windows_attributes(&mut self, windows_attributes: bool) {
+        self.options.windows_attributes = windows_attributes;
+    }
+
+    /// Store the modification time of the files added to the nanosecond, and
+    /// their access and, on Unix, status change times, like `xattrs` does in
+    /// `mtime`, `atime` and `ctime` PAX records, which `Entry::mtime`,
+    /// `Entry::atime` and `Entry::ctime` read back. Defaults to false.
+    ///
+    /// The `mtime` record is left out for files modified on a whole second,
+    /// whose header already holds it exactly.
+    ///
+    /// Nothing is stored in `HeaderMode::Deterministic`.
+    pub fn extended_times(&mut self, extended_times: bool) {
+        self.options.extended_times = extended_times;
+    }
+
+    /// Store the names of the users and groups owning the files added,
+    /// looked up on this system, alongside their numeric ids so that
+    /// `Archive::set_numeric_owner` can map them back to local accounts, as
+    /// `tar` does by default. Defaults to false.
+    ///
+    /// Names too long for the header are stored in `uname` and `gname` PAX
+    /// records, which `Entry::username` and `Entry::groupname` read back.
+    /// Names are only looked up on Unix, and nothing is stored in
+    /// `HeaderMode::Deterministic`.
+    pub fn owner_names(&mut self, owner_names: bool) {
+        self.options.owner_names = owner_names;
+    }
+
+    /// Converts the paths of the entries added, and the targets of links, to
+    /// a Unicode normalization form, so that names read from filesystems
+    /// which normalize differently, such as macOS and Linux ones, are stored
+    /// the same way. Defaults to `None`, storing names as they are given.
+    ///
+    /// This applies to every method which adds an entry by path, but not to
+    /// `append`, whose header is written as it is. Paths which aren't valid
+    /// UTF-8 are left as they are.
+    pub fn normalize_paths(&mut self, form: Option<NormalizationForm>) {
+        self.options.normalization = form;
+    }
+
     /// Sets the number of threads to use for parallel operations.
     /// None means single-threaded operation (default).
     pub fn threads(&mut self, threads: Option<usize>) {
         self.options.thread = threads;
     }
 
+    /// Sets how much of the contents given to `append_stream` are held in
+    /// memory, 8 MiB by default, before spooling them to a temporary file
+    /// instead.
+    pub fn spool_threshold(&mut self, bytes: usize) {
+        self.options.spool_threshold = bytes;
+    }
+
+    /// Sets the number of 512-byte blocks in each record of the archive, 1
+    /// by default.
+    ///
+    /// `finish` pads the archive with zeros after its end-of-archive marker
+    /// to a whole number of records, as tape drives and some older tars
+    /// expect. GNU tar writes records of 20 blocks.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `factor` is 0.
+    pub fn blocking_factor(&mut self, factor: u32) {
+        assert!(factor > 0, "blocking factor must be at least 1");
+        self.options.blocking_factor = factor;
+    }
+
+    /// Limit writing to the underlying object to `rate` bytes a second,
+    /// sleeping as needed, so that building a large archive doesn't saturate
+    /// a disk or network link shared with other work. This defaults to
+    /// `None`, writing as fast as possible.
+    pub fn max_bytes_per_sec(&mut self, rate: Option<u64>) {
+        self.throttle = rate.map(RateLimiter::new);
+    }
+
+    /// Report the progress of building the archive to `observer`.
+    ///
+    /// Each entry added is reported as it starts and finishes, with the
+    /// bytes of its contents once they have been archived, and the archive
+    /// is reported finished by `finish`. Entries added with `append_writer`
+    /// aren't reported.
+    pub fn progress_observer<P: ProgressObserver + Send + 'static>(&mut self, observer: P) {
+        self.progress = Some(Box::new(observer));
+    }
+
     /// Gets shared reference to the underlying object.
     pub fn get_ref(&self) -> &W {
         self.obj.as_ref().unwrap()
@@ -87,6 +317,37 @@ impl<W: Write> Builder<W> {
         self.obj.as_mut().unwrap()
     }
 
+    /// The underlying object, written to no faster than `max_bytes_per_sec`
+    /// allows.
+    pub(crate) fn dst(&mut self) -> Throttled<'_, W> {
+        Throttled::new(
+            self.obj.as_mut().unwrap(),
+            &mut self.throttle,
+            &mut self.written,
+        )
+    }
+
+    /// Adds an entry with `add`, telling the progress observer as it starts
+    /// and finishes. `entry` gives the entry's name and size, and is only
+    /// called if there is an observer or the entry is traced.
+    fn observe(
+        &mut self,
+        entry: impl FnOnce() -> (PathBuf, u64),
+        add: impl FnOnce(&mut Self) -> io::Result<()>,
+    ) -> io::Result<()> {
+        if !observing(self.progress.is_some()) {
+            return add(self);
+        }
+        let (name, size) = entry();
+        #[cfg(feature = "tracing")]
+        let _span = entry_span(&name, size);
+        let start = Instant::now();
+        entry_started(self.progress.as_deref_mut(), &name, size);
+        add(self)?;
+        entry_finished(self.progress.as_deref_mut(), &name, size, start);
+        Ok(())
+    }
+
     /// Unwrap this archive, returning the underlying object.
     ///
     /// This function will finish writing the archive if the `finish` function
@@ -136,7 +397,11 @@ impl<W: Write> Builder<W> {
     /// let data = ar.into_inner().unwrap();
     /// ```
     pub fn append<R: Read>(&mut self, header: &Header, mut data: R) -> io::Result<()> {
-        append(self.get_mut(), header, &mut data)
+        let entry = || {
+            let name = header.path().map(Cow::into_owned).unwrap_or_default();
+            (name, header.entry_size().unwrap_or(0))
+        };
+        self.observe(entry, |ar| append(&mut ar.dst(), header, &mut data))
     }
 
     /// Adds a new entry to this archive with the specified path.
@@ -183,11 +448,20 @@ impl<W: Write> Builder<W> {
         &mut self,
         header: &mut Header,
         path: P,
-        data: R,
+        mut data: R,
     ) -> io::Result<()> {
-        prepare_header_path(self.get_mut(), header, path.as_ref())?;
-        header.set_cksum();
-        self.append(&header, data)
+        let path = path.as_ref();
+        let size = header.entry_size().unwrap_or(0);
+        self.observe(
+            || (path.to_path_buf(), size),
+            |ar| {
+                let normalization = ar.options.normalization;
+                let mut dst = ar.dst();
+                prepare_header_path(&mut dst, header, path, normalization)?;
+                header.set_cksum();
+                append(&mut dst, header, &mut data)
+            },
+        )
     }
 
     /// Adds a new entry to this archive and returns an [`EntryWriter`] for
@@ -228,7 +502,184 @@ impl<W: Write> Builder<W> {
     where
         W: Seek,
     {
-        EntryWriter::start(self.get_mut(), header, path.as_ref())
+        EntryWriter::start(
+            self.obj.as_mut().unwrap(),
+            self.throttle.as_mut(),
+            &mut self.written,
+            header,
+            path.as_ref(),
+            self.options.normalization,
+        )
+    }
+
+    /// Adds a new entry to this archive with the specified path, and
+    /// contents of a length which isn't known in advance.
+    ///
+    /// This function is similar to [`Self::append_data`], but reads all of
+    /// `data` before writing anything, so that the size in `header` can be
+    /// set to its length. Contents up to the builder's `spool_threshold` are
+    /// held in memory, and longer ones are spooled to a temporary file which
+    /// is removed again once they have been archived. Unlike
+    /// [`Self::append_writer`], the underlying writer needn't implement
+    /// [`Seek`], so content generated on the fly can be archived in one pass
+    /// into a pipe or compressor.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error for any intermittent I/O error which
+    /// occurs when either reading, spooling or writing.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::io::{self, Read};
+    /// use tar::{Builder, Header};
+    ///
+    /// let mut header = Header::new_gnu();
+    /// header.set_mode(0o644);
+    ///
+    /// let data = io::repeat(b'x').take(100);
+    /// let mut ar = Builder::new(Vec::new());
+    /// ar.append_stream(&mut header, "generated.txt", data).unwrap();
+    /// let data = ar.into_inner().unwrap();
+    /// ```
+    pub fn append_stream<P: AsRef<Path>, R: Read>(
+        &mut self,
+        header: &mut Header,
+        path: P,
+        mut data: R,
+    ) -> io::Result<()> {
+        let threshold = self.options.spool_threshold as u64;
+        let mut buf = Vec::new();
+        // Reading one byte more than the threshold tells whether it's reached
+        (&mut data).take(threshold + 1).read_to_end(&mut buf)?;
+        if buf.len() as u64 <= threshold {
+            header.set_size(buf.len() as u64);
+            return self.append_data(header, path, &buf[..]);
+        }
+        let mut spool = SpoolFile::create()?;
+        spool.file.write_all(&buf)?;
+        let size = buf.len() as u64 + io::copy(&mut data, &mut spool.file)?;
+        drop(buf);
+        spool.file.seek(SeekFrom::Start(0))?;
+        header.set_size(size);
+        self.append_data(header, path, &mut spool.file)
+    }
+
+    /// Adds a copy of an entry read from another archive to this archive.
+    ///
+    /// The entry's header and contents are copied as they were archived,
+    /// without being decoded, along with any GNU long name and PAX headers
+    /// describing it, so archives can be filtered or repacked quickly. The
+    /// contents of `entry` must not have been read from.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if `entry` has been partly read,
+    /// or for any intermittent I/O error which occurs when either reading or
+    /// writing.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use std::fs::File;
+    /// use tar::{Archive, Builder};
+    ///
+    /// let mut src = Archive::new(File::open("foo.tar").unwrap());
+    /// let mut ar = Builder::new(File::create("docs.tar").unwrap());
+    /// for entry in src.entries().unwrap() {
+    ///     let mut entry = entry.unwrap();
+    ///     if entry.path().unwrap().starts_with("docs") {
+    ///         ar.append_entry(&mut entry).unwrap();
+    ///     }
+    /// }
+    /// ar.finish().unwrap();
+    /// ```
+    pub fn append_entry<R: Read>(&mut self, entry: &mut Entry<'_, R>) -> io::Result<()> {
+        let name = entry.path().map(Cow::into_owned).unwrap_or_default();
+        let size = entry.size();
+        self.observe(|| (name, size), |ar| entry.copy_raw_to(&mut ar.dst()))
+    }
+
+    /// Adds the members of each of `archives` in turn, keeping only one of
+    /// those with the same path as chosen by `policy`, and returns how many
+    /// were left out.
+    ///
+    /// This combines layered archives, such as container image layers, into
+    /// one. Paths are compared ignoring trailing slashes, and members which
+    /// repeat a path within a single archive count as duplicates too. Kept
+    /// members are copied as they were archived, in the order they appear
+    /// in `archives`. The archives are read twice, first to pick the members
+    /// to keep, so nothing is written if `policy` is `MergePolicy::Error`
+    /// and a path is duplicated.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind `AlreadyExists` naming the first duplicated
+    /// path found when `policy` is `MergePolicy::Error`, and an error if any
+    /// archive can't be read.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use std::fs::File;
+    /// use tar::{Builder, MergePolicy};
+    ///
+    /// let mut layers = vec![File::open("base.tar").unwrap(), File::open("app.tar").unwrap()];
+    /// let mut ar = Builder::new(File::create("image.tar").unwrap());
+    /// ar.append_merged(&mut layers, MergePolicy::KeepLast).unwrap();
+    /// ar.finish().unwrap();
+    /// ```
+    pub fn append_merged<R: Read + Seek>(
+        &mut self,
+        archives: &mut [R],
+        policy: MergePolicy,
+    ) -> io::Result<u64> {
+        // The index of the member kept for each path among all the members
+        // of all the archives, with its modification time
+        let mut kept: HashMap<Vec<u8>, (u64, SystemTime)> = HashMap::new();
+        let mut index = 0;
+        for archive in archives.iter_mut() {
+            archive.seek(SeekFrom::Start(0))?;
+            for entry in Archive::new(&mut *archive).entries_with_seek()? {
+                let mut entry = entry?;
+                let key = archived_key(&entry.path_bytes());
+                let mtime = entry.mtime()?;
+                let replace = match (kept.get(&key), policy) {
+                    (None, _) => true,
+                    (Some(_), MergePolicy::Error) => {
+                        return Err(io::Error::new(
+                            io::ErrorKind::AlreadyExists,
+                            format!(
+                                "`{}` appears more than once in the archives",
+                                String::from_utf8_lossy(&key)
+                            ),
+                        ));
+                    }
+                    (Some(_), MergePolicy::KeepFirst) => false,
+                    (Some(_), MergePolicy::KeepLast) => true,
+                    (Some(&(_, prev)), MergePolicy::KeepNewestMtime) => mtime >= prev,
+                };
+                if replace {
+                    kept.insert(key, (index, mtime));
+                }
+                index += 1;
+            }
+        }
+
+        let keep = kept.values().map(|&(i, _)| i).collect::<HashSet<_>>();
+        let mut index = 0;
+        for archive in archives.iter_mut() {
+            archive.seek(SeekFrom::Start(0))?;
+            for entry in Archive::new(&mut *archive).entries_with_seek()? {
+                let mut entry = entry?;
+                if keep.contains(&index) {
+                    self.append_entry(&mut entry)?;
+                }
+                index += 1;
+            }
+        }
+        Ok(index - keep.len() as u64)
     }
 
     /// Adds a new link (symbolic or hard) entry to this archive with the specified path and target.
@@ -273,10 +724,17 @@ impl<W: Write> Builder<W> {
     }
 
     fn _append_link(&mut self, header: &mut Header, path: &Path, target: &Path) -> io::Result<()> {
-        prepare_header_path(self.get_mut(), header, path)?;
-        prepare_header_link(self.get_mut(), header, target)?;
-        header.set_cksum();
-        self.append(&header, std::io::empty())
+        self.observe(
+            || (path.to_path_buf(), 0),
+            |ar| {
+                let normalization = ar.options.normalization;
+                let mut dst = ar.dst();
+                prepare_header_path(&mut dst, header, path, normalization)?;
+                prepare_header_link(&mut dst, header, target, normalization)?;
+                header.set_cksum();
+                append(&mut dst, header, &mut io::empty())
+            },
+        )
     }
 
     /// Adds a file on the local filesystem to this archive.
@@ -304,8 +762,24 @@ impl<W: Write> Builder<W> {
     /// ar.append_path("foo/bar.txt").unwrap();
     /// ```
     pub fn append_path<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
-        let options = self.options;
-        append_path_with_name(self.get_mut(), path.as_ref(), None, options)
+        self._append_path(path.as_ref(), None, self.options)
+    }
+
+    fn _append_path(
+        &mut self,
+        path: &Path,
+        name: Option<&Path>,
+        options: BuilderOptions,
+    ) -> io::Result<()> {
+        let entry = || {
+            let name = name.unwrap_or(path).to_path_buf();
+            (name, archived_size(path, options.follow))
+        };
+        self.observe(entry, |ar| {
+            let dst =
+                &mut Throttled::new(ar.obj.as_mut().unwrap(), &mut ar.throttle, &mut ar.written);
+            append_path_with_name(dst, path, name, options, &mut ar.links)
+        })
     }
 
     /// Adds a file on the local filesystem to this archive under another name.
@@ -341,8 +815,68 @@ impl<W: Write> Builder<W> {
         path: P,
         name: N,
     ) -> io::Result<()> {
-        let options = self.options;
-        append_path_with_name(self.get_mut(), path.as_ref(), Some(name.as_ref()), options)
+        self._append_path(path.as_ref(), Some(name.as_ref()), self.options)
+    }
+
+    /// Adds a file on the local filesystem to this archive under another name
+    /// like `append_path_with_name`, but archiving the file a symlink points
+    /// to or the symlink itself as given by `follow` rather than by
+    /// `follow_symlinks`.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use tar::Builder;
+    ///
+    /// let mut ar = Builder::new(Vec::new());
+    ///
+    /// // Store the file "current" points to, even if the builder keeps
+    /// // symlinks as they are elsewhere.
+    /// ar.follow_symlinks(false);
+    /// ar.append_path_with_follow("current", "release", true).unwrap();
+    /// ```
+    pub fn append_path_with_follow<P: AsRef<Path>, N: AsRef<Path>>(
+        &mut self,
+        path: P,
+        name: N,
+        follow: bool,
+    ) -> io::Result<()> {
+        let options = BuilderOptions {
+            follow,
+            ..self.options
+        };
+        self._append_path(path.as_ref(), Some(name.as_ref()), options)
+    }
+
+    /// Adds a file on the local filesystem to this archive under another name,
+    /// but only if it is newer than the member of that name already in the
+    /// archive, like `tar -u`.
+    ///
+    /// Members are only known for builders opened with `Builder::append_to`
+    /// and for files previously added through this function, so for any other
+    /// builder this behaves like `append_path_with_name`. Returns whether the
+    /// file was added.
+    pub fn update_path_with_name<P: AsRef<Path>, N: AsRef<Path>>(
+        &mut self,
+        path: P,
+        name: N,
+    ) -> io::Result<bool> {
+        let path = path.as_ref();
+        let stat = if self.options.follow {
+            fs::metadata(path)?
+        } else {
+            fs::symlink_metadata(path)?
+        };
+        let mtime = filetime::FileTime::from_last_modification_time(&stat).unix_seconds();
+        let mtime = u64::try_from(mtime).unwrap_or(0);
+        let stored = normalize_path(Cow::Borrowed(name.as_ref()), self.options.normalization);
+        let key = archived_key(&path2bytes(&stored)?);
+        if self.archived.get(&key).is_some_and(|&prev| prev >= mtime) {
+            return Ok(false);
+        }
+        self.append_path_with_name(path, name)?;
+        self.archived.insert(key, mtime);
+        Ok(true)
     }
 
     /// Adds a file to this archive with the given path as the name of the file
@@ -372,8 +906,20 @@ impl<W: Write> Builder<W> {
     /// ar.append_file("bar/baz.txt", &mut f).unwrap();
     /// ```
     pub fn append_file<P: AsRef<Path>>(&mut self, path: P, file: &mut fs::File) -> io::Result<()> {
+        let path = path.as_ref();
         let options = self.options;
-        append_file(self.get_mut(), path.as_ref(), file, options)
+        let size = file.metadata().map_or(0, |stat| stat.len());
+        self.observe(
+            || (path.to_path_buf(), size),
+            |ar| {
+                let dst = &mut Throttled::new(
+                    ar.obj.as_mut().unwrap(),
+                    &mut ar.throttle,
+                    &mut ar.written,
+                );
+                append_file(dst, path, file, options, &mut ar.links)
+            },
+        )
     }
 
     /// Adds a directory to this archive with the given path as the name of the
@@ -409,8 +955,12 @@ impl<W: Write> Builder<W> {
         P: AsRef<Path>,
         Q: AsRef<Path>,
     {
+        let path = path.as_ref();
         let options = self.options;
-        append_dir(self.get_mut(), path.as_ref(), src_path.as_ref(), options)
+        self.observe(
+            || (path.to_path_buf(), 0),
+            |ar| append_dir(&mut ar.dst(), path, src_path.as_ref(), options),
+        )
     }
 
     /// Adds a directory and all of its contents (recursively) to this archive
@@ -472,7 +1022,230 @@ impl<W: Write> Builder<W> {
         Q: AsRef<Path>,
     {
         let options = self.options;
-        append_dir_all(self.get_mut(), path.as_ref(), src_path.as_ref(), options)
+        let dst = &mut Throttled::new(
+            self.obj.as_mut().unwrap(),
+            &mut self.throttle,
+            &mut self.written,
+        );
+        append_dir_all(
+            dst,
+            path.as_ref(),
+            src_path.as_ref(),
+            options,
+            &mut self.links,
+            self.progress.as_deref_mut(),
+        )
+    }
+
+    /// Adds a directory and all of its contents like `append_dir_all`, but
+    /// following symlinks within it or not as given by `follow` rather than by
+    /// `follow_symlinks`.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use tar::Builder;
+    ///
+    /// let mut ar = Builder::new(Vec::new());
+    ///
+    /// // Store the symlinks in "rootfs" as symlinks, whatever the builder
+    /// // does elsewhere.
+    /// ar.append_dir_all_with_follow("rootfs", "/srv/rootfs", false).unwrap();
+    /// ```
+    pub fn append_dir_all_with_follow<P, Q>(
+        &mut self,
+        path: P,
+        src_path: Q,
+        follow: bool,
+    ) -> io::Result<()>
+    where
+        P: AsRef<Path>,
+        Q: AsRef<Path>,
+    {
+        let options = BuilderOptions {
+            follow,
+            ..self.options
+        };
+        let dst = &mut Throttled::new(
+            self.obj.as_mut().unwrap(),
+            &mut self.throttle,
+            &mut self.written,
+        );
+        append_dir_all(
+            dst,
+            path.as_ref(),
+            src_path.as_ref(),
+            options,
+            &mut self.links,
+            self.progress.as_deref_mut(),
+        )
+    }
+
+    /// Adds a directory and its contents as part of a GNU incremental backup,
+    /// archiving only the files which changed since the backup recorded in
+    /// `snapshot`.
+    ///
+    /// Every directory is archived, as a dump directory entry listing what
+    /// it holds, followed by its files which were modified or had their
+    /// status changed since the recorded backup started, or all of them if
+    /// the directory is new. Unpacking the backups in order with
+    /// `Archive::set_incremental` recreates the tree, deleting what was
+    /// deleted in between. `snapshot` is updated to record this backup, for
+    /// making the next one.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use std::fs::File;
+    /// use tar::{Builder, Snapshot};
+    ///
+    /// let mut snapshot = Snapshot::new();
+    /// let mut full = Builder::new(File::create("full.tar").unwrap());
+    /// full.append_dir_incremental("data", "/srv/data", &mut snapshot).unwrap();
+    /// full.finish().unwrap();
+    /// snapshot.write(File::create("data.snar").unwrap()).unwrap();
+    ///
+    /// // Later, archive only what changed since
+    /// let mut snapshot = Snapshot::read(File::open("data.snar").unwrap()).unwrap();
+    /// let mut changes = Builder::new(File::create("changes.tar").unwrap());
+    /// changes.append_dir_incremental("data", "/srv/data", &mut snapshot).unwrap();
+    /// changes.finish().unwrap();
+    /// snapshot.write(File::create("data.snar").unwrap()).unwrap();
+    /// ```
+    pub fn append_dir_incremental<P, Q>(
+        &mut self,
+        path: P,
+        src_path: Q,
+        snapshot: &mut Snapshot,
+    ) -> io::Result<()>
+    where
+        P: AsRef<Path>,
+        Q: AsRef<Path>,
+    {
+        let since = snapshot.start_dump();
+        let mut dirs = Vec::new();
+        let mut stack = vec![(path.as_ref().to_path_buf(), src_path.as_ref().to_path_buf())];
+        while let Some((name, src)) = stack.pop() {
+            let meta = metadata(&src, self.options.follow)?;
+            let changed_only = snapshot.contains(&name);
+            let mut children = fs::read_dir(&src)?
+                .map(|child| child.map(|child| child.file_name()))
+                .collect::<io::Result<Vec<_>>>()?;
+            children.sort();
+
+            let mut contents = Vec::new();
+            let mut files = Vec::new();
+            let mut subdirs = Vec::new();
+            for child in children {
+                let child_meta = metadata(&src.join(&child), self.options.follow)?;
+                let code = if child_meta.is_dir() {
+                    subdirs.push(child.clone());
+                    b'D'
+                } else if changed_only && !changed_since(&child_meta, since) {
+                    b'N'
+                } else {
+                    files.push(child.clone());
+                    b'Y'
+                };
+                contents.push(code);
+                let listed =
+                    normalize_path(Cow::Borrowed(Path::new(&child)), self.options.normalization);
+                contents.extend_from_slice(&path2bytes(&listed)?);
+                contents.push(0);
+            }
+            contents.push(0);
+
+            let mut header = Header::new_gnu();
+            set_header_metadata(&mut header, &meta, self.options);
+            header.set_entry_type(EntryType::GNUDumpDir);
+            header.set_size(contents.len() as u64);
+            self.append_data(&mut header, &name, &contents[..])?;
+            for file in files {
+                self.append_path_with_name(src.join(&file), name.join(&file))?;
+            }
+            // Subdirectories are archived after their parent, in order
+            for subdir in subdirs.iter().rev() {
+                stack.push((name.join(subdir), src.join(subdir)));
+            }
+
+            let (dev, ino) = device_and_inode(&meta);
+            let dir = SnapshotDir {
+                nfs: false,
+                mtime: meta
+                    .modified()?
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default(),
+                dev,
+                ino,
+                contents,
+            };
+            dirs.push((name, dir));
+        }
+        snapshot.record(path.as_ref(), dirs);
+        Ok(())
+    }
+
+    /// Adds a file or directory and everything under it, archiving only what
+    /// is new or changed since `previous` was taken, and returns a snapshot
+    /// of everything found for making the next incremental archive.
+    ///
+    /// A file counts as changed when its size, modification time or inode
+    /// differs from what `previous` records for its path in the archive. This
+    /// includes directories, whose modification time changes as files are
+    /// added to or removed from them. Files deleted since `previous` are left
+    /// out of the returned snapshot but not otherwise recorded, so the
+    /// archive is an ordinary one which can be unpacked over the last.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use tar::{Builder, FileSnapshot};
+    ///
+    /// let mut full = Builder::new(Vec::new());
+    /// let snapshot = full
+    ///     .append_incremental("data", "/srv/data", &FileSnapshot::new())
+    ///     .unwrap();
+    ///
+    /// // Later, archive only what changed since
+    /// let mut changes = Builder::new(Vec::new());
+    /// let snapshot = changes
+    ///     .append_incremental("data", "/srv/data", &snapshot)
+    ///     .unwrap();
+    /// ```
+    pub fn append_incremental<P, Q>(
+        &mut self,
+        path: P,
+        src_path: Q,
+        previous: &FileSnapshot,
+    ) -> io::Result<FileSnapshot>
+    where
+        P: AsRef<Path>,
+        Q: AsRef<Path>,
+    {
+        let mut snapshot = FileSnapshot::new();
+        let mut stack = vec![(path.as_ref().to_path_buf(), src_path.as_ref().to_path_buf())];
+        while let Some((name, src)) = stack.pop() {
+            let meta = metadata(&src, self.options.follow)?;
+            if meta.is_dir() {
+                let mut children = fs::read_dir(&src)?
+                    .map(|child| child.map(|child| child.file_name()))
+                    .collect::<io::Result<Vec<_>>>()?;
+                // Popped in order, each directory before what it holds
+                children.sort();
+                for child in children.iter().rev() {
+                    stack.push((name.join(child), src.join(child)));
+                }
+            }
+            if name == Path::new("") {
+                continue;
+            }
+            let state = FileState::new(&meta, device_and_inode(&meta));
+            if previous.changed(&name, &state) {
+                self.append_path_with_name(&src, &name)?;
+            }
+            snapshot.insert(name, state);
+        }
+        Ok(snapshot)
     }
 
     /// Finish writing this archive, emitting the termination sections.
@@ -487,7 +1260,83 @@ impl<W: Write> Builder<W> {
             return Ok(());
         }
         self.finished = true;
-        self.get_mut().write_all(&[0; 1024])
+        self.dst().write_all(&[0; 1024])?;
+        let record = u64::from(self.options.blocking_factor) * BLOCK_SIZE;
+        let padding = (record - self.written % record) % record;
+        io::copy(&mut io::repeat(0).take(padding), &mut self.dst())?;
+        #[cfg(feature = "tracing")]
+        tracing::debug!("finished archive");
+        if let Some(progress) = &mut self.progress {
+            progress.archive_finished();
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "encrypt")]
+impl<W: Write> Builder<EncryptingWriter<W>> {
+    /// Create a new archive builder which encrypts the archive to
+    /// `recipients` with age before writing it to `obj`, so that it never
+    /// reaches `obj` unencrypted.
+    ///
+    /// The encrypted archive is only complete once `finish` has also been
+    /// called on the writer returned by `into_inner`.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use std::fs::File;
+    /// use tar::{Builder, Recipient};
+    ///
+    /// let recipient: Recipient = "age1...".parse().unwrap();
+    /// let file = File::create("foo.tar.age").unwrap();
+    /// let mut ar = Builder::with_encryption(file, &[recipient]).unwrap();
+    /// ar.append_path("bar.txt").unwrap();
+    /// ar.into_inner().unwrap().finish().unwrap();
+    /// ```
+    pub fn with_encryption(obj: W, recipients: &[Recipient]) -> io::Result<Self> {
+        Ok(Builder::new(EncryptingWriter::new(obj, recipients)?))
+    }
+}
+
+impl<W: Read + Write + Seek> Builder<W> {
+    /// Opens an existing archive so that new entries can be added to the end
+    /// of it in place, like `tar -r`.
+    ///
+    /// The archive is read from the start and the writer is left positioned at
+    /// its end-of-archive marker, which is written again by `finish`. The
+    /// modification times of the existing members are remembered for use by
+    /// `update_path_with_name`.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use std::fs::OpenOptions;
+    /// use tar::Builder;
+    ///
+    /// let file = OpenOptions::new().read(true).write(true).open("foo.tar").unwrap();
+    /// let mut ar = Builder::append_to(file).unwrap();
+    /// ar.append_path("bar.txt").unwrap();
+    /// ar.finish().unwrap();
+    /// ```
+    pub fn append_to(mut obj: W) -> io::Result<Builder<W>> {
+        obj.seek(SeekFrom::Start(0))?;
+        let mut archived = HashMap::new();
+        let end = {
+            let mut archive = Archive::new(&mut obj);
+            let mut entries = archive.entries_with_seek()?;
+            for entry in entries.by_ref() {
+                let entry = entry?;
+                let mtime = entry.header().mtime()?;
+                archived.insert(archived_key(&entry.path_bytes()), mtime);
+            }
+            entries.end_position()
+        };
+        obj.seek(SeekFrom::Start(end))?;
+        let mut builder = Builder::new(obj);
+        builder.archived = archived;
+        builder.written = end;
+        Ok(builder)
     }
 }
 
@@ -512,6 +1361,10 @@ pub struct EntryWriter<'a> {
     // NOTE: Do not add any fields here which require Drop!
     // See the comment below in finish().
     obj: &'a mut dyn SeekWrite,
+    limiter: Option<&'a mut RateLimiter>,
+    // The builder's count of bytes written, and where this entry started
+    archive_written: &'a mut u64,
+    start: u64,
     header: &'a mut Header,
     written: u64,
 }
@@ -519,16 +1372,23 @@ pub struct EntryWriter<'a> {
 impl EntryWriter<'_> {
     fn start<'a>(
         obj: &'a mut dyn SeekWrite,
+        limiter: Option<&'a mut RateLimiter>,
+        archive_written: &'a mut u64,
         header: &'a mut Header,
         path: &Path,
+        normalization: Option<NormalizationForm>,
     ) -> io::Result<EntryWriter<'a>> {
-        prepare_header_path(obj.as_write(), header, path)?;
+        let start = obj.stream_position()?;
+        prepare_header_path(obj.as_write(), header, path, normalization)?;
 
         // Reserve space for header, will be overwritten once data is written.
         obj.write_all([0u8; BLOCK_SIZE as usize].as_ref())?;
 
         Ok(EntryWriter {
             obj,
+            limiter,
+            archive_written,
+            start,
             header,
             written: 0,
         })
@@ -565,7 +1425,8 @@ impl EntryWriter<'_> {
         self.obj.write_all(self.header.as_bytes())?;
 
         // Seek forward to restore the position.
-        self.obj.seek(io::SeekFrom::Current(written))?;
+        let end = self.obj.seek(io::SeekFrom::Current(written))?;
+        *self.archive_written += end - self.start;
 
         Ok(())
     }
@@ -574,6 +1435,9 @@ impl EntryWriter<'_> {
 impl Write for EntryWriter<'_> {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
         let len = self.obj.write(buf)?;
+        if let Some(limiter) = &mut self.limiter {
+            limiter.consume(len);
+        }
         self.written += len as u64;
         Ok(len)
     }
@@ -589,55 +1453,181 @@ impl Drop for EntryWriter<'_> {
     }
 }
 
-fn append(mut dst: &mut dyn Write, header: &Header, mut data: &mut dyn Read) -> io::Result<()> {
-    dst.write_all(header.as_bytes())?;
-    let len = io::copy(&mut data, &mut dst)?;
-    pad_zeroes(&mut dst, len)?;
+pub(crate) fn append(dst: &mut dyn Write, header: &Header, data: &mut dyn Read) -> io::Result<()> {
+    write_member(dst, header.as_bytes(), data)?;
     Ok(())
 }
 
-fn pad_zeroes(dst: &mut dyn Write, len: u64) -> io::Result<()> {
-    let buf = [0; BLOCK_SIZE as usize];
-    let remaining = BLOCK_SIZE - (len % BLOCK_SIZE);
-    if remaining < BLOCK_SIZE {
-        dst.write_all(&buf[..remaining as usize])?;
+/// A file in the temporary directory holding the contents of an entry until
+/// they have all been read, removed again once dropped.
+struct SpoolFile {
+    file: fs::File,
+    path: PathBuf,
+}
+
+impl SpoolFile {
+    fn create() -> io::Result<SpoolFile> {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+        let path = env::temp_dir().join(format!(
+            ".tar-spool-{}-{}",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        let file = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&path)?;
+        Ok(SpoolFile { file, path })
+    }
+}
+
+impl Drop for SpoolFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Writes `header`, everything read from `data` and the padding after it,
+/// returning the length of the data.
+///
+/// Members small enough to buffer whole are submitted in one vectored write,
+/// rather than a write for each part, where `dst` supports it.
+fn write_member(dst: &mut dyn Write, header: &[u8], data: &mut dyn Read) -> io::Result<u64> {
+    let mut buf = [0; 32 * 1024];
+    let mut len = 0;
+    while len < buf.len() {
+        match data.read(&mut buf[len..]) {
+            Ok(0) => {
+                let padding = [0; BLOCK_SIZE as usize];
+                let mut bufs = [
+                    IoSlice::new(header),
+                    IoSlice::new(&buf[..len]),
+                    IoSlice::new(&padding[..padding_len(len as u64)]),
+                ];
+                write_all_vectored(dst, &mut bufs)?;
+                return Ok(len as u64);
+            }
+            Ok(n) => len += n,
+            Err(ref e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    dst.write_all(header)?;
+    dst.write_all(&buf)?;
+    let len = len as u64 + io::copy(data, dst)?;
+    pad_zeroes(dst, len)?;
+    Ok(len)
+}
+
+/// Writes all of `bufs` to `dst`, like the unstable `Write::write_all_vectored`.
+fn write_all_vectored(dst: &mut dyn Write, mut bufs: &mut [IoSlice<'_>]) -> io::Result<()> {
+    IoSlice::advance_slices(&mut bufs, 0);
+    while !bufs.is_empty() {
+        match dst.write_vectored(bufs) {
+            Ok(0) => {
+                return Err(io::Error::new(
+                    ErrorKind::WriteZero,
+                    "failed to write whole buffer",
+                ))
+            }
+            Ok(n) => IoSlice::advance_slices(&mut bufs, n),
+            Err(ref e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
     }
     Ok(())
 }
 
+/// Normalizes a member name for comparing against the names of previously
+/// archived members, as directories may or may not carry a trailing slash.
+fn archived_key(name: &[u8]) -> Vec<u8> {
+    let mut key = name.to_vec();
+    while key.len() > 1 && key.last() == Some(&b'/') {
+        key.pop();
+    }
+    key
+}
+
+pub(crate) fn pad_zeroes(dst: &mut dyn Write, len: u64) -> io::Result<()> {
+    let buf = [0; BLOCK_SIZE as usize];
+    dst.write_all(&buf[..padding_len(len)])
+}
+
+/// Returns how many bytes of padding follow `len` bytes of data, to fill out
+/// the last block.
+fn padding_len(len: u64) -> usize {
+    ((BLOCK_SIZE - len % BLOCK_SIZE) % BLOCK_SIZE) as usize
+}
+
+/// Reads the metadata of `path`, or of what it points to if it is a symlink
+/// and `follow` is set.
+fn metadata(path: &Path, follow: bool) -> io::Result<fs::Metadata> {
+    let stat = if follow {
+        fs::metadata(path)
+    } else {
+        fs::symlink_metadata(path)
+    };
+    stat.map_err(|err| {
+        io::Error::new(
+            err.kind(),
+            format!("{} when getting metadata for {}", err, path.display()),
+        )
+    })
+}
+
+/// Returns whether the file with `stat` was modified, or had its status
+/// changed, at or after `since`, in time since the Unix epoch.
+fn changed_since(stat: &fs::Metadata, since: Duration) -> bool {
+    let since = UNIX_EPOCH + since;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+
+        let ctime = Duration::new(stat.ctime().max(0) as u64, stat.ctime_nsec() as u32);
+        if UNIX_EPOCH + ctime >= since {
+            return true;
+        }
+    }
+    stat.modified().map_or(true, |mtime| mtime >= since)
+}
+
+#[cfg(unix)]
+fn device_and_inode(stat: &fs::Metadata) -> (u64, u64) {
+    use std::os::unix::fs::MetadataExt;
+
+    (stat.dev(), stat.ino())
+}
+
+#[cfg(not(unix))]
+fn device_and_inode(_: &fs::Metadata) -> (u64, u64) {
+    (0, 0)
+}
+
 fn append_path_with_name(
     dst: &mut dyn Write,
     path: &Path,
     name: Option<&Path>,
     options: BuilderOptions,
+    links: &mut Links,
 ) -> io::Result<()> {
-    let stat = if options.follow {
-        fs::metadata(path).map_err(|err| {
-            io::Error::new(
-                err.kind(),
-                format!("{} when getting metadata for {}", err, path.display()),
-            )
-        })?
-    } else {
-        fs::symlink_metadata(path).map_err(|err| {
-            io::Error::new(
-                err.kind(),
-                format!("{} when getting metadata for {}", err, path.display()),
-            )
-        })?
-    };
+    let stat = metadata(path, options.follow)?;
     let ar_name = name.unwrap_or(path);
     if stat.is_file() {
-        append_file(dst, ar_name, &mut fs::File::open(path)?, options)
+        append_file(dst, ar_name, &mut fs::File::open(path)?, options, links)
     } else if stat.is_dir() {
-        append_fs(dst, ar_name, &stat, options.mode, None)
+        append_metadata(dst, XattrSource::Path(path), &stat, options)?;
+        append_fs(dst, ar_name, &stat, options, None)
     } else if stat.file_type().is_symlink() {
         let link_name = fs::read_link(path)?;
-        append_fs(dst, ar_name, &stat, options.mode, Some(&link_name))
+        append_metadata(dst, XattrSource::Link(path), &stat, options)?;
+        append_fs(dst, ar_name, &stat, options, Some(&link_name))
     } else {
         #[cfg(unix)]
         {
-            append_special(dst, path, &stat, options.mode)
+            append_metadata(dst, XattrSource::Path(path), &stat, options)?;
+            append_special(dst, path, &stat, options)
         }
         #[cfg(not(unix))]
         {
@@ -651,7 +1641,7 @@ fn append_special(
     dst: &mut dyn Write,
     path: &Path,
     stat: &fs::Metadata,
-    mode: HeaderMode,
+    options: BuilderOptions,
 ) -> io::Result<()> {
     use ::std::os::unix::fs::{FileTypeExt, MetadataExt};
 
@@ -674,8 +1664,8 @@ fn append_special(
     }
 
     let mut header = Header::new_gnu();
-    header.set_metadata_in_mode(stat, mode);
-    prepare_header_path(dst, &mut header, path)?;
+    set_header_metadata(&mut header, stat, options);
+    prepare_header_path(dst, &mut header, path, options.normalization)?;
 
     header.set_entry_type(entry_type);
     let dev_id = stat.rdev();
@@ -695,25 +1685,58 @@ fn append_file(
     path: &Path,
     file: &mut fs::File,
     options: BuilderOptions,
+    links: &mut Links,
 ) -> io::Result<()> {
     let stat = file.metadata()?;
+    if options.hardlinks {
+        if let Some(target) = hard_link_target(&stat, path, links) {
+            return append_hard_link(dst, path, &target, &stat, options);
+        }
+    }
+    if let Some(algorithm) = options.dedup.filter(|_| stat.len() > 0) {
+        let start = file.stream_position()?;
+        let mut hasher = Hasher::new(algorithm);
+        io::copy(&mut file.take(stat.len()), &mut hasher)?;
+        file.seek(io::SeekFrom::Start(start))?;
+        match links.contents.entry((hasher.len(), hasher.finish())) {
+            hash_map::Entry::Occupied(first) => {
+                return append_hard_link(dst, path, first.get(), &stat, options);
+            }
+            hash_map::Entry::Vacant(slot) => {
+                slot.insert(path.to_path_buf());
+            }
+        }
+    }
+    append_metadata(dst, XattrSource::File(file), &stat, options)?;
     let mut header = Header::new_gnu();
 
-    prepare_header_path(dst, &mut header, path)?;
-    header.set_metadata_in_mode(&stat, options.mode);
+    prepare_header_path(dst, &mut header, path, options.normalization)?;
+    set_header_metadata(&mut header, &stat, options);
     let sparse_entries = if options.sparse {
         prepare_header_sparse(file, &stat, &mut header)?
     } else {
         None
     };
     header.set_cksum();
+    if options.thread.is_none() && sparse_entries.is_none() {
+        write_member(dst, header.as_bytes(), file)?;
+        return Ok(());
+    }
     dst.write_all(header.as_bytes())?;
 
     if let Some(threads) = options.thread {
-        // Parallel processing for non-sparse files
-        if sparse_entries.is_none() {
+        if let Some(sparse_entries) = &sparse_entries {
+            // Fall back to sequential for sparse files
+            append_extended_sparse_headers(dst, sparse_entries)?;
+            for entry in &sparse_entries.entries {
+                file.seek(io::SeekFrom::Start(entry.offset))?;
+                io::copy(&mut file.take(entry.num_bytes), dst)?;
+            }
+            pad_zeroes(dst, sparse_entries.on_disk_size)?;
+        } else {
+            // Parallel processing for non-sparse files
             let file_size = stat.len();
-            let chunk_size = (file_size + threads as u64 - 1) / threads as u64;
+            let chunk_size = file_size.div_ceil(threads as u64);
             let chunks: Vec<_> = (0..threads)
                 .map(|i| {
                     let start = i as u64 * chunk_size;
@@ -749,34 +1772,59 @@ fn append_file(
             }
 
             pad_zeroes(dst, file_size)?;
-        } else {
-            // Fall back to sequential for sparse files
-            let sparse_entries = sparse_entries.as_ref().unwrap();
-            append_extended_sparse_headers(dst, sparse_entries)?;
-            for entry in &sparse_entries.entries {
-                file.seek(io::SeekFrom::Start(entry.offset))?;
-                io::copy(&mut file.take(entry.num_bytes), dst)?;
-            }
-            pad_zeroes(dst, sparse_entries.on_disk_size)?;
         }
-    } else {
-        // Original sequential implementation
-        if let Some(sparse_entries) = sparse_entries {
-            append_extended_sparse_headers(dst, &sparse_entries)?;
-            for entry in sparse_entries.entries {
-                file.seek(io::SeekFrom::Start(entry.offset))?;
-                io::copy(&mut file.take(entry.num_bytes), dst)?;
-            }
-            pad_zeroes(dst, sparse_entries.on_disk_size)?;
-        } else {
-            let len = io::copy(file, dst)?;
-            pad_zeroes(dst, len)?;
+    } else if let Some(sparse_entries) = sparse_entries {
+        append_extended_sparse_headers(dst, &sparse_entries)?;
+        for entry in sparse_entries.entries {
+            file.seek(io::SeekFrom::Start(entry.offset))?;
+            io::copy(&mut file.take(entry.num_bytes), dst)?;
         }
+        pad_zeroes(dst, sparse_entries.on_disk_size)?;
     }
 
     Ok(())
 }
 
+/// Returns the name a file with several hard links was already archived
+/// under, or records `path` as that name if this is the first of its links.
+#[cfg(unix)]
+fn hard_link_target(stat: &fs::Metadata, path: &Path, links: &mut Links) -> Option<PathBuf> {
+    use std::os::unix::fs::MetadataExt;
+
+    if stat.nlink() < 2 {
+        return None;
+    }
+    match links.inodes.entry((stat.dev(), stat.ino())) {
+        hash_map::Entry::Occupied(first) => Some(first.get().clone()),
+        hash_map::Entry::Vacant(slot) => {
+            slot.insert(path.to_path_buf());
+            None
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn hard_link_target(_: &fs::Metadata, _: &Path, _: &mut Links) -> Option<PathBuf> {
+    None
+}
+
+fn append_hard_link(
+    dst: &mut dyn Write,
+    path: &Path,
+    target: &Path,
+    stat: &fs::Metadata,
+    options: BuilderOptions,
+) -> io::Result<()> {
+    let mut header = Header::new_gnu();
+    prepare_header_path(dst, &mut header, path, options.normalization)?;
+    set_header_metadata(&mut header, stat, options);
+    header.set_entry_type(EntryType::Link);
+    header.set_size(0);
+    prepare_header_link(dst, &mut header, target, options.normalization)?;
+    header.set_cksum();
+    dst.write_all(header.as_bytes())
+}
+
 fn append_dir(
     dst: &mut dyn Write,
     path: &Path,
@@ -784,10 +1832,251 @@ fn append_dir(
     options: BuilderOptions,
 ) -> io::Result<()> {
     let stat = fs::metadata(src_path)?;
-    append_fs(dst, path, &stat, options.mode, None)
+    append_metadata(dst, XattrSource::Path(src_path), &stat, options)?;
+    append_fs(dst, path, &stat, options, None)
+}
+
+/// Writes the metadata of a file which its header can't hold, such as its
+/// extended attributes, to `dst` as a PAX extended header for the entry about
+/// to be appended, if `options` asks for any.
+fn append_metadata(
+    dst: &mut dyn Write,
+    src: XattrSource,
+    stat: &fs::Metadata,
+    options: BuilderOptions,
+) -> io::Result<()> {
+    let mut records = Vec::new();
+    xattr_records(&src, options, &mut records)?;
+    windows_attribute_records(stat, options, &mut records);
+    time_records(stat, options, &mut records);
+    owner_records(stat, options, &mut records);
+    crate::pax::append_pax_extensions(
+        dst,
+        records.iter().map(|(key, value)| (&key[..], &value[..])),
+    )
+}
+
+/// Adds `mtime`, `atime` and `ctime` records to `records` if `options` asks
+/// for them.
+fn time_records(
+    stat: &fs::Metadata,
+    options: BuilderOptions,
+    records: &mut Vec<(Vec<u8>, Vec<u8>)>,
+) {
+    if !options.extended_times || options.mode != HeaderMode::Complete {
+        return;
+    }
+    let mtime = FileTime::from_last_modification_time(stat);
+    if mtime.nanoseconds() != 0 {
+        records.push((
+            PAX_MTIME.as_bytes().to_vec(),
+            format_pax_time(mtime.unix_seconds(), mtime.nanoseconds()).into_bytes(),
+        ));
+    }
+    let atime = FileTime::from_last_access_time(stat);
+    records.push((
+        PAX_ATIME.as_bytes().to_vec(),
+        format_pax_time(atime.unix_seconds(), atime.nanoseconds()).into_bytes(),
+    ));
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+
+        records.push((
+            PAX_CTIME.as_bytes().to_vec(),
+            format_pax_time(stat.ctime(), stat.ctime_nsec() as u32).into_bytes(),
+        ));
+    }
+}
+
+/// Sets the metadata of `header` from `stat` as `options` asks, including
+/// the names of the file's owner and group which fit in the header.
+fn set_header_metadata(header: &mut Header, stat: &fs::Metadata, options: BuilderOptions) {
+    header.set_metadata_in_mode(stat, options.mode);
+    if !options.owner_names || options.mode != HeaderMode::Complete {
+        return;
+    }
+    let (username, groupname) = owner_names(stat);
+    if let Some(name) = username.filter(|name| fits_header(name)) {
+        let _ = header.set_username(&name);
+    }
+    if let Some(name) = groupname.filter(|name| fits_header(name)) {
+        let _ = header.set_groupname(&name);
+    }
 }
 
-fn prepare_header(size: u64, entry_type: u8) -> Header {
+/// Adds `uname` and `gname` records to `records` for the names of the file's
+/// owner and group which are too long for the header, if `options` asks for
+/// them.
+fn owner_records(
+    stat: &fs::Metadata,
+    options: BuilderOptions,
+    records: &mut Vec<(Vec<u8>, Vec<u8>)>,
+) {
+    if !options.owner_names || options.mode != HeaderMode::Complete {
+        return;
+    }
+    let (username, groupname) = owner_names(stat);
+    for (key, name) in [(PAX_UNAME, username), (PAX_GNAME, groupname)] {
+        if let Some(name) = name.filter(|name| !fits_header(name)) {
+            records.push((key.as_bytes().to_vec(), name.into_bytes()));
+        }
+    }
+}
+
+/// Returns whether `name` fits in the nul terminated owner name fields of a
+/// header.
+fn fits_header(name: &str) -> bool {
+    name.len() < 32
+}
+
+/// Looks up the names of the local user and group owning a file.
+#[cfg(unix)]
+fn owner_names(stat: &fs::Metadata) -> (Option<String>, Option<String>) {
+    use crate::entry::{group_name, user_name};
+    use std::os::unix::fs::MetadataExt;
+
+    (user_name(stat.uid()), group_name(stat.gid()))
+}
+
+#[cfg(not(unix))]
+fn owner_names(_stat: &fs::Metadata) -> (Option<String>, Option<String>) {
+    (None, None)
+}
+
+/// Where `xattr_records` reads extended attributes from.
+#[cfg_attr(not(all(unix, feature = "xattr")), allow(dead_code))]
+enum XattrSource<'a> {
+    File(&'a fs::File),
+    /// A path, following it if it's a symlink.
+    Path(&'a Path),
+    /// A symlink itself.
+    Link(&'a Path),
+}
+
+/// The extended attributes POSIX ACLs are kept in on Linux.
+#[cfg(all(unix, feature = "xattr"))]
+const ACL_XATTRS: [&str; 2] = ["system.posix_acl_access", "system.posix_acl_default"];
+
+/// Adds a `SCHILY.xattr.*` record to `records` for each of the extended
+/// attributes of `src` selected by `options`.
+#[cfg(all(unix, feature = "xattr"))]
+fn xattr_records(
+    src: &XattrSource,
+    options: BuilderOptions,
+    records: &mut Vec<(Vec<u8>, Vec<u8>)>,
+) -> io::Result<()> {
+    use std::os::unix::ffi::OsStrExt;
+    use xattr::FileExt;
+
+    if !(options.xattrs
+        || options.selinux
+        || options.mac_metadata
+        || options.acls && cfg!(feature = "acl"))
+    {
+        return Ok(());
+    }
+    let names = match src {
+        XattrSource::File(file) => file.list_xattr(),
+        XattrSource::Path(path) => xattr::list_deref(path),
+        XattrSource::Link(path) => xattr::list(path),
+    };
+    let names = match names {
+        Ok(names) => names,
+        // Filesystems without xattrs simply have none to archive
+        Err(err) if err.kind() == io::ErrorKind::Unsupported => return Ok(()),
+        Err(err) => return Err(xattr_error(err, src)),
+    };
+    for name in names {
+        let wanted = if ACL_XATTRS.iter().any(|acl| name == *acl) {
+            options.acls && cfg!(feature = "acl")
+        } else if name == crate::pax::SELINUX_XATTR {
+            options.selinux
+        } else if name
+            .as_bytes()
+            .starts_with(crate::pax::APPLE_XATTR_PREFIX.as_bytes())
+        {
+            options.mac_metadata
+        } else {
+            options.xattrs
+        };
+        if !wanted {
+            continue;
+        }
+        let value = match src {
+            XattrSource::File(file) => file.get_xattr(&name),
+            XattrSource::Path(path) => xattr::get_deref(path, &name),
+            XattrSource::Link(path) => xattr::get(path, &name),
+        };
+        // The attribute may have been removed since it was listed
+        if let Some(value) = value.map_err(|err| xattr_error(err, src))? {
+            let mut key = crate::pax::PAX_SCHILYXATTR.as_bytes().to_vec();
+            key.extend_from_slice(name.as_bytes());
+            records.push((key, value));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(all(unix, feature = "xattr"))]
+fn xattr_error(err: io::Error, src: &XattrSource) -> io::Error {
+    match src {
+        XattrSource::File(_) => io::Error::new(
+            err.kind(),
+            format!("{} when reading extended attributes", err),
+        ),
+        XattrSource::Path(path) | XattrSource::Link(path) => io::Error::new(
+            err.kind(),
+            format!(
+                "{} when reading extended attributes of {}",
+                err,
+                path.display()
+            ),
+        ),
+    }
+}
+
+#[cfg(not(all(unix, feature = "xattr")))]
+fn xattr_records(
+    _: &XattrSource,
+    _: BuilderOptions,
+    _: &mut Vec<(Vec<u8>, Vec<u8>)>,
+) -> io::Result<()> {
+    Ok(())
+}
+
+/// Adds a `SCHILY.fflags` record to `records` naming the readonly, hidden and
+/// system attributes a file has, if `options` asks for them.
+#[cfg(windows)]
+fn windows_attribute_records(
+    stat: &fs::Metadata,
+    options: BuilderOptions,
+    records: &mut Vec<(Vec<u8>, Vec<u8>)>,
+) {
+    use std::os::windows::fs::MetadataExt;
+
+    if !options.windows_attributes {
+        return;
+    }
+    let attributes = stat.file_attributes();
+    let flags = crate::pax::WINDOWS_FILE_FLAGS
+        .iter()
+        .filter(|(bit, _)| attributes & bit != 0)
+        .map(|(_, name)| *name)
+        .collect::<Vec<_>>();
+    if !flags.is_empty() {
+        records.push((
+            crate::pax::PAX_SCHILYFFLAGS.as_bytes().to_vec(),
+            flags.join(",").into_bytes(),
+        ));
+    }
+}
+
+#[cfg(not(windows))]
+fn windows_attribute_records(_: &fs::Metadata, _: BuilderOptions, _: &mut Vec<(Vec<u8>, Vec<u8>)>) {
+}
+
+pub(crate) fn prepare_header(size: u64, entry_type: u8) -> Header {
     let mut header = Header::new_gnu();
     let name = b"././@LongLink";
     header.as_gnu_mut().unwrap().name[..name.len()].clone_from_slice(&name[..]);
@@ -802,13 +2091,19 @@ fn prepare_header(size: u64, entry_type: u8) -> Header {
     header
 }
 
-fn prepare_header_path(dst: &mut dyn Write, header: &mut Header, path: &Path) -> io::Result<()> {
+fn prepare_header_path(
+    dst: &mut dyn Write,
+    header: &mut Header,
+    path: &Path,
+    normalization: Option<NormalizationForm>,
+) -> io::Result<()> {
+    let path = &*normalize_path(Cow::Borrowed(path), normalization);
     // Try to encode the path directly in the header, but if it ends up not
     // working (probably because it's too long) then try to use the GNU-specific
     // long name extension by emitting an entry which indicates that it's the
     // filename.
     if let Err(e) = header.set_path(path) {
-        let data = path2bytes(&path)?;
+        let data = path2bytes(path)?;
         let max = header.as_old().name.len();
         // Since `e` isn't specific enough to let us know the path is indeed too
         // long, verify it first before using the extension.
@@ -829,7 +2124,7 @@ fn prepare_header_path(dst: &mut dyn Write, header: &mut Header, path: &Path) ->
             Ok(s) => s,
             Err(e) => str::from_utf8(&data[..e.valid_up_to()]).unwrap(),
         };
-        header.set_truncated_path_for_gnu_header(&truncated)?;
+        header.set_truncated_path_for_gnu_header(truncated)?;
     }
     Ok(())
 }
@@ -838,10 +2133,12 @@ fn prepare_header_link(
     dst: &mut dyn Write,
     header: &mut Header,
     link_name: &Path,
+    normalization: Option<NormalizationForm>,
 ) -> io::Result<()> {
     // Same as previous function but for linkname
-    if let Err(e) = header.set_link_name(&link_name) {
-        let data = path2bytes(&link_name)?;
+    let link_name = &*normalize_path(Cow::Borrowed(link_name), normalization);
+    if let Err(e) = header.set_link_name(link_name) {
+        let data = path2bytes(link_name)?;
         if data.len() < header.as_old().linkname.len() {
             return Err(e);
         }
@@ -913,15 +2210,15 @@ fn append_fs(
     dst: &mut dyn Write,
     path: &Path,
     meta: &fs::Metadata,
-    mode: HeaderMode,
+    options: BuilderOptions,
     link_name: Option<&Path>,
 ) -> io::Result<()> {
     let mut header = Header::new_gnu();
 
-    prepare_header_path(dst, &mut header, path)?;
-    header.set_metadata_in_mode(meta, mode);
+    prepare_header_path(dst, &mut header, path, options.normalization)?;
+    set_header_metadata(&mut header, meta, options);
     if let Some(link_name) = link_name {
-        prepare_header_link(dst, &mut header, link_name)?;
+        prepare_header_link(dst, &mut header, link_name, options.normalization)?;
     }
     header.set_cksum();
     dst.write_all(header.as_bytes())
@@ -932,10 +2229,24 @@ fn append_dir_all(
     path: &Path,
     src_path: &Path,
     options: BuilderOptions,
+    links: &mut Links,
+    mut progress: Option<&mut (dyn ProgressObserver + Send + 'static)>,
 ) -> io::Result<()> {
     let mut stack = vec![(src_path.to_path_buf(), true, false)];
     while let Some((src, is_dir, is_symlink)) = stack.pop() {
-        let dest = path.join(src.strip_prefix(&src_path).unwrap());
+        let dest = path.join(src.strip_prefix(src_path).unwrap());
+        let observed = dest != Path::new("") && observing(progress.is_some());
+        let size = if observed {
+            archived_size(&src, options.follow)
+        } else {
+            0
+        };
+        #[cfg(feature = "tracing")]
+        let _span = observed.then(|| entry_span(&dest, size));
+        let start = Instant::now();
+        if observed {
+            entry_started(progress.as_deref_mut(), &dest, size);
+        }
         // In case of a symlink pointing to a directory, is_dir is false, but src.is_dir() will return true
         if is_dir || (is_symlink && options.follow && src.is_dir()) {
             for entry in fs::read_dir(&src)? {
@@ -949,22 +2260,82 @@ fn append_dir_all(
         } else if !options.follow && is_symlink {
             let stat = fs::symlink_metadata(&src)?;
             let link_name = fs::read_link(&src)?;
-            append_fs(dst, &dest, &stat, options.mode, Some(&link_name))?;
+            append_metadata(dst, XattrSource::Link(&src), &stat, options)?;
+            append_fs(dst, &dest, &stat, options, Some(&link_name))?;
         } else {
             #[cfg(unix)]
             {
                 let stat = fs::metadata(&src)?;
                 if !stat.is_file() {
-                    append_special(dst, &dest, &stat, options.mode)?;
+                    append_metadata(dst, XattrSource::Path(&src), &stat, options)?;
+                    append_special(dst, &dest, &stat, options)?;
+                    if observed {
+                        entry_finished(progress.as_deref_mut(), &dest, size, start);
+                    }
                     continue;
                 }
             }
-            append_file(dst, &dest, &mut fs::File::open(src)?, options)?;
+            append_file(dst, &dest, &mut fs::File::open(src)?, options, links)?;
+        }
+        if observed {
+            entry_finished(progress.as_deref_mut(), &dest, size, start);
         }
     }
     Ok(())
 }
 
+/// The size of the contents `append_path_with_name` archives for `path`, or
+/// 0 if it isn't a regular file.
+fn archived_size(path: &Path, follow: bool) -> u64 {
+    let stat = if follow {
+        fs::metadata(path)
+    } else {
+        fs::symlink_metadata(path)
+    };
+    stat.map_or(0, |stat| if stat.is_file() { stat.len() } else { 0 })
+}
+
+/// Whether entries need naming and sizing as they are added, because there
+/// is a progress observer or they are being traced.
+fn observing(has_observer: bool) -> bool {
+    #[cfg(feature = "tracing")]
+    if tracing::enabled!(tracing::Level::DEBUG) {
+        return true;
+    }
+    has_observer
+}
+
+#[cfg(feature = "tracing")]
+fn entry_span(path: &Path, size: u64) -> tracing::span::EnteredSpan {
+    tracing::debug_span!("append_entry", path = %path.display(), size).entered()
+}
+
+fn entry_started(
+    progress: Option<&mut (dyn ProgressObserver + Send + 'static)>,
+    path: &Path,
+    size: u64,
+) {
+    if let Some(progress) = progress {
+        progress.entry_started(path, size);
+    }
+}
+
+fn entry_finished(
+    progress: Option<&mut (dyn ProgressObserver + Send + 'static)>,
+    path: &Path,
+    size: u64,
+    start: Instant,
+) {
+    #[cfg(feature = "tracing")]
+    tracing::debug!(elapsed = ?start.elapsed(), "appended entry");
+    #[cfg(not(feature = "tracing"))]
+    let _ = start;
+    if let Some(progress) = progress {
+        progress.bytes_processed(size);
+        progress.entry_finished(path);
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 struct SparseEntries {
     entries: Vec<SparseEntry>,
@@ -986,10 +2357,10 @@ struct SparseEntry {
 /// Find sparse entries in a file. Returns:
 /// * `Ok(Some(_))` if the file is sparse.
 /// * `Ok(None)` if the file is not sparse, or if the file system does not
-///    support sparse files.
+///   support sparse files.
 /// * `Err(_)` if an error occurred. The lack of support for sparse files is not
-///    considered an error. It might return an error if the file is modified
-///    while reading.
+///   considered an error. It might return an error if the file is modified
+///   while reading.
 fn find_sparse_entries(
     file: &mut fs::File,
     stat: &fs::Metadata,
@@ -1075,15 +2446,11 @@ fn find_sparse_entries_seek(
             Ok(0) if off_s == 0 => (), // (a) The file starts with data.
             Ok(off) if off < off_s => {
                 // (b) Unlikely.
-                return Err(std::io::Error::new(
-                    io::ErrorKind::Other,
-                    "lseek(SEEK_DATA) went backwards",
-                ));
+                return Err(std::io::Error::other("lseek(SEEK_DATA) went backwards"));
             }
             Ok(off) if off == off_s => {
                 // (c) The data at the same offset as the hole.
-                return Err(std::io::Error::new(
-                    io::ErrorKind::Other,
+                return Err(std::io::Error::other(
                     "lseek(SEEK_DATA) did not advance. \
                      Did the file change while appending?",
                 ));
@@ -1106,15 +2473,11 @@ fn find_sparse_entries_seek(
             }
             Ok(off_e) if off_e < off_s => {
                 // (b) Unlikely.
-                return Err(std::io::Error::new(
-                    io::ErrorKind::Other,
-                    "lseek(SEEK_HOLE) went backwards",
-                ));
+                return Err(std::io::Error::other("lseek(SEEK_HOLE) went backwards"));
             }
             Ok(off_e) if off_e == off_s => {
                 // (c) The hole at the same offset as the data.
-                return Err(std::io::Error::new(
-                    io::ErrorKind::Other,
+                return Err(std::io::Error::other(
                     "lseek(SEEK_HOLE) did not advance. \
                      Did the file change while appending?",
                 ));
@@ -1131,8 +2494,7 @@ fn find_sparse_entries_seek(
             }
             Err(libc::ENXIO) => {
                 // (e) off_s was already beyond the end of the file.
-                return Err(std::io::Error::new(
-                    io::ErrorKind::Other,
+                return Err(std::io::Error::other(
                     "lseek(SEEK_HOLE) returned ENXIO. \
                      Did the file change while appending?",
                 ));
@@ -1142,8 +2504,7 @@ fn find_sparse_entries_seek(
     }
 
     if off_s as u64 > stat.size() {
-        return Err(std::io::Error::new(
-            io::ErrorKind::Other,
+        return Err(std::io::Error::other(
             "lseek(SEEK_DATA) went beyond the end of the file. \
              Did the file change while appending?",
         ));
@@ -1267,7 +2628,7 @@ mod tests {
                 "|    |####|####|    |",
                 &[
                     SparseEntry {
-                        offset: 1 * SPARSE_BLOCK_SIZE,
+                        offset: SPARSE_BLOCK_SIZE,
                         num_bytes: 2 * SPARSE_BLOCK_SIZE,
                     },
                     SparseEntry {
@@ -1364,7 +2725,7 @@ mod tests {
         // Check invariants of SparseEntries.
         let mut prev_end = None;
         for e in &reported.entries[..reported.entries.len()] {
-            if prev_end.map_or(false, |p| e.offset < p) {
+            if prev_end.is_some_and(|p| e.offset < p) {
                 return Err("Overlapping or unsorted entries");
             }
             prev_end = Some(e.offset + e.num_bytes);
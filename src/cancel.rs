@@ -0,0 +1,75 @@
+use std::error;
+use std::fmt;
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+
+/// A flag which cancels an unpack started with `Archive::unpack_with_cancel`
+/// when set, from any thread.
+///
+/// Clones share the same flag, so one can be kept to cancel with while
+/// another is handed to the unpack.
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+    /// Creates a token which hasn't been cancelled.
+    pub fn new() -> CancellationToken {
+        CancellationToken::default()
+    }
+
+    /// Cancels whatever this token, or any clone of it, was given to.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    /// Returns whether `cancel` has been called on this token or any clone of
+    /// it.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn check(&self) -> io::Result<()> {
+        if self.is_cancelled() {
+            Err(Cancelled.into())
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// The error an unpack returns when it is cancelled with a
+/// `CancellationToken`.
+///
+/// It is returned inside an `io::Error`, and can be told apart from other
+/// errors with `err.get_ref().is_some_and(|e| e.is::<Cancelled>())`.
+#[derive(Debug)]
+pub struct Cancelled;
+
+impl fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("unpacking was cancelled")
+    }
+}
+
+impl error::Error for Cancelled {}
+
+impl From<Cancelled> for io::Error {
+    fn from(err: Cancelled) -> io::Error {
+        io::Error::other(err)
+    }
+}
+
+/// Fails with `TimedOut` if `deadline` has passed.
+pub(crate) fn check_deadline(deadline: Option<Instant>) -> io::Result<()> {
+    match deadline {
+        Some(deadline) if Instant::now() >= deadline => Err(io::Error::new(
+            io::ErrorKind::TimedOut,
+            "timed out reading the archive",
+        )),
+        _ => Ok(()),
+    }
+}
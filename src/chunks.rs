@@ -0,0 +1,125 @@
+use std::collections::VecDeque;
+use std::io::{self, Read, Seek, SeekFrom};
+
+/// Somewhere a file can be fetched from a range at a time, such as a web
+/// server or an object store.
+pub(crate) trait FetchRange {
+    /// Fetches the `len` bytes starting at `first`, all of which are within
+    /// the file.
+    fn fetch(&mut self, first: u64, len: u64) -> io::Result<Vec<u8>>;
+}
+
+/// How much of the file is fetched at once unless `set_chunk_size` is called.
+pub(crate) const DEFAULT_CHUNK_SIZE: u64 = 1 << 20;
+
+/// A reader over a remote file which fetches it in fixed size chunks as it
+/// is read, keeping the most recently read chunks.
+pub(crate) struct ChunkedReader<F> {
+    source: F,
+    len: u64,
+    pos: u64,
+    chunk_size: u64,
+    cache_chunks: usize,
+    // Least recently read first
+    cache: VecDeque<(u64, Vec<u8>)>,
+}
+
+impl<F: FetchRange> ChunkedReader<F> {
+    pub fn new(source: F, len: u64) -> ChunkedReader<F> {
+        ChunkedReader {
+            source,
+            len,
+            pos: 0,
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            cache_chunks: 16,
+            cache: VecDeque::new(),
+        }
+    }
+
+    #[cfg(feature = "http")]
+    pub fn source_mut(&mut self) -> &mut F {
+        &mut self.source
+    }
+
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn set_chunk_size(&mut self, size: u64) {
+        self.chunk_size = size.max(1);
+        self.cache.clear();
+    }
+
+    pub fn set_cache_chunks(&mut self, chunks: usize) {
+        self.cache_chunks = chunks.max(1);
+        while self.cache.len() > self.cache_chunks {
+            self.cache.pop_front();
+        }
+    }
+
+    /// Caches the first chunk, when it has been fetched along with the
+    /// length of the file.
+    #[cfg(feature = "http")]
+    pub fn insert_first(&mut self, data: Vec<u8>) {
+        if data.len() as u64 == self.chunk_size.min(self.len) {
+            self.cache.push_back((0, data));
+        }
+    }
+
+    /// Returns the chunk with the given index, fetching it if it isn't
+    /// cached.
+    fn chunk(&mut self, index: u64) -> io::Result<&[u8]> {
+        match self.cache.iter().position(|(i, _)| *i == index) {
+            Some(cached) => {
+                let chunk = self.cache.remove(cached).unwrap();
+                self.cache.push_back(chunk);
+            }
+            None => {
+                let first = index * self.chunk_size;
+                let len = self.chunk_size.min(self.len - first);
+                let data = self.source.fetch(first, len)?;
+                if (data.len() as u64) < len {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "response ended before the range requested",
+                    ));
+                }
+                if self.cache.len() >= self.cache_chunks {
+                    self.cache.pop_front();
+                }
+                self.cache.push_back((index, data));
+            }
+        }
+        Ok(&self.cache.back().unwrap().1)
+    }
+}
+
+impl<F: FetchRange> Read for ChunkedReader<F> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() || self.pos >= self.len {
+            return Ok(0);
+        }
+        let offset = (self.pos % self.chunk_size) as usize;
+        let chunk = self.chunk(self.pos / self.chunk_size)?;
+        let n = (&chunk[offset..]).read(buf)?;
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl<F: FetchRange> Seek for ChunkedReader<F> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let pos = match pos {
+            SeekFrom::Start(n) => Some(n),
+            SeekFrom::End(n) => self.len.checked_add_signed(n),
+            SeekFrom::Current(n) => self.pos.checked_add_signed(n),
+        };
+        self.pos = pos.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )
+        })?;
+        Ok(self.pos)
+    }
+}
@@ -0,0 +1,220 @@
+// With no hash functions enabled `DigestAlgorithm` has no variants, leaving
+// everything here unreachable.
+#![cfg_attr(
+    not(any(feature = "sha2", feature = "blake3")),
+    allow(unused, unreachable_code)
+)]
+
+use std::fmt;
+use std::io::{self, Read, Write};
+
+/// A hash function used to compute digests of entry contents.
+///
+/// Each algorithm is available when the crate feature of the same name is
+/// enabled; the default `cli` feature enables `sha2`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum DigestAlgorithm {
+    /// SHA-256, as printed by `sha256sum`.
+    #[cfg(feature = "sha2")]
+    Sha256,
+    /// SHA-512, as printed by `sha512sum`.
+    #[cfg(feature = "sha2")]
+    Sha512,
+    /// BLAKE3 with its default 32-byte output, as printed by `b3sum`.
+    #[cfg(feature = "blake3")]
+    Blake3,
+}
+
+impl DigestAlgorithm {
+    /// Returns the conventional lowercase name of this algorithm, such as
+    /// `sha256`.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            #[cfg(feature = "sha2")]
+            DigestAlgorithm::Sha256 => "sha256",
+            #[cfg(feature = "sha2")]
+            DigestAlgorithm::Sha512 => "sha512",
+            #[cfg(feature = "blake3")]
+            DigestAlgorithm::Blake3 => "blake3",
+        }
+    }
+
+    /// Looks up an algorithm by the name `name` returns.
+    pub(crate) fn from_name(name: &str) -> Option<DigestAlgorithm> {
+        match name {
+            #[cfg(feature = "sha2")]
+            "sha256" => Some(DigestAlgorithm::Sha256),
+            #[cfg(feature = "sha2")]
+            "sha512" => Some(DigestAlgorithm::Sha512),
+            #[cfg(feature = "blake3")]
+            "blake3" => Some(DigestAlgorithm::Blake3),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for DigestAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// The digest of an entry's contents, computed while it was read or
+/// unpacked.
+///
+/// Its `Display` implementation prints the digest in lowercase hex.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct Digest {
+    algorithm: DigestAlgorithm,
+    bytes: Vec<u8>,
+}
+
+impl Digest {
+    /// Computes the digest of `data` directly.
+    pub fn of(algorithm: DigestAlgorithm, data: &[u8]) -> Digest {
+        let mut hasher = Hasher::new(algorithm);
+        hasher.update(data);
+        hasher.finish()
+    }
+
+    /// Computes the digest of everything read from `reader`.
+    pub(crate) fn of_reader(
+        algorithm: DigestAlgorithm,
+        reader: &mut dyn Read,
+    ) -> io::Result<Digest> {
+        let mut hasher = Hasher::new(algorithm);
+        io::copy(reader, &mut hasher)?;
+        Ok(hasher.finish())
+    }
+
+    /// Returns the algorithm this digest was computed with.
+    pub fn algorithm(&self) -> DigestAlgorithm {
+        self.algorithm
+    }
+
+    /// Returns the raw bytes of this digest.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// Returns this digest in lowercase hex.
+    pub fn to_hex(&self) -> String {
+        self.to_string()
+    }
+}
+
+impl fmt::Display for Digest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for byte in &self.bytes {
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Digest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Digest({}:{})", self.algorithm, self)
+    }
+}
+
+/// The running state of a digest, along with how many bytes it has seen.
+#[derive(Clone)]
+pub(crate) struct Hasher {
+    state: State,
+    len: u64,
+}
+
+#[derive(Clone)]
+enum State {
+    #[cfg(feature = "sha2")]
+    Sha256(sha2::Sha256),
+    #[cfg(feature = "sha2")]
+    Sha512(sha2::Sha512),
+    #[cfg(feature = "blake3")]
+    Blake3(Box<blake3::Hasher>),
+}
+
+impl Hasher {
+    pub(crate) fn new(algorithm: DigestAlgorithm) -> Hasher {
+        #[cfg(feature = "sha2")]
+        use sha2::Digest as _;
+
+        let state = match algorithm {
+            #[cfg(feature = "sha2")]
+            DigestAlgorithm::Sha256 => State::Sha256(sha2::Sha256::new()),
+            #[cfg(feature = "sha2")]
+            DigestAlgorithm::Sha512 => State::Sha512(sha2::Sha512::new()),
+            #[cfg(feature = "blake3")]
+            DigestAlgorithm::Blake3 => State::Blake3(Box::new(blake3::Hasher::new())),
+        };
+        Hasher { state, len: 0 }
+    }
+
+    /// Returns the number of bytes hashed so far.
+    pub(crate) fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub(crate) fn update(&mut self, data: &[u8]) {
+        #[cfg(feature = "sha2")]
+        use sha2::Digest as _;
+
+        self.len += data.len() as u64;
+        match self.state {
+            #[cfg(feature = "sha2")]
+            State::Sha256(ref mut h) => h.update(data),
+            #[cfg(feature = "sha2")]
+            State::Sha512(ref mut h) => h.update(data),
+            #[cfg(feature = "blake3")]
+            State::Blake3(ref mut h) => {
+                h.update(data);
+            }
+        }
+    }
+
+    pub(crate) fn finish(self) -> Digest {
+        #[cfg(feature = "sha2")]
+        use sha2::Digest as _;
+
+        let (algorithm, bytes) = match self.state {
+            #[cfg(feature = "sha2")]
+            State::Sha256(h) => (DigestAlgorithm::Sha256, h.finalize().to_vec()),
+            #[cfg(feature = "sha2")]
+            State::Sha512(h) => (DigestAlgorithm::Sha512, h.finalize().to_vec()),
+            #[cfg(feature = "blake3")]
+            State::Blake3(h) => (DigestAlgorithm::Blake3, h.finalize().as_bytes().to_vec()),
+        };
+        Digest { algorithm, bytes }
+    }
+}
+
+impl Write for Hasher {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// A writer which hashes everything written through it to `inner`.
+pub(crate) struct HashingWriter<'a, W> {
+    pub(crate) inner: W,
+    pub(crate) hasher: &'a mut Hasher,
+}
+
+impl<W: Write> Write for HashingWriter<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.hasher.update(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
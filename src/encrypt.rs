@@ -0,0 +1,168 @@
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::str::FromStr;
+
+/// The public key of someone an archive is encrypted to, in the `age1...`
+/// form printed by `age-keygen`.
+#[derive(Clone)]
+pub struct Recipient {
+    key: age::x25519::Recipient,
+}
+
+/// A secret key which can decrypt archives encrypted to its recipient, in the
+/// `AGE-SECRET-KEY-1...` form written by `age-keygen`.
+#[derive(Clone)]
+pub struct Identity {
+    key: age::x25519::Identity,
+}
+
+impl FromStr for Recipient {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> io::Result<Recipient> {
+        let key = s
+            .trim()
+            .parse()
+            .map_err(|e| invalid(&format!("invalid age recipient: {}", e)))?;
+        Ok(Recipient { key })
+    }
+}
+
+impl fmt::Display for Recipient {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.key.fmt(f)
+    }
+}
+
+impl fmt::Debug for Recipient {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Recipient({})", self.key)
+    }
+}
+
+impl Identity {
+    /// Generates a new random identity.
+    pub fn generate() -> Identity {
+        Identity {
+            key: age::x25519::Identity::generate(),
+        }
+    }
+
+    /// Parses every identity in an identity file as written by `age-keygen`,
+    /// skipping blank lines and `#` comments.
+    pub fn from_file(text: &str) -> io::Result<Vec<Identity>> {
+        text.lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(str::parse)
+            .collect()
+    }
+
+    /// Returns the recipient which archives must be encrypted to for this
+    /// identity to decrypt them.
+    pub fn to_public(&self) -> Recipient {
+        Recipient {
+            key: self.key.to_public(),
+        }
+    }
+}
+
+impl FromStr for Identity {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> io::Result<Identity> {
+        let key = s
+            .trim()
+            .parse()
+            .map_err(|e| invalid(&format!("invalid age identity: {}", e)))?;
+        Ok(Identity { key })
+    }
+}
+
+impl fmt::Debug for Identity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Identity({})", self.key.to_public())
+    }
+}
+
+/// A writer which encrypts everything written through it to a set of
+/// recipients with age.
+///
+/// The encrypted stream is only complete once `finish` has been called;
+/// dropping the writer instead leaves it truncated, which decrypting will
+/// report as an error.
+pub struct EncryptingWriter<W: Write> {
+    inner: age::stream::StreamWriter<W>,
+}
+
+impl<W: Write> EncryptingWriter<W> {
+    /// Creates a writer which encrypts to `recipients`, writing the age
+    /// header to `inner` straight away.
+    ///
+    /// Returns an error if `recipients` is empty.
+    pub fn new(inner: W, recipients: &[Recipient]) -> io::Result<EncryptingWriter<W>> {
+        let encryptor = age::Encryptor::with_recipients(
+            recipients.iter().map(|r| &r.key as &dyn age::Recipient),
+        )
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?;
+        Ok(EncryptingWriter {
+            inner: encryptor.wrap_output(inner)?,
+        })
+    }
+
+    /// Encrypts and writes the last chunk of the stream, returning the
+    /// underlying writer.
+    pub fn finish(self) -> io::Result<W> {
+        self.inner.finish()
+    }
+}
+
+impl<W: Write> Write for EncryptingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// A reader which decrypts a stream encrypted with age.
+///
+/// Reading fails rather than returning unauthenticated data if the stream
+/// has been tampered with or truncated.
+pub struct DecryptingReader<R: Read> {
+    inner: age::stream::StreamReader<R>,
+}
+
+impl<R: Read> DecryptingReader<R> {
+    /// Creates a reader decrypting `inner` with whichever of `identities` the
+    /// stream was encrypted to, reading its age header straight away.
+    ///
+    /// Returns an error if `inner` isn't encrypted with age, or isn't
+    /// encrypted to any of `identities`.
+    pub fn new(inner: R, identities: &[Identity]) -> io::Result<DecryptingReader<R>> {
+        let decryptor = age::Decryptor::new(inner).map_err(decrypt_error)?;
+        let inner = decryptor
+            .decrypt(identities.iter().map(|i| &i.key as &dyn age::Identity))
+            .map_err(decrypt_error)?;
+        Ok(DecryptingReader { inner })
+    }
+}
+
+impl<R: Read> Read for DecryptingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf)
+    }
+}
+
+fn decrypt_error(err: age::DecryptError) -> io::Error {
+    match err {
+        age::DecryptError::Io(err) => err,
+        err => io::Error::new(io::ErrorKind::InvalidData, err.to_string()),
+    }
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, msg)
+}
@@ -1,19 +1,39 @@
 use std::borrow::Cow;
+use std::cell::RefCell;
 use std::cmp;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::ffi::{OsStr, OsString};
+use std::fmt;
 use std::fs;
 use std::fs::OpenOptions;
 use std::io::prelude::*;
 use std::io::{self, Error, ErrorKind, SeekFrom};
 use std::marker;
 use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
 
 use filetime::{self, FileTime};
 
-use crate::archive::ArchiveInner;
+use crate::archive::{ArchiveInner, BufferPool, PathTransform, Progress};
+use crate::builder::{append, pad_zeroes, prepare_header};
+use crate::cancel::{check_deadline, CancellationToken};
+use crate::digest::{Digest, Hasher, HashingWriter};
 use crate::error::TarError;
-use crate::header::bytes2path;
+use crate::header::{bytes2path, GnuHeader};
+use crate::incremental::{parse_dumpdir, prune, DumpDirEntry};
+use crate::normalize::{normalize_path, NormalizationForm};
 use crate::other;
-use crate::{Archive, Header, PaxExtensions};
+use crate::owner_map::OwnerMap;
+use crate::pax::{
+    parse_pax_time, PAX_ATIME, PAX_CTIME, PAX_GNAME, PAX_LINKPATH, PAX_MTIME, PAX_PATH,
+    PAX_SCHILYXATTR, PAX_SIZE, PAX_UNAME,
+};
+use crate::read_at::{EntryReader, ReadAt};
+use crate::sniff::{content_type, SNIFF_LEN};
+use crate::{Archive, ArchiveFormat, EntryType, GnuExtSparseHeader, Header, PaxExtensions};
 
 /// A read-only view into an entry of an archive.
 ///
@@ -31,6 +51,9 @@ pub struct EntryFields<'a> {
     pub long_pathname: Option<Vec<u8>>,
     pub long_linkname: Option<Vec<u8>>,
     pub pax_extensions: Option<Vec<u8>>,
+    // How many bytes at the end of `pax_extensions` are the entry's own
+    // records, those before them coming from global headers
+    pub local_pax_len: usize,
     pub mask: u32,
     pub header: Header,
     pub size: u64,
@@ -38,10 +61,37 @@ pub struct EntryFields<'a> {
     pub file_pos: u64,
     pub data: Vec<EntryIo<'a>>,
     pub unpack_xattrs: bool,
+    pub unpack_selinux: bool,
+    pub unpack_mac_metadata: bool,
+    pub unpack_windows_attributes: bool,
+    pub unpack_devices: bool,
     pub preserve_permissions: bool,
     pub preserve_ownerships: bool,
+    pub numeric_owner: bool,
+    pub owner_map: Option<Arc<OwnerMap>>,
     pub preserve_mtime: bool,
-    pub overwrite: bool,
+    pub preserve_atime: bool,
+    pub overwrite: OverwritePolicy,
+    pub strip_components: usize,
+    pub path_transform: Option<PathTransform>,
+    pub allow_external_symlinks: bool,
+    pub copy_external_symlinks: bool,
+    pub case_folding: Option<(CaseCollisionPolicy, &'a RefCell<CaseFolding>)>,
+    pub windows_names: bool,
+    pub normalization: Option<NormalizationForm>,
+    pub non_utf8_paths: NonUtf8PathPolicy,
+    pub symlink_fallback: SymlinkFallback,
+    pub atomic_unpack: bool,
+    pub sync: SyncPolicy,
+    pub preallocate_threshold: Option<u64>,
+    pub unpack_sparse: bool,
+    pub incremental: bool,
+    pub digest: Option<Hasher>,
+    pub mapped: Option<&'a Arc<dyn AsRef<[u8]> + Send + Sync>>,
+    pub pool: &'a BufferPool,
+    pub cancel: Option<&'a CancellationToken>,
+    pub progress: Option<&'a Progress>,
+    pub deadline: Option<Instant>,
 }
 
 pub enum EntryIo<'a> {
@@ -61,6 +111,209 @@ pub enum Unpacked {
     __Nonexhaustive,
 }
 
+/// A way in which an entry differs from the file on disk it was compared
+/// against, as returned by `Entry::compare_to`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Difference {
+    /// There is no file at the path.
+    Missing,
+    /// The file is of a different type than the entry, for example a directory
+    /// where the entry is a regular file.
+    Type,
+    /// The file has a different size.
+    Size,
+    /// The file has the same size but different contents.
+    Contents,
+    /// The symlink points somewhere else.
+    LinkName,
+    /// The permission bits differ.
+    Mode,
+    /// The modification time differs.
+    Mtime,
+    /// The owning user differs.
+    Uid,
+    /// The owning group differs.
+    Gid,
+}
+
+impl fmt::Display for Difference {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Difference::Missing => "missing",
+            Difference::Type => "file type differs",
+            Difference::Size => "size differs",
+            Difference::Contents => "contents differ",
+            Difference::LinkName => "symlink target differs",
+            Difference::Mode => "mode differs",
+            Difference::Mtime => "mtime differs",
+            Difference::Uid => "uid differs",
+            Difference::Gid => "gid differs",
+        })
+    }
+}
+
+/// What to do when the destination of an entry being unpacked already exists,
+/// as set by `Archive::set_overwrite_policy`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OverwritePolicy {
+    /// Replace the existing file, like `tar --overwrite`.
+    Overwrite,
+    /// Fail with an error, like `tar --keep-old-files`.
+    Error,
+    /// Leave the existing file alone and skip the entry, like
+    /// `tar --skip-old-files`.
+    Skip,
+    /// Replace the existing file only if it is older than the entry, like
+    /// `tar --keep-newer-files`.
+    KeepNewer,
+}
+
+/// What to do when a symlink entry can't be unpacked as a symlink, as set by
+/// `Archive::set_symlink_fallback`.
+///
+/// This is mostly a concern on Windows, where creating symlinks requires a
+/// privilege or developer mode which users often don't have.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SymlinkFallback {
+    /// Fail with an error.
+    Error,
+    /// Leave the symlink out and carry on.
+    Skip,
+    /// Unpack a copy of what the symlink points to instead, which must already
+    /// have been unpacked.
+    CopyTarget,
+    /// Create an NTFS junction instead of a symlink to a directory, which
+    /// needs no privileges. Symlinks to anything else, and all symlinks on
+    /// other platforms, fail as with `Error`.
+    CreateJunctionForDirs,
+}
+
+/// How much of what is unpacked is flushed to stable storage before unpacking
+/// returns, as set by `Archive::set_sync_on_unpack`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SyncPolicy {
+    /// Leave flushing to the operating system.
+    None,
+    /// Sync the contents and metadata of each regular file once it is
+    /// written.
+    Data,
+    /// Sync regular files like `Data`, and also every directory entries were
+    /// unpacked into, so that their names are durable too.
+    DataAndDirs,
+}
+
+/// What to do with entries whose path or link name isn't valid UTF-8 on
+/// platforms whose paths must be Unicode, such as Windows, as set by
+/// `Archive::set_non_utf8_path_policy`.
+///
+/// On Unix any bytes can be a path, so this has no effect there.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NonUtf8PathPolicy {
+    /// Fail with an error from `Entry::path` and when unpacking.
+    Error,
+    /// Replace the invalid bytes with U+FFFD, the Unicode replacement
+    /// character, as `String::from_utf8_lossy` does.
+    LossyReplace,
+    /// Replace each invalid byte with `%` followed by its value in hex, such
+    /// as `%FF`, so that different names stay different.
+    PercentEncode,
+    /// Leave the entry out of `entries` and of what is unpacked.
+    Skip,
+}
+
+/// What to do when unpacking an entry whose path differs only in case from
+/// one unpacked before, as set by `Archive::set_case_collision_policy`.
+///
+/// On case-insensitive filesystems, such as the defaults on macOS and
+/// Windows, such entries would otherwise silently overwrite each other.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CaseCollisionPolicy {
+    /// Don't look for collisions, unpacking every entry at its own path.
+    Ignore,
+    /// Fail with an error.
+    Error,
+    /// Unpack the later entry with a `.~N~` suffix, as GNU `tar --backup`
+    /// names numbered backups. Entries beneath a renamed directory, and hard
+    /// links to a renamed file, follow it.
+    Rename,
+}
+
+/// The destinations unpacked to so far, keyed by their case-folded path, for
+/// `CaseCollisionPolicy`.
+#[derive(Default)]
+pub(crate) struct CaseFolding {
+    seen: HashMap<String, PathBuf>,
+    renamed: HashMap<PathBuf, PathBuf>,
+}
+
+impl CaseFolding {
+    /// Returns where to unpack an entry destined for `file_dst` within `dst`,
+    /// renaming any of its components which collide with an earlier entry's.
+    fn resolve(
+        &mut self,
+        dst: &Path,
+        file_dst: &Path,
+        policy: CaseCollisionPolicy,
+    ) -> io::Result<PathBuf> {
+        let rel = file_dst.strip_prefix(dst).unwrap_or(file_dst);
+        let mut orig = dst.to_path_buf();
+        let mut mapped = dst.to_path_buf();
+        for part in rel.components() {
+            orig.push(part);
+            if let Some(renamed) = self.renamed.get(&orig) {
+                mapped.clone_from(renamed);
+                continue;
+            }
+            mapped.push(part);
+            let existing = match self.seen.get(&fold_case(&mapped)) {
+                Some(existing) if *existing != mapped => existing,
+                Some(_) => continue,
+                None => {
+                    self.seen.insert(fold_case(&mapped), mapped.clone());
+                    continue;
+                }
+            };
+            if policy != CaseCollisionPolicy::Rename {
+                return Err(TarError::new(
+                    format!(
+                        "`{}` differs only in case from `{}`",
+                        mapped.display(),
+                        existing.display()
+                    ),
+                    Error::new(ErrorKind::AlreadyExists, "case collision"),
+                )
+                .into());
+            }
+            let name = mapped.file_name().unwrap_or_default().to_owned();
+            let renamed = (1..)
+                .map(|i| {
+                    let mut name = name.clone();
+                    name.push(format!(".~{}~", i));
+                    mapped.with_file_name(name)
+                })
+                .find(|path| !self.seen.contains_key(&fold_case(path)))
+                .unwrap();
+            self.seen.insert(fold_case(&renamed), renamed.clone());
+            self.renamed.insert(orig.clone(), renamed.clone());
+            mapped = renamed;
+        }
+        Ok(mapped)
+    }
+
+    /// Maps `path` to where it was actually unpacked, if it or one of its
+    /// parents was renamed.
+    fn renamed(&self, path: &Path) -> Option<PathBuf> {
+        path.ancestors().find_map(|ancestor| {
+            let renamed = self.renamed.get(ancestor)?;
+            Some(renamed.join(path.strip_prefix(ancestor).ok()?))
+        })
+    }
+}
+
+fn fold_case(path: &Path) -> String {
+    path.to_string_lossy().to_lowercase()
+}
+
 impl<'a, R: Read> Entry<'a, R> {
     /// Returns the path name for this entry.
     ///
@@ -74,7 +327,7 @@ impl<'a, R: Read> Entry<'a, R> {
     ///
     /// It is recommended to use this method instead of inspecting the `header`
     /// directly to ensure that various archive formats are handled correctly.
-    pub fn path(&self) -> io::Result<Cow<Path>> {
+    pub fn path(&self) -> io::Result<Cow<'_, Path>> {
         self.fields.path()
     }
 
@@ -84,7 +337,7 @@ impl<'a, R: Read> Entry<'a, R> {
     /// separators, and it will not always return the same value as
     /// `self.header().path_bytes()` as some archive formats have support for
     /// longer path names described in separate entries.
-    pub fn path_bytes(&self) -> Cow<[u8]> {
+    pub fn path_bytes(&self) -> Cow<'_, [u8]> {
         self.fields.path_bytes()
     }
 
@@ -101,7 +354,7 @@ impl<'a, R: Read> Entry<'a, R> {
     ///
     /// It is recommended to use this method instead of inspecting the `header`
     /// directly to ensure that various archive formats are handled correctly.
-    pub fn link_name(&self) -> io::Result<Option<Cow<Path>>> {
+    pub fn link_name(&self) -> io::Result<Option<Cow<'_, Path>>> {
         self.fields.link_name()
     }
 
@@ -110,7 +363,7 @@ impl<'a, R: Read> Entry<'a, R> {
     /// Note that this will not always return the same value as
     /// `self.header().link_name_bytes()` as some archive formats have support for
     /// longer path names described in separate entries.
-    pub fn link_name_bytes(&self) -> Option<Cow<[u8]>> {
+    pub fn link_name_bytes(&self) -> Option<Cow<'_, [u8]>> {
         self.fields.link_name_bytes()
     }
 
@@ -127,15 +380,160 @@ impl<'a, R: Read> Entry<'a, R> {
     /// contains extensions, or if there were no previous extensions describing
     /// it.
     ///
-    /// Note that global pax extensions are intended to be applied to all
-    /// archive entries.
+    /// The records of global pax extensions earlier in the archive are
+    /// included, before the entry's own, leaving out those its own override.
     ///
     /// Also note that this function will read the entire entry if the entry
     /// itself is a list of extensions.
-    pub fn pax_extensions(&mut self) -> io::Result<Option<PaxExtensions>> {
+    pub fn pax_extensions(&mut self) -> io::Result<Option<PaxExtensions<'_>>> {
         self.fields.pax_extensions()
     }
 
+    /// Returns the records of this entry's PAX extended header as keys and
+    /// values, in the order they were written, or none if it has no PAX
+    /// extended header.
+    ///
+    /// Unlike `pax_extensions`, malformed records and keys which aren't valid
+    /// UTF-8 are an error rather than items to check. Values are left as
+    /// bytes, as some hold binary data such as extended attributes; the
+    /// typed getters like `pax_mtime` parse the standard ones.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use std::fs::File;
+    /// use tar::Archive;
+    ///
+    /// let mut ar = Archive::new(File::open("foo.tar").unwrap());
+    /// for entry in ar.entries().unwrap() {
+    ///     let mut entry = entry.unwrap();
+    ///     for (key, value) in entry.pax_records().unwrap() {
+    ///         println!("{} = {}", key, String::from_utf8_lossy(value));
+    ///     }
+    /// }
+    /// ```
+    pub fn pax_records(&mut self) -> io::Result<impl Iterator<Item = (&str, &[u8])> + '_> {
+        let records = match self.fields.pax_extensions()? {
+            Some(exts) => exts
+                .map(|ext| {
+                    let ext = ext?;
+                    let key = ext
+                        .key()
+                        .map_err(|_| other("pax record key is not valid UTF-8"))?;
+                    Ok((key, ext.value_bytes()))
+                })
+                .collect::<io::Result<Vec<_>>>()?,
+            None => Vec::new(),
+        };
+        Ok(records.into_iter())
+    }
+
+    /// Returns the modification time in this entry's `mtime` PAX record, with
+    /// any fraction of a second, or `None` if it has none.
+    pub fn pax_mtime(&mut self) -> io::Result<Option<SystemTime>> {
+        let time = self.fields.pax_time(PAX_MTIME)?;
+        Ok(time.map(|(secs, nanos)| system_time(secs, nanos)))
+    }
+
+    /// Returns the size in this entry's `size` PAX record, or `None` if it
+    /// has none.
+    pub fn pax_size(&mut self) -> io::Result<Option<u64>> {
+        match self.pax_record(PAX_SIZE)? {
+            Some(value) => str::from_utf8(value)
+                .ok()
+                .and_then(|value| value.parse().ok())
+                .map(Some)
+                .ok_or_else(|| other("invalid size in pax extensions")),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the path in this entry's `path` PAX record, or `None` if it
+    /// has none.
+    ///
+    /// This is the record alone; `path` also looks at GNU long names and the
+    /// header.
+    pub fn pax_path(&mut self) -> io::Result<Option<PathBuf>> {
+        self.pax_path_record(PAX_PATH)
+    }
+
+    /// Returns the link target in this entry's `linkpath` PAX record, or
+    /// `None` if it has none.
+    pub fn pax_linkpath(&mut self) -> io::Result<Option<PathBuf>> {
+        self.pax_path_record(PAX_LINKPATH)
+    }
+
+    /// Returns the extended attributes in this entry's `SCHILY.xattr.*` PAX
+    /// records, as written by `Builder::xattrs` and GNU tar, as names and
+    /// values.
+    pub fn pax_xattrs(&mut self) -> io::Result<impl Iterator<Item = (&str, &[u8])> + '_> {
+        Ok(self
+            .pax_records()?
+            .filter_map(|(key, value)| Some((key.strip_prefix(PAX_SCHILYXATTR)?, value))))
+    }
+
+    fn pax_record(&mut self, key: &str) -> io::Result<Option<&[u8]>> {
+        Ok(self
+            .pax_records()?
+            .find(|(name, _)| *name == key)
+            .map(|(_, value)| value))
+    }
+
+    fn pax_path_record(&mut self, key: &str) -> io::Result<Option<PathBuf>> {
+        let value = match self.pax_record(key)? {
+            Some(value) => value.to_vec(),
+            None => return Ok(None),
+        };
+        let path = self.fields.decode_path(Cow::Owned(value))?;
+        Ok(Some(path.into_owned()))
+    }
+
+    /// Returns the name of the user owning this entry, from its `uname` PAX
+    /// record or, failing that, the header, or `None` if the archive doesn't
+    /// record one.
+    ///
+    /// Unlike the header's, the name in a PAX record may be longer than 31
+    /// bytes.
+    pub fn username(&self) -> Result<Option<&str>, std::str::Utf8Error> {
+        self.fields.username_bytes().map(str::from_utf8).transpose()
+    }
+
+    /// Returns the name of the group owning this entry, from its `gname` PAX
+    /// record or, failing that, the header, or `None` if the archive doesn't
+    /// record one.
+    pub fn groupname(&self) -> Result<Option<&str>, std::str::Utf8Error> {
+        self.fields
+            .groupname_bytes()
+            .map(str::from_utf8)
+            .transpose()
+    }
+
+    /// Returns the last modification time of this entry, from its `mtime` PAX
+    /// record with any fraction of a second or, failing that, the header.
+    pub fn mtime(&mut self) -> io::Result<SystemTime> {
+        let (secs, nanos) = self.fields.mtime()?;
+        Ok(system_time(secs, nanos))
+    }
+
+    /// Returns the last access time of this entry, from its `atime` PAX
+    /// record or, failing that, the field of a GNU header, or `None` if the
+    /// archive doesn't record one.
+    ///
+    /// Unlike the header's, the time in a PAX record may have a fraction of a
+    /// second.
+    pub fn atime(&mut self) -> io::Result<Option<SystemTime>> {
+        let time = self.fields.extra_time(PAX_ATIME, GnuHeader::atime)?;
+        Ok(time.map(|(secs, nanos)| system_time(secs, nanos)))
+    }
+
+    /// Returns the last status change time of this entry, from its `ctime`
+    /// PAX record or, failing that, the field of a GNU header, or `None` if
+    /// the archive doesn't record one.
+    pub fn ctime(&mut self) -> io::Result<Option<SystemTime>> {
+        let time = self.fields.extra_time(PAX_CTIME, GnuHeader::ctime)?;
+        Ok(time.map(|(secs, nanos)| system_time(secs, nanos)))
+    }
+
     /// Returns access to the header of this entry in the archive.
     ///
     /// This provides access to the metadata for this entry in the archive.
@@ -232,6 +630,249 @@ impl<'a, R: Read> Entry<'a, R> {
         self.fields.unpack_in(dst.as_ref())
     }
 
+    /// Returns the path that `unpack_in` would unpack this entry to within
+    /// `dst`, without touching the filesystem.
+    ///
+    /// This accounts for the archive's path transform and stripped
+    /// components. `None` is returned for entries that `unpack_in` skips
+    /// outright, such as those with a `..` in their path. The path returned
+    /// for an entry with an effectively empty name is `dst` itself. Note that
+    /// `unpack_in` may still refuse the entry if a symlink on disk would lead
+    /// it outside of `dst`.
+    pub fn destination_in<P: AsRef<Path>>(&self, dst: P) -> io::Result<Option<PathBuf>> {
+        self.fields.destination_in(dst.as_ref())
+    }
+
+    /// Gets the directory this entry unpacks to within `dst` ready for what
+    /// goes inside it, for callers which unpack directory entries only once
+    /// everything else is unpacked, as `Archive::unpack` does.
+    ///
+    /// A directory which already exists without write and search permission
+    /// for its owner, as left by unpacking an archive with a read-only
+    /// directory before, is given them so that its contents can be unpacked
+    /// again. Its mode from the archive is restored when the directory entry
+    /// itself is unpacked. This is only done on Unix.
+    ///
+    /// With `Archive::set_incremental`, whatever is in the directory but not
+    /// listed in a dump directory entry is removed, which reads the entry's
+    /// data.
+    ///
+    /// Directories reached through a symlink, or lying outside of `dst`, are
+    /// left alone. This does nothing for entries other than directories.
+    pub fn prepare_dir_in<P: AsRef<Path>>(&mut self, dst: P) -> io::Result<()> {
+        self.fields.prepare_dir_in(dst.as_ref())
+    }
+
+    /// Returns the dialect of tar this entry is written in.
+    ///
+    /// This is PAX if a PAX extended header came before the entry, GNU if a
+    /// GNU long name or link name did, and otherwise depends on the magic of
+    /// the entry's header.
+    pub fn format(&self) -> ArchiveFormat {
+        self.fields.format()
+    }
+
+    pub(crate) fn hard_link_source(&self) -> io::Result<Option<PathBuf>> {
+        self.fields.hard_link_source()
+    }
+
+    pub(crate) fn copy_raw_to(&mut self, dst: &mut dyn Write) -> io::Result<()> {
+        self.fields.copy_raw_to(dst)
+    }
+
+    /// Returns the digest of this entry's contents, if the archive was
+    /// configured to compute one with `Archive::set_digest_algorithm`.
+    ///
+    /// The digest is computed as the contents are read or unpacked, so `None`
+    /// is returned until they have been consumed in full. Only regular files
+    /// are hashed.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # #[cfg(feature = "sha2")] {
+    /// use std::fs::File;
+    /// use tar::{Archive, DigestAlgorithm};
+    ///
+    /// let mut ar = Archive::new(File::open("foo.tar").unwrap());
+    /// ar.set_digest_algorithm(Some(DigestAlgorithm::Sha256));
+    ///
+    /// for file in ar.entries().unwrap() {
+    ///     let mut file = file.unwrap();
+    ///     file.unpack_in("target").unwrap();
+    ///     if let Some(digest) = file.digest() {
+    ///         println!("{}  {}", digest, file.path().unwrap().display());
+    ///     }
+    /// }
+    /// # }
+    /// ```
+    pub fn digest(&self) -> Option<Digest> {
+        self.fields.digest()
+    }
+
+    /// Returns this entry's contents borrowed straight from the archive, if
+    /// it was read with `Archive::from_mmap` or `Archive::from_bytes`.
+    ///
+    /// This is independent of how much of the entry has been read. `None` is
+    /// returned for archives which aren't mapped into memory, and for sparse
+    /// files, whose contents aren't stored in one piece.
+    pub fn mapped_data(&self) -> Option<&'a [u8]> {
+        let fields = &self.fields;
+        if fields.header.entry_type().is_gnu_sparse() {
+            return None;
+        }
+        let start = usize::try_from(fields.file_pos).ok()?;
+        let end = start.checked_add(usize::try_from(fields.size).ok()?)?;
+        let mapped: &'a (dyn AsRef<[u8]> + Send + Sync) = &**fields.mapped?;
+        mapped.as_ref().get(start..end)
+    }
+
+    /// Returns a reader over this entry's contents which reads them from
+    /// `source` with positioned reads, independently of the archive.
+    ///
+    /// `source` must hold the same data the archive is reading, starting at
+    /// the archive's first header, such as the archive's file opened again
+    /// or shared in an `Arc`. The returned reader stays usable after the
+    /// archive moves on to later entries, and any number of them can be read
+    /// at once.
+    ///
+    /// On Windows, reading from a `File` moves its cursor, so when `source`
+    /// is the very file the archive is reading, as in the example below, the
+    /// readers mustn't be read until the archive has finished iterating. A
+    /// separately opened handle to the file has no such restriction.
+    ///
+    /// Returns an error for sparse files, whose contents aren't stored in one
+    /// piece.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use std::fs::File;
+    /// use std::io::Read;
+    /// use std::sync::Arc;
+    /// use std::thread;
+    /// use tar::Archive;
+    ///
+    /// let file = Arc::new(File::open("foo.tar").unwrap());
+    /// let mut ar = Archive::new(&*file);
+    /// let mut readers = Vec::new();
+    /// for entry in ar.entries_with_seek().unwrap() {
+    ///     readers.push(entry.unwrap().reader_at(file.clone()).unwrap());
+    /// }
+    /// let threads = readers.into_iter().map(|mut reader| {
+    ///     thread::spawn(move || {
+    ///         let mut contents = Vec::new();
+    ///         reader.read_to_end(&mut contents).unwrap();
+    ///         contents.len()
+    ///     })
+    /// });
+    /// let total: usize = threads.map(|t| t.join().unwrap()).sum();
+    /// ```
+    pub fn reader_at<S: ReadAt>(&self, source: S) -> io::Result<EntryReader<S>> {
+        if self.fields.header.entry_type().is_gnu_sparse() {
+            return Err(other(
+                "sparse files can't be read independently of the archive",
+            ));
+        }
+        Ok(EntryReader::new(
+            source,
+            self.fields.file_pos,
+            self.fields.size,
+        ))
+    }
+
+    /// Reads the contents of a GNU dump directory entry, listing what the
+    /// directory held when its incremental backup was made.
+    ///
+    /// This reads the entry's data, so should be called before the entry is
+    /// otherwise read. Returns an error if this isn't a dump directory entry.
+    pub fn dumpdir(&mut self) -> io::Result<Vec<DumpDirEntry>> {
+        if !self.header().entry_type().is_gnu_dumpdir() {
+            return Err(other("entry is not a GNU dump directory"));
+        }
+        let data = self.fields.read_all()?;
+        let entries = parse_dumpdir(&data, self.fields.normalization);
+        self.fields.pool.give(data);
+        entries
+    }
+
+    /// Detects the media type of this entry's contents from their first
+    /// bytes, such as `image/png` or `text/plain`, for auditing what an
+    /// archive holds without unpacking it.
+    ///
+    /// Types are recognized by the magic bytes of common image, audio,
+    /// archive, compression and executable formats, falling back to
+    /// `text/plain` for UTF-8 text and `application/octet-stream` for
+    /// anything else. Returns `None` for entries other than regular files and
+    /// for empty files.
+    ///
+    /// This reads up to the first 512 bytes of the entry, so should be called
+    /// before the entry is otherwise read.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use std::fs::File;
+    /// use tar::Archive;
+    ///
+    /// let mut ar = Archive::new(File::open("foo.tar").unwrap());
+    /// for entry in ar.entries().unwrap() {
+    ///     let mut entry = entry.unwrap();
+    ///     let kind = entry.content_type().unwrap().unwrap_or("-");
+    ///     println!("{}: {}", entry.path().unwrap().display(), kind);
+    /// }
+    /// ```
+    pub fn content_type(&mut self) -> io::Result<Option<&'static str>> {
+        if !self.header().entry_type().is_file() {
+            return Ok(None);
+        }
+        let mut data = Vec::with_capacity(SNIFF_LEN);
+        self.by_ref()
+            .take(SNIFF_LEN as u64)
+            .read_to_end(&mut data)?;
+        Ok(content_type(&data))
+    }
+
+    /// Reads the rest of this entry's contents into a `Bytes`.
+    ///
+    /// If the archive was read with `Archive::from_mmap` or
+    /// `Archive::from_bytes`, the returned `Bytes` shares the archive's
+    /// memory rather than copying out of it, and keeps it alive for as long
+    /// as it is around. Otherwise the contents are read as by `read_to_end`.
+    #[cfg(feature = "bytes")]
+    pub fn read_all_bytes(&mut self) -> io::Result<bytes::Bytes> {
+        self.fields.read_all_bytes()
+    }
+
+    /// Compares this entry against the file at `path`, returning every way in
+    /// which they differ, like `tar --diff`.
+    ///
+    /// The contents of regular files are compared by reading this entry's
+    /// data, so this should be called before the entry is otherwise read.
+    /// Ownership and permissions are only compared on Unix. An empty list
+    /// means the file matches the entry.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use std::fs::File;
+    /// use std::path::Path;
+    /// use tar::Archive;
+    ///
+    /// let mut ar = Archive::new(File::open("foo.tar").unwrap());
+    ///
+    /// for file in ar.entries().unwrap() {
+    ///     let mut file = file.unwrap();
+    ///     let path = Path::new("target").join(file.path().unwrap());
+    ///     for difference in file.compare_to(&path).unwrap() {
+    ///         println!("{}: {}", path.display(), difference);
+    ///     }
+    /// }
+    /// ```
+    pub fn compare_to<P: AsRef<Path>>(&mut self, path: P) -> io::Result<Vec<Difference>> {
+        self.fields.compare_to(path.as_ref())
+    }
+
     /// Set the mask of the permission bits when unpacking this entry.
     ///
     /// The mask will be inverted when applying against a mode, similar to how
@@ -257,6 +898,53 @@ impl<'a, R: Read> Entry<'a, R> {
         self.fields.unpack_xattrs = unpack_xattrs;
     }
 
+    /// Indicate whether the SELinux context is restored when unpacking this
+    /// entry.
+    ///
+    /// See `Archive::set_unpack_selinux` for more information.
+    pub fn set_unpack_selinux(&mut self, unpack_selinux: bool) {
+        self.fields.unpack_selinux = unpack_selinux;
+    }
+
+    /// Indicate whether macOS metadata is restored when unpacking this entry.
+    ///
+    /// See `Archive::set_unpack_mac_metadata` for more information.
+    pub fn set_unpack_mac_metadata(&mut self, unpack_mac_metadata: bool) {
+        self.fields.unpack_mac_metadata = unpack_mac_metadata;
+    }
+
+    /// Indicate whether Windows file attributes are restored when unpacking
+    /// this entry.
+    ///
+    /// See `Archive::set_unpack_windows_attributes` for more information.
+    pub fn set_unpack_windows_attributes(&mut self, unpack_windows_attributes: bool) {
+        self.fields.unpack_windows_attributes = unpack_windows_attributes;
+    }
+
+    /// Indicate whether this entry is created as a device or FIFO when it is
+    /// one.
+    ///
+    /// See `Archive::set_unpack_devices` for more information.
+    pub fn set_unpack_devices(&mut self, unpack_devices: bool) {
+        self.fields.unpack_devices = unpack_devices;
+    }
+
+    /// Indicate whether blocks of zeros in this entry's data are left as
+    /// holes when unpacking it.
+    ///
+    /// See `Archive::set_unpack_sparse` for more information.
+    pub fn set_unpack_sparse(&mut self, unpack_sparse: bool) {
+        self.fields.unpack_sparse = unpack_sparse;
+    }
+
+    /// Indicate whether `prepare_dir_in` removes what this dump directory
+    /// entry doesn't list from the directory on disk.
+    ///
+    /// See `Archive::set_incremental` for more information.
+    pub fn set_incremental(&mut self, incremental: bool) {
+        self.fields.incremental = incremental;
+    }
+
     /// Indicate whether extended permissions (like suid on Unix) are preserved
     /// when unpacking this entry.
     ///
@@ -273,6 +961,13 @@ impl<'a, R: Read> Entry<'a, R> {
     pub fn set_preserve_mtime(&mut self, preserve: bool) {
         self.fields.preserve_mtime = preserve;
     }
+
+    /// Indicate whether the access time is restored when unpacking this entry.
+    ///
+    /// See `Archive::set_preserve_atime` for more information.
+    pub fn set_preserve_atime(&mut self, preserve: bool) {
+        self.fields.preserve_atime = preserve;
+    }
 }
 
 impl<'a, R: Read> Read for Entry<'a, R> {
@@ -293,18 +988,174 @@ impl<'a> EntryFields<'a> {
         }
     }
 
+    fn digest(&self) -> Option<Digest> {
+        match &self.digest {
+            Some(hasher) if hasher.len() == self.size => Some(hasher.clone().finish()),
+            _ => None,
+        }
+    }
+
+    fn format(&self) -> ArchiveFormat {
+        let kind = self.header.entry_type();
+        if self.pax_extensions.is_some()
+            || kind.is_pax_local_extensions()
+            || kind.is_pax_global_extensions()
+        {
+            return ArchiveFormat::Pax;
+        }
+        let format = ArchiveFormat::of_header(&self.header);
+        let gnu_extension = self.long_pathname.is_some()
+            || self.long_linkname.is_some()
+            || kind.is_gnu_longname()
+            || kind.is_gnu_longlink();
+        match format {
+            ArchiveFormat::V7 | ArchiveFormat::Ustar if gnu_extension => ArchiveFormat::Gnu,
+            format => format,
+        }
+    }
+
+    /// Writes this entry to `dst` as it was archived, contents and all, along
+    /// with any GNU long name and PAX headers describing it.
+    pub fn copy_raw_to(&mut self, dst: &mut dyn Write) -> io::Result<()> {
+        // The offset and length of each block of stored contents, which
+        // sparse entries list in their headers
+        let mut blocks = Vec::new();
+        let mut stored = 0;
+        let mut total = 0;
+        for io in &self.data {
+            match io {
+                EntryIo::Pad(pad) => total += pad.limit(),
+                EntryIo::Data(data) => {
+                    blocks.push((total, data.limit()));
+                    stored += data.limit();
+                    total += data.limit();
+                }
+            }
+        }
+        // Reading shrinks or drops the blocks, so their sizes only add up to
+        // the entry's size while none of it has been read
+        if total != self.size {
+            return Err(other("cannot copy an entry which has been partly read"));
+        }
+
+        // Records from global headers stay in the global headers, which are
+        // the copier's to write
+        let pax = self
+            .pax_extensions
+            .as_ref()
+            .map(|pax| &pax[pax.len() - self.local_pax_len..])
+            .filter(|pax| !pax.is_empty());
+        if let Some(pax) = pax {
+            let mut header = Header::new_ustar();
+            header.set_size(pax.len() as u64);
+            header.set_entry_type(EntryType::XHeader);
+            header.set_cksum();
+            append(dst, &header, &mut &pax[..])?;
+        }
+        for (kind, name) in [(b'K', &self.long_linkname), (b'L', &self.long_pathname)] {
+            if let Some(name) = name {
+                let mut header = prepare_header(0, kind);
+                header.set_size(name.len() as u64);
+                header.set_cksum();
+                append(dst, &header, &mut &name[..])?;
+            }
+        }
+
+        dst.write_all(self.header.as_bytes())?;
+        let extended = self
+            .header
+            .as_gnu()
+            .filter(|gnu| self.header.entry_type().is_gnu_sparse() && gnu.is_extended());
+        if let Some(gnu) = extended {
+            let in_header = gnu.sparse.iter().filter(|block| !block.is_empty()).count();
+            let mut rest = blocks[in_header.min(blocks.len())..]
+                .chunks(GnuExtSparseHeader::new().sparse.len())
+                .peekable();
+            while let Some(chunk) = rest.next() {
+                let mut ext = GnuExtSparseHeader::new();
+                for (block, &(offset, len)) in ext.sparse.iter_mut().zip(chunk) {
+                    block.set_offset(offset);
+                    block.set_length(len);
+                }
+                ext.set_is_extended(rest.peek().is_some());
+                dst.write_all(ext.as_bytes())?;
+            }
+        }
+        for io in &mut self.data {
+            if let EntryIo::Data(data) = io {
+                io::copy(data, dst)?;
+            }
+        }
+        pad_zeroes(dst, stored)
+    }
+
+    /// Reads the rest of the entry into a buffer taken from the archive's
+    /// pool. Such a buffer may carry up to 1 MiB of capacity left over from
+    /// an earlier entry, so callers which hold on to it should give it back
+    /// to the pool or shrink it rather than keep it around.
     pub fn read_all(&mut self) -> io::Result<Vec<u8>> {
         // Preallocate some data but don't let ourselves get too crazy now.
         let cap = cmp::min(self.size, 128 * 1024);
-        let mut v = Vec::with_capacity(cap as usize);
+        let mut v = self.pool.take();
+        v.clear();
+        v.reserve(cap as usize);
         self.read_to_end(&mut v).map(|_| v)
     }
 
-    fn path(&self) -> io::Result<Cow<Path>> {
-        bytes2path(self.path_bytes())
+    #[cfg(feature = "bytes")]
+    fn read_all_bytes(&mut self) -> io::Result<bytes::Bytes> {
+        let sparse = self.header.entry_type().is_gnu_sparse();
+        if let (Some(mapped), [EntryIo::Data(data)], false) =
+            (self.mapped, &mut self.data[..], sparse)
+        {
+            // Whatever hasn't been read yet is the end of the contents
+            let end = self.file_pos.saturating_add(self.size);
+            let start = end.saturating_sub(data.limit());
+            let mapped_len = (**mapped).as_ref().len();
+            let range = usize::try_from(start).ok().zip(usize::try_from(end).ok());
+            if let Some((start, end)) = range.filter(|&(_, end)| end <= mapped_len) {
+                if let Some(hasher) = &mut self.digest {
+                    hasher.update(&(**mapped).as_ref()[start..end]);
+                }
+                data.set_limit(0);
+                let shared = bytes::Bytes::from_owner(SharedArchive(mapped.clone()));
+                return Ok(shared.slice(start..end));
+            }
+        }
+        let mut v = self.read_all()?;
+        v.shrink_to_fit();
+        Ok(bytes::Bytes::from(v))
+    }
+
+    fn path(&self) -> io::Result<Cow<'_, Path>> {
+        self.decode_path(self.path_bytes())
     }
 
-    fn path_bytes(&self) -> Cow<[u8]> {
+    /// Converts the bytes of a path or link name to a path this platform can
+    /// hold, as `non_utf8_paths` asks for those which aren't valid UTF-8.
+    fn decode_path<'b>(&self, bytes: Cow<'b, [u8]>) -> io::Result<Cow<'b, Path>> {
+        if representable(&bytes) {
+            return bytes2path(bytes);
+        }
+        match self.non_utf8_paths {
+            NonUtf8PathPolicy::LossyReplace => Ok(Cow::Owned(PathBuf::from(
+                String::from_utf8_lossy(&bytes).into_owned(),
+            ))),
+            NonUtf8PathPolicy::PercentEncode => {
+                let mut encoded = String::new();
+                for chunk in bytes.utf8_chunks() {
+                    encoded.push_str(chunk.valid());
+                    for byte in chunk.invalid() {
+                        encoded.push_str(&format!("%{:02X}", byte));
+                    }
+                }
+                Ok(Cow::Owned(PathBuf::from(encoded)))
+            }
+            NonUtf8PathPolicy::Error | NonUtf8PathPolicy::Skip => bytes2path(bytes),
+        }
+    }
+
+    pub fn path_bytes(&self) -> Cow<'_, [u8]> {
         match self.long_pathname {
             Some(ref bytes) => {
                 if let Some(&0) = bytes.last() {
@@ -333,14 +1184,14 @@ impl<'a> EntryFields<'a> {
         String::from_utf8_lossy(&self.path_bytes()).to_string()
     }
 
-    fn link_name(&self) -> io::Result<Option<Cow<Path>>> {
+    fn link_name(&self) -> io::Result<Option<Cow<'_, Path>>> {
         match self.link_name_bytes() {
-            Some(bytes) => bytes2path(bytes).map(Some),
+            Some(bytes) => self.decode_path(bytes).map(Some),
             None => Ok(None),
         }
     }
 
-    fn link_name_bytes(&self) -> Option<Cow<[u8]>> {
+    pub fn link_name_bytes(&self) -> Option<Cow<'_, [u8]>> {
         match self.long_linkname {
             Some(ref bytes) => {
                 if let Some(&0) = bytes.last() {
@@ -364,7 +1215,69 @@ impl<'a> EntryFields<'a> {
         }
     }
 
-    fn pax_extensions(&mut self) -> io::Result<Option<PaxExtensions>> {
+    /// Returns the modification time from the `mtime` PAX record, or else the
+    /// header, as seconds and nanoseconds.
+    fn mtime(&mut self) -> io::Result<(i64, u32)> {
+        match self.pax_time(PAX_MTIME)? {
+            Some(time) => Ok(time),
+            None => Ok((self.header.mtime()? as i64, 0)),
+        }
+    }
+
+    /// Returns the value of the PAX record `key`, if this entry has a PAX
+    /// extended header with one.
+    fn pax_value(&self, key: &str) -> Option<&[u8]> {
+        PaxExtensions::new(self.pax_extensions.as_deref()?)
+            .filter_map(Result::ok)
+            .find(|ext| ext.key_bytes() == key.as_bytes())
+            .map(|ext| ext.value_bytes())
+    }
+
+    fn username_bytes(&self) -> Option<&[u8]> {
+        self.pax_value(PAX_UNAME)
+            .or_else(|| self.header.username_bytes())
+    }
+
+    fn groupname_bytes(&self) -> Option<&[u8]> {
+        self.pax_value(PAX_GNAME)
+            .or_else(|| self.header.groupname_bytes())
+    }
+
+    /// Returns the time in the PAX record `key` as seconds and nanoseconds.
+    fn pax_time(&mut self, key: &str) -> io::Result<Option<(i64, u32)>> {
+        if let Some(exts) = self.pax_extensions()? {
+            for ext in exts {
+                let ext = ext?;
+                if ext.key_bytes() == key.as_bytes() {
+                    return match parse_pax_time(ext.value_bytes()) {
+                        Some(time) => Ok(Some(time)),
+                        None => Err(other(&format!("invalid {} in pax extensions", key))),
+                    };
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    /// Returns the time in the PAX record `key` as seconds and nanoseconds,
+    /// falling back to the field `gnu_time` reads from a GNU header, where
+    /// zero or nothing means unset.
+    fn extra_time(
+        &mut self,
+        key: &str,
+        gnu_time: fn(&GnuHeader) -> io::Result<u64>,
+    ) -> io::Result<Option<(i64, u32)>> {
+        if let Some(time) = self.pax_time(key)? {
+            return Ok(Some(time));
+        }
+        // GNU tar leaves these fields empty, which doesn't parse as a number
+        match self.header.as_gnu().map(gnu_time) {
+            Some(Ok(secs)) if secs != 0 => Ok(Some((secs as i64, 0))),
+            _ => Ok(None),
+        }
+    }
+
+    fn pax_extensions(&mut self) -> io::Result<Option<PaxExtensions<'_>>> {
         if self.pax_extensions.is_none() {
             if !self.header.entry_type().is_pax_global_extensions()
                 && !self.header.entry_type().is_pax_local_extensions()
@@ -378,7 +1291,43 @@ impl<'a> EntryFields<'a> {
         )))
     }
 
-    fn unpack_in(&mut self, dst: &Path) -> io::Result<bool> {
+    fn prepare_dir_in(&mut self, dst: &Path) -> io::Result<()> {
+        if !self.header.entry_type().is_dir() {
+            return Ok(());
+        }
+        let path = match self.destination_in(dst)? {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        // Only a directory already inside `dst` is changed, not one a symlink
+        // on disk leads to
+        let dir = match self.dir_inside_dst(dst, &path)? {
+            Some(dir) => dir,
+            None => return Ok(()),
+        };
+        if self.incremental && self.header.entry_type().is_gnu_dumpdir() {
+            let data = self.read_all()?;
+            let listing = parse_dumpdir(&data, self.normalization);
+            self.pool.give(data);
+            prune(&dir, &listing?)
+                .map_err(|e| TarError::new(format!("failed to prune `{}`", path.display()), e))?;
+        }
+        #[cfg(unix)]
+        {
+            use std::os::unix::prelude::*;
+
+            let mode = fs::metadata(&dir)?.permissions().mode();
+            if mode & 0o300 != 0o300 {
+                let perm = fs::Permissions::from_mode(mode | 0o300);
+                fs::set_permissions(&dir, perm).map_err(|e| {
+                    TarError::new(format!("failed to make `{}` writable", path.display()), e)
+                })?;
+            }
+        }
+        Ok(())
+    }
+
+    fn destination_in(&self, dst: &Path) -> io::Result<Option<PathBuf>> {
         // Notes regarding bsdtar 2.8.3 / libarchive 2.8.3:
         // * Leading '/'s are trimmed. For example, `///test` is treated as
         //   `test`.
@@ -393,52 +1342,237 @@ impl<'a> EntryFields<'a> {
         // library, but we specially handle a few cases here as well.
 
         let mut file_dst = dst.to_path_buf();
-        {
-            let path = self.path().map_err(|e| {
-                TarError::new(
-                    format!("invalid path in entry header: {}", self.path_lossy()),
-                    e,
-                )
-            })?;
-            for part in path.components() {
-                match part {
-                    // Leading '/' characters, root paths, and '.'
-                    // components are just ignored and treated as "empty
-                    // components"
-                    Component::Prefix(..) | Component::RootDir | Component::CurDir => continue,
-
-                    // If any part of the filename is '..', then skip over
-                    // unpacking the file to prevent directory traversal
-                    // security issues.  See, e.g.: CVE-2001-1267,
-                    // CVE-2002-0399, CVE-2005-1918, CVE-2007-4131
-                    Component::ParentDir => return Ok(false),
-
-                    Component::Normal(part) => file_dst.push(part),
+        let path = self.path().map_err(|e| {
+            TarError::new(
+                format!("invalid path in entry header: {}", self.path_lossy()),
+                e,
+            )
+        })?;
+        let path = match &self.path_transform {
+            Some(transform) => match transform(&path) {
+                Some(path) => Cow::Owned(path),
+                None => return Ok(None),
+            },
+            None => path,
+        };
+        let path = normalize_path(path, self.normalization);
+        let mut to_strip = self.strip_components;
+        for part in path.components() {
+            match part {
+                // Leading '/' characters, root paths, and '.'
+                // components are just ignored and treated as "empty
+                // components"
+                Component::Prefix(..) | Component::RootDir | Component::CurDir => continue,
+
+                // If any part of the filename is '..', then skip over
+                // unpacking the file to prevent directory traversal
+                // security issues.  See, e.g.: CVE-2001-1267,
+                // CVE-2002-0399, CVE-2005-1918, CVE-2007-4131
+                Component::ParentDir => return Ok(None),
+
+                Component::Normal(_) if to_strip > 0 => to_strip -= 1,
+                Component::Normal(part) if self.windows_names => file_dst.push(windows_name(part)),
+                Component::Normal(part) => file_dst.push(part),
+            }
+        }
+        Ok(Some(file_dst))
+    }
+
+    fn unpack_in(&mut self, dst: &Path) -> io::Result<bool> {
+        let (canon_target, file_dst) = match self.prepare_unpack_in(dst)? {
+            Ok(paths) => paths,
+            Err(unpacked) => return Ok(unpacked),
+        };
+        let parent = file_dst.parent().unwrap();
+
+        if self.header.entry_type().is_symlink() && !self.allow_external_symlinks {
+            if let Some(src) = self.external_symlink_target(&canon_target, parent)? {
+                if !self.copy_external_symlinks {
+                    return Err(TarError::new(
+                        format!(
+                            "symlink `{}` points outside of destination: `{}`",
+                            file_dst.display(),
+                            src.display()
+                        ),
+                        Error::new(ErrorKind::InvalidData, "external symlink"),
+                    )
+                    .into());
                 }
+                self.copy_symlink_target(&parent.join(&src), &file_dst)
+                    .map_err(|e| {
+                        TarError::new(
+                            format!(
+                                "failed to copy `{}` to `{}`",
+                                src.display(),
+                                file_dst.display()
+                            ),
+                            e,
+                        )
+                    })?;
+                return Ok(true);
             }
         }
 
+        self.unpack(Some(&canon_target), &file_dst)
+            .map_err(|e| TarError::new(format!("failed to unpack `{}`", file_dst.display()), e))?;
+
+        Ok(true)
+    }
+
+    /// Works out where this entry is unpacked to in `dst` and creates its
+    /// parent directories, returning the canonical parent directory and the
+    /// destination, or whether the entry counts as unpacked if there is
+    /// nothing to write.
+    fn prepare_unpack_in(&mut self, dst: &Path) -> io::Result<Result<(PathBuf, PathBuf), bool>> {
+        let file_dst = match self.destination_in(dst)? {
+            Some(file_dst) => file_dst,
+            None => return Ok(Err(false)),
+        };
+
         // Skip cases where only slashes or '.' parts were seen, because
         // this is effectively an empty filename.
         if *dst == *file_dst {
-            return Ok(true);
+            return Ok(Err(true));
         }
 
+        // Windows refuses paths of MAX_PATH characters or more unless they
+        // are in the verbatim `\\?\` form.
+        #[cfg(windows)]
+        let long_dst;
+        #[cfg(windows)]
+        let (dst, file_dst) = if file_dst.as_os_str().len() >= 260 {
+            long_dst = verbatim_path(dst)?;
+            (&*long_dst, verbatim_path(&file_dst)?)
+        } else {
+            (dst, file_dst)
+        };
+
+        let file_dst = match self.case_folding {
+            Some((policy, folding)) => folding.borrow_mut().resolve(dst, &file_dst, policy)?,
+            None => file_dst,
+        };
+
         // Skip entries without a parent (i.e. outside of FS root)
         let parent = match file_dst.parent() {
             Some(p) => p,
-            None => return Ok(false),
+            None => return Ok(Err(false)),
         };
 
-        self.ensure_dir_created(&dst, parent)
+        self.ensure_dir_created(dst, parent)
             .map_err(|e| TarError::new(format!("failed to create `{}`", parent.display()), e))?;
 
-        let canon_target = self.validate_inside_dst(&dst, parent)?;
+        let canon_target = self.validate_inside_dst(dst, parent)?;
 
-        self.unpack(Some(&canon_target), &file_dst)
-            .map_err(|e| TarError::new(format!("failed to unpack `{}`", file_dst.display()), e))?;
+        if !self.header.entry_type().is_dir() && !self.should_replace(&file_dst)? {
+            return Ok(Err(false));
+        }
 
-        Ok(true)
+        Ok(Ok((canon_target, file_dst)))
+    }
+
+    /// Returns the target of this symlink entry if it is absolute or resolves
+    /// outside of `root`, the canonical unpack destination, when created in
+    /// `parent`.
+    ///
+    /// `..` components are resolved against any symlinks already on disk, as
+    /// the kernel would, so chains of links can't escape either.
+    fn external_symlink_target(&self, root: &Path, parent: &Path) -> io::Result<Option<PathBuf>> {
+        let src = match self.link_name()? {
+            Some(src) => normalize_path(src, self.normalization).into_owned(),
+            None => return Ok(None),
+        };
+        let parent = parent.canonicalize()?;
+        let mut resolved = match parent.strip_prefix(root) {
+            Ok(rel) => rel.components().map(|c| c.as_os_str().to_owned()).collect(),
+            Err(_) => return Ok(Some(src)),
+        };
+        if resolves_inside(root, &mut resolved, &src) {
+            Ok(None)
+        } else {
+            Ok(Some(src))
+        }
+    }
+
+    /// Unpacks this symlink entry as a copy of the file at `src` instead.
+    fn copy_symlink_target(&self, src: &Path, dst: &Path) -> io::Result<()> {
+        if !fs::metadata(src)?.is_file() {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "only symlinks to regular files can be copied",
+            ));
+        }
+        // Never copy through whatever is already at `dst`, which could itself
+        // be a symlink leading elsewhere.
+        match fs::symlink_metadata(dst) {
+            Ok(_) if self.overwrite == OverwritePolicy::Error => {
+                return Err(Error::new(
+                    ErrorKind::AlreadyExists,
+                    format!("{} already exists", dst.display()),
+                ));
+            }
+            Ok(existing) if !existing.is_dir() => fs::remove_file(dst)?,
+            _ => {}
+        }
+        fs::copy(src, dst)?;
+        Ok(())
+    }
+
+    /// Unpacks this symlink entry as set by `Archive::set_symlink_fallback`,
+    /// after creating the symlink itself failed with `err`.
+    fn unpack_symlink_fallback(&self, src: &Path, dst: &Path, err: &Error) -> io::Result<()> {
+        let target = match dst.parent() {
+            Some(parent) => parent.join(src),
+            None => src.to_path_buf(),
+        };
+        let is_dir = fs::metadata(&target).is_ok_and(|m| m.is_dir());
+        let unsupported = || {
+            Error::new(
+                err.kind(),
+                format!(
+                    "{} when symlinking {} to {}",
+                    err,
+                    src.display(),
+                    dst.display()
+                ),
+            )
+        };
+        match self.symlink_fallback {
+            SymlinkFallback::Error => Err(unsupported()),
+            SymlinkFallback::Skip => Ok(()),
+            SymlinkFallback::CopyTarget if is_dir => {
+                let parent = dst.parent().unwrap_or(dst).canonicalize()?;
+                if parent.starts_with(target.canonicalize()?) {
+                    return Err(other(&format!(
+                        "cannot copy {} into itself at {}",
+                        target.display(),
+                        dst.display()
+                    )));
+                }
+                copy_dir(&target, dst)
+            }
+            SymlinkFallback::CopyTarget => self.copy_symlink_target(&target, dst),
+            SymlinkFallback::CreateJunctionForDirs if is_dir => junction(&target, dst),
+            SymlinkFallback::CreateJunctionForDirs => Err(unsupported()),
+        }
+    }
+
+    /// Whether the overwrite policy allows unpacking over whatever is at
+    /// `dst`. `OverwritePolicy::Error` is left to `unpack`, which refuses to
+    /// replace existing files unless overwriting.
+    fn should_replace(&self, dst: &Path) -> io::Result<bool> {
+        let existing = match fs::symlink_metadata(dst) {
+            Ok(existing) => existing,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(true),
+            Err(e) => return Err(e),
+        };
+        Ok(match self.overwrite {
+            OverwritePolicy::Overwrite | OverwritePolicy::Error => true,
+            OverwritePolicy::Skip => false,
+            OverwritePolicy::KeepNewer => {
+                let mtime = FileTime::from_last_modification_time(&existing).unix_seconds();
+                i64::try_from(self.header.mtime()?).is_ok_and(|entry| mtime < entry)
+            }
+        })
     }
 
     /// Unpack as destination directory `dst`.
@@ -458,6 +1592,25 @@ impl<'a> EntryFields<'a> {
         })
     }
 
+    /// Restores the times of the directory unpacked at `dst`.
+    ///
+    /// Writing into a directory changes its modification time, so this is
+    /// only of use once everything inside it has been unpacked, which is why
+    /// `Archive::unpack` leaves directories until the end.
+    fn set_dir_times(&mut self, dst: &Path) -> io::Result<()> {
+        let mtime = self.mtime_to_restore()?;
+        let atime = self.atime_to_restore()?.or(mtime);
+        let set = match (atime, mtime) {
+            (Some(atime), Some(mtime)) => filetime::set_file_times(dst, atime, mtime),
+            (Some(atime), None) => filetime::set_file_atime(dst, atime),
+            (None, Some(mtime)) => filetime::set_file_mtime(dst, mtime),
+            (None, None) => return Ok(()),
+        };
+        set.map_err(|e| {
+            TarError::new(format!("failed to set mtime for `{}`", dst.display()), e).into()
+        })
+    }
+
     /// Returns access to the header of this entry in the archive.
     fn unpack(&mut self, target_base: Option<&Path>, dst: &Path) -> io::Result<Unpacked> {
         fn set_perms_ownerships(
@@ -466,11 +1619,11 @@ impl<'a> EntryFields<'a> {
             header: &Header,
             mask: u32,
             perms: bool,
-            ownerships: bool,
+            owner: Option<(u64, u64)>,
         ) -> io::Result<()> {
             // ownerships need to be set first to avoid stripping SUID bits in the permissions ...
-            if ownerships {
-                set_ownerships(dst, &f, header.uid()?, header.gid()?)?;
+            if let Some((uid, gid)) = owner {
+                set_ownerships(dst, &f, uid, gid)?;
             }
             // ... then set permissions, SUID bits set here is kept
             if let Ok(mode) = header.mode() {
@@ -480,31 +1633,25 @@ impl<'a> EntryFields<'a> {
             Ok(())
         }
 
-        fn get_mtime(header: &Header) -> Option<FileTime> {
-            header.mtime().ok().map(|mtime| {
-                // For some more information on this see the comments in
-                // `Header::fill_platform_from`, but the general idea is that
-                // we're trying to avoid 0-mtime files coming out of archives
-                // since some tools don't ingest them well. Perhaps one day
-                // when Cargo stops working with 0-mtime archives we can remove
-                // this.
-                let mtime = if mtime == 0 { 1 } else { mtime };
-                FileTime::from_unix_time(mtime as i64, 0)
-            })
-        }
-
         let kind = self.header.entry_type();
 
         if kind.is_dir() {
             self.unpack_dir(dst)?;
+            if self.unpack_xattrs || self.unpack_selinux || self.unpack_mac_metadata {
+                set_xattrs(self, dst)?;
+            }
+            self.set_dir_times(dst)?;
             set_perms_ownerships(
                 dst,
                 None,
                 &self.header,
                 self.mask,
                 self.preserve_permissions,
-                self.preserve_ownerships,
+                self.owner()?,
             )?;
+            if self.unpack_windows_attributes {
+                set_windows_attributes(self, dst)?;
+            }
             return Ok(Unpacked::__Nonexhaustive);
         } else if kind.is_hard_link() || kind.is_symlink() {
             let src = match self.link_name()? {
@@ -537,57 +1684,84 @@ impl<'a> EntryFields<'a> {
                     // use canonicalization to ensure this guarantee. For hard
                     // links though they're canonicalized to their existing path
                     // so we need to validate at this time.
-                    Some(ref p) => {
-                        let link_src = p.join(src);
+                    Some(p) => {
+                        let mut link_src = p.join(self.link_target(&src));
+                        if let Some((_, folding)) = self.case_folding {
+                            if let Some(renamed) = folding.borrow().renamed(&link_src) {
+                                link_src = renamed;
+                            }
+                        }
                         self.validate_inside_dst(p, &link_src)?;
                         link_src
                     }
-                    None => src.into_owned(),
+                    None => normalize_path(src, self.normalization).into_owned(),
                 };
-                fs::hard_link(&link_src, dst).map_err(|err| {
-                    Error::new(
-                        err.kind(),
-                        format!(
-                            "{} when hard linking {} to {}",
-                            err,
-                            link_src.display(),
-                            dst.display()
-                        ),
-                    )
-                })?;
-            } else {
-                symlink(&src, dst)
-                    .or_else(|err_io| {
-                        if err_io.kind() == io::ErrorKind::AlreadyExists && self.overwrite {
-                            // remove dest and try once more
-                            std::fs::remove_file(dst).and_then(|()| symlink(&src, dst))
+                fs::hard_link(&link_src, dst)
+                    .or_else(|err| {
+                        if err.kind() == io::ErrorKind::AlreadyExists
+                            && self.overwrite != OverwritePolicy::Error
+                        {
+                            fs::remove_file(dst).and_then(|()| fs::hard_link(&link_src, dst))
                         } else {
-                            Err(err_io)
+                            Err(err)
                         }
                     })
                     .map_err(|err| {
                         Error::new(
                             err.kind(),
                             format!(
-                                "{} when symlinking {} to {}",
+                                "{} when hard linking {} to {}",
                                 err,
-                                src.display(),
+                                link_src.display(),
                                 dst.display()
                             ),
                         )
                     })?;
+            } else {
+                let src = normalize_path(src, self.normalization);
+                let created = symlink(&src, dst).or_else(|err_io| {
+                    if err_io.kind() == io::ErrorKind::AlreadyExists
+                        && self.overwrite != OverwritePolicy::Error
+                    {
+                        // remove dest and try once more
+                        std::fs::remove_file(dst).and_then(|()| symlink(&src, dst))
+                    } else {
+                        Err(err_io)
+                    }
+                });
+                let refused = created.as_ref().is_err_and(|err| {
+                    err.kind() == io::ErrorKind::AlreadyExists
+                        && self.overwrite == OverwritePolicy::Error
+                });
+                if let (Err(err), false) = (&created, refused) {
+                    if self.symlink_fallback != SymlinkFallback::Error {
+                        return self
+                            .unpack_symlink_fallback(&src, dst, err)
+                            .map(|()| Unpacked::__Nonexhaustive);
+                    }
+                }
+                created.map_err(|err| {
+                    Error::new(
+                        err.kind(),
+                        format!(
+                            "{} when symlinking {} to {}",
+                            err,
+                            src.display(),
+                            dst.display()
+                        ),
+                    )
+                })?;
                 // While permissions on symlinks are meaningless on most systems, the ownership
                 // of symlinks is important as it dictates the access control to the symlink
                 // itself.
-                if self.preserve_ownerships {
-                    set_ownerships(dst, &None, self.header.uid()?, self.header.gid()?)?;
+                if let Some((uid, gid)) = self.owner()? {
+                    set_ownerships(dst, &None, uid, gid)?;
                 }
-                if self.preserve_mtime {
-                    if let Some(mtime) = get_mtime(&self.header) {
-                        filetime::set_symlink_file_times(dst, mtime, mtime).map_err(|e| {
-                            TarError::new(format!("failed to set mtime for `{}`", dst.display()), e)
-                        })?;
-                    }
+                if let Some(mtime) = self.mtime_to_restore()? {
+                    let atime = self.atime_to_restore()?.unwrap_or(mtime);
+                    filetime::set_symlink_file_times(dst, atime, mtime).map_err(|e| {
+                        TarError::new(format!("failed to set mtime for `{}`", dst.display()), e)
+                    })?;
                 }
             }
             return Ok(Unpacked::__Nonexhaustive);
@@ -598,21 +1772,92 @@ impl<'a> EntryFields<'a> {
                 Err(io::Error::new(io::ErrorKind::Other, "Not implemented"))
             }
 
-            #[cfg(windows)]
-            fn symlink(src: &Path, dst: &Path) -> io::Result<()> {
-                ::std::os::windows::fs::symlink_file(src, dst)
+            #[cfg(windows)]
+            fn symlink(src: &Path, dst: &Path) -> io::Result<()> {
+                ::std::os::windows::fs::symlink_file(src, dst)
+            }
+
+            #[cfg(unix)]
+            fn symlink(src: &Path, dst: &Path) -> io::Result<()> {
+                ::std::os::unix::fs::symlink(src, dst)
+            }
+        } else if kind.is_pax_global_extensions()
+            || kind.is_pax_local_extensions()
+            || kind.is_gnu_longname()
+            || kind.is_gnu_longlink()
+        {
+            return Ok(Unpacked::__Nonexhaustive);
+        } else if self.unpack_devices
+            && (kind.is_character_special() || kind.is_block_special() || kind.is_fifo())
+        {
+            // FIFOs have no device numbers, and often leave the fields empty
+            let (major, minor) = if kind.is_fifo() {
+                (0, 0)
+            } else {
+                let major = self.header.device_major()?.unwrap_or(0);
+                (major, self.header.device_minor()?.unwrap_or(0))
+            };
+            mknod(dst, kind, major, minor)
+                .or_else(|err| {
+                    if err.kind() == io::ErrorKind::AlreadyExists
+                        && self.overwrite != OverwritePolicy::Error
+                    {
+                        fs::remove_file(dst).and_then(|()| mknod(dst, kind, major, minor))
+                    } else {
+                        Err(err)
+                    }
+                })
+                .map_err(|err| {
+                    Error::new(
+                        err.kind(),
+                        format!("{} when creating device {}", err, dst.display()),
+                    )
+                })?;
+            if let Some(mtime) = self.mtime_to_restore()? {
+                let atime = self.atime_to_restore()?.unwrap_or(mtime);
+                // Set by path, since opening a FIFO would block
+                filetime::set_symlink_file_times(dst, atime, mtime).map_err(|e| {
+                    TarError::new(format!("failed to set mtime for `{}`", dst.display()), e)
+                })?;
+            }
+            set_perms_ownerships(
+                dst,
+                None,
+                &self.header,
+                self.mask,
+                self.preserve_permissions,
+                self.owner()?,
+            )?;
+            return Ok(Unpacked::__Nonexhaustive);
+
+            #[cfg(unix)]
+            fn mknod(dst: &Path, kind: EntryType, major: u32, minor: u32) -> io::Result<()> {
+                use std::os::unix::prelude::*;
+
+                let path = std::ffi::CString::new(dst.as_os_str().as_bytes()).map_err(|e| {
+                    io::Error::other(format!("path contains null character: {:?}", e))
+                })?;
+                let file_type = if kind.is_character_special() {
+                    libc::S_IFCHR
+                } else if kind.is_block_special() {
+                    libc::S_IFBLK
+                } else {
+                    libc::S_IFIFO
+                };
+                // Permissions are set afterwards like for any other file
+                let mode = file_type | 0o600;
+                let dev = libc::makedev(major as _, minor as _);
+                if unsafe { libc::mknod(path.as_ptr(), mode, dev) } != 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                Ok(())
             }
 
-            #[cfg(unix)]
-            fn symlink(src: &Path, dst: &Path) -> io::Result<()> {
-                ::std::os::unix::fs::symlink(src, dst)
+            #[cfg(not(unix))]
+            #[allow(unused_variables)]
+            fn mknod(dst: &Path, kind: EntryType, major: u32, minor: u32) -> io::Result<()> {
+                Err(other("devices can only be created on Unix"))
             }
-        } else if kind.is_pax_global_extensions()
-            || kind.is_pax_local_extensions()
-            || kind.is_gnu_longname()
-            || kind.is_gnu_longlink()
-        {
-            return Ok(Unpacked::__Nonexhaustive);
         };
 
         // Old BSD-tar compatibility.
@@ -620,13 +1865,14 @@ impl<'a> EntryFields<'a> {
         // Only applies to old headers.
         if self.header.as_ustar().is_none() && self.path_bytes().ends_with(b"/") {
             self.unpack_dir(dst)?;
+            self.set_dir_times(dst)?;
             set_perms_ownerships(
                 dst,
                 None,
                 &self.header,
                 self.mask,
                 self.preserve_permissions,
-                self.preserve_ownerships,
+                self.owner()?,
             )?;
             return Ok(Unpacked::__Nonexhaustive);
         }
@@ -645,11 +1891,35 @@ impl<'a> EntryFields<'a> {
         fn open(dst: &Path) -> io::Result<std::fs::File> {
             OpenOptions::new().write(true).create_new(true).open(dst)
         }
+        // When unpacking atomically everything is done to a temporary file,
+        // which is only renamed over `dst` once it is complete.
+        let temp = if self.atomic_unpack {
+            if self.overwrite == OverwritePolicy::Error && dst.symlink_metadata().is_ok() {
+                return Err(TarError::new(
+                    format!("failed to unpack `{}`", dst.display()),
+                    Error::new(ErrorKind::AlreadyExists, "file exists"),
+                )
+                .into());
+            }
+            Some(TempFile {
+                path: temp_path(dst),
+                persisted: false,
+            })
+        } else {
+            None
+        };
+        let target = dst;
+        let dst = temp.as_ref().map_or(target, |temp| &temp.path);
+        let preallocate = self
+            .preallocate_threshold
+            .is_some_and(|min| self.size >= min)
+            && self.data.iter().all(|io| matches!(io, EntryIo::Data(_)))
+            && !self.unpack_sparse;
         let mut f = (|| -> io::Result<std::fs::File> {
             let mut f = open(dst).or_else(|err| {
                 if err.kind() != ErrorKind::AlreadyExists {
                     Err(err)
-                } else if self.overwrite {
+                } else if self.overwrite != OverwritePolicy::Error {
                     match fs::remove_file(dst) {
                         Ok(()) => open(dst),
                         Err(ref e) if e.kind() == io::ErrorKind::NotFound => open(dst),
@@ -659,42 +1929,84 @@ impl<'a> EntryFields<'a> {
                     Err(err)
                 }
             })?;
+            if preallocate {
+                preallocate_file(&f, self.size)?;
+            }
             for io in self.data.drain(..) {
                 match io {
                     EntryIo::Data(mut d) => {
                         let expected = d.limit();
-                        if io::copy(&mut d, &mut f)? != expected {
+                        let mut out = HoleWriter {
+                            file: &mut f,
+                            holes: self.unpack_sparse,
+                            skipped: 0,
+                        };
+                        let copied = match &mut self.digest {
+                            Some(hasher) => copy(
+                                self.pool,
+                                self.cancel,
+                                self.deadline,
+                                self.progress,
+                                &mut d,
+                                &mut HashingWriter {
+                                    inner: &mut out,
+                                    hasher,
+                                },
+                            )?,
+                            None => copy(
+                                self.pool,
+                                self.cancel,
+                                self.deadline,
+                                self.progress,
+                                &mut d,
+                                &mut out,
+                            )?,
+                        };
+                        out.finish()?;
+                        if copied != expected {
                             return Err(other("failed to write entire file"));
                         }
                     }
                     EntryIo::Pad(d) => {
+                        if let Some(hasher) = &mut self.digest {
+                            io::copy(&mut io::repeat(0).take(d.limit()), hasher)?;
+                        }
                         // TODO: checked cast to i64
                         let to = SeekFrom::Current(d.limit() as i64);
                         let size = f.seek(to)?;
                         f.set_len(size)?;
+                        if let Some(progress) = self.progress {
+                            progress.borrow_mut().bytes_processed(d.limit());
+                        }
                     }
                 }
             }
             Ok(f)
         })()
         .map_err(|e| {
+            // A temporary file removes itself
+            if temp.is_none() && self.cancel.is_some_and(CancellationToken::is_cancelled) {
+                let _ = fs::remove_file(dst);
+            }
             let header = self.header.path_bytes();
             TarError::new(
                 format!(
                     "failed to unpack `{}` into `{}`",
                     String::from_utf8_lossy(&header),
-                    dst.display()
+                    target.display()
                 ),
                 e,
             )
         })?;
 
-        if self.preserve_mtime {
-            if let Some(mtime) = get_mtime(&self.header) {
-                filetime::set_file_handle_times(&f, Some(mtime), Some(mtime)).map_err(|e| {
-                    TarError::new(format!("failed to set mtime for `{}`", dst.display()), e)
-                })?;
-            }
+        let atime = self.atime_to_restore()?;
+        let mtime = self.mtime_to_restore()?;
+        if atime.is_some() || mtime.is_some() {
+            // Files get their modification time as access time too unless
+            // one was stored
+            filetime::set_file_handle_times(&f, atime.or(mtime), mtime).map_err(|e| {
+                TarError::new(format!("failed to set mtime for `{}`", dst.display()), e)
+            })?;
         }
         set_perms_ownerships(
             dst,
@@ -702,11 +2014,26 @@ impl<'a> EntryFields<'a> {
             &self.header,
             self.mask,
             self.preserve_permissions,
-            self.preserve_ownerships,
+            self.owner()?,
         )?;
-        if self.unpack_xattrs {
+        if self.unpack_xattrs || self.unpack_selinux || self.unpack_mac_metadata {
             set_xattrs(self, dst)?;
         }
+        if self.unpack_windows_attributes {
+            set_windows_attributes(self, dst)?;
+        }
+        if self.sync != SyncPolicy::None {
+            f.sync_all()
+                .map_err(|e| TarError::new(format!("failed to sync `{}`", target.display()), e))?;
+        }
+        if preallocate {
+            drop_file_cache(&f);
+        }
+        if let Some(temp) = temp {
+            temp.persist(target).map_err(|e| {
+                TarError::new(format!("failed to rename into `{}`", target.display()), e)
+            })?;
+        }
         return Ok(Unpacked::File(f));
 
         fn set_ownerships(
@@ -738,12 +2065,12 @@ impl<'a> EntryFields<'a> {
         ) -> io::Result<()> {
             use std::os::unix::prelude::*;
 
-            let uid: libc::uid_t = uid.try_into().map_err(|_| {
-                io::Error::new(io::ErrorKind::Other, format!("UID {} is too large!", uid))
-            })?;
-            let gid: libc::gid_t = gid.try_into().map_err(|_| {
-                io::Error::new(io::ErrorKind::Other, format!("GID {} is too large!", gid))
-            })?;
+            let uid: libc::uid_t = uid
+                .try_into()
+                .map_err(|_| io::Error::other(format!("UID {} is too large!", uid)))?;
+            let gid: libc::gid_t = gid
+                .try_into()
+                .map_err(|_| io::Error::other(format!("GID {} is too large!", gid)))?;
             match f {
                 Some(f) => unsafe {
                     let fd = f.as_raw_fd();
@@ -755,10 +2082,7 @@ impl<'a> EntryFields<'a> {
                 },
                 None => unsafe {
                     let path = std::ffi::CString::new(dst.as_os_str().as_bytes()).map_err(|e| {
-                        io::Error::new(
-                            io::ErrorKind::Other,
-                            format!("path contains null character: {:?}", e),
-                        )
+                        io::Error::other(format!("path contains null character: {:?}", e))
                     })?;
                     if libc::lchown(path.as_ptr(), uid, gid) != 0 {
                         Err(io::Error::last_os_error())
@@ -844,16 +2168,16 @@ impl<'a> EntryFields<'a> {
             }
         }
 
+        // WASI has no file permissions to set
         #[cfg(target_arch = "wasm32")]
-        #[allow(unused_variables)]
         fn _set_perms(
-            dst: &Path,
-            f: Option<&mut std::fs::File>,
-            mode: u32,
-            mask: u32,
-            _preserve: bool,
+            _: &Path,
+            _: Option<&mut std::fs::File>,
+            _: u32,
+            _: u32,
+            _: bool,
         ) -> io::Result<()> {
-            Err(io::Error::new(io::ErrorKind::Other, "Not implemented"))
+            Ok(())
         }
 
         #[cfg(all(unix, feature = "xattr"))]
@@ -861,17 +2185,34 @@ impl<'a> EntryFields<'a> {
             use std::ffi::OsStr;
             use std::os::unix::prelude::*;
 
+            let (unpack_xattrs, unpack_selinux, unpack_mac_metadata) =
+                (me.unpack_xattrs, me.unpack_selinux, me.unpack_mac_metadata);
             let exts = match me.pax_extensions() {
                 Ok(Some(e)) => e,
                 _ => return Ok(()),
             };
+            let selinux = crate::pax::SELINUX_XATTR.as_bytes();
             let exts = exts
                 .filter_map(|e| e.ok())
                 .filter_map(|e| {
                     let key = e.key_bytes();
+                    if key == crate::pax::PAX_RHTSELINUX.as_bytes() {
+                        return Some((selinux, e));
+                    }
                     let prefix = crate::pax::PAX_SCHILYXATTR.as_bytes();
                     key.strip_prefix(prefix).map(|rest| (rest, e))
                 })
+                // SELinux contexts and macOS metadata are restored
+                // separately, as setting them only works on some systems
+                .filter(|(key, _)| {
+                    if *key == selinux {
+                        unpack_selinux
+                    } else if key.starts_with(crate::pax::APPLE_XATTR_PREFIX.as_bytes()) {
+                        unpack_mac_metadata
+                    } else {
+                        unpack_xattrs
+                    }
+                })
                 .map(|(key, e)| (OsStr::from_bytes(key), e.value_bytes()));
 
             for (key, value) in exts {
@@ -898,6 +2239,96 @@ impl<'a> EntryFields<'a> {
         fn set_xattrs(_: &mut EntryFields, _: &Path) -> io::Result<()> {
             Ok(())
         }
+
+        #[cfg(windows)]
+        fn set_windows_attributes(me: &mut EntryFields, dst: &Path) -> io::Result<()> {
+            use std::os::windows::ffi::OsStrExt;
+            use std::os::windows::fs::MetadataExt;
+
+            const FILE_ATTRIBUTE_DIRECTORY: u32 = 0x10;
+            const FILE_ATTRIBUTE_NORMAL: u32 = 0x80;
+
+            #[link(name = "kernel32")]
+            extern "system" {
+                fn SetFileAttributesW(name: *const u16, attributes: u32) -> i32;
+            }
+
+            let flags = match me.pax_extensions() {
+                Ok(Some(exts)) => exts
+                    .filter_map(|e| e.ok())
+                    .find(|e| e.key_bytes() == crate::pax::PAX_SCHILYFFLAGS.as_bytes())
+                    .map(|e| e.value_bytes().to_vec()),
+                _ => None,
+            };
+            let mut bits = 0;
+            for name in flags.iter().flat_map(|f| f.split(|b| *b == b',')) {
+                for (bit, flag) in crate::pax::WINDOWS_FILE_FLAGS {
+                    if name.trim_ascii() == flag.as_bytes() {
+                        bits |= bit;
+                    }
+                }
+            }
+            if bits == 0 {
+                return Ok(());
+            }
+
+            // Neither of these can be set, and `NORMAL` only alone
+            let attributes = fs::metadata(dst)?.file_attributes()
+                & !(FILE_ATTRIBUTE_DIRECTORY | FILE_ATTRIBUTE_NORMAL)
+                | bits;
+            let name = dst
+                .as_os_str()
+                .encode_wide()
+                .chain(Some(0))
+                .collect::<Vec<_>>();
+            if unsafe { SetFileAttributesW(name.as_ptr(), attributes) } == 0 {
+                return Err(TarError::new(
+                    format!("failed to set attributes of `{}`", dst.display()),
+                    io::Error::last_os_error(),
+                )
+                .into());
+            }
+            Ok(())
+        }
+
+        #[cfg(not(windows))]
+        fn set_windows_attributes(_: &mut EntryFields, _: &Path) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Returns the modification time to give the unpacked entry, if it is to
+    /// be restored, to the nanosecond if the archive has an `mtime` PAX
+    /// record.
+    fn mtime_to_restore(&mut self) -> io::Result<Option<FileTime>> {
+        if !self.preserve_mtime {
+            return Ok(None);
+        }
+        let (secs, nanos) = match self.pax_time(PAX_MTIME)? {
+            Some(time) => time,
+            None => match self.header.mtime() {
+                Ok(secs) => (secs as i64, 0),
+                Err(_) => return Ok(None),
+            },
+        };
+        // For some more information on this see the comments in
+        // `Header::fill_platform_from`, but the general idea is that
+        // we're trying to avoid 0-mtime files coming out of archives
+        // since some tools don't ingest them well. Perhaps one day
+        // when Cargo stops working with 0-mtime archives we can remove
+        // this.
+        let secs = if (secs, nanos) == (0, 0) { 1 } else { secs };
+        Ok(Some(FileTime::from_unix_time(secs, nanos)))
+    }
+
+    /// Returns the access time to give the unpacked entry, if one is to be
+    /// restored and the archive stores it.
+    fn atime_to_restore(&mut self) -> io::Result<Option<FileTime>> {
+        if !self.preserve_atime {
+            return Ok(None);
+        }
+        let time = self.extra_time(PAX_ATIME, GnuHeader::atime)?;
+        Ok(time.map(|(secs, nanos)| FileTime::from_unix_time(secs, nanos)))
     }
 
     fn ensure_dir_created(&self, dst: &Path, dir: &Path) -> io::Result<()> {
@@ -920,6 +2351,82 @@ impl<'a> EntryFields<'a> {
         Ok(())
     }
 
+    /// The uid and gid to give the unpacked file, if ownerships are preserved.
+    fn owner(&self) -> io::Result<Option<(u64, u64)>> {
+        if !self.preserve_ownerships {
+            return Ok(None);
+        }
+        let uid = self.header.uid()?;
+        let gid = self.header.gid()?;
+        let username = self.username_bytes();
+        let groupname = self.groupname_bytes();
+        // Translations take precedence over looking names up
+        let map = self.owner_map.as_deref();
+        let uid = match map.and_then(|map| map.uid(uid, username)) {
+            Some(id) => id,
+            None if self.numeric_owner => uid,
+            None => username.and_then(uid_by_name).unwrap_or(uid),
+        };
+        let gid = match map.and_then(|map| map.gid(gid, groupname)) {
+            Some(id) => id,
+            None if self.numeric_owner => gid,
+            None => groupname.and_then(gid_by_name).unwrap_or(gid),
+        };
+        Ok(Some((uid, gid)))
+    }
+
+    /// Returns the path a hard link entry links to relative to the unpack
+    /// destination, or `None` if it has no target or the target would lead
+    /// outside of the destination.
+    fn hard_link_source(&self) -> io::Result<Option<PathBuf>> {
+        let src = match self.link_name()? {
+            Some(src) => src,
+            None => return Ok(None),
+        };
+        let mut source = PathBuf::new();
+        for part in self.link_target(&src).components() {
+            match part {
+                Component::Prefix(..) | Component::RootDir | Component::CurDir => continue,
+                Component::ParentDir => return Ok(None),
+                Component::Normal(part) => source.push(part),
+            }
+        }
+        Ok(Some(source))
+    }
+
+    /// Maps the target of a hard link to the path it was unpacked at, relative
+    /// to the unpack destination.
+    fn link_target(&self, src: &Path) -> PathBuf {
+        let src = match &self.path_transform {
+            Some(transform) => transform(src).map_or(Cow::Borrowed(src), Cow::Owned),
+            None => Cow::Borrowed(src),
+        };
+        let src = strip_components(&src, self.strip_components);
+        let src = normalize_path(src, self.normalization);
+        if self.windows_names {
+            src.components()
+                .map(|part| match part {
+                    Component::Normal(part) => windows_name(part),
+                    part => Cow::Borrowed(part.as_os_str()),
+                })
+                .collect()
+        } else {
+            src.into_owned()
+        }
+    }
+
+    /// Returns the canonical form of `path` if it is a directory inside `dst`,
+    /// and not a symlink to one, for changing what is already on disk there.
+    fn dir_inside_dst(&self, dst: &Path, path: &Path) -> io::Result<Option<PathBuf>> {
+        match fs::symlink_metadata(path) {
+            Ok(meta) if meta.is_dir() => {}
+            _ => return Ok(None),
+        }
+        let canon_dir = path.canonicalize()?;
+        let canon_dst = dst.canonicalize()?;
+        Ok(Some(canon_dir).filter(|dir| dir.starts_with(&canon_dst)))
+    }
+
     fn validate_inside_dst(&self, dst: &Path, file_dst: &Path) -> io::Result<PathBuf> {
         // Abort if target (canonical) parent is outside of `dst`
         let canon_parent = file_dst.canonicalize().map_err(|err| {
@@ -941,7 +2448,7 @@ impl<'a> EntryFields<'a> {
                     canon_target.display()
                 ),
                 // TODO: use ErrorKind::InvalidInput here? (minor breaking change)
-                Error::new(ErrorKind::Other, "Invalid argument"),
+                Error::other("Invalid argument"),
             );
             return Err(err.into());
         }
@@ -949,13 +2456,566 @@ impl<'a> EntryFields<'a> {
     }
 }
 
+#[cfg(all(target_os = "linux", feature = "uring"))]
+impl<R: Read> Entry<'_, R> {
+    /// Reads this regular file out to be written to `dst` through io_uring,
+    /// or returns whether it counts as unpacked if there is nothing to write.
+    pub(crate) fn pending_file_in(
+        &mut self,
+        dst: &Path,
+    ) -> io::Result<Result<crate::uring::PendingFile, bool>> {
+        self.fields.pending_file_in(dst)
+    }
+}
+
+#[cfg(all(target_os = "linux", feature = "uring"))]
+impl EntryFields<'_> {
+    fn pending_file_in(
+        &mut self,
+        dst: &Path,
+    ) -> io::Result<Result<crate::uring::PendingFile, bool>> {
+        let path = match self.prepare_unpack_in(dst)? {
+            Ok((_, path)) => path,
+            Err(unpacked) => return Ok(Err(unpacked)),
+        };
+        let mut data = Vec::with_capacity(self.size as usize);
+        self.read_to_end(&mut data)
+            .map_err(|e| TarError::new(format!("failed to unpack `{}`", path.display()), e))?;
+        // Permissions are worked out as `unpack` does on Unix
+        let mode = self.header.mode().ok().map(|mode| {
+            let mode = if self.preserve_permissions {
+                mode
+            } else {
+                mode & 0o777
+            };
+            mode & !self.mask
+        });
+        let mtime = self.mtime_to_restore()?;
+        let atime = self.atime_to_restore()?.or(mtime);
+        Ok(Ok(crate::uring::PendingFile {
+            path,
+            data,
+            mode,
+            atime,
+            mtime,
+            replace: self.overwrite != OverwritePolicy::Error,
+        }))
+    }
+}
+
+impl EntryFields<'_> {
+    fn compare_to(&mut self, path: &Path) -> io::Result<Vec<Difference>> {
+        let stat = match fs::symlink_metadata(path) {
+            Ok(stat) => stat,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(vec![Difference::Missing]),
+            Err(e) => return Err(e),
+        };
+        let kind = self.header.entry_type();
+        let file_type = stat.file_type();
+        let same_type = if kind.is_file() || kind.is_hard_link() || kind.is_gnu_sparse() {
+            file_type.is_file()
+        } else if kind.is_dir() {
+            file_type.is_dir()
+        } else if kind.is_symlink() {
+            file_type.is_symlink()
+        } else {
+            special_type_matches(kind, &stat)
+        };
+        if !same_type {
+            return Ok(vec![Difference::Type]);
+        }
+
+        let mut differences = Vec::new();
+        if kind.is_symlink() {
+            if self.link_name()?.as_deref() != Some(&*fs::read_link(path)?) {
+                differences.push(Difference::LinkName);
+            }
+        } else if kind.is_file() || kind.is_gnu_sparse() {
+            if self.size != stat.len() {
+                differences.push(Difference::Size);
+            } else if !self.same_contents(&mut fs::File::open(path)?)? {
+                differences.push(Difference::Contents);
+            }
+        }
+
+        if !kind.is_symlink() {
+            let mtime = FileTime::from_last_modification_time(&stat).unix_seconds();
+            if i64::try_from(self.header.mtime()?).ok() != Some(mtime) {
+                differences.push(Difference::Mtime);
+            }
+        }
+        #[cfg(unix)]
+        {
+            use std::os::unix::prelude::*;
+
+            if !kind.is_symlink() && self.header.mode()? & 0o7777 != stat.mode() & 0o7777 {
+                differences.push(Difference::Mode);
+            }
+            if self.header.uid()? != u64::from(stat.uid()) {
+                differences.push(Difference::Uid);
+            }
+            if self.header.gid()? != u64::from(stat.gid()) {
+                differences.push(Difference::Gid);
+            }
+        }
+        Ok(differences)
+    }
+
+    /// Reads this entry's data alongside `file`, returning whether they hold
+    /// the same bytes.
+    fn same_contents(&mut self, file: &mut fs::File) -> io::Result<bool> {
+        let mut ours = [0; 8 * 1024];
+        let mut theirs = [0; 8 * 1024];
+        loop {
+            let n = self.read(&mut ours)?;
+            if n == 0 {
+                return Ok(file.read(&mut theirs[..1])? == 0);
+            }
+            if file.read_exact(&mut theirs[..n]).is_err() || ours[..n] != theirs[..n] {
+                return Ok(false);
+            }
+        }
+    }
+}
+
+#[cfg(unix)]
+fn special_type_matches(kind: EntryType, stat: &fs::Metadata) -> bool {
+    use std::os::unix::fs::FileTypeExt;
+
+    let file_type = stat.file_type();
+    match kind {
+        EntryType::Char => file_type.is_char_device(),
+        EntryType::Block => file_type.is_block_device(),
+        EntryType::Fifo => file_type.is_fifo(),
+        _ => false,
+    }
+}
+
+#[cfg(not(unix))]
+fn special_type_matches(_kind: EntryType, _stat: &fs::Metadata) -> bool {
+    false
+}
+
+/// Looks up the uid of the local user called `name`.
+#[cfg(unix)]
+pub(crate) fn uid_by_name(name: &[u8]) -> Option<u64> {
+    let name = std::ffi::CString::new(name).ok()?;
+    lookup_account(name.as_ptr(), libc::getpwnam_r, |pwd: &libc::passwd| {
+        Some(pwd.pw_uid.into())
+    })
+}
+
+/// Looks up the gid of the local group called `name`.
+#[cfg(unix)]
+pub(crate) fn gid_by_name(name: &[u8]) -> Option<u64> {
+    let name = std::ffi::CString::new(name).ok()?;
+    lookup_account(name.as_ptr(), libc::getgrnam_r, |grp: &libc::group| {
+        Some(grp.gr_gid.into())
+    })
+}
+
+/// Looks up the name of the local user with id `uid`.
+#[cfg(unix)]
+pub(crate) fn user_name(uid: u32) -> Option<String> {
+    lookup_account(uid, libc::getpwuid_r, |pwd: &libc::passwd| {
+        account_name(pwd.pw_name)
+    })
+}
+
+/// Looks up the name of the local group with id `gid`.
+#[cfg(unix)]
+pub(crate) fn group_name(gid: u32) -> Option<String> {
+    lookup_account(gid, libc::getgrgid_r, |grp: &libc::group| {
+        account_name(grp.gr_name)
+    })
+}
+
+/// Copies the nul terminated name of a `passwd` or `group` entry.
+#[cfg(unix)]
+fn account_name(name: *const libc::c_char) -> Option<String> {
+    let name = unsafe { std::ffi::CStr::from_ptr(name) };
+    name.to_str().ok().map(str::to_owned)
+}
+
+/// Looks up a user or group by `key` with one of the `getpw*_r` or
+/// `getgr*_r` functions, growing the buffer for the entry's strings until it
+/// fits.
+#[cfg(unix)]
+fn lookup_account<K: Copy, T, R>(
+    key: K,
+    lookup: unsafe extern "C" fn(
+        K,
+        *mut T,
+        *mut libc::c_char,
+        libc::size_t,
+        *mut *mut T,
+    ) -> libc::c_int,
+    read: fn(&T) -> Option<R>,
+) -> Option<R> {
+    let mut buf = vec![0; 1024];
+    loop {
+        // `T` is `passwd` or `group`, for which all zeroes is a valid value.
+        let mut entry: T = unsafe { std::mem::zeroed() };
+        let mut result = std::ptr::null_mut();
+        let rc = unsafe { lookup(key, &mut entry, buf.as_mut_ptr(), buf.len(), &mut result) };
+        match rc {
+            libc::ERANGE if buf.len() < 1 << 20 => buf.resize(buf.len() * 2, 0),
+            0 if !result.is_null() => return read(&entry),
+            _ => return None,
+        }
+    }
+}
+
+#[cfg(not(unix))]
+pub(crate) fn uid_by_name(_name: &[u8]) -> Option<u64> {
+    None
+}
+
+#[cfg(not(unix))]
+pub(crate) fn gid_by_name(_name: &[u8]) -> Option<u64> {
+    None
+}
+
+/// Recursively copies the directory `src` to `dst`, following no symlinks
+/// within it.
+fn copy_dir(src: &Path, dst: &Path) -> io::Result<()> {
+    fs::create_dir_all(dst)?;
+    for entry in fs::read_dir(src)? {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        let dst = dst.join(entry.file_name());
+        if file_type.is_dir() {
+            copy_dir(&entry.path(), &dst)?;
+        } else if file_type.is_file() {
+            fs::copy(entry.path(), dst)?;
+        }
+    }
+    Ok(())
+}
+
+/// Copies everything from `src` to `dst` like `io::copy`, through a buffer
+/// from `pool`, failing with `Cancelled` once `cancel` is cancelled or
+/// `TimedOut` once `deadline` passes, and reporting each chunk to `progress`.
+fn copy(
+    pool: &BufferPool,
+    cancel: Option<&CancellationToken>,
+    deadline: Option<Instant>,
+    progress: Option<&Progress>,
+    src: &mut dyn Read,
+    dst: &mut dyn Write,
+) -> io::Result<u64> {
+    let mut buf = pool.take();
+    buf.resize(64 * 1024, 0);
+    let mut copied = 0;
+    loop {
+        if let Some(token) = cancel {
+            token.check()?;
+        }
+        check_deadline(deadline)?;
+        let n = match src.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(ref e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        dst.write_all(&buf[..n])?;
+        copied += n as u64;
+        if let Some(progress) = progress {
+            progress.borrow_mut().bytes_processed(n as u64);
+        }
+    }
+    pool.give(buf);
+    Ok(copied)
+}
+
+/// Reserves `len` bytes for a file about to be written from the start, and
+/// hints that it will be written sequentially.
+fn preallocate_file(f: &fs::File, len: u64) -> io::Result<()> {
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    {
+        use std::os::unix::prelude::*;
+
+        let fd = f.as_raw_fd();
+        let len = libc::off_t::try_from(len).map_err(|_| other("file too large to preallocate"))?;
+        // Hints are only advice, so failing to give one doesn't matter
+        unsafe { libc::posix_fadvise(fd, 0, 0, libc::POSIX_FADV_SEQUENTIAL) };
+        if unsafe { libc::fallocate(fd, 0, 0, len) } == 0 {
+            return Ok(());
+        }
+        let err = io::Error::last_os_error();
+        // Not every filesystem can preallocate
+        if !matches!(
+            err.raw_os_error(),
+            Some(libc::EOPNOTSUPP) | Some(libc::ENOSYS)
+        ) {
+            return Err(err);
+        }
+    }
+    f.set_len(len)
+}
+
+/// Hints that a file just written won't be read back soon, so its pages can
+/// be dropped from the cache once written out.
+fn drop_file_cache(f: &fs::File) {
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    {
+        use std::os::unix::prelude::*;
+
+        unsafe { libc::posix_fadvise(f.as_raw_fd(), 0, 0, libc::POSIX_FADV_DONTNEED) };
+    }
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    let _ = f;
+}
+
+/// The size of the blocks of zeros `HoleWriter` leaves as holes, which is
+/// the block size of most filesystems.
+const HOLE_BLOCK_SIZE: usize = 4096;
+
+/// Writes to a file, seeking past blocks of zeros instead of writing them if
+/// `holes` is set, so that they are left as holes on filesystems with sparse
+/// files.
+struct HoleWriter<'a> {
+    file: &'a mut fs::File,
+    holes: bool,
+    // Zeros seeked past since the last write
+    skipped: u64,
+}
+
+impl HoleWriter<'_> {
+    /// Moves past any zeros at the end of what was written, extending the
+    /// file over them.
+    fn finish(mut self) -> io::Result<()> {
+        if self.skipped > 0 {
+            let size = self.seek_past_skipped()?;
+            self.file.set_len(size)?;
+        }
+        Ok(())
+    }
+
+    fn seek_past_skipped(&mut self) -> io::Result<u64> {
+        let skipped = i64::try_from(self.skipped).map_err(|_| other("hole too large"))?;
+        self.skipped = 0;
+        self.file.seek(SeekFrom::Current(skipped))
+    }
+}
+
+impl Write for HoleWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if !self.holes {
+            return self.file.write(buf);
+        }
+        for block in buf.chunks(HOLE_BLOCK_SIZE) {
+            if block.iter().all(|b| *b == 0) {
+                self.skipped += block.len() as u64;
+            } else {
+                if self.skipped > 0 {
+                    self.seek_past_skipped()?;
+                }
+                self.file.write_all(block)?;
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+/// A file being unpacked under a temporary name, removed when dropped unless
+/// it has been renamed into place.
+struct TempFile {
+    path: PathBuf,
+    persisted: bool,
+}
+
+impl TempFile {
+    fn persist(mut self, dst: &Path) -> io::Result<()> {
+        fs::rename(&self.path, dst)?;
+        self.persisted = true;
+        Ok(())
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        if !self.persisted {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
+
+/// Returns a name next to `dst` for unpacking it under until it's complete,
+/// hidden on Unix and unique within this process.
+fn temp_path(dst: &Path) -> PathBuf {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+    let mut name = OsString::from(".");
+    name.push(dst.file_name().unwrap_or_default());
+    name.push(format!(
+        ".tar-{}-{}",
+        std::process::id(),
+        COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+    dst.with_file_name(name)
+}
+
+/// Converts seconds and nanoseconds since the epoch, as stored in an archive,
+/// into a `SystemTime`.
+fn system_time(secs: i64, nanos: u32) -> SystemTime {
+    let time = match u64::try_from(secs) {
+        Ok(secs) => SystemTime::UNIX_EPOCH + Duration::from_secs(secs),
+        Err(_) => SystemTime::UNIX_EPOCH - Duration::from_secs(secs.unsigned_abs()),
+    };
+    time + Duration::from_nanos(u64::from(nanos))
+}
+
+/// Creates an NTFS junction at `dst` pointing at the directory `target`.
+#[cfg(windows)]
+fn junction(target: &Path, dst: &Path) -> io::Result<()> {
+    // Junctions must point at absolute paths, and `mklink` is the only way of
+    // creating one short of building the reparse point by hand.
+    let target = std::path::absolute(target)?;
+    let status = std::process::Command::new("cmd")
+        .arg("/C")
+        .arg("mklink")
+        .arg("/J")
+        .arg(dst)
+        .arg(&target)
+        .stdout(std::process::Stdio::null())
+        .status()?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(other(&format!(
+            "failed to create junction {} to {}",
+            dst.display(),
+            target.display()
+        )))
+    }
+}
+
+#[cfg(not(windows))]
+fn junction(_target: &Path, _dst: &Path) -> io::Result<()> {
+    Err(Error::new(
+        ErrorKind::Unsupported,
+        "junctions are only supported on Windows",
+    ))
+}
+
+/// Returns whether `bytes` can be a path on this platform as they are, which
+/// on platforms other than Unix needs them to be valid UTF-8.
+pub(crate) fn representable(bytes: &[u8]) -> bool {
+    cfg!(unix) || str::from_utf8(bytes).is_ok()
+}
+
+/// Rewrites a file name into one Windows can create.
+///
+/// Characters Windows doesn't allow in names are replaced with `_`, trailing
+/// dots and spaces, which it would silently drop, are removed, and `_` is
+/// appended to the stem of reserved device names like `CON` or `nul.txt`.
+fn windows_name(name: &OsStr) -> Cow<'_, OsStr> {
+    const RESERVED: &[&str] = &[
+        "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+        "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+    ];
+
+    let lossy = name.to_string_lossy();
+    let mut fixed: String = lossy
+        .chars()
+        .map(|c| match c {
+            '<' | '>' | ':' | '"' | '|' | '?' | '*' | '\\' | '\0'..='\x1f' => '_',
+            c => c,
+        })
+        .collect();
+    fixed.truncate(fixed.trim_end_matches(['.', ' ']).len());
+    if fixed.is_empty() {
+        fixed.push('_');
+    }
+    let stem = fixed.split('.').next().unwrap_or_default().len();
+    if RESERVED
+        .iter()
+        .any(|r| r.eq_ignore_ascii_case(&fixed[..stem]))
+    {
+        fixed.insert(stem, '_');
+    }
+    if fixed == lossy && matches!(lossy, Cow::Borrowed(_)) {
+        Cow::Borrowed(name)
+    } else {
+        Cow::Owned(fixed.into())
+    }
+}
+
+/// Converts `path` into the verbatim `\\?\` form, which lifts Windows' limit
+/// of MAX_PATH characters.
+#[cfg(windows)]
+fn verbatim_path(path: &Path) -> io::Result<PathBuf> {
+    use std::path::Prefix;
+
+    let path = std::path::absolute(path)?;
+    let mut verbatim = OsString::new();
+    match path.components().next() {
+        Some(Component::Prefix(prefix)) => match prefix.kind() {
+            Prefix::Disk(_) => {
+                verbatim.push(r"\\?\");
+                verbatim.push(&path);
+            }
+            Prefix::UNC(..) => {
+                verbatim.push(r"\\?\UNC");
+                verbatim.push(&path.as_os_str().to_string_lossy()[1..]);
+            }
+            _ => return Ok(path),
+        },
+        _ => return Ok(path),
+    }
+    Ok(PathBuf::from(verbatim))
+}
+
+/// Drops the first `count` normal components of `path`, along with any root
+/// or `.` components found before them.
+fn strip_components(path: &Path, count: usize) -> Cow<'_, Path> {
+    if count == 0 {
+        return Cow::Borrowed(path);
+    }
+    let mut to_strip = count;
+    let mut components = path.components();
+    while to_strip > 0 {
+        match components.next() {
+            Some(Component::Normal(_)) => to_strip -= 1,
+            Some(_) => {}
+            None => break,
+        }
+    }
+    Cow::Owned(components.as_path().to_path_buf())
+}
+
+impl Drop for EntryFields<'_> {
+    fn drop(&mut self) {
+        let bufs = [
+            self.long_pathname.take(),
+            self.long_linkname.take(),
+            self.pax_extensions.take(),
+        ];
+        for buf in bufs.into_iter().flatten() {
+            self.pool.give(buf);
+        }
+    }
+}
+
 impl<'a> Read for EntryFields<'a> {
     fn read(&mut self, into: &mut [u8]) -> io::Result<usize> {
+        check_deadline(self.deadline)?;
         loop {
             match self.data.get_mut(0).map(|io| io.read(into)) {
                 Some(Ok(0)) => {
                     self.data.remove(0);
                 }
+                Some(Ok(n)) => {
+                    if let Some(hasher) = &mut self.digest {
+                        hasher.update(&into[..n]);
+                    }
+                    return Ok(n);
+                }
                 Some(r) => return r,
                 None => return Ok(0),
             }
@@ -963,6 +3023,17 @@ impl<'a> Read for EntryFields<'a> {
     }
 }
 
+/// An archive in memory, shared with the `Bytes` returned by `read_all_bytes`.
+#[cfg(feature = "bytes")]
+struct SharedArchive(Arc<dyn AsRef<[u8]> + Send + Sync>);
+
+#[cfg(feature = "bytes")]
+impl AsRef<[u8]> for SharedArchive {
+    fn as_ref(&self) -> &[u8] {
+        (*self.0).as_ref()
+    }
+}
+
 impl<'a> Read for EntryIo<'a> {
     fn read(&mut self, into: &mut [u8]) -> io::Result<usize> {
         match *self {
@@ -971,3 +3042,50 @@ impl<'a> Read for EntryIo<'a> {
         }
     }
 }
+
+/// Resolves `target` relative to the directory `resolved` within `root`,
+/// following symlinks found on disk along the way, and returns whether it
+/// stays inside `root`.
+fn resolves_inside(root: &Path, resolved: &mut Vec<OsString>, target: &Path) -> bool {
+    // The maximum number of symlinks Linux follows when resolving a path.
+    const MAX_LINKS: usize = 40;
+
+    // Queues the components of `target` to resolve next, returning false if
+    // it is absolute.
+    fn push(todo: &mut Vec<OsString>, target: &Path) -> bool {
+        for part in target.components().rev() {
+            match part {
+                Component::Prefix(..) | Component::RootDir => return false,
+                Component::CurDir => {}
+                Component::ParentDir | Component::Normal(_) => {
+                    todo.push(part.as_os_str().to_owned())
+                }
+            }
+        }
+        true
+    }
+
+    let mut links = 0;
+    let mut todo = Vec::new();
+    if !push(&mut todo, target) {
+        return false;
+    }
+    while let Some(part) = todo.pop() {
+        if part == ".." {
+            if resolved.pop().is_none() {
+                return false;
+            }
+            continue;
+        }
+        resolved.push(part);
+        let path = resolved.iter().fold(root.to_path_buf(), |p, c| p.join(c));
+        if let Ok(link) = fs::read_link(&path) {
+            links += 1;
+            resolved.pop();
+            if links > MAX_LINKS || !push(&mut todo, &link) {
+                return false;
+            }
+        }
+    }
+    true
+}
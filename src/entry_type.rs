@@ -3,9 +3,10 @@
 ///
 /// Each `Header` has an `entry_type` method returning an instance of this type
 /// which can be used to inspect what the header is describing.
-
+///
 /// A non-exhaustive enum representing the possible entry types
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum EntryType {
     /// Regular file
     Regular,
@@ -29,6 +30,9 @@ pub enum EntryType {
     GNULongLink,
     /// GNU extension - sparse file
     GNUSparse,
+    /// GNU extension - directory in an incremental backup, whose contents
+    /// list what the directory held
+    GNUDumpDir,
     /// Global extended header
     XGlobalHeader,
     /// Extended Header
@@ -62,6 +66,7 @@ impl EntryType {
             b'L' => EntryType::GNULongName,
             b'K' => EntryType::GNULongLink,
             b'S' => EntryType::GNUSparse,
+            b'D' => EntryType::GNUDumpDir,
             b => EntryType::__Nonexhaustive(b),
         }
     }
@@ -82,6 +87,7 @@ impl EntryType {
             EntryType::GNULongName => b'L',
             EntryType::GNULongLink => b'K',
             EntryType::GNUSparse => b'S',
+            EntryType::GNUDumpDir => b'D',
             EntryType::__Nonexhaustive(b) => b,
         }
     }
@@ -151,9 +157,10 @@ impl EntryType {
         self == &EntryType::Block
     }
 
-    /// Returns whether this type represents a directory.
+    /// Returns whether this type represents a directory, including a GNU
+    /// dump directory.
     pub fn is_dir(&self) -> bool {
-        self == &EntryType::Directory
+        self == &EntryType::Directory || self == &EntryType::GNUDumpDir
     }
 
     /// Returns whether this type represents a FIFO.
@@ -176,6 +183,12 @@ impl EntryType {
         self == &EntryType::GNUSparse
     }
 
+    /// Returns whether this type represents a GNU dump directory, the
+    /// directory entries of an incremental backup.
+    pub fn is_gnu_dumpdir(&self) -> bool {
+        self == &EntryType::GNUDumpDir
+    }
+
     /// Returns whether this type represents a GNU long link header.
     pub fn is_gnu_longlink(&self) -> bool {
         self == &EntryType::GNULongLink
@@ -0,0 +1,172 @@
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::str;
+use std::time::{Duration, UNIX_EPOCH};
+
+use crate::header::{bytes2path, path2bytes};
+use crate::other;
+
+/// The first line of a file written by `FileSnapshot::write`.
+const STATE_HEADER: &[u8] = b"tar-rs incremental state 1\n";
+
+/// The state of every file archived by `Builder::append_incremental`, against
+/// which the next incremental archive is made.
+///
+/// Unlike a GNU `Snapshot`, this records each file's size, modification time
+/// and inode, so a file counts as changed whenever any of them differ, and
+/// the archives made with it are ordinary tar archives. Files deleted between
+/// archives are only forgotten, not recorded as deleted.
+///
+/// # Examples
+///
+/// ```no_run
+/// use std::fs::File;
+/// use tar::{Builder, FileSnapshot};
+///
+/// let previous = match File::open("data.state") {
+///     Ok(file) => FileSnapshot::read(file).unwrap(),
+///     Err(_) => FileSnapshot::new(),
+/// };
+/// let mut ar = Builder::new(File::create("data-1.tar").unwrap());
+/// let snapshot = ar.append_incremental("data", "/srv/data", &previous).unwrap();
+/// ar.finish().unwrap();
+/// snapshot.write(File::create("data.state").unwrap()).unwrap();
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct FileSnapshot {
+    files: BTreeMap<PathBuf, FileState>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct FileState {
+    size: u64,
+    mtime: Duration,
+    dev: u64,
+    ino: u64,
+}
+
+impl FileState {
+    pub(crate) fn new(stat: &fs::Metadata, (dev, ino): (u64, u64)) -> FileState {
+        FileState {
+            size: stat.len(),
+            mtime: stat
+                .modified()
+                .ok()
+                .and_then(|mtime| mtime.duration_since(UNIX_EPOCH).ok())
+                .unwrap_or_default(),
+            dev,
+            ino,
+        }
+    }
+}
+
+impl FileSnapshot {
+    /// Creates an empty snapshot, against which everything counts as changed.
+    pub fn new() -> FileSnapshot {
+        FileSnapshot::default()
+    }
+
+    /// Reads a snapshot written by `write`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the data is corrupt or wasn't written by `write`.
+    pub fn read<R: Read>(mut reader: R) -> io::Result<FileSnapshot> {
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)?;
+        let records = data
+            .strip_prefix(STATE_HEADER)
+            .ok_or_else(|| other("not an incremental state file"))?;
+
+        let mut fields = records.split(|b| *b == 0);
+        let mut next = || {
+            fields
+                .next()
+                .ok_or_else(|| other("incremental state file cut short"))
+        };
+        let mut files = BTreeMap::new();
+        loop {
+            let name = match next() {
+                // The last record is followed by the end of the file
+                Ok([]) | Err(_) => break,
+                Ok(name) => bytes2path(Cow::Borrowed(name))?.into_owned(),
+            };
+            let state = FileState {
+                size: number(next()?)?,
+                mtime: Duration::new(number(next()?)?, nanos(next()?)?),
+                dev: number(next()?)?,
+                ino: number(next()?)?,
+            };
+            files.insert(name, state);
+        }
+        Ok(FileSnapshot { files })
+    }
+
+    /// Writes this snapshot, to be read back by `read` when making the next
+    /// incremental archive.
+    pub fn write<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writer.write_all(STATE_HEADER)?;
+        for (name, state) in &self.files {
+            writer.write_all(&path2bytes(name)?)?;
+            write!(
+                writer,
+                "\0{}\0{}\0{}\0{}\0{}\0",
+                state.size,
+                state.mtime.as_secs(),
+                state.mtime.subsec_nanos(),
+                state.dev,
+                state.ino
+            )?;
+        }
+        writer.flush()
+    }
+
+    /// Adds the files recorded by `other`, as when archiving several
+    /// directories into one archive, replacing what this snapshot records
+    /// for the same paths.
+    pub fn merge(&mut self, other: FileSnapshot) {
+        self.files.extend(other.files);
+    }
+
+    /// Returns the archive paths of the files this snapshot records.
+    pub fn paths(&self) -> impl Iterator<Item = &Path> {
+        self.files.keys().map(PathBuf::as_path)
+    }
+
+    /// Returns the number of files this snapshot records.
+    pub fn len(&self) -> usize {
+        self.files.len()
+    }
+
+    /// Returns whether this snapshot records no files.
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+
+    /// Returns whether the file archived as `path` is new or has a different
+    /// state than when this snapshot was taken.
+    pub(crate) fn changed(&self, path: &Path, state: &FileState) -> bool {
+        self.files.get(path) != Some(state)
+    }
+
+    pub(crate) fn insert(&mut self, path: PathBuf, state: FileState) {
+        self.files.insert(path, state);
+    }
+}
+
+fn number(field: &[u8]) -> io::Result<u64> {
+    str::from_utf8(field)
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .ok_or_else(|| other("invalid number in incremental state file"))
+}
+
+fn nanos(field: &[u8]) -> io::Result<u32> {
+    u32::try_from(number(field)?)
+        .ok()
+        .filter(|nanos| *nanos < 1_000_000_000)
+        .ok_or_else(|| other("invalid time in incremental state file"))
+}
@@ -0,0 +1,238 @@
+use std::collections::HashSet;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use filetime::FileTime;
+
+use crate::error::TarError;
+use crate::{other, Entry, Header};
+
+/// Somewhere `Archive::unpack_into` can unpack an archive to, such as an
+/// in-memory tree, a FUSE backend or a sandboxed directory.
+///
+/// Every path given is relative, has no `..` components, and names a place
+/// inside what is being unpacked to. Parent directories are always created
+/// with `create_dir` before anything is created within them, even when the
+/// archive has no entries for them. `OsFileSystem` unpacks onto the local
+/// filesystem.
+pub trait FileSystem {
+    /// Creates the directory at `path`, succeeding if it already exists.
+    fn create_dir(&mut self, path: &Path) -> io::Result<()>;
+
+    /// Creates the regular file at `path`, replacing any file already there,
+    /// and returns a writer for its contents.
+    fn create_file(&mut self, path: &Path) -> io::Result<Box<dyn Write + '_>>;
+
+    /// Creates a symlink at `path` pointing at `target`, which is the link
+    /// name stored in the archive and isn't checked in any way.
+    fn symlink(&mut self, target: &Path, path: &Path) -> io::Result<()>;
+
+    /// Creates a hard link at `path` to the file already unpacked at
+    /// `target`.
+    fn hard_link(&mut self, target: &Path, path: &Path) -> io::Result<()>;
+
+    /// Applies the metadata in `header`, such as its mode and modification
+    /// time, to what was created at `path`.
+    ///
+    /// This is called for everything but hard links, after the contents of
+    /// files have been written, and for directories once everything inside
+    /// them has been unpacked. It does nothing by default.
+    fn set_metadata(&mut self, path: &Path, header: &Header) -> io::Result<()> {
+        let _ = (path, header);
+        Ok(())
+    }
+}
+
+/// A `FileSystem` unpacking onto the local filesystem under a root
+/// directory.
+///
+/// Symlinks are never followed on the way to anything created, so symlinks
+/// in the archive can't lead outside of the root: a symlink where something
+/// is to be created is replaced by it. Permissions are applied
+/// without any set-user-ID, set-group-ID or sticky bits, and ownership
+/// isn't changed.
+pub struct OsFileSystem {
+    root: PathBuf,
+}
+
+impl OsFileSystem {
+    /// Creates a filesystem unpacking into `root`, which is created if it
+    /// doesn't exist.
+    pub fn new<P: AsRef<Path>>(root: P) -> OsFileSystem {
+        OsFileSystem {
+            root: root.as_ref().to_path_buf(),
+        }
+    }
+
+    /// Returns where `path` is under the root, failing if any of its parent
+    /// directories is a symlink.
+    fn resolve(&self, path: &Path) -> io::Result<PathBuf> {
+        let mut resolved = self.root.clone();
+        let mut parts = path.components().peekable();
+        while let Some(part) = parts.next() {
+            resolved.push(part);
+            if parts.peek().is_some() && is_symlink(&resolved) {
+                return Err(other(&format!(
+                    "`{}` is a symlink, which would be followed",
+                    resolved.display()
+                )));
+            }
+        }
+        Ok(resolved)
+    }
+
+    /// Resolves `path` like `resolve`, removing a symlink already there so
+    /// that it isn't followed.
+    fn resolve_new(&self, path: &Path) -> io::Result<PathBuf> {
+        let resolved = self.resolve(path)?;
+        if is_symlink(&resolved) {
+            fs::remove_file(&resolved)?;
+        }
+        Ok(resolved)
+    }
+}
+
+impl FileSystem for OsFileSystem {
+    fn create_dir(&mut self, path: &Path) -> io::Result<()> {
+        if self.root.symlink_metadata().is_err() {
+            fs::create_dir_all(&self.root)?;
+        }
+        match fs::create_dir(self.resolve_new(path)?) {
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => Ok(()),
+            result => result,
+        }
+    }
+
+    fn create_file(&mut self, path: &Path) -> io::Result<Box<dyn Write + '_>> {
+        Ok(Box::new(fs::File::create(self.resolve_new(path)?)?))
+    }
+
+    fn symlink(&mut self, target: &Path, path: &Path) -> io::Result<()> {
+        let path = self.resolve_new(path)?;
+        if path.symlink_metadata().is_ok() {
+            fs::remove_file(&path)?;
+        }
+        symlink(target, &path)
+    }
+
+    fn hard_link(&mut self, target: &Path, path: &Path) -> io::Result<()> {
+        let target = self.resolve(target)?;
+        let path = self.resolve_new(path)?;
+        if path.symlink_metadata().is_ok() {
+            fs::remove_file(&path)?;
+        }
+        fs::hard_link(target, path)
+    }
+
+    fn set_metadata(&mut self, path: &Path, header: &Header) -> io::Result<()> {
+        let path = self.resolve(path)?;
+        let mtime = FileTime::from_unix_time(header.mtime()? as i64, 0);
+        if header.entry_type().is_symlink() {
+            return filetime::set_symlink_file_times(&path, mtime, mtime);
+        }
+        #[cfg(unix)]
+        {
+            use std::os::unix::prelude::*;
+
+            let mode = header.mode()? & 0o777;
+            fs::set_permissions(&path, fs::Permissions::from_mode(mode))?;
+        }
+        filetime::set_file_mtime(&path, mtime)
+    }
+}
+
+fn is_symlink(path: &Path) -> bool {
+    path.symlink_metadata()
+        .is_ok_and(|meta| meta.file_type().is_symlink())
+}
+
+#[cfg(unix)]
+fn symlink(target: &Path, path: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(target, path)
+}
+
+#[cfg(windows)]
+fn symlink(target: &Path, path: &Path) -> io::Result<()> {
+    std::os::windows::fs::symlink_file(target, path)
+}
+
+#[cfg(target_arch = "wasm32")]
+fn symlink(_: &Path, _: &Path) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "symlinks aren't supported",
+    ))
+}
+
+/// Unpacks `entries` into `fs` as `Archive::unpack_into` describes.
+pub(crate) fn unpack<'a>(
+    entries: impl Iterator<Item = io::Result<Entry<'a, io::Empty>>>,
+    fs: &mut dyn FileSystem,
+) -> io::Result<()> {
+    let mut created = HashSet::new();
+    // Directories get their metadata last, in case it makes them read-only
+    let mut directories = Vec::new();
+    for entry in entries {
+        let mut entry = entry.map_err(|e| TarError::new("failed to iterate over archive", e))?;
+        let path = match entry.destination_in("")? {
+            Some(path) if !path.as_os_str().is_empty() => path,
+            _ => continue,
+        };
+        let kind = entry.header().entry_type();
+        let parents: Vec<_> = path.ancestors().skip(1).collect();
+        for parent in parents.into_iter().rev() {
+            if !parent.as_os_str().is_empty() && created.insert(parent.to_path_buf()) {
+                fs.create_dir(parent)?;
+            }
+        }
+        let unpacked = if kind.is_dir() {
+            if created.insert(path.clone()) {
+                fs.create_dir(&path)
+            } else {
+                Ok(())
+            }
+        } else if kind.is_symlink() {
+            match entry.link_name()? {
+                Some(target) => fs.symlink(&target, &path),
+                None => Err(other("symlink has no target")),
+            }
+        } else if kind.is_hard_link() {
+            match entry.hard_link_source()? {
+                Some(target) => fs.hard_link(&target, &path),
+                None => Err(other("hard link target is missing or outside the archive")),
+            }
+        } else if kind.is_file() || kind.is_contiguous() || kind.is_gnu_sparse() {
+            fs.create_file(&path).and_then(|mut dst| {
+                io::copy(&mut entry, &mut dst)?;
+                dst.flush()
+            })
+        } else {
+            // Device files, FIFOs and anything else have nowhere to go
+            continue;
+        };
+        unpacked.map_err(|e| TarError::new(format!("failed to unpack `{}`", path.display()), e))?;
+        if kind.is_dir() {
+            directories.push((path, entry.header().clone()));
+        } else if !kind.is_hard_link() {
+            fs.set_metadata(&path, entry.header()).map_err(|e| {
+                TarError::new(
+                    format!("failed to set metadata for `{}`", path.display()),
+                    e,
+                )
+            })?;
+        }
+    }
+    // Deeper directories first, so a parent is only made read-only once
+    // its children are done
+    directories.sort_by(|a, b| b.0.cmp(&a.0));
+    for (path, header) in &directories {
+        fs.set_metadata(path, header).map_err(|e| {
+            TarError::new(
+                format!("failed to set metadata for `{}`", path.display()),
+                e,
+            )
+        })?;
+    }
+    Ok(())
+}
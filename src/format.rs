@@ -0,0 +1,59 @@
+use crate::Header;
+
+/// The dialect of tar an entry or archive is written in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ArchiveFormat {
+    /// The original Unix V7 format, whose headers have no magic.
+    V7,
+    /// The POSIX.1-1988 UStar format.
+    Ustar,
+    /// The GNU format, including its long name and sparse file extensions.
+    Gnu,
+    /// The POSIX.1-2001 PAX format, a UStar header with extended headers.
+    Pax,
+    /// Headers with a magic and version which aren't recognized, such as
+    /// those of a tar implementation with its own dialect.
+    Unknown {
+        /// The magic field of the header.
+        magic: [u8; 6],
+        /// The version field of the header.
+        version: [u8; 2],
+    },
+}
+
+impl ArchiveFormat {
+    /// Returns the format of `header` alone, without any extended headers
+    /// which came before it.
+    pub(crate) fn of_header(header: &Header) -> ArchiveFormat {
+        if header.as_ustar().is_some() {
+            return ArchiveFormat::Ustar;
+        }
+        if header.as_gnu().is_some() {
+            return ArchiveFormat::Gnu;
+        }
+        // The magic and version follow the link name, at the same place in
+        // every header with them
+        let bytes = header.as_bytes();
+        let mut magic = [0; 6];
+        let mut version = [0; 2];
+        magic.copy_from_slice(&bytes[257..263]);
+        version.copy_from_slice(&bytes[263..265]);
+        if magic == [0; 6] {
+            ArchiveFormat::V7
+        } else {
+            ArchiveFormat::Unknown { magic, version }
+        }
+    }
+
+    /// Orders the known formats by what they can hold, for reporting the
+    /// most capable of those an archive mixes.
+    pub(crate) fn rank(self) -> u8 {
+        match self {
+            ArchiveFormat::V7 => 0,
+            ArchiveFormat::Ustar => 1,
+            ArchiveFormat::Gnu => 2,
+            ArchiveFormat::Pax => 3,
+            ArchiveFormat::Unknown { .. } => 4,
+        }
+    }
+}
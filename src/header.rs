@@ -7,7 +7,6 @@ use std::borrow::Cow;
 use std::fmt;
 use std::fs;
 use std::io;
-use std::iter;
 use std::iter::{once, repeat};
 use std::mem;
 use std::path::{Component, Path, PathBuf};
@@ -351,7 +350,7 @@ impl Header {
     ///
     /// Note that this function will convert any `\` characters to directory
     /// separators.
-    pub fn path(&self) -> io::Result<Cow<Path>> {
+    pub fn path(&self) -> io::Result<Cow<'_, Path>> {
         bytes2path(self.path_bytes())
     }
 
@@ -362,7 +361,7 @@ impl Header {
     ///
     /// Note that this function will convert any `\` characters to directory
     /// separators.
-    pub fn path_bytes(&self) -> Cow<[u8]> {
+    pub fn path_bytes(&self) -> Cow<'_, [u8]> {
         if let Some(ustar) = self.as_ustar() {
             ustar.path_bytes()
         } else {
@@ -426,7 +425,7 @@ impl Header {
     ///
     /// Note that this function will convert any `\` characters to directory
     /// separators.
-    pub fn link_name(&self) -> io::Result<Option<Cow<Path>>> {
+    pub fn link_name(&self) -> io::Result<Option<Cow<'_, Path>>> {
         match self.link_name_bytes() {
             Some(bytes) => bytes2path(bytes).map(Some),
             None => Ok(None),
@@ -440,7 +439,7 @@ impl Header {
     ///
     /// Note that this function will convert any `\` characters to directory
     /// separators.
-    pub fn link_name_bytes(&self) -> Option<Cow<[u8]>> {
+    pub fn link_name_bytes(&self) -> Option<Cow<'_, [u8]>> {
         let old = self.as_old();
         if old.linkname[0] != 0 {
             Some(Cow::Borrowed(truncate(&old.linkname)))
@@ -505,14 +504,12 @@ impl Header {
     ///
     /// May return an error if the field is corrupted.
     pub fn uid(&self) -> io::Result<u64> {
-        num_field_wrapper_from(&self.as_old().uid)
-            .map(|u| u as u64)
-            .map_err(|err| {
-                io::Error::new(
-                    err.kind(),
-                    format!("{} when getting uid for {}", err, self.path_lossy()),
-                )
-            })
+        num_field_wrapper_from(&self.as_old().uid).map_err(|err| {
+            io::Error::new(
+                err.kind(),
+                format!("{} when getting uid for {}", err, self.path_lossy()),
+            )
+        })
     }
 
     /// Encodes the `uid` provided into this header.
@@ -522,14 +519,12 @@ impl Header {
 
     /// Returns the value of the group's user ID field
     pub fn gid(&self) -> io::Result<u64> {
-        num_field_wrapper_from(&self.as_old().gid)
-            .map(|u| u as u64)
-            .map_err(|err| {
-                io::Error::new(
-                    err.kind(),
-                    format!("{} when getting gid for {}", err, self.path_lossy()),
-                )
-            })
+        num_field_wrapper_from(&self.as_old().gid).map_err(|err| {
+            io::Error::new(
+                err.kind(),
+                format!("{} when getting gid for {}", err, self.path_lossy()),
+            )
+        })
     }
 
     /// Encodes the `gid` provided into this header.
@@ -658,6 +653,9 @@ impl Header {
 
     /// Encodes the value `major` into the dev_major field of this header.
     ///
+    /// Numbers too large for the field's seven octal digits are stored in
+    /// GNU tar's base-256 form, which is also read back by `device_major`.
+    ///
     /// This function will return an error if this header format cannot encode a
     /// major device number.
     pub fn set_device_major(&mut self, major: u32) -> io::Result<()> {
@@ -691,6 +689,9 @@ impl Header {
 
     /// Encodes the value `minor` into the dev_minor field of this header.
     ///
+    /// Numbers too large for the field's seven octal digits are stored in
+    /// GNU tar's base-256 form, which is also read back by `device_minor`.
+    ///
     /// This function will return an error if this header format cannot encode a
     /// minor device number.
     pub fn set_device_minor(&mut self, minor: u32) -> io::Result<()> {
@@ -736,17 +737,99 @@ impl Header {
         octal_into(&mut self.as_old_mut().cksum, cksum);
     }
 
-    fn calculate_cksum(&self) -> u32 {
-        let old = self.as_old();
-        let start = old as *const _ as usize;
-        let cksum_start = old.cksum.as_ptr() as *const _ as usize;
-        let offset = cksum_start - start;
-        let len = old.cksum.len();
-        self.bytes[0..offset]
+    /// Computes the checksum of this header, counting the checksum field
+    /// itself as spaces.
+    pub(crate) fn calculate_cksum(&self) -> u32 {
+        // The bytes are summed eight at a time into four 16-bit lanes, which
+        // can't overflow over a whole block: 512 / 8 * 2 * 255 < 65536.
+        const LOW_BYTES: u64 = 0x00ff_00ff_00ff_00ff;
+        let mut lanes = 0;
+        for chunk in self.bytes.chunks_exact(8) {
+            let x = u64::from_le_bytes(chunk.try_into().unwrap());
+            lanes += (x & LOW_BYTES) + ((x >> 8) & LOW_BYTES);
+        }
+        let sum =
+            (lanes & 0xffff) + (lanes >> 16 & 0xffff) + (lanes >> 32 & 0xffff) + (lanes >> 48);
+
+        let cksum = &self.as_old().cksum;
+        let field = cksum.iter().map(|&b| u32::from(b)).sum::<u32>();
+        sum as u32 - field + cksum.len() as u32 * u32::from(b' ')
+    }
+
+    /// Computes the checksum of this header as some historical tars did,
+    /// summing its bytes as signed.
+    pub(crate) fn calculate_signed_cksum(&self) -> i64 {
+        // The checksum field follows the name, mode, uid, gid, size and mtime
+        let offset = 148;
+        let field = self.as_old().cksum.len();
+        self.bytes
             .iter()
-            .chain(iter::repeat(&b' ').take(len))
-            .chain(&self.bytes[offset + len..])
-            .fold(0, |a, b| a + (*b as u32))
+            .enumerate()
+            .map(|(i, &b)| {
+                if (offset..offset + field).contains(&i) {
+                    i64::from(b' ')
+                } else {
+                    i64::from(b as i8)
+                }
+            })
+            .sum()
+    }
+
+    /// Returns the checksum stored in this header, or with `lenient` the
+    /// octal digits the field starts with if it doesn't parse.
+    pub(crate) fn stored_cksum(&self, lenient: bool) -> io::Result<u32> {
+        match self.cksum() {
+            Err(_) if lenient => Ok(lenient_octal(&self.as_old().cksum) as u32),
+            result => result,
+        }
+    }
+
+    /// Checks that every numeric field is in base-256 or is octal digits
+    /// with nothing but spaces before them and spaces or nuls after them.
+    pub(crate) fn check_numeric_fields(&self) -> io::Result<()> {
+        let old = self.as_old();
+        let fields = [
+            ("mode", &old.mode[..]),
+            ("uid", &old.uid[..]),
+            ("gid", &old.gid[..]),
+            ("size", &old.size[..]),
+            ("mtime", &old.mtime[..]),
+            ("cksum", &old.cksum[..]),
+        ];
+        for (name, field) in fields {
+            if field[0] & 0x80 == 0 && !is_strict_octal(field) {
+                return Err(other(&format!(
+                    "malformed {} field in header for {}",
+                    name,
+                    self.path_lossy()
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Rewrites each numeric field which doesn't parse as the octal digits
+    /// it starts with, after any spaces or nuls, updating the checksum if
+    /// anything was rewritten.
+    pub(crate) fn repair_numeric_fields(&mut self) {
+        let old = self.as_old_mut();
+        let mut repaired = false;
+        for field in [
+            &mut old.mode[..],
+            &mut old.uid[..],
+            &mut old.gid[..],
+            &mut old.size[..],
+            &mut old.mtime[..],
+        ] {
+            if field[0] & 0x80 == 0 && octal_from(field).is_err() {
+                let value = lenient_octal(field);
+                octal_into(field, value);
+                repaired = true;
+            }
+        }
+        if repaired {
+            self.set_cksum();
+        }
     }
 
     fn fill_from(&mut self, meta: &fs::Metadata, mode: HeaderMode) {
@@ -780,7 +863,7 @@ impl Header {
                 self.set_mtime(meta.mtime() as u64);
                 self.set_uid(meta.uid() as u64);
                 self.set_gid(meta.gid() as u64);
-                self.set_mode(meta.mode() as u32);
+                self.set_mode(meta.mode());
             }
             HeaderMode::Deterministic => {
                 // We could in theory set the mtime to zero here, but not all tools seem to behave
@@ -977,7 +1060,7 @@ impl fmt::Debug for OldHeader {
 
 impl UstarHeader {
     /// See `Header::path_bytes`
-    pub fn path_bytes(&self) -> Cow<[u8]> {
+    pub fn path_bytes(&self) -> Cow<'_, [u8]> {
         if self.prefix[0] == 0 && !self.name.contains(&b'\\') {
             Cow::Borrowed(truncate(&self.name))
         } else {
@@ -1087,8 +1170,10 @@ impl UstarHeader {
 
     /// See `Header::device_major`
     pub fn device_major(&self) -> io::Result<u32> {
-        octal_from(&self.dev_major)
-            .map(|u| u as u32)
+        num_field_wrapper_from(&self.dev_major)
+            .and_then(|u| {
+                u32::try_from(u).map_err(|_| other("device number does not fit in 32 bits"))
+            })
             .map_err(|err| {
                 io::Error::new(
                     err.kind(),
@@ -1103,13 +1188,15 @@ impl UstarHeader {
 
     /// See `Header::set_device_major`
     pub fn set_device_major(&mut self, major: u32) {
-        octal_into(&mut self.dev_major, major);
+        num_field_wrapper_into(&mut self.dev_major, major.into());
     }
 
     /// See `Header::device_minor`
     pub fn device_minor(&self) -> io::Result<u32> {
-        octal_from(&self.dev_minor)
-            .map(|u| u as u32)
+        num_field_wrapper_from(&self.dev_minor)
+            .and_then(|u| {
+                u32::try_from(u).map_err(|_| other("device number does not fit in 32 bits"))
+            })
             .map_err(|err| {
                 io::Error::new(
                     err.kind(),
@@ -1124,7 +1211,7 @@ impl UstarHeader {
 
     /// See `Header::set_device_minor`
     pub fn set_device_minor(&mut self, minor: u32) {
-        octal_into(&mut self.dev_minor, minor);
+        num_field_wrapper_into(&mut self.dev_minor, minor.into());
     }
 
     /// Views this as a normal `Header`
@@ -1196,8 +1283,10 @@ impl GnuHeader {
 
     /// See `Header::device_major`
     pub fn device_major(&self) -> io::Result<u32> {
-        octal_from(&self.dev_major)
-            .map(|u| u as u32)
+        num_field_wrapper_from(&self.dev_major)
+            .and_then(|u| {
+                u32::try_from(u).map_err(|_| other("device number does not fit in 32 bits"))
+            })
             .map_err(|err| {
                 io::Error::new(
                     err.kind(),
@@ -1212,13 +1301,15 @@ impl GnuHeader {
 
     /// See `Header::set_device_major`
     pub fn set_device_major(&mut self, major: u32) {
-        octal_into(&mut self.dev_major, major);
+        num_field_wrapper_into(&mut self.dev_major, major.into());
     }
 
     /// See `Header::device_minor`
     pub fn device_minor(&self) -> io::Result<u32> {
-        octal_from(&self.dev_minor)
-            .map(|u| u as u32)
+        num_field_wrapper_from(&self.dev_minor)
+            .and_then(|u| {
+                u32::try_from(u).map_err(|_| other("device number does not fit in 32 bits"))
+            })
             .map_err(|err| {
                 io::Error::new(
                     err.kind(),
@@ -1233,7 +1324,7 @@ impl GnuHeader {
 
     /// See `Header::set_device_minor`
     pub fn set_device_minor(&mut self, minor: u32) {
-        octal_into(&mut self.dev_minor, minor);
+        num_field_wrapper_into(&mut self.dev_minor, minor.into());
     }
 
     /// Returns the last modification time in Unix time format
@@ -1472,6 +1563,31 @@ fn octal_from(slice: &[u8]) -> io::Result<u64> {
     }
 }
 
+fn is_octal_digit(byte: u8) -> bool {
+    (b'0'..=b'7').contains(&byte)
+}
+
+/// Returns whether `field` holds octal digits with nothing but spaces
+/// before them and spaces or nuls after them.
+fn is_strict_octal(field: &[u8]) -> bool {
+    let start = field.iter().take_while(|&&b| b == b' ').count();
+    let digits = field[start..]
+        .iter()
+        .take_while(|&&b| is_octal_digit(b))
+        .count();
+    digits > 0 && field[start + digits..].iter().all(|&b| b == b' ' || b == 0)
+}
+
+/// Reads the octal digits at the start of `field`, after any spaces or
+/// nuls, ignoring whatever follows them.
+fn lenient_octal(field: &[u8]) -> u64 {
+    field
+        .iter()
+        .skip_while(|&&b| b == b' ' || b == 0)
+        .take_while(|&&b| is_octal_digit(b))
+        .fold(0, |n, &b| n * 8 + u64::from(b - b'0'))
+}
+
 fn octal_into<T: fmt::Octal>(dst: &mut [u8], val: T) {
     let o = format!("{:o}", val);
     let value = once(b'\0').chain(o.bytes().rev().chain(repeat(b'0')));
@@ -1507,8 +1623,7 @@ fn num_field_wrapper_from(src: &[u8]) -> io::Result<u64> {
 fn numeric_extended_into(dst: &mut [u8], src: u64) {
     let len: usize = dst.len();
     for (slot, val) in dst.iter_mut().zip(
-        repeat(0)
-            .take(len - 8) // to zero init extra bytes
+        std::iter::repeat_n(0, len - 8) // to zero init extra bytes
             .chain((0..8).rev().map(|x| ((src >> (8 * x)) & 0xff) as u8)),
     ) {
         *slot = val;
@@ -1545,7 +1660,7 @@ fn truncate(slice: &[u8]) -> &[u8] {
 fn copy_into(slot: &mut [u8], bytes: &[u8]) -> io::Result<()> {
     if bytes.len() > slot.len() {
         Err(other("provided value is too long"))
-    } else if bytes.iter().any(|b| *b == 0) {
+    } else if bytes.contains(&0) {
         Err(other("provided value contains a nul byte"))
     } else {
         for (slot, val) in slot.iter_mut().zip(bytes.iter().chain(Some(&0))) {
@@ -1591,7 +1706,7 @@ fn copy_path_into_inner(
                 return Err(other("path component in archive cannot contain `/`"));
             }
         }
-        copy(&mut slot, &*bytes)?;
+        copy(&mut slot, &bytes)?;
         if &*bytes != b"/" {
             needs_slash = true;
         }
@@ -1601,13 +1716,13 @@ fn copy_path_into_inner(
         return Err(other("paths in archives must have at least one component"));
     }
     if ends_with_slash(path) {
-        copy(&mut slot, &[b'/'])?;
+        copy(&mut slot, b"/")?;
     }
     return Ok(());
 
     fn copy(slot: &mut &mut [u8], bytes: &[u8]) -> io::Result<()> {
-        copy_into(*slot, bytes)?;
-        let tmp = mem::replace(slot, &mut []);
+        copy_into(slot, bytes)?;
+        let tmp = std::mem::take(slot);
         *slot = &mut tmp[bytes.len()..];
         Ok(())
     }
@@ -1652,7 +1767,7 @@ fn ends_with_slash(p: &Path) -> bool {
 
 #[cfg(unix)]
 fn ends_with_slash(p: &Path) -> bool {
-    p.as_os_str().as_bytes().ends_with(&[b'/'])
+    p.as_os_str().as_bytes().ends_with(b"/")
 }
 
 #[cfg(any(windows, target_arch = "wasm32"))]
@@ -1679,8 +1794,8 @@ pub fn path2bytes(p: &Path) -> io::Result<Cow<[u8]>> {
 
 #[cfg(unix)]
 /// On unix this will never fail
-pub fn path2bytes(p: &Path) -> io::Result<Cow<[u8]>> {
-    Ok(p.as_os_str().as_bytes()).map(Cow::Borrowed)
+pub fn path2bytes(p: &Path) -> io::Result<Cow<'_, [u8]>> {
+    Ok(Cow::Borrowed(p.as_os_str().as_bytes()))
 }
 
 #[cfg(windows)]
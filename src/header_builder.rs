@@ -0,0 +1,228 @@
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+use crate::header::path2bytes;
+use crate::{Builder, EntryType, Header};
+
+/// The largest value each numeric field of a UStar header holds in octal.
+const USTAR_ID_MAX: u64 = 0o7777777;
+const USTAR_NUM_MAX: u64 = 0o77777777777;
+
+/// A fluent way of making a `Header`, picking a format which can hold what
+/// it's given and setting the checksum.
+///
+/// A UStar header is made when everything fits in one, and a GNU header
+/// when the owner IDs, size or modification time are too large for UStar
+/// but the path and link name are short enough for GNU. Paths and link names
+/// too long for either are stored in PAX extended headers by `append_to`.
+///
+/// # Examples
+///
+/// ```
+/// use tar::{Builder, EntryType, HeaderBuilder};
+///
+/// let header = HeaderBuilder::new()
+///     .path("hello.txt")
+///     .size(6)
+///     .mode(0o644)
+///     .build()
+///     .unwrap();
+///
+/// let mut ar = Builder::new(Vec::new());
+/// ar.append(&header, &b"hello\n"[..]).unwrap();
+/// HeaderBuilder::new()
+///     .entry_type(EntryType::Symlink)
+///     .path("greeting")
+///     .link_name("hello.txt")
+///     .append_to(&mut ar, std::io::empty())
+///     .unwrap();
+/// ```
+#[derive(Clone, Debug)]
+pub struct HeaderBuilder {
+    path: PathBuf,
+    link_name: Option<PathBuf>,
+    entry_type: EntryType,
+    size: u64,
+    mode: Option<u32>,
+    mtime: u64,
+    uid: u64,
+    gid: u64,
+    username: Option<String>,
+    groupname: Option<String>,
+}
+
+impl HeaderBuilder {
+    /// Starts a header for a regular file, with no path and nothing else set.
+    pub fn new() -> HeaderBuilder {
+        HeaderBuilder {
+            path: PathBuf::new(),
+            link_name: None,
+            entry_type: EntryType::Regular,
+            size: 0,
+            mode: None,
+            mtime: 0,
+            uid: 0,
+            gid: 0,
+            username: None,
+            groupname: None,
+        }
+    }
+
+    /// Sets the path of the entry.
+    pub fn path<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.path = path.as_ref().to_path_buf();
+        self
+    }
+
+    /// Sets the size of the entry's contents, 0 by default.
+    pub fn size(mut self, size: u64) -> Self {
+        self.size = size;
+        self
+    }
+
+    /// Sets the permission bits, which default to `0o755` for directories,
+    /// `0o777` for symlinks and `0o644` for everything else.
+    pub fn mode(mut self, mode: u32) -> Self {
+        self.mode = Some(mode);
+        self
+    }
+
+    /// Sets the modification time, in seconds since the Unix epoch, 0 by
+    /// default.
+    pub fn mtime(mut self, mtime: u64) -> Self {
+        self.mtime = mtime;
+        self
+    }
+
+    /// Sets the owner's user and group IDs, both 0 by default.
+    pub fn owner(mut self, uid: u64, gid: u64) -> Self {
+        self.uid = uid;
+        self.gid = gid;
+        self
+    }
+
+    /// Sets the owner's user and group names, which are empty by default.
+    pub fn owner_names(mut self, username: &str, groupname: &str) -> Self {
+        self.username = Some(username.to_string());
+        self.groupname = Some(groupname.to_string());
+        self
+    }
+
+    /// Sets the kind of entry, a regular file by default.
+    pub fn entry_type(mut self, entry_type: EntryType) -> Self {
+        self.entry_type = entry_type;
+        self
+    }
+
+    /// Sets the target of a symlink or hard link.
+    pub fn link_name<P: AsRef<Path>>(mut self, link_name: P) -> Self {
+        self.link_name = Some(link_name.as_ref().to_path_buf());
+        self
+    }
+
+    /// Makes the header, with its checksum set.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the path or link name is too long for any header,
+    /// in which case `append_to` can still add the entry to an archive, or
+    /// if a user or group name is too long.
+    pub fn build(&self) -> io::Result<Header> {
+        let mut header = self.header(true)?;
+        if header.set_path(&self.path).is_err() {
+            header = self.header(false)?;
+            header.set_path(&self.path)?;
+        }
+        header.set_cksum();
+        Ok(header)
+    }
+
+    /// Appends an entry with this header and the contents read from `data`
+    /// to `ar`.
+    ///
+    /// This is like `Builder::append` with the header from `build`, except
+    /// that a path or link name too long for it is stored in a PAX extended
+    /// header, along with any owner IDs, size or modification time too
+    /// large for UStar.
+    pub fn append_to<W: Write, R: Read>(&self, ar: &mut Builder<W>, data: R) -> io::Result<()> {
+        if let Ok(header) = self.build() {
+            return ar.append(&header, data);
+        }
+
+        let mut header = Header::new_ustar();
+        self.set_fields(&mut header)?;
+        let mut records = Vec::new();
+        if header.set_path(&self.path).is_err() {
+            let path = path2bytes(&self.path)?;
+            // Something is still needed in the header for readers which
+            // don't understand PAX
+            let name = &mut header.as_old_mut().name;
+            let len = path.len().min(name.len());
+            name[..len].copy_from_slice(&path[..len]);
+            records.push(("path", path.to_vec()));
+        }
+        if let Some(link) = &self.link_name {
+            if header.set_link_name(link).is_err() {
+                records.push(("linkpath", path2bytes(link)?.to_vec()));
+            }
+        }
+        for (key, value, max) in [
+            ("uid", self.uid, USTAR_ID_MAX),
+            ("gid", self.gid, USTAR_ID_MAX),
+            ("size", self.size, USTAR_NUM_MAX),
+            ("mtime", self.mtime, USTAR_NUM_MAX),
+        ] {
+            if value > max {
+                records.push((key, value.to_string().into_bytes()));
+            }
+        }
+        header.set_cksum();
+        ar.append_pax_extensions(records.iter().map(|(key, value)| (*key, &value[..])))?;
+        ar.append(&header, data)
+    }
+
+    /// Returns a UStar header with everything but the path set, or a GNU
+    /// one when the numeric fields don't fit or `ustar` is `false`.
+    fn header(&self, ustar: bool) -> io::Result<Header> {
+        let fits = self.uid <= USTAR_ID_MAX
+            && self.gid <= USTAR_ID_MAX
+            && self.size <= USTAR_NUM_MAX
+            && self.mtime <= USTAR_NUM_MAX;
+        let mut header = if ustar && fits {
+            Header::new_ustar()
+        } else {
+            Header::new_gnu()
+        };
+        self.set_fields(&mut header)?;
+        if let Some(link) = &self.link_name {
+            header.set_link_name(link)?;
+        }
+        Ok(header)
+    }
+
+    fn set_fields(&self, header: &mut Header) -> io::Result<()> {
+        header.set_entry_type(self.entry_type);
+        header.set_size(self.size);
+        header.set_mode(self.mode.unwrap_or(match self.entry_type {
+            EntryType::Directory => 0o755,
+            EntryType::Symlink => 0o777,
+            _ => 0o644,
+        }));
+        header.set_mtime(self.mtime);
+        header.set_uid(self.uid);
+        header.set_gid(self.gid);
+        if let Some(username) = &self.username {
+            header.set_username(username)?;
+        }
+        if let Some(groupname) = &self.groupname {
+            header.set_groupname(groupname)?;
+        }
+        Ok(())
+    }
+}
+
+impl Default for HeaderBuilder {
+    fn default() -> HeaderBuilder {
+        HeaderBuilder::new()
+    }
+}
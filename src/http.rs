@@ -0,0 +1,174 @@
+use std::io::{self, Read, Seek, SeekFrom};
+use std::time::Duration;
+
+use crate::chunks::{ChunkedReader, FetchRange, DEFAULT_CHUNK_SIZE};
+use crate::other;
+
+/// A reader over a file served over HTTP, fetching it in chunks with range
+/// requests as it is read.
+///
+/// Together with `Archive::entries_with_seek`, this reads just the headers
+/// and the entries wanted out of an archive on a web server or object store,
+/// rather than downloading all of it. The most recently read chunks are
+/// kept, so reading back and forth within them doesn't fetch them again.
+pub struct HttpReader {
+    inner: ChunkedReader<HttpSource>,
+}
+
+struct HttpSource {
+    agent: ureq::Agent,
+    url: String,
+}
+
+impl HttpReader {
+    /// Opens the file at `url`, fetching its first chunk to learn its length.
+    ///
+    /// Returns an error if the server doesn't support range requests.
+    pub fn open(url: &str) -> io::Result<HttpReader> {
+        let source = HttpSource {
+            agent: ureq::Agent::new(),
+            url: url.to_string(),
+        };
+        let response = match source.request(0, DEFAULT_CHUNK_SIZE).call() {
+            Ok(response) => response,
+            // Any range of an empty file is unsatisfiable
+            Err(ureq::Error::Status(416, response)) => {
+                return match total_len(&response) {
+                    Some(0) => Ok(HttpReader {
+                        inner: ChunkedReader::new(source, 0),
+                    }),
+                    _ => Err(other(&format!("range request to {} failed", url))),
+                };
+            }
+            Err(e) => return Err(http_error(e)),
+        };
+        let len = total_len(&response)
+            .ok_or_else(|| other(&format!("{} doesn't support range requests", url)))?;
+        let first = read_body(response, DEFAULT_CHUNK_SIZE.min(len))?;
+        let mut inner = ChunkedReader::new(source, len);
+        inner.insert_first(first);
+        Ok(HttpReader { inner })
+    }
+
+    /// Returns the length of the file.
+    pub fn len(&self) -> u64 {
+        self.inner.len()
+    }
+
+    /// Returns whether the file is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Sets how much of the file is fetched by each request, 1 MiB by
+    /// default.
+    ///
+    /// Smaller chunks fetch less that isn't needed when entries are picked
+    /// out of a large archive, at the cost of more requests when reading
+    /// through it. Changing this empties the cache.
+    pub fn set_chunk_size(&mut self, size: u64) {
+        self.inner.set_chunk_size(size);
+    }
+
+    /// Sets how many chunks are kept after they have been read, 16 by
+    /// default.
+    pub fn set_cache_chunks(&mut self, chunks: usize) {
+        self.inner.set_cache_chunks(chunks);
+    }
+
+    /// Sets how long to wait for the server to send more of a response
+    /// before failing the read with a `TimedOut` error, so a stalled server
+    /// can't hang reading forever. This defaults to `None`, waiting
+    /// indefinitely.
+    pub fn set_read_timeout(&mut self, timeout: Option<Duration>) {
+        let mut agent = ureq::AgentBuilder::new();
+        if let Some(timeout) = timeout {
+            agent = agent.timeout_read(timeout);
+        }
+        self.inner.source_mut().agent = agent.build();
+    }
+}
+
+impl HttpSource {
+    fn request(&self, first: u64, len: u64) -> ureq::Request {
+        self.agent
+            .get(&self.url)
+            .set("Range", &format!("bytes={}-{}", first, first + len - 1))
+    }
+}
+
+impl FetchRange for HttpSource {
+    fn fetch(&mut self, first: u64, len: u64) -> io::Result<Vec<u8>> {
+        let response = self.request(first, len).call().map_err(http_error)?;
+        if response.status() != 206 {
+            return Err(other(&format!(
+                "{} didn't respond to a range request with a range",
+                self.url
+            )));
+        }
+        read_body(response, len)
+    }
+}
+
+impl Read for HttpReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf)
+    }
+}
+
+impl Seek for HttpReader {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.inner.seek(pos)
+    }
+}
+
+/// Returns the length of the whole file from the `Content-Range` header of a
+/// response to a range request, such as `bytes 0-1023/4096`.
+fn total_len(response: &ureq::Response) -> Option<u64> {
+    if response.status() != 206 && response.status() != 416 {
+        return None;
+    }
+    let range = response.header("Content-Range")?.strip_prefix("bytes ")?;
+    range.rsplit_once('/')?.1.trim().parse().ok()
+}
+
+fn read_body(response: ureq::Response, len: u64) -> io::Result<Vec<u8>> {
+    let mut data = Vec::with_capacity(len as usize);
+    // A read timing out on a socket shows up as `WouldBlock` on Unix
+    match response.into_reader().take(len).read_to_end(&mut data) {
+        Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+            Err(io::Error::new(io::ErrorKind::TimedOut, e))
+        }
+        Err(e) => Err(e),
+        Ok(_) => Ok(data),
+    }
+}
+
+fn http_error(err: ureq::Error) -> io::Error {
+    match err {
+        ureq::Error::Status(404, response) => io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("{} not found", response.get_url()),
+        ),
+        ureq::Error::Status(code, response) => other(&format!(
+            "request to {} failed with status {}",
+            response.get_url(),
+            code
+        )),
+        ureq::Error::Transport(e) => {
+            let timed_out = std::error::Error::source(&e)
+                .and_then(|e| e.downcast_ref::<io::Error>())
+                .is_some_and(|e| {
+                    matches!(
+                        e.kind(),
+                        io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock
+                    )
+                });
+            if timed_out {
+                io::Error::new(io::ErrorKind::TimedOut, e.to_string())
+            } else {
+                other(&e.to_string())
+            }
+        }
+    }
+}
@@ -0,0 +1,280 @@
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::str;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::header::{bytes2path, path2bytes};
+use crate::normalize::{normalize_path, NormalizationForm};
+use crate::other;
+
+/// The version of GNU tar's snapshot file format which is read and written.
+const SNAPSHOT_VERSION: &str = "2";
+
+/// The state of the directories archived by a GNU incremental backup, kept
+/// between backups in a snapshot file as with GNU tar's
+/// `--listed-incremental`.
+///
+/// A backup made with `Builder::append_dir_incremental` archives only what
+/// changed since the backup recorded in its snapshot, and updates the
+/// snapshot to record itself. Starting from `Snapshot::new` makes a full
+/// backup. Snapshot files are read and written in GNU tar's format 2, so
+/// backup chains can be continued by either.
+///
+/// # Examples
+///
+/// ```no_run
+/// use std::fs::File;
+/// use tar::{Builder, Snapshot};
+///
+/// let mut snapshot = match File::open("home.snar") {
+///     Ok(file) => Snapshot::read(file).unwrap(),
+///     Err(_) => Snapshot::new(),
+/// };
+/// let mut ar = Builder::new(File::create("home-1.tar").unwrap());
+/// ar.append_dir_incremental("home", "/home", &mut snapshot).unwrap();
+/// ar.finish().unwrap();
+/// snapshot.write(File::create("home.snar").unwrap()).unwrap();
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct Snapshot {
+    // When the recorded backup started, since the Unix epoch
+    started: Duration,
+    // When the backup being made started, once it has
+    dumping: Option<Duration>,
+    dirs: BTreeMap<PathBuf, SnapshotDir>,
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct SnapshotDir {
+    pub nfs: bool,
+    pub mtime: Duration,
+    pub dev: u64,
+    pub ino: u64,
+    // The dump directory listing, as stored in the archive
+    pub contents: Vec<u8>,
+}
+
+/// A name listed in the contents of a GNU dump directory entry, as returned
+/// by `Entry::dumpdir`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DumpDirEntry {
+    /// A file which changed since the last backup, so is in the archive.
+    Archived(PathBuf),
+    /// A file which hasn't changed since the last backup, so isn't in the
+    /// archive.
+    Unchanged(PathBuf),
+    /// A subdirectory.
+    Directory(PathBuf),
+}
+
+impl DumpDirEntry {
+    /// Returns the name of the file or subdirectory, relative to the
+    /// directory.
+    pub fn name(&self) -> &Path {
+        match self {
+            DumpDirEntry::Archived(name)
+            | DumpDirEntry::Unchanged(name)
+            | DumpDirEntry::Directory(name) => name,
+        }
+    }
+}
+
+impl Snapshot {
+    /// Creates an empty snapshot, from which a full backup is made.
+    pub fn new() -> Snapshot {
+        Snapshot::default()
+    }
+
+    /// Reads a snapshot file written by `write` or by GNU tar.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file is corrupt or in a format other than
+    /// GNU tar's format 2.
+    pub fn read<R: Read>(mut reader: R) -> io::Result<Snapshot> {
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)?;
+        let newline = data
+            .iter()
+            .position(|b| *b == b'\n')
+            .ok_or_else(|| other("snapshot file has no header line"))?;
+        let version = data[..newline].rsplit(|b| *b == b'-').next().unwrap();
+        if !data.starts_with(b"GNU tar-") || version != SNAPSHOT_VERSION.as_bytes() {
+            return Err(other("unsupported snapshot file format"));
+        }
+
+        let mut fields = data[newline + 1..].split(|b| *b == 0);
+        let mut next = || {
+            fields
+                .next()
+                .ok_or_else(|| other("snapshot file cut short"))
+        };
+        let started = time(next()?, next()?)?;
+        let mut dirs = BTreeMap::new();
+        loop {
+            let nfs = match next() {
+                // The last record is followed by the end of the file
+                Ok([]) | Err(_) => break,
+                Ok(nfs) => nfs == b"1",
+            };
+            let mtime = time(next()?, next()?)?;
+            let dev = number(next()?)?;
+            let ino = number(next()?)?;
+            let name = bytes2path(Cow::Borrowed(next()?))?.into_owned();
+            let mut contents = Vec::new();
+            loop {
+                let entry = next()?;
+                contents.extend_from_slice(entry);
+                contents.push(0);
+                if entry.is_empty() {
+                    break;
+                }
+            }
+            let dir = SnapshotDir {
+                nfs,
+                mtime,
+                dev,
+                ino,
+                contents,
+            };
+            dirs.insert(name, dir);
+        }
+        Ok(Snapshot {
+            started,
+            dumping: None,
+            dirs,
+        })
+    }
+
+    /// Writes this snapshot as a GNU tar snapshot file, recording the backup
+    /// made with it if there was one.
+    pub fn write<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        let started = self.dumping.unwrap_or(self.started);
+        write!(
+            writer,
+            "GNU tar-{}-{}\n{}\0{}\0",
+            env!("CARGO_PKG_VERSION"),
+            SNAPSHOT_VERSION,
+            started.as_secs(),
+            started.subsec_nanos()
+        )?;
+        for (name, dir) in &self.dirs {
+            write!(
+                writer,
+                "{}\0{}\0{}\0{}\0{}\0",
+                dir.nfs as u8,
+                dir.mtime.as_secs(),
+                dir.mtime.subsec_nanos(),
+                dir.dev,
+                dir.ino
+            )?;
+            writer.write_all(&path2bytes(name)?)?;
+            writer.write_all(&[0])?;
+            writer.write_all(&dir.contents)?;
+        }
+        writer.flush()
+    }
+
+    /// Returns when the backup recorded by this snapshot started, or `None`
+    /// if it is empty.
+    pub fn started(&self) -> Option<SystemTime> {
+        (self.started > Duration::ZERO || !self.dirs.is_empty()).then(|| UNIX_EPOCH + self.started)
+    }
+
+    /// Returns the archive paths of the directories this snapshot records.
+    pub fn directories(&self) -> impl Iterator<Item = &Path> {
+        self.dirs.keys().map(PathBuf::as_path)
+    }
+
+    /// Starts recording a backup, returning when the backup recorded so far
+    /// started, against which files are checked for changes.
+    pub(crate) fn start_dump(&mut self) -> Duration {
+        self.dumping.get_or_insert_with(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+        });
+        self.started
+    }
+
+    /// Returns whether `path` was archived by the backup recorded so far.
+    pub(crate) fn contains(&self, path: &Path) -> bool {
+        self.dirs.contains_key(path)
+    }
+
+    /// Records the directories under `root` archived by the backup being
+    /// made, forgetting those which no longer exist.
+    pub(crate) fn record(&mut self, root: &Path, dirs: Vec<(PathBuf, SnapshotDir)>) {
+        let kept = dirs
+            .iter()
+            .map(|(path, _)| path.clone())
+            .collect::<HashSet<_>>();
+        self.dirs
+            .retain(|path, _| !path.starts_with(root) || kept.contains(path));
+        self.dirs.extend(dirs);
+    }
+}
+
+fn number(field: &[u8]) -> io::Result<u64> {
+    str::from_utf8(field)
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .ok_or_else(|| other("invalid number in snapshot file"))
+}
+
+fn time(secs: &[u8], nanos: &[u8]) -> io::Result<Duration> {
+    let nanos = u32::try_from(number(nanos)?)
+        .ok()
+        .filter(|nanos| *nanos < 1_000_000_000)
+        .ok_or_else(|| other("invalid time in snapshot file"))?;
+    Ok(Duration::new(number(secs)?, nanos))
+}
+
+/// Parses the contents of a dump directory entry, skipping the rename
+/// records GNU tar may add, with names converted to `normalization`.
+pub(crate) fn parse_dumpdir(
+    data: &[u8],
+    normalization: Option<NormalizationForm>,
+) -> io::Result<Vec<DumpDirEntry>> {
+    let mut listing = Vec::new();
+    for record in data.split(|b| *b == 0).take_while(|r| !r.is_empty()) {
+        let name = bytes2path(Cow::Borrowed(&record[1..]))?;
+        let name = normalize_path(name, normalization).into_owned();
+        match record[0] {
+            b'Y' => listing.push(DumpDirEntry::Archived(name)),
+            b'N' => listing.push(DumpDirEntry::Unchanged(name)),
+            b'D' => listing.push(DumpDirEntry::Directory(name)),
+            _ => {}
+        }
+    }
+    Ok(listing)
+}
+
+/// Removes everything in `dir` which isn't in `listing`, as restoring an
+/// incremental backup does with what was deleted since the last one.
+pub(crate) fn prune(dir: &Path, listing: &[DumpDirEntry]) -> io::Result<()> {
+    let read = match fs::read_dir(dir) {
+        Ok(read) => read,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    let names = listing
+        .iter()
+        .map(DumpDirEntry::name)
+        .collect::<HashSet<_>>();
+    for child in read {
+        let child = child?;
+        if names.contains(Path::new(&child.file_name())) {
+            continue;
+        }
+        if child.file_type()?.is_dir() {
+            fs::remove_dir_all(child.path())?;
+        } else {
+            fs::remove_file(child.path())?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,151 @@
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+use crate::{Digest, DigestAlgorithm, Entry};
+
+/// A record of the regular files an unpack has finished writing, letting an
+/// interrupted unpack of the same archive pick up where it left off.
+///
+/// The journal is a text file with a line for each file, giving the position
+/// of its header in the archive, its size and, if the archive computes
+/// digests, the digest of its contents. On resuming, a file is skipped if it
+/// is in the journal and the file at its destination still has the same size
+/// and digest. Everything else, including directories and links, is unpacked
+/// again.
+///
+/// Without digests only the size is checked, so a file whose contents were
+/// lost in a crash after its size reached the disk may be skipped; set a
+/// digest algorithm or `SyncPolicy::Data` on the archive to rule this out.
+pub struct UnpackJournal {
+    path: PathBuf,
+    file: File,
+    done: HashMap<u64, Record>,
+}
+
+struct Record {
+    size: u64,
+    // `algorithm:hex`, as written to the journal
+    digest: Option<String>,
+}
+
+impl UnpackJournal {
+    /// Opens the journal at `path`, reading the files an earlier unpack
+    /// recorded in it, or creates it if it doesn't exist.
+    ///
+    /// Lines which can't be parsed, such as one cut short by the interruption,
+    /// are ignored.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<UnpackJournal> {
+        let path = path.as_ref();
+        let mut file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(path)?;
+        let mut contents = Vec::new();
+        file.read_to_end(&mut contents)?;
+        // Whatever follows the last newline was cut short
+        let mut lines = contents.split(|&b| b == b'\n').collect::<Vec<_>>();
+        lines.pop();
+        let done = lines.into_iter().filter_map(parse_line).collect();
+        Ok(UnpackJournal {
+            path: path.to_path_buf(),
+            file,
+            done,
+        })
+    }
+
+    /// Returns the number of files recorded in this journal.
+    pub fn len(&self) -> usize {
+        self.done.len()
+    }
+
+    /// Returns whether no files are recorded in this journal.
+    pub fn is_empty(&self) -> bool {
+        self.done.is_empty()
+    }
+
+    /// Returns whether `entry` was recorded as unpacked into `dst`, and the
+    /// file there still matches what was recorded.
+    pub fn is_complete<R: Read>(&self, entry: &Entry<'_, R>, dst: &Path) -> io::Result<bool> {
+        if !entry.header().entry_type().is_file() {
+            return Ok(false);
+        }
+        let record = match self.done.get(&entry.raw_header_position()) {
+            Some(record) if record.size == entry.size() => record,
+            _ => return Ok(false),
+        };
+        let path = match entry.destination_in(dst)? {
+            Some(path) => path,
+            None => return Ok(false),
+        };
+        match fs::symlink_metadata(&path) {
+            Ok(meta) if meta.is_file() && meta.len() == record.size => {}
+            _ => return Ok(false),
+        }
+        let (algorithm, hex) = match record.digest.as_ref().and_then(|d| d.split_once(':')) {
+            Some(digest) => digest,
+            None => return Ok(record.digest.is_none()),
+        };
+        let algorithm = match DigestAlgorithm::from_name(algorithm) {
+            Some(algorithm) => algorithm,
+            None => return Ok(false),
+        };
+        let actual = Digest::of_reader(algorithm, &mut File::open(&path)?)?;
+        Ok(actual.to_hex() == hex)
+    }
+
+    /// Records that `entry` has been unpacked, once it has been read to the
+    /// end.
+    ///
+    /// Entries other than regular files aren't recorded.
+    pub fn record<R: Read>(&mut self, entry: &Entry<'_, R>) -> io::Result<()> {
+        if !entry.header().entry_type().is_file() {
+            return Ok(());
+        }
+        let digest = entry
+            .digest()
+            .map(|digest| format!("{}:{}", digest.algorithm(), digest));
+        let mut line = format!(
+            "{} {} {} ",
+            entry.raw_header_position(),
+            entry.size(),
+            digest.as_deref().unwrap_or("-")
+        )
+        .into_bytes();
+        // The path is only there for people reading the journal
+        line.extend(entry.path_bytes().iter().map(|&b| match b {
+            b'\n' => b' ',
+            b => b,
+        }));
+        line.push(b'\n');
+        self.file.write_all(&line)?;
+        self.done.insert(
+            entry.raw_header_position(),
+            Record {
+                size: entry.size(),
+                digest,
+            },
+        );
+        Ok(())
+    }
+
+    /// Removes the journal once the unpack it records has finished.
+    pub fn finish(self) -> io::Result<()> {
+        drop(self.file);
+        fs::remove_file(&self.path)
+    }
+}
+
+fn parse_line(line: &[u8]) -> Option<(u64, Record)> {
+    let mut fields = line.splitn(4, |&b| b == b' ');
+    let position = std::str::from_utf8(fields.next()?).ok()?.parse().ok()?;
+    let size = std::str::from_utf8(fields.next()?).ok()?.parse().ok()?;
+    let digest = match std::str::from_utf8(fields.next()?).ok()? {
+        "-" => None,
+        digest => Some(digest.to_string()),
+    };
+    fields.next()?;
+    Some((position, Record { size, digest }))
+}
@@ -5,6 +5,11 @@
 //! an archive is never required to be fully resident in memory, and all objects
 //! provide largely a streaming interface to read bytes from.
 //!
+//! Reading and writing is blocking, over `std::io` readers and writers. From
+//! async code, run archive work where blocking is allowed, such as in tokio's
+//! `spawn_blocking`, on a thread of its own, or for a whole unpack, with
+//! `Archive::unpack_with_cancel` so that it can be stopped from a task.
+//!
 //! [1]: http://en.wikipedia.org/wiki/Tar_%28computing%29
 
 // More docs about the detailed tar format can also be found here:
@@ -21,24 +26,87 @@
 #![deny(missing_docs)]
 #![cfg_attr(test, deny(warnings))]
 
-use std::io::{Error, ErrorKind};
+use std::io::Error;
 
-pub use crate::archive::{Archive, Entries};
-pub use crate::builder::{Builder, EntryWriter};
-pub use crate::entry::{Entry, Unpacked};
+pub use crate::archive::{
+    Archive, Entries, ParseProfile, RepairReport, SalvageEntries, Salvaged, UnpackReport,
+};
+pub use crate::builder::{Builder, EntryWriter, MergePolicy};
+pub use crate::cancel::{CancellationToken, Cancelled};
+pub use crate::digest::{Digest, DigestAlgorithm};
+#[cfg(feature = "encrypt")]
+pub use crate::encrypt::{DecryptingReader, EncryptingWriter, Identity, Recipient};
+pub use crate::entry::{
+    CaseCollisionPolicy, Difference, Entry, NonUtf8PathPolicy, OverwritePolicy, SymlinkFallback,
+    SyncPolicy, Unpacked,
+};
 pub use crate::entry_type::EntryType;
+pub use crate::file_snapshot::FileSnapshot;
+pub use crate::filesystem::{FileSystem, OsFileSystem};
+pub use crate::format::ArchiveFormat;
 pub use crate::header::GnuExtSparseHeader;
 pub use crate::header::{GnuHeader, GnuSparseHeader, Header, HeaderMode, OldHeader, UstarHeader};
+pub use crate::header_builder::HeaderBuilder;
+#[cfg(feature = "http")]
+pub use crate::http::HttpReader;
+pub use crate::incremental::{DumpDirEntry, Snapshot};
+pub use crate::journal::UnpackJournal;
+pub use crate::manifest::{ManifestFormat, ManifestMismatch};
+pub use crate::metadata::{ArchiveMetadata, EntryMetadata};
+#[cfg(feature = "mmap")]
+pub use crate::mmap::MmapReader;
+pub use crate::normalize::NormalizationForm;
+pub use crate::owner_map::OwnerMap;
 pub use crate::pax::{PaxExtension, PaxExtensions};
+pub use crate::progress::ProgressObserver;
+pub use crate::read_at::{EntryReader, ReadAt};
+#[cfg(feature = "sign")]
+pub use crate::sign::{PublicKey, SecretKey, Signature, SigningWriter};
+#[cfg(feature = "store")]
+pub use crate::store::{StoreReader, StoreWriter};
+pub use crate::tree::{VirtualEntry, VirtualTreeBuilder};
+pub use crate::volume::{MultiVolumeReader, MultiVolumeWriter};
 
 mod archive;
 mod builder;
+mod cancel;
+#[cfg(any(feature = "http", feature = "store"))]
+mod chunks;
+mod digest;
+#[cfg(feature = "encrypt")]
+mod encrypt;
 mod entry;
 mod entry_type;
 mod error;
+mod file_snapshot;
+mod filesystem;
+mod format;
 mod header;
+mod header_builder;
+#[cfg(feature = "http")]
+mod http;
+mod incremental;
+mod journal;
+mod manifest;
+mod metadata;
+#[cfg(feature = "mmap")]
+mod mmap;
+mod normalize;
+mod owner_map;
 mod pax;
+mod progress;
+mod read_at;
+#[cfg(feature = "sign")]
+mod sign;
+mod sniff;
+#[cfg(feature = "store")]
+mod store;
+mod throttle;
+mod tree;
+#[cfg(all(target_os = "linux", feature = "uring"))]
+mod uring;
+mod volume;
 
 fn other(msg: &str) -> Error {
-    Error::new(ErrorKind::Other, msg)
+    Error::other(msg)
 }
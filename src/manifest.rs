@@ -0,0 +1,553 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, BufRead, Read, Write};
+use std::path::PathBuf;
+
+#[cfg(feature = "sha2")]
+use crate::digest::{DigestAlgorithm, Hasher};
+use crate::header::bytes2path;
+use crate::{Difference, Entry, EntryType};
+
+/// The format of a manifest written by `Archive::write_manifest`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum ManifestFormat {
+    /// An mtree(8) specification with a line per member giving its type,
+    /// mode, modification time, and for regular files the size and, with the
+    /// `sha2` feature, SHA-256 digest.
+    Mtree,
+    /// A `sha256sum`-compatible listing of the regular files, which can be
+    /// checked with `sha256sum -c` in the directory the archive was
+    /// unpacked to.
+    #[cfg(feature = "sha2")]
+    Sha256Sums,
+}
+
+/// A way in which an archive doesn't match a manifest, as returned by
+/// `Archive::verify_manifest`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ManifestMismatch {
+    /// A member listed in the manifest isn't in the archive.
+    Missing(PathBuf),
+    /// A member of the archive isn't listed in the manifest.
+    Unlisted(PathBuf),
+    /// A member differs from its manifest entry.
+    Differs(PathBuf, Difference),
+}
+
+impl fmt::Display for ManifestMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ManifestMismatch::Missing(path) => {
+                write!(f, "{}: missing from archive", path.display())
+            }
+            ManifestMismatch::Unlisted(path) => {
+                write!(f, "{}: not in manifest", path.display())
+            }
+            ManifestMismatch::Differs(path, difference) => {
+                write!(f, "{}: {}", path.display(), difference)
+            }
+        }
+    }
+}
+
+/// What a manifest records about a member.
+struct Member {
+    path: Vec<u8>,
+    // The mtree type keyword, or `None` for members manifests leave out
+    kind: Option<&'static str>,
+    mode: u32,
+    mtime: u64,
+    // The size and SHA-256 digest of regular files and hard links to them
+    contents: Option<(u64, Option<String>)>,
+    link: Option<Vec<u8>>,
+    device: Option<(u32, u32)>,
+}
+
+/// The size and digest of each regular file seen so far, as hard links to
+/// them are listed with the same contents.
+type Files = HashMap<Vec<u8>, (u64, Option<String>)>;
+
+impl Member {
+    fn read<R: Read>(entry: &mut Entry<'_, R>, files: &mut Files) -> io::Result<Member> {
+        let path = normalize(&entry.path_bytes());
+        let kind = entry.header().entry_type();
+        let contents = if kind.is_file() || kind.is_contiguous() || kind.is_gnu_sparse() {
+            let contents = (entry.size(), hash(entry)?);
+            files.insert(path.clone(), contents.clone());
+            Some(contents)
+        } else if kind.is_hard_link() {
+            let target = entry.link_name_bytes().map(|name| normalize(&name));
+            target.and_then(|target| files.get(&target).cloned())
+        } else {
+            None
+        };
+        let header = entry.header();
+        let device = if kind == EntryType::Char || kind == EntryType::Block {
+            Some((
+                header.device_major()?.unwrap_or(0),
+                header.device_minor()?.unwrap_or(0),
+            ))
+        } else {
+            None
+        };
+        Ok(Member {
+            kind: match kind {
+                _ if contents.is_some() => Some("file"),
+                EntryType::Directory | EntryType::GNUDumpDir => Some("dir"),
+                EntryType::Symlink => Some("link"),
+                EntryType::Char => Some("char"),
+                EntryType::Block => Some("block"),
+                EntryType::Fifo => Some("fifo"),
+                // Metadata-only members such as volume labels, and hard
+                // links to files not in the archive
+                _ => None,
+            },
+            mode: header.mode()? & 0o7777,
+            mtime: header.mtime()?,
+            link: match kind {
+                EntryType::Symlink => Some(entry.link_name_bytes().unwrap_or_default().into()),
+                _ => None,
+            },
+            path,
+            contents,
+            device,
+        })
+    }
+}
+
+/// Writes the lines of a manifest as an archive's entries are read.
+pub(crate) struct ManifestWriter<'a> {
+    format: ManifestFormat,
+    dst: &'a mut dyn Write,
+    files: Files,
+}
+
+impl<'a> ManifestWriter<'a> {
+    pub(crate) fn new(format: ManifestFormat, dst: &'a mut dyn Write) -> io::Result<Self> {
+        if format == ManifestFormat::Mtree {
+            dst.write_all(b"#mtree\n")?;
+        }
+        Ok(ManifestWriter {
+            format,
+            dst,
+            files: HashMap::new(),
+        })
+    }
+
+    pub(crate) fn add<R: Read>(&mut self, entry: &mut Entry<'_, R>) -> io::Result<()> {
+        let member = Member::read(entry, &mut self.files)?;
+        let kind = match member.kind {
+            Some(kind) => kind,
+            None => return Ok(()),
+        };
+        match self.format {
+            ManifestFormat::Mtree => {
+                if member.path.is_empty() {
+                    write!(self.dst, ". type={}", kind)?;
+                } else {
+                    write!(self.dst, "./{} type={}", escape(&member.path), kind)?;
+                }
+                write!(self.dst, " mode={:04o}", member.mode)?;
+                write!(self.dst, " time={}.000000000", member.mtime)?;
+                if let Some((size, digest)) = &member.contents {
+                    write!(self.dst, " size={}", size)?;
+                    if let Some(digest) = digest {
+                        write!(self.dst, " sha256digest={}", digest)?;
+                    }
+                }
+                if let Some(link) = &member.link {
+                    write!(self.dst, " link={}", escape(link))?;
+                }
+                if let Some((major, minor)) = member.device {
+                    write!(self.dst, " device=native,{},{}", major, minor)?;
+                }
+                self.dst.write_all(b"\n")
+            }
+            #[cfg(feature = "sha2")]
+            ManifestFormat::Sha256Sums => match &member.contents {
+                Some((_, Some(digest))) => write_sum_line(self.dst, digest, &member.path),
+                _ => Ok(()),
+            },
+        }
+    }
+}
+
+/// What a manifest line expects of a member; anything the line doesn't
+/// mention isn't checked.
+#[derive(Default)]
+struct Expected {
+    kind: Option<String>,
+    mode: Option<u32>,
+    mtime: Option<u64>,
+    size: Option<u64>,
+    sha256: Option<String>,
+    link: Option<Vec<u8>>,
+    optional: bool,
+    seen: bool,
+}
+
+/// Checks an archive's entries against a manifest in either of the formats
+/// `ManifestWriter` produces, telling them apart by the first line.
+pub(crate) struct ManifestVerifier {
+    mtree: bool,
+    expected: HashMap<Vec<u8>, Expected>,
+    // Manifest order, to report missing members in
+    order: Vec<Vec<u8>>,
+    files: Files,
+    mismatches: Vec<ManifestMismatch>,
+}
+
+impl ManifestVerifier {
+    pub(crate) fn new(manifest: &mut dyn BufRead) -> io::Result<ManifestVerifier> {
+        let mut lines = Vec::new();
+        let mut line = Vec::new();
+        while manifest.read_until(b'\n', &mut line)? > 0 {
+            if line.last() == Some(&b'\n') {
+                line.pop();
+            }
+            lines.push(std::mem::take(&mut line));
+        }
+        let mtree = lines.first().is_some_and(|l| l.starts_with(b"#mtree"));
+        let mut verifier = ManifestVerifier {
+            mtree,
+            expected: HashMap::new(),
+            order: Vec::new(),
+            files: HashMap::new(),
+            mismatches: Vec::new(),
+        };
+        if mtree {
+            verifier.parse_mtree(&lines)?;
+        } else {
+            verifier.parse_sums(&lines)?;
+        }
+        Ok(verifier)
+    }
+
+    fn insert(&mut self, path: Vec<u8>, expected: Expected) {
+        if self.expected.insert(path.clone(), expected).is_none() {
+            self.order.push(path);
+        }
+    }
+
+    fn parse_sums(&mut self, lines: &[Vec<u8>]) -> io::Result<()> {
+        for (i, line) in lines.iter().enumerate() {
+            if line.is_empty() {
+                continue;
+            }
+            let (escaped, line) = match line.strip_prefix(b"\\") {
+                Some(rest) => (true, rest),
+                None => (false, &line[..]),
+            };
+            let invalid = || invalid_line(i, "expected a digest and a file name");
+            let space = line.iter().position(|&b| b == b' ').ok_or_else(invalid)?;
+            let digest = std::str::from_utf8(&line[..space]).map_err(|_| invalid())?;
+            // Two spaces for text mode, or a space and `*` for binary mode
+            let name = match &line[space + 1..] {
+                [b' ' | b'*', name @ ..] if !name.is_empty() => name,
+                _ => return Err(invalid()),
+            };
+            let name = if escaped {
+                unescape_sum(name)
+            } else {
+                name.to_vec()
+            };
+            let expected = Expected {
+                kind: Some("file".to_string()),
+                sha256: Some(digest.to_ascii_lowercase()),
+                ..Expected::default()
+            };
+            self.insert(normalize(&name), expected);
+        }
+        Ok(())
+    }
+
+    fn parse_mtree(&mut self, lines: &[Vec<u8>]) -> io::Result<()> {
+        let mut defaults: HashMap<String, String> = HashMap::new();
+        // The directory that names without a slash are relative to, in the
+        // classic hierarchical format
+        let mut cwd: Vec<Vec<u8>> = Vec::new();
+        let mut pending = Vec::new();
+        for (i, line) in lines.iter().enumerate() {
+            // Lines ending in a backslash continue on the next line
+            if let Some(rest) = line.strip_suffix(b"\\") {
+                pending.extend_from_slice(rest);
+                pending.push(b' ');
+                continue;
+            }
+            pending.extend_from_slice(line);
+            let line = String::from_utf8(std::mem::take(&mut pending))
+                .map_err(|_| invalid_line(i, "names must be encoded as ASCII"))?;
+            let mut words = line.split_ascii_whitespace();
+            let name = match words.next() {
+                Some(name) if !name.starts_with('#') => name,
+                _ => continue,
+            };
+            match name {
+                "/set" => {
+                    for word in words {
+                        let (key, value) = word.split_once('=').unwrap_or((word, ""));
+                        defaults.insert(key.to_string(), value.to_string());
+                    }
+                    continue;
+                }
+                "/unset" => {
+                    for key in words {
+                        if key == "all" {
+                            defaults.clear();
+                        } else {
+                            defaults.remove(key);
+                        }
+                    }
+                    continue;
+                }
+                ".." => {
+                    cwd.pop();
+                    continue;
+                }
+                _ => {}
+            }
+
+            let mut keywords = defaults.clone();
+            for word in words {
+                let (key, value) = word.split_once('=').unwrap_or((word, ""));
+                keywords.insert(key.to_string(), value.to_string());
+            }
+            let name = unescape_mtree(name.as_bytes());
+            let path = if name.contains(&b'/') {
+                normalize(&name)
+            } else if name == b"." {
+                cwd.join(&b'/')
+            } else {
+                let mut path = cwd.join(&b'/');
+                if !path.is_empty() {
+                    path.push(b'/');
+                }
+                path.extend_from_slice(&name);
+                if keywords.get("type").map(|t| &t[..]) == Some("dir") {
+                    cwd.push(name);
+                }
+                path
+            };
+
+            let number = |key: &str, radix| -> io::Result<Option<u64>> {
+                let value = match keywords.get(key) {
+                    Some(value) => value,
+                    None => return Ok(None),
+                };
+                // Only the whole seconds of a time are checked
+                let digits = value.split('.').next().unwrap_or_default();
+                u64::from_str_radix(digits, radix)
+                    .map(Some)
+                    .map_err(|_| invalid_line(i, &format!("invalid {} `{}`", key, value)))
+            };
+            let expected = Expected {
+                kind: keywords.get("type").cloned(),
+                mode: number("mode", 8)?.map(|mode| mode as u32 & 0o7777),
+                mtime: number("time", 10)?,
+                size: number("size", 10)?,
+                sha256: keywords
+                    .get("sha256digest")
+                    .or_else(|| keywords.get("sha256"))
+                    .map(|digest| digest.to_ascii_lowercase()),
+                link: keywords
+                    .get("link")
+                    .map(|link| unescape_mtree(link.as_bytes())),
+                optional: keywords.contains_key("optional"),
+                seen: false,
+            };
+            self.insert(path, expected);
+        }
+        Ok(())
+    }
+
+    pub(crate) fn check<R: Read>(&mut self, entry: &mut Entry<'_, R>) -> io::Result<()> {
+        let member = Member::read(entry, &mut self.files)?;
+        let kind = match member.kind {
+            Some(kind) => kind,
+            None => return Ok(()),
+        };
+        let path = bytes2path(Cow::Borrowed(&member.path))?.into_owned();
+        let expected = match self.expected.get_mut(&member.path) {
+            Some(expected) => expected,
+            // A sha256sum listing only covers regular files
+            None if !self.mtree && kind != "file" => return Ok(()),
+            None => {
+                self.mismatches.push(ManifestMismatch::Unlisted(path));
+                return Ok(());
+            }
+        };
+        expected.seen = true;
+
+        let mut differences = Vec::new();
+        if expected.kind.as_deref().is_some_and(|k| k != kind) {
+            differences.push(Difference::Type);
+        } else {
+            let (size, digest) = match &member.contents {
+                Some((size, digest)) => (Some(*size), digest.as_deref()),
+                None => (None, None),
+            };
+            if expected.size.is_some() && expected.size != size {
+                differences.push(Difference::Size);
+            }
+            // Without the `sha2` feature there is no digest to check
+            if let (Some(expected), Some(actual)) = (&expected.sha256, digest) {
+                if expected != actual {
+                    differences.push(Difference::Contents);
+                }
+            }
+            if expected.link.is_some() && expected.link != member.link {
+                differences.push(Difference::LinkName);
+            }
+        }
+        if expected.mode.is_some_and(|mode| mode != member.mode) {
+            differences.push(Difference::Mode);
+        }
+        if expected.mtime.is_some_and(|mtime| mtime != member.mtime) {
+            differences.push(Difference::Mtime);
+        }
+        for difference in differences {
+            self.mismatches
+                .push(ManifestMismatch::Differs(path.clone(), difference));
+        }
+        Ok(())
+    }
+
+    pub(crate) fn finish(mut self) -> io::Result<Vec<ManifestMismatch>> {
+        for name in &self.order {
+            let expected = &self.expected[name];
+            // Archives often leave out the root directory itself
+            if !expected.seen && !expected.optional && !name.is_empty() {
+                let path = bytes2path(Cow::Borrowed(name))?.into_owned();
+                self.mismatches.push(ManifestMismatch::Missing(path));
+            }
+        }
+        Ok(self.mismatches)
+    }
+}
+
+fn invalid_line(index: usize, msg: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("invalid manifest line {}: {}", index + 1, msg),
+    )
+}
+
+#[cfg(feature = "sha2")]
+fn hash<R: Read>(entry: &mut Entry<'_, R>) -> io::Result<Option<String>> {
+    let mut hasher = Hasher::new(DigestAlgorithm::Sha256);
+    io::copy(entry, &mut hasher)?;
+    Ok(Some(hasher.finish().to_hex()))
+}
+
+#[cfg(not(feature = "sha2"))]
+fn hash<R: Read>(_entry: &mut Entry<'_, R>) -> io::Result<Option<String>> {
+    Ok(None)
+}
+
+/// Writes a `sha256sum` line, which like coreutils marks names containing a
+/// backslash or newline with a leading backslash and escapes those.
+#[cfg(feature = "sha2")]
+fn write_sum_line(dst: &mut dyn Write, digest: &str, path: &[u8]) -> io::Result<()> {
+    if path.contains(&b'\\') || path.contains(&b'\n') {
+        let mut escaped = Vec::with_capacity(path.len());
+        for &b in path {
+            match b {
+                b'\\' => escaped.extend_from_slice(b"\\\\"),
+                b'\n' => escaped.extend_from_slice(b"\\n"),
+                b => escaped.push(b),
+            }
+        }
+        write!(dst, "\\{}  ", digest)?;
+        dst.write_all(&escaped)?;
+    } else {
+        write!(dst, "{}  ", digest)?;
+        dst.write_all(path)?;
+    }
+    dst.write_all(b"\n")
+}
+
+/// Reverses the escaping of `write_sum_line`.
+fn unescape_sum(name: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(name.len());
+    let mut bytes = name.iter().peekable();
+    while let Some(&b) = bytes.next() {
+        match (b, bytes.peek()) {
+            (b'\\', Some(b'n')) => {
+                out.push(b'\n');
+                bytes.next();
+            }
+            (b'\\', Some(b'\\')) => {
+                out.push(b'\\');
+                bytes.next();
+            }
+            (b, _) => out.push(b),
+        }
+    }
+    out
+}
+
+/// Strips the `./` prefixes and trailing slashes that archivers commonly
+/// add, so every member is named relative to the archive root.
+fn normalize(path: &[u8]) -> Vec<u8> {
+    let mut path = path;
+    while let Some(rest) = path.strip_prefix(b"./") {
+        path = rest;
+    }
+    while let Some(rest) = path.strip_suffix(b"/") {
+        path = rest;
+    }
+    if path == b"." {
+        path = b"";
+    }
+    path.to_vec()
+}
+
+/// Encodes a name the way mtree(5) expects, with whitespace, `#`, `\` and
+/// non-printable bytes written as three octal digits after a backslash.
+fn escape(name: &[u8]) -> String {
+    let mut out = String::with_capacity(name.len());
+    for &b in name {
+        if b.is_ascii_graphic() && b != b'\\' && b != b'#' {
+            out.push(b as char);
+        } else {
+            out.push_str(&format!("\\{:03o}", b));
+        }
+    }
+    out
+}
+
+/// Decodes a name encoded by `escape`, or with the single character escapes
+/// such as `\s` that other mtree writers use.
+fn unescape_mtree(name: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(name.len());
+    let mut i = 0;
+    while i < name.len() {
+        let octal = name
+            .get(i + 1..i + 4)
+            .filter(|digits| digits.iter().all(|d| (b'0'..=b'7').contains(d)));
+        match (name[i], octal, name.get(i + 1)) {
+            (b'\\', Some(digits), _) => {
+                let value = digits.iter().fold(0u32, |n, d| n * 8 + (d - b'0') as u32);
+                out.push(value as u8);
+                i += 4;
+            }
+            (b'\\', None, Some(&c)) => {
+                out.push(match c {
+                    b's' => b' ',
+                    b't' => b'\t',
+                    b'n' => b'\n',
+                    b'r' => b'\r',
+                    c => c,
+                });
+                i += 2;
+            }
+            (b, _, _) => {
+                out.push(b);
+                i += 1;
+            }
+        }
+    }
+    out
+}
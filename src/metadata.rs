@@ -0,0 +1,170 @@
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::io::{self, Read};
+use std::path::PathBuf;
+
+use crate::pax::{PAX_COMMENT, PAX_TARRS_REVISION, PAX_TARRS_TOOL};
+use crate::{Entry, EntryType};
+
+/// A snapshot of what an archive says about an entry, detached from the
+/// archive so it can be kept, compared or sent elsewhere.
+///
+/// With the `serde` feature this can be serialized and deserialized, so
+/// listings can be exchanged as JSON or any other format serde supports.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct EntryMetadata {
+    /// The path of the entry, from any GNU long name or PAX header.
+    pub path: PathBuf,
+    /// The size of the entry's contents, which for sparse files is their
+    /// size once unpacked.
+    pub size: u64,
+    /// The permission bits.
+    pub mode: u32,
+    /// The owner's user ID.
+    pub uid: u64,
+    /// The owner's group ID.
+    pub gid: u64,
+    /// The modification time, in seconds since the Unix epoch.
+    pub mtime: u64,
+    /// The kind of entry.
+    pub entry_type: EntryType,
+    /// The target of a symlink or hard link.
+    pub link_name: Option<PathBuf>,
+}
+
+impl EntryMetadata {
+    /// Takes a snapshot of the metadata of `entry`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a field of the entry's header is corrupt, or if
+    /// its path isn't valid Unicode on Windows.
+    pub fn from_entry<R: Read>(entry: &Entry<'_, R>) -> io::Result<EntryMetadata> {
+        let header = entry.header();
+        Ok(EntryMetadata {
+            path: entry.path()?.into_owned(),
+            size: entry.size(),
+            mode: header.mode()?,
+            uid: header.uid()?,
+            gid: header.gid()?,
+            mtime: header.mtime()?,
+            entry_type: header.entry_type(),
+            link_name: entry.link_name()?.map(Cow::into_owned),
+        })
+    }
+}
+
+/// Metadata describing a whole archive rather than one of its entries, such
+/// as a comment, the source revision it was made from or the tool which made
+/// it, kept in a global PAX header.
+///
+/// `Builder::append_archive_metadata` writes it, usually before any entries,
+/// and `Entries::archive_metadata` reads it back. Besides the fields with
+/// accessors, applications can keep their own records with `set`, under a
+/// key prefixed with their name as PAX asks of vendor records.
+///
+/// # Examples
+///
+/// ```
+/// use tar::{Archive, ArchiveMetadata, Builder, Header};
+///
+/// let mut metadata = ArchiveMetadata::new();
+/// metadata.set_comment("nightly build");
+/// metadata.set_revision("4b825dc642cb6eb9a060e54bf8d69288fbee4904");
+/// let mut ar = Builder::new(Vec::new());
+/// ar.append_archive_metadata(&metadata).unwrap();
+/// let mut header = Header::new_ustar();
+/// header.set_size(0);
+/// ar.append_data(&mut header, "README", &[][..]).unwrap();
+/// let data = ar.into_inner().unwrap();
+///
+/// let mut ar = Archive::new(&data[..]);
+/// let mut entries = ar.entries().unwrap();
+/// entries.next().unwrap().unwrap();
+/// assert_eq!(entries.archive_metadata(), metadata);
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ArchiveMetadata {
+    records: BTreeMap<String, String>,
+}
+
+impl ArchiveMetadata {
+    /// Creates metadata with no records.
+    pub fn new() -> ArchiveMetadata {
+        ArchiveMetadata::default()
+    }
+
+    /// Returns the comment describing the archive, such as the commit ID
+    /// `git archive` stores.
+    pub fn comment(&self) -> Option<&str> {
+        self.get(PAX_COMMENT)
+    }
+
+    /// Sets the comment describing the archive, in the standard `comment`
+    /// record.
+    pub fn set_comment<S: Into<String>>(&mut self, comment: S) {
+        self.set(PAX_COMMENT, comment);
+    }
+
+    /// Returns the revision of the sources the archive was made from.
+    pub fn revision(&self) -> Option<&str> {
+        self.get(PAX_TARRS_REVISION)
+    }
+
+    /// Sets the revision of the sources the archive was made from, such as a
+    /// commit ID or release tag.
+    pub fn set_revision<S: Into<String>>(&mut self, revision: S) {
+        self.set(PAX_TARRS_REVISION, revision);
+    }
+
+    /// Returns the name of the tool which made the archive.
+    pub fn tool(&self) -> Option<&str> {
+        self.get(PAX_TARRS_TOOL)
+    }
+
+    /// Sets the name of the tool which made the archive, conventionally
+    /// followed by its version, such as `mytool 1.2.0`.
+    pub fn set_tool<S: Into<String>>(&mut self, tool: S) {
+        self.set(PAX_TARRS_TOOL, tool);
+    }
+
+    /// Returns the value of the record `key`.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.records.get(key).map(String::as_str)
+    }
+
+    /// Sets the record `key` to `value`.
+    ///
+    /// An empty value removes the record when the metadata is read back, as
+    /// it does in a global PAX header.
+    pub fn set<K: Into<String>, V: Into<String>>(&mut self, key: K, value: V) {
+        self.records.insert(key.into(), value.into());
+    }
+
+    /// Returns every record, sorted by key.
+    pub fn records(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.records
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_str()))
+    }
+
+    /// Returns whether there are no records.
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+
+    /// Collects the global PAX records read from an archive, leaving out any
+    /// which aren't valid UTF-8.
+    pub(crate) fn from_records(records: &[(Vec<u8>, Vec<u8>)]) -> ArchiveMetadata {
+        let records = records
+            .iter()
+            .filter_map(|(key, value)| {
+                let key = String::from_utf8(key.clone()).ok()?;
+                let value = String::from_utf8(value.clone()).ok()?;
+                Some((key, value))
+            })
+            .collect();
+        ArchiveMetadata { records }
+    }
+}
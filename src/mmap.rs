@@ -0,0 +1,83 @@
+use std::cmp;
+use std::fs::File;
+use std::io::{self, BufRead, Read, Seek, SeekFrom};
+use std::path::Path;
+use std::sync::Arc;
+
+/// A reader over a file mapped into memory, as read by `Archive::from_mmap`.
+///
+/// Reads copy straight out of the mapping without any system calls, and
+/// `fill_buf` returns the rest of the file without copying it at all.
+pub struct MmapReader {
+    map: Arc<memmap2::Mmap>,
+    pos: u64,
+}
+
+impl MmapReader {
+    /// Maps the file at `path` into memory.
+    ///
+    /// The file must not be truncated while it is mapped: reading a part of
+    /// the mapping which is no longer backed by the file kills the process
+    /// with `SIGBUS` on Unix.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<MmapReader> {
+        let file = File::open(path)?;
+        // SAFETY: the mapping is read only, and the caller is told above
+        // what happens if the file changes underneath it.
+        let map = unsafe { memmap2::Mmap::map(&file)? };
+        #[cfg(unix)]
+        let _ = map.advise(memmap2::Advice::Sequential);
+        Ok(MmapReader {
+            map: Arc::new(map),
+            pos: 0,
+        })
+    }
+
+    /// Returns the whole of the mapped file.
+    pub fn as_slice(&self) -> &[u8] {
+        &self.map
+    }
+
+    pub(crate) fn mapping(&self) -> Arc<memmap2::Mmap> {
+        self.map.clone()
+    }
+
+    fn remaining(&self) -> &[u8] {
+        let start = cmp::min(self.pos, self.map.len() as u64) as usize;
+        &self.map[start..]
+    }
+}
+
+impl Read for MmapReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.remaining().read(buf)?;
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl BufRead for MmapReader {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        Ok(self.remaining())
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.pos += amt as u64;
+    }
+}
+
+impl Seek for MmapReader {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let pos = match pos {
+            SeekFrom::Start(n) => Some(n),
+            SeekFrom::End(n) => (self.map.len() as u64).checked_add_signed(n),
+            SeekFrom::Current(n) => self.pos.checked_add_signed(n),
+        };
+        self.pos = pos.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )
+        })?;
+        Ok(self.pos)
+    }
+}
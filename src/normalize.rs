@@ -0,0 +1,63 @@
+// Without the `unicode-normalization` feature `NormalizationForm` has no
+// variants, leaving everything here unreachable.
+#![cfg_attr(
+    not(feature = "unicode-normalization"),
+    allow(unused, unreachable_code)
+)]
+
+use std::borrow::Cow;
+use std::path::{Path, PathBuf};
+
+#[cfg(feature = "unicode-normalization")]
+use unicode_normalization::{is_nfc, is_nfd, UnicodeNormalization};
+
+/// A Unicode normalization form member paths are converted to, so that names
+/// written by systems which normalize differently, such as macOS (NFD) and
+/// Linux (which usually ends up with NFC), don't unpack as distinct files
+/// which look the same.
+///
+/// The forms are available when the `unicode-normalization` crate feature is
+/// enabled.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum NormalizationForm {
+    /// Canonical composition, as Linux and Windows tools usually write names.
+    #[cfg(feature = "unicode-normalization")]
+    Nfc,
+    /// Canonical decomposition, as HFS+ on macOS stores names.
+    #[cfg(feature = "unicode-normalization")]
+    Nfd,
+}
+
+/// Converts `name` to `form`, borrowing it if it is already normalized.
+pub(crate) fn normalize_str(name: &str, form: NormalizationForm) -> Cow<'_, str> {
+    match form {
+        #[cfg(feature = "unicode-normalization")]
+        NormalizationForm::Nfc if is_nfc(name) => Cow::Borrowed(name),
+        #[cfg(feature = "unicode-normalization")]
+        NormalizationForm::Nfc => Cow::Owned(name.nfc().collect()),
+        #[cfg(feature = "unicode-normalization")]
+        NormalizationForm::Nfd if is_nfd(name) => Cow::Borrowed(name),
+        #[cfg(feature = "unicode-normalization")]
+        NormalizationForm::Nfd => Cow::Owned(name.nfd().collect()),
+    }
+}
+
+/// Converts `path` to `form` if there is one.
+///
+/// Separators are left alone by every form, so the whole path is converted
+/// at once. Paths which aren't valid UTF-8 have no normalized form, so are
+/// returned unchanged.
+pub(crate) fn normalize_path(
+    path: Cow<'_, Path>,
+    form: Option<NormalizationForm>,
+) -> Cow<'_, Path> {
+    let normalized = match (form, path.to_str()) {
+        (Some(form), Some(name)) => match normalize_str(name, form) {
+            Cow::Owned(name) => Some(PathBuf::from(name)),
+            Cow::Borrowed(_) => None,
+        },
+        _ => None,
+    };
+    normalized.map_or(path, Cow::Owned)
+}
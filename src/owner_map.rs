@@ -0,0 +1,143 @@
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, Read};
+
+use crate::entry::{gid_by_name, uid_by_name};
+use crate::other;
+
+/// Translations of the users and groups owning entries to the ids they are
+/// given when unpacked with `Archive::set_preserve_ownerships`, for
+/// restoring an archive on a system whose accounts have different ids.
+///
+/// An entry's owner is looked up by its user name first and then by its
+/// numeric uid, and likewise for its group. Owners with no translation are
+/// restored as usual.
+///
+/// # Examples
+///
+/// ```
+/// use tar::OwnerMap;
+///
+/// let mut map = OwnerMap::new();
+/// map.map_uid(1000, 1001);
+/// map.read_groups("staff +50\n+20 wheel:0\n".as_bytes()).unwrap();
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct OwnerMap {
+    users: IdMap,
+    groups: IdMap,
+}
+
+#[derive(Clone, Debug, Default)]
+struct IdMap {
+    ids: HashMap<u64, u64>,
+    names: HashMap<Vec<u8>, u64>,
+}
+
+impl IdMap {
+    fn get(&self, id: u64, name: Option<&[u8]>) -> Option<u64> {
+        name.and_then(|name| self.names.get(name))
+            .or_else(|| self.ids.get(&id))
+            .copied()
+    }
+
+    fn read<R: Read>(&mut self, reader: R, by_name: fn(&[u8]) -> Option<u64>) -> io::Result<()> {
+        for line in BufReader::new(reader).lines() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut fields = line.split_whitespace();
+            let (from, to) = match (fields.next(), fields.next(), fields.next()) {
+                (Some(from), Some(to), None) => (from, to),
+                _ => return Err(other(&format!("invalid owner map line: {}", line))),
+            };
+            let to = match to.strip_prefix('+').or_else(|| Some(to.split_once(':')?.1)) {
+                Some(id) => id.parse().ok(),
+                None => by_name(to.as_bytes()),
+            }
+            .ok_or_else(|| other(&format!("no id to map to in owner map line: {}", line)))?;
+            match from.strip_prefix('+') {
+                Some(id) => {
+                    let id = id
+                        .parse()
+                        .map_err(|_| other(&format!("invalid id in owner map line: {}", line)))?;
+                    self.ids.insert(id, to);
+                }
+                None => {
+                    self.names.insert(from.as_bytes().to_vec(), to);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl OwnerMap {
+    /// Creates a map which translates nothing.
+    pub fn new() -> OwnerMap {
+        OwnerMap::default()
+    }
+
+    /// Restores entries owned by the user with uid `from` as owned by `to`.
+    pub fn map_uid(&mut self, from: u64, to: u64) {
+        self.users.ids.insert(from, to);
+    }
+
+    /// Restores entries owned by the group with gid `from` as owned by `to`.
+    pub fn map_gid(&mut self, from: u64, to: u64) {
+        self.groups.ids.insert(from, to);
+    }
+
+    /// Restores entries owned by the user called `from` as owned by `to`.
+    pub fn map_user(&mut self, from: &str, to: u64) {
+        self.users.names.insert(from.as_bytes().to_vec(), to);
+    }
+
+    /// Restores entries owned by the group called `from` as owned by `to`.
+    pub fn map_group(&mut self, from: &str, to: u64) {
+        self.groups.names.insert(from.as_bytes().to_vec(), to);
+    }
+
+    /// Adds the user translations in a file in the format of GNU tar's
+    /// `--owner-map`.
+    ///
+    /// Each line holds the user to translate, as a name or `+` and a uid,
+    /// followed by the user to restore it as, as `+` and a uid, a name and
+    /// uid separated by `:`, or a name looked up on this system. Blank lines
+    /// and lines starting with `#` are ignored.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a line is malformed or names a user to restore as
+    /// which doesn't exist.
+    pub fn read_users<R: Read>(&mut self, reader: R) -> io::Result<()> {
+        self.users.read(reader, uid_by_name)
+    }
+
+    /// Adds the group translations in a file in the format of GNU tar's
+    /// `--group-map`, which is the same as that of `read_users`.
+    pub fn read_groups<R: Read>(&mut self, reader: R) -> io::Result<()> {
+        self.groups.read(reader, gid_by_name)
+    }
+
+    /// Returns whether this map translates nothing.
+    pub fn is_empty(&self) -> bool {
+        self.users.ids.is_empty()
+            && self.users.names.is_empty()
+            && self.groups.ids.is_empty()
+            && self.groups.names.is_empty()
+    }
+
+    /// Returns the uid to restore an entry owned by `uid` and the user
+    /// called `name` as, if it is translated.
+    pub(crate) fn uid(&self, uid: u64, name: Option<&[u8]>) -> Option<u64> {
+        self.users.get(uid, name)
+    }
+
+    /// Returns the gid to restore an entry owned by `gid` and the group
+    /// called `name` as, if it is translated.
+    pub(crate) fn gid(&self, gid: u64, name: Option<&[u8]>) -> Option<u64> {
+        self.groups.get(gid, name)
+    }
+}
@@ -1,10 +1,11 @@
 #![allow(dead_code)]
 use std::io;
-use std::io::Write;
+use std::io::{Read, Write};
+use std::path::Path;
 use std::slice;
 use std::str;
 
-use crate::other;
+use crate::{other, ArchiveMetadata};
 
 // Keywords for PAX extended header records.
 pub const PAX_NONE: &str = ""; // Indicates that no PAX key is suitable
@@ -19,9 +20,32 @@ pub const PAX_MTIME: &str = "mtime";
 pub const PAX_ATIME: &str = "atime";
 pub const PAX_CTIME: &str = "ctime"; // Removed from later revision of PAX spec, but was valid
 pub const PAX_CHARSET: &str = "charset"; // Currently unused
-pub const PAX_COMMENT: &str = "comment"; // Currently unused
+pub const PAX_COMMENT: &str = "comment";
+
+// Records of this crate's own describing a whole archive, in a global header.
+pub const PAX_TARRS_REVISION: &str = "TARRS.revision";
+pub const PAX_TARRS_TOOL: &str = "TARRS.tool";
 
 pub const PAX_SCHILYXATTR: &str = "SCHILY.xattr.";
+// Where GNU tar stores the SELinux context of a file.
+pub const PAX_RHTSELINUX: &str = "RHT.security.selinux";
+
+// The extended attribute holding the SELinux context of a file.
+pub const SELINUX_XATTR: &str = "security.selinux";
+// The flags of a file as named by BSD `chflags`, or its Windows attributes as
+// named by libarchive.
+pub const PAX_SCHILYFFLAGS: &str = "SCHILY.fflags";
+
+// The Windows file attributes kept in `SCHILY.fflags`, and their names there.
+pub const WINDOWS_FILE_FLAGS: [(u32, &str); 3] =
+    [(0x1, "rdonly"), (0x2, "hidden"), (0x4, "system")];
+
+// The prefix of the extended attributes holding macOS metadata, such as
+// Finder info and resource forks.
+pub const APPLE_XATTR_PREFIX: &str = "com.apple.";
+
+// The name of global extended headers, as git writes them.
+pub const GLOBAL_HEADER_NAME: &str = "pax_global_header";
 
 // Keywords for GNU sparse files in a PAX extended header.
 pub const PAX_GNUSPARSE: &str = "GNU.sparse.";
@@ -84,12 +108,46 @@ pub fn pax_extensions_value(a: &[u8], key: &str) -> Option<u64> {
     None
 }
 
+/// Parses a time in a PAX record, a possibly negative number of seconds since
+/// the epoch with an optional fraction, into seconds and nanoseconds.
+pub fn parse_pax_time(value: &[u8]) -> Option<(i64, u32)> {
+    let value = str::from_utf8(value).ok()?;
+    let (negative, value) = match value.strip_prefix('-') {
+        Some(value) => (true, value),
+        None => (false, value),
+    };
+    let (secs, frac) = value.split_once('.').unwrap_or((value, ""));
+    if secs.is_empty() || !frac.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let secs = secs.parse::<i64>().ok()?;
+    let mut nanos = 0;
+    for (i, digit) in frac.bytes().take(9).enumerate() {
+        nanos += u32::from(digit - b'0') * 10u32.pow(8 - i as u32);
+    }
+    Some(match (negative, nanos) {
+        (false, _) => (secs, nanos),
+        (true, 0) => (-secs, 0),
+        (true, _) => (-secs - 1, 1_000_000_000 - nanos),
+    })
+}
+
+/// Formats a time as seconds and nanoseconds since the epoch for a PAX
+/// record, the inverse of `parse_pax_time`.
+pub fn format_pax_time(secs: i64, nanos: u32) -> String {
+    match (secs, nanos) {
+        (_, 0) => secs.to_string(),
+        (0.., _) => format!("{}.{:09}", secs, nanos),
+        _ => format!("-{}.{:09}", -(secs + 1), 1_000_000_000 - nanos),
+    }
+}
+
 impl<'entry> Iterator for PaxExtensions<'entry> {
     type Item = io::Result<PaxExtension<'entry>>;
 
     fn next(&mut self) -> Option<io::Result<PaxExtension<'entry>>> {
         let line = match self.data.next() {
-            Some(line) if line.is_empty() => return None,
+            Some([]) => return None,
             Some(line) => line,
             None => return None,
         };
@@ -158,38 +216,209 @@ impl<T: Write> crate::Builder<T> {
         &mut self,
         headers: impl IntoIterator<Item = (&'key str, &'value [u8])>,
     ) -> Result<(), io::Error> {
-        // Store the headers formatted before write
-        let mut data: Vec<u8> = Vec::new();
-
-        // For each key in headers, convert into a sized space and add it to data.
-        // This will then be written in the file
-        for (key, value) in headers {
-            let mut len_len = 1;
-            let mut max_len = 10;
-            let rest_len = 3 + key.len() + value.len();
-            while rest_len + len_len >= max_len {
-                len_len += 1;
-                max_len *= 10;
-            }
-            let len = rest_len + len_len;
-            write!(&mut data, "{} {}=", len, key)?;
-            data.extend_from_slice(value);
-            data.push(b'\n');
+        append_pax_extensions(
+            &mut self.dst(),
+            headers
+                .into_iter()
+                .map(|(key, value)| (key.as_bytes(), value)),
+        )
+    }
+
+    /// Appends a global PAX extended header holding `records`, which apply
+    /// to every entry after it, such as the `comment` holding the commit ID
+    /// `git archive` writes.
+    ///
+    /// Records in a later global header replace those with the same key, and
+    /// one with an empty value removes the record. An entry's own records
+    /// take precedence over global ones. `Archive::entries` applies global
+    /// headers to the entries after them rather than yielding them.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use tar::{Builder, Header};
+    ///
+    /// let mut ar = Builder::new(Vec::new());
+    /// ar.set_global_pax([("comment", &b"4b825dc642cb6eb9a060e54bf8d69288fbee4904"[..])])
+    ///     .unwrap();
+    /// let mut header = Header::new_ustar();
+    /// header.set_size(0);
+    /// ar.append_data(&mut header, "README", &[][..]).unwrap();
+    /// let data = ar.into_inner().unwrap();
+    /// ```
+    pub fn set_global_pax<'key, 'value>(
+        &mut self,
+        records: impl IntoIterator<Item = (&'key str, &'value [u8])>,
+    ) -> io::Result<()> {
+        append_pax_header(
+            &mut self.dst(),
+            crate::EntryType::XGlobalHeader,
+            records
+                .into_iter()
+                .map(|(key, value)| (key.as_bytes(), value)),
+        )
+    }
+
+    /// Appends `metadata` describing the whole archive, as a global PAX
+    /// extended header which `Entries::archive_metadata` reads back.
+    ///
+    /// This is usually called before appending any entries, so that readers
+    /// find the metadata at the start of the archive.
+    pub fn append_archive_metadata(&mut self, metadata: &ArchiveMetadata) -> io::Result<()> {
+        self.set_global_pax(
+            metadata
+                .records()
+                .map(|(key, value)| (key, value.as_bytes())),
+        )
+    }
+
+    /// Appends an entry preceded by a PAX extended header holding `records`,
+    /// which describe it, such as an `mtime` with a fraction of a second or
+    /// `SCHILY.xattr.*` extended attributes.
+    ///
+    /// The entry's path and checksum are set as `append_data` sets them.
+    /// Records are written as given, so must be ones readers understand;
+    /// `Entry::pax_records` reads them back.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use tar::{Builder, Header};
+    ///
+    /// let mut ar = Builder::new(Vec::new());
+    /// let mut header = Header::new_ustar();
+    /// header.set_size(4);
+    /// header.set_mode(0o644);
+    /// let records = [
+    ///     ("mtime", &b"1700000000.5"[..]),
+    ///     ("SCHILY.xattr.user.origin", b"web"),
+    /// ];
+    /// ar.append_pax_records(&mut header, "foo", records, &b"data"[..])
+    ///     .unwrap();
+    /// let data = ar.into_inner().unwrap();
+    /// ```
+    pub fn append_pax_records<'key, 'value, P: AsRef<Path>, R: Read>(
+        &mut self,
+        header: &mut crate::Header,
+        path: P,
+        records: impl IntoIterator<Item = (&'key str, &'value [u8])>,
+        data: R,
+    ) -> io::Result<()> {
+        self.append_pax_extensions(records)?;
+        self.append_data(header, path, data)
+    }
+}
+
+/// Writes a PAX extended header holding `headers` to `dst`, or nothing if
+/// there are none.
+pub(crate) fn append_pax_extensions<'key, 'value>(
+    dst: &mut dyn Write,
+    headers: impl IntoIterator<Item = (&'key [u8], &'value [u8])>,
+) -> io::Result<()> {
+    append_pax_header(dst, crate::EntryType::XHeader, headers)
+}
+
+/// Writes a PAX extended header of type `kind`, local or global, holding
+/// `headers` to `dst`, or nothing if there are none.
+fn append_pax_header<'key, 'value>(
+    dst: &mut dyn Write,
+    kind: crate::EntryType,
+    headers: impl IntoIterator<Item = (&'key [u8], &'value [u8])>,
+) -> io::Result<()> {
+    let data = pax_records_data(headers)?;
+
+    // Ignore the header append if it's empty.
+    if data.is_empty() {
+        return Ok(());
+    }
+
+    // Create a header of type XHeader, set the size to the length of the
+    // data, set the entry type to XHeader, and set the checksum
+    // then append the header and the data to the archive.
+    let mut header = crate::Header::new_ustar();
+    if kind == crate::EntryType::XGlobalHeader {
+        // The name git uses, which tars that don't know PAX unpack it as
+        header.set_path(GLOBAL_HEADER_NAME)?;
+    }
+    let mut data_as_bytes: &[u8] = &data;
+    header.set_size(data_as_bytes.len() as u64);
+    header.set_entry_type(kind);
+    header.set_cksum();
+    crate::builder::append(dst, &header, &mut data_as_bytes)
+}
+
+/// Formats `headers` as the records of a PAX extended header.
+pub(crate) fn pax_records_data<'key, 'value>(
+    headers: impl IntoIterator<Item = (&'key [u8], &'value [u8])>,
+) -> io::Result<Vec<u8>> {
+    // Store the headers formatted before write
+    let mut data: Vec<u8> = Vec::new();
+
+    // For each key in headers, convert into a sized space and add it to data.
+    // This will then be written in the file
+    for (key, value) in headers {
+        let mut len_len = 1;
+        let mut max_len = 10;
+        let rest_len = 3 + key.len() + value.len();
+        while rest_len + len_len >= max_len {
+            len_len += 1;
+            max_len *= 10;
         }
+        let len = rest_len + len_len;
+        write!(&mut data, "{} ", len)?;
+        data.extend_from_slice(key);
+        data.push(b'=');
+        data.extend_from_slice(value);
+        data.push(b'\n');
+    }
+    Ok(data)
+}
 
-        // Ignore the header append if it's empty.
-        if data.is_empty() {
-            return Ok(());
+/// Records from global PAX extended headers, which apply to every entry after
+/// them until they are changed.
+#[derive(Default)]
+pub(crate) struct GlobalPax {
+    records: Vec<(Vec<u8>, Vec<u8>)>,
+}
+
+impl GlobalPax {
+    pub(crate) fn records(&self) -> &[(Vec<u8>, Vec<u8>)] {
+        &self.records
+    }
+
+    /// Applies the records of a global extended header, where an empty value
+    /// removes the record for its key.
+    pub(crate) fn update(&mut self, data: &[u8]) -> io::Result<()> {
+        for ext in PaxExtensions::new(data) {
+            let ext = ext?;
+            self.records.retain(|(key, _)| key != ext.key_bytes());
+            if !ext.value_bytes().is_empty() {
+                self.records
+                    .push((ext.key_bytes().to_vec(), ext.value_bytes().to_vec()));
+            }
         }
+        Ok(())
+    }
 
-        // Create a header of type XHeader, set the size to the length of the
-        // data, set the entry type to XHeader, and set the checksum
-        // then append the header and the data to the archive.
-        let mut header = crate::Header::new_ustar();
-        let data_as_bytes: &[u8] = &data;
-        header.set_size(data_as_bytes.len() as u64);
-        header.set_entry_type(crate::EntryType::XHeader);
-        header.set_cksum();
-        self.append(&header, data_as_bytes)
+    /// Returns the records of an entry with the local extended header
+    /// `local`, if any: the global records it doesn't override, followed by
+    /// its own.
+    pub(crate) fn apply(&self, local: Option<Vec<u8>>) -> io::Result<Option<Vec<u8>>> {
+        if self.records.is_empty() {
+            return Ok(local);
+        }
+        let local = local.unwrap_or_default();
+        let overridden = PaxExtensions::new(&local)
+            .filter_map(|ext| ext.ok())
+            .map(|ext| ext.key_bytes())
+            .collect::<Vec<_>>();
+        let mut data = pax_records_data(
+            self.records
+                .iter()
+                .filter(|(key, _)| !overridden.contains(&&key[..]))
+                .map(|(key, value)| (&key[..], &value[..])),
+        )?;
+        data.extend_from_slice(&local);
+        Ok(Some(data))
     }
 }
@@ -0,0 +1,31 @@
+use std::path::Path;
+
+/// Told how unpacking or building an archive is getting on, for showing
+/// progress.
+///
+/// An observer is given to `Archive::set_progress_observer` or
+/// `Builder::progress_observer`, and is called on the thread doing the work.
+/// Every method does nothing by default, so only those of interest need to
+/// be implemented.
+pub trait ProgressObserver {
+    /// Called as an entry starts being unpacked or added, with its path in
+    /// the archive and the size of its contents.
+    fn entry_started(&mut self, path: &Path, size: u64) {
+        let _ = (path, size);
+    }
+
+    /// Called as the contents of the current entry are processed, with how
+    /// many more bytes of them have been written out or archived.
+    fn bytes_processed(&mut self, bytes: u64) {
+        let _ = bytes;
+    }
+
+    /// Called once an entry has been unpacked or added.
+    fn entry_finished(&mut self, path: &Path) {
+        let _ = path;
+    }
+
+    /// Called once a whole archive has been unpacked, or once a builder has
+    /// finished writing it.
+    fn archive_finished(&mut self) {}
+}
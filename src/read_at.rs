@@ -0,0 +1,141 @@
+use std::cmp;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::sync::Arc;
+
+/// A source of data which can be read at any position without a shared
+/// cursor, so that several readers can use it at once.
+///
+/// This is `pread` for files, and plain indexing for data in memory.
+///
+/// On Windows, reading a `File` at a position also moves its cursor, so a
+/// file shared with an `Archive` that is still iterating over its entries
+/// mustn't be read this way until the iteration is finished.
+pub trait ReadAt {
+    /// Reads into `buf` from `offset`, returning how many bytes were read.
+    /// Reading at or past the end returns 0.
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize>;
+}
+
+impl ReadAt for File {
+    #[cfg(unix)]
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        std::os::unix::fs::FileExt::read_at(self, buf, offset)
+    }
+
+    // Windows has no positioned read which leaves the file's cursor alone:
+    // `seek_read` moves it to just past the bytes read, which upsets anything
+    // else reading the same file through its cursor.
+    #[cfg(windows)]
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        std::os::windows::fs::FileExt::seek_read(self, buf, offset)
+    }
+
+    #[cfg(not(any(unix, windows)))]
+    fn read_at(&self, _buf: &mut [u8], _offset: u64) -> io::Result<usize> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+}
+
+impl ReadAt for [u8] {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        let start = cmp::min(offset, self.len() as u64) as usize;
+        (&self[start..]).read(buf)
+    }
+}
+
+impl ReadAt for Vec<u8> {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        self[..].read_at(buf, offset)
+    }
+}
+
+impl<T: ReadAt + ?Sized> ReadAt for &T {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        (**self).read_at(buf, offset)
+    }
+}
+
+impl<T: ReadAt + ?Sized> ReadAt for Arc<T> {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        (**self).read_at(buf, offset)
+    }
+}
+
+/// A reader over the contents of one entry, reading them from their place in
+/// the archive with positioned reads, as returned by `Entry::reader_at`.
+///
+/// Entry readers don't borrow the archive and don't share a position with
+/// it or each other, so several can be read at once, from different threads
+/// if the source allows it.
+#[derive(Clone, Debug)]
+pub struct EntryReader<S> {
+    source: S,
+    start: u64,
+    len: u64,
+    pos: u64,
+}
+
+impl<S: ReadAt> EntryReader<S> {
+    pub(crate) fn new(source: S, start: u64, len: u64) -> EntryReader<S> {
+        EntryReader {
+            source,
+            start,
+            len,
+            pos: 0,
+        }
+    }
+
+    /// Returns the size of the entry's contents.
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Returns whether the entry has no contents.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the source this reader reads from.
+    pub fn into_inner(self) -> S {
+        self.source
+    }
+}
+
+impl<S: ReadAt> Read for EntryReader<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let remaining = self.len.saturating_sub(self.pos);
+        let max = cmp::min(buf.len() as u64, remaining) as usize;
+        if max == 0 {
+            return Ok(0);
+        }
+        let n = self
+            .source
+            .read_at(&mut buf[..max], self.start + self.pos)?;
+        if n == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "archive ended in the middle of an entry",
+            ));
+        }
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl<S: ReadAt> Seek for EntryReader<S> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let pos = match pos {
+            SeekFrom::Start(n) => Some(n),
+            SeekFrom::End(n) => self.len.checked_add_signed(n),
+            SeekFrom::Current(n) => self.pos.checked_add_signed(n),
+        };
+        self.pos = pos.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )
+        })?;
+        Ok(self.pos)
+    }
+}
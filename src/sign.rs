@@ -0,0 +1,369 @@
+use std::fmt;
+use std::io::{self, Read, Write};
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use blake2::digest::consts::U32;
+use blake2::{Blake2b, Blake2b512, Digest};
+use ed25519_dalek::{Signer, Verifier};
+
+/// The algorithm tag of keys, and of signatures over the data itself.
+const ED25519: [u8; 2] = *b"Ed";
+/// The algorithm tag of signatures over the BLAKE2b-512 hash of the data.
+const ED25519_PREHASHED: [u8; 2] = *b"ED";
+const KDF_NONE: [u8; 2] = [0, 0];
+const CHECKSUM_BLAKE2B: [u8; 2] = *b"B2";
+
+/// An Ed25519 key for signing archives, stored in the minisign format.
+///
+/// Only unencrypted secret keys, as created by `minisign -G -W` or
+/// `SecretKey::generate`, can be read.
+pub struct SecretKey {
+    key_id: [u8; 8],
+    key: ed25519_dalek::SigningKey,
+}
+
+/// An Ed25519 key for verifying signed archives, stored in the minisign
+/// format.
+#[derive(Clone, PartialEq, Eq)]
+pub struct PublicKey {
+    key_id: [u8; 8],
+    key: ed25519_dalek::VerifyingKey,
+}
+
+/// A detached signature of an archive, compatible with `minisign -V`.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Signature {
+    key_id: [u8; 8],
+    signature: ed25519_dalek::Signature,
+    trusted_comment: String,
+    global_signature: ed25519_dalek::Signature,
+}
+
+impl SecretKey {
+    /// Generates a new random key.
+    pub fn generate() -> io::Result<SecretKey> {
+        let mut seed = [0; 32];
+        let mut key_id = [0; 8];
+        getrandom::getrandom(&mut seed)?;
+        getrandom::getrandom(&mut key_id)?;
+        Ok(SecretKey {
+            key_id,
+            key: ed25519_dalek::SigningKey::from_bytes(&seed),
+        })
+    }
+
+    /// Parses a secret key file as written by minisign.
+    pub fn from_minisign(text: &str) -> io::Result<SecretKey> {
+        let data = decode_key_file(text)?;
+        if data.len() != 158 || data[..2] != ED25519 || data[4..6] != CHECKSUM_BLAKE2B {
+            return Err(invalid("not a minisign secret key"));
+        }
+        if data[2..4] != KDF_NONE {
+            return Err(invalid(
+                "password-protected minisign keys aren't supported; \
+                 create the key with `minisign -G -W`",
+            ));
+        }
+        let keynum = &data[54..];
+        let key_id: [u8; 8] = keynum[..8].try_into().unwrap();
+        let keypair: [u8; 64] = keynum[8..72].try_into().unwrap();
+        if secret_key_checksum(&key_id, &keypair)[..] != keynum[72..] {
+            return Err(invalid("minisign secret key checksum mismatch"));
+        }
+        let key = ed25519_dalek::SigningKey::from_keypair_bytes(&keypair)
+            .map_err(|_| invalid("minisign secret key is corrupt"))?;
+        Ok(SecretKey { key_id, key })
+    }
+
+    /// Returns this key in the minisign secret key file format.
+    pub fn to_minisign(&self) -> String {
+        let keypair = self.key.to_keypair_bytes();
+        let mut data = Vec::with_capacity(158);
+        data.extend_from_slice(&ED25519);
+        data.extend_from_slice(&KDF_NONE);
+        data.extend_from_slice(&CHECKSUM_BLAKE2B);
+        // The salt and limits of the (absent) key derivation function
+        data.extend_from_slice(&[0; 48]);
+        data.extend_from_slice(&self.key_id);
+        data.extend_from_slice(&keypair);
+        data.extend_from_slice(&secret_key_checksum(&self.key_id, &keypair));
+        format!(
+            "untrusted comment: minisign secret key {}\n{}\n",
+            key_id_hex(&self.key_id),
+            BASE64.encode(data)
+        )
+    }
+
+    /// Returns the public key to verify this key's signatures with.
+    pub fn public_key(&self) -> PublicKey {
+        PublicKey {
+            key_id: self.key_id,
+            key: self.key.verifying_key(),
+        }
+    }
+
+    /// Signs the archive read from `archive`.
+    ///
+    /// The `trusted_comment` is covered by the signature as well, and is
+    /// conventionally a tab-separated list such as
+    /// `timestamp:1700000000\tfile:foo.tar`. It must not contain newlines.
+    pub fn sign<R: Read>(&self, mut archive: R, trusted_comment: &str) -> io::Result<Signature> {
+        let mut writer = SigningWriter::new(io::sink());
+        io::copy(&mut archive, &mut writer)?;
+        writer.finish(self, trusted_comment).map(|(_, sig)| sig)
+    }
+
+    fn sign_hash(&self, hash: &[u8], trusted_comment: &str) -> io::Result<Signature> {
+        if trusted_comment.contains(['\r', '\n']) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "trusted comments cannot contain newlines",
+            ));
+        }
+        let signature = self.key.sign(hash);
+        let global_signature = self.key.sign(&global_message(&signature, trusted_comment));
+        Ok(Signature {
+            key_id: self.key_id,
+            signature,
+            trusted_comment: trusted_comment.to_string(),
+            global_signature,
+        })
+    }
+}
+
+impl fmt::Debug for SecretKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "SecretKey({})", key_id_hex(&self.key_id))
+    }
+}
+
+impl PublicKey {
+    /// Parses a public key file as written by minisign, or the bare base64
+    /// key it contains.
+    pub fn from_minisign(text: &str) -> io::Result<PublicKey> {
+        let data = decode_key_file(text)?;
+        if data.len() != 42 || data[..2] != ED25519 {
+            return Err(invalid("not a minisign public key"));
+        }
+        let key = ed25519_dalek::VerifyingKey::from_bytes(&data[10..].try_into().unwrap())
+            .map_err(|_| invalid("minisign public key is corrupt"))?;
+        Ok(PublicKey {
+            key_id: data[2..10].try_into().unwrap(),
+            key,
+        })
+    }
+
+    /// Returns this key in the minisign public key file format.
+    pub fn to_minisign(&self) -> String {
+        let mut data = Vec::with_capacity(42);
+        data.extend_from_slice(&ED25519);
+        data.extend_from_slice(&self.key_id);
+        data.extend_from_slice(self.key.as_bytes());
+        format!(
+            "untrusted comment: minisign public key {}\n{}\n",
+            key_id_hex(&self.key_id),
+            BASE64.encode(data)
+        )
+    }
+
+    /// Checks that `signature` was made by this key over the archive read
+    /// from `archive`, returning an error of kind `InvalidData` if not.
+    pub fn verify<R: Read>(&self, mut archive: R, signature: &Signature) -> io::Result<()> {
+        let mut hasher = Blake2b512::new();
+        io::copy(&mut archive, &mut HashWriter(&mut hasher))?;
+        self.verify_hash(&hasher.finalize(), signature)
+    }
+
+    fn verify_hash(&self, hash: &[u8], signature: &Signature) -> io::Result<()> {
+        if signature.key_id != self.key_id {
+            return Err(invalid(&format!(
+                "signature was made by key {}, not {}",
+                key_id_hex(&signature.key_id),
+                key_id_hex(&self.key_id)
+            )));
+        }
+        self.key
+            .verify(hash, &signature.signature)
+            .map_err(|_| invalid("signature verification failed"))?;
+        let global = global_message(&signature.signature, &signature.trusted_comment);
+        self.key
+            .verify(&global, &signature.global_signature)
+            .map_err(|_| invalid("trusted comment signature verification failed"))
+    }
+}
+
+impl fmt::Debug for PublicKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "PublicKey({})", key_id_hex(&self.key_id))
+    }
+}
+
+impl Signature {
+    /// Parses a signature file as written by minisign.
+    ///
+    /// Only signatures of the hashed data, the default since minisign 0.11,
+    /// are supported.
+    pub fn from_minisign(text: &str) -> io::Result<Signature> {
+        let mut lines = text.lines();
+        let mut line = || {
+            lines
+                .next()
+                .ok_or_else(|| invalid("truncated minisign signature"))
+        };
+        if !line()?.starts_with("untrusted comment:") {
+            return Err(invalid("not a minisign signature"));
+        }
+        let data = decode(line()?)?;
+        let trusted_comment = line()?
+            .strip_prefix("trusted comment: ")
+            .ok_or_else(|| invalid("minisign signature is missing its trusted comment"))?
+            .to_string();
+        let global = decode(line()?)?;
+        if data.len() != 74 || global.len() != 64 {
+            return Err(invalid("not a minisign signature"));
+        }
+        if data[..2] != ED25519_PREHASHED {
+            return Err(invalid(
+                "only minisign signatures of hashed data (`ED`) are supported",
+            ));
+        }
+        Ok(Signature {
+            key_id: data[2..10].try_into().unwrap(),
+            signature: ed25519_dalek::Signature::from_bytes(&data[10..].try_into().unwrap()),
+            trusted_comment,
+            global_signature: ed25519_dalek::Signature::from_bytes(&global[..].try_into().unwrap()),
+        })
+    }
+
+    /// Returns this signature in the minisign signature file format.
+    pub fn to_minisign(&self) -> String {
+        let mut data = Vec::with_capacity(74);
+        data.extend_from_slice(&ED25519_PREHASHED);
+        data.extend_from_slice(&self.key_id);
+        data.extend_from_slice(&self.signature.to_bytes());
+        format!(
+            "untrusted comment: signature from tar secret key {}\n{}\ntrusted comment: {}\n{}\n",
+            key_id_hex(&self.key_id),
+            BASE64.encode(data),
+            self.trusted_comment,
+            BASE64.encode(self.global_signature.to_bytes())
+        )
+    }
+
+    /// Returns the comment that was signed along with the archive.
+    pub fn trusted_comment(&self) -> &str {
+        &self.trusted_comment
+    }
+}
+
+impl fmt::Debug for Signature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Signature")
+            .field("key_id", &key_id_hex(&self.key_id))
+            .field("trusted_comment", &self.trusted_comment)
+            .finish()
+    }
+}
+
+/// A writer which hashes an archive as it is written, to sign it without
+/// reading it back.
+///
+/// # Examples
+///
+/// ```no_run
+/// use std::fs::{self, File};
+/// use tar::{Builder, SecretKey, SigningWriter};
+///
+/// let key = SecretKey::from_minisign(&fs::read_to_string("tar.key").unwrap()).unwrap();
+/// let mut ar = Builder::new(SigningWriter::new(File::create("foo.tar").unwrap()));
+/// ar.append_path("bar.txt").unwrap();
+/// let (_, signature) = ar.into_inner().unwrap().finish(&key, "file:foo.tar").unwrap();
+/// fs::write("foo.tar.minisig", signature.to_minisign()).unwrap();
+/// ```
+pub struct SigningWriter<W> {
+    inner: W,
+    hasher: Blake2b512,
+}
+
+impl<W: Write> SigningWriter<W> {
+    /// Creates a writer which hashes everything written to `inner`.
+    pub fn new(inner: W) -> SigningWriter<W> {
+        SigningWriter {
+            inner,
+            hasher: Blake2b512::new(),
+        }
+    }
+
+    /// Flushes the archive and signs everything written, returning the
+    /// inner writer along with the signature.
+    pub fn finish(mut self, key: &SecretKey, trusted_comment: &str) -> io::Result<(W, Signature)> {
+        self.inner.flush()?;
+        let signature = key.sign_hash(&self.hasher.finalize(), trusted_comment)?;
+        Ok((self.inner, signature))
+    }
+}
+
+impl<W: Write> Write for SigningWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.hasher.update(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+struct HashWriter<'a>(&'a mut Blake2b512);
+
+impl Write for HashWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn global_message(signature: &ed25519_dalek::Signature, trusted_comment: &str) -> Vec<u8> {
+    let mut message = signature.to_bytes().to_vec();
+    message.extend_from_slice(trusted_comment.as_bytes());
+    message
+}
+
+fn secret_key_checksum(key_id: &[u8; 8], keypair: &[u8; 64]) -> [u8; 32] {
+    let mut hasher = Blake2b::<U32>::new();
+    hasher.update(ED25519);
+    hasher.update(key_id);
+    hasher.update(keypair);
+    hasher.finalize().into()
+}
+
+/// Decodes the base64 line of a key file, skipping its comment if present.
+fn decode_key_file(text: &str) -> io::Result<Vec<u8>> {
+    let line = text
+        .lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty() && !line.starts_with("untrusted comment:"))
+        .ok_or_else(|| invalid("empty minisign key"))?;
+    decode(line)
+}
+
+fn decode(line: &str) -> io::Result<Vec<u8>> {
+    BASE64
+        .decode(line.trim())
+        .map_err(|_| invalid("invalid base64 in minisign data"))
+}
+
+/// Formats a key id the way minisign prints it.
+fn key_id_hex(key_id: &[u8; 8]) -> String {
+    format!("{:016X}", u64::from_le_bytes(*key_id))
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
@@ -0,0 +1,84 @@
+use std::str;
+
+/// How many bytes of an entry's contents are read to detect its type, which
+/// is enough to reach the magic of a tar header.
+pub(crate) const SNIFF_LEN: usize = 512;
+
+/// Magic bytes at the start of a file, and the media type they identify.
+const SIGNATURES: &[(&[u8], &str)] = &[
+    (b"\x89PNG\r\n\x1a\n", "image/png"),
+    (b"\xff\xd8\xff", "image/jpeg"),
+    (b"GIF87a", "image/gif"),
+    (b"GIF89a", "image/gif"),
+    (b"%PDF-", "application/pdf"),
+    (b"PK\x03\x04", "application/zip"),
+    (b"PK\x05\x06", "application/zip"),
+    (b"\x1f\x8b", "application/gzip"),
+    (b"BZh", "application/x-bzip2"),
+    (b"\xfd7zXZ\x00", "application/x-xz"),
+    (b"\x28\xb5\x2f\xfd", "application/zstd"),
+    (b"7z\xbc\xaf\x27\x1c", "application/x-7z-compressed"),
+    (b"\x7fELF", "application/x-executable"),
+    (b"\xfe\xed\xfa\xce", "application/x-mach-binary"),
+    (b"\xfe\xed\xfa\xcf", "application/x-mach-binary"),
+    (b"\xce\xfa\xed\xfe", "application/x-mach-binary"),
+    (b"\xcf\xfa\xed\xfe", "application/x-mach-binary"),
+    (b"MZ", "application/vnd.microsoft.portable-executable"),
+    (b"\x00asm", "application/wasm"),
+    (b"SQLite format 3\x00", "application/vnd.sqlite3"),
+    (b"OggS", "audio/ogg"),
+    (b"ID3", "audio/mpeg"),
+    (b"fLaC", "audio/flac"),
+    (b"-----BEGIN ", "application/x-pem-file"),
+];
+
+/// Detects the media type of a file from `data`, its first bytes, returning
+/// `None` if there are none.
+///
+/// Files are recognized by their magic bytes, falling back to `text/plain`
+/// for UTF-8 without control characters and `application/octet-stream` for
+/// anything else.
+pub(crate) fn content_type(data: &[u8]) -> Option<&'static str> {
+    if data.is_empty() {
+        return None;
+    }
+    if let Some((_, kind)) = SIGNATURES.iter().find(|(magic, _)| data.starts_with(magic)) {
+        return Some(kind);
+    }
+    // Formats whose magic isn't at the start
+    match (data.get(..4), data.get(4..8), data.get(8..12)) {
+        (Some(b"RIFF"), _, Some(b"WEBP")) => return Some("image/webp"),
+        (Some(b"RIFF"), _, Some(b"WAVE")) => return Some("audio/wav"),
+        (_, Some(b"ftyp"), _) => return Some("video/mp4"),
+        _ => {}
+    }
+    if data.get(257..262) == Some(b"ustar") {
+        return Some("application/x-tar");
+    }
+
+    let text = match str::from_utf8(data) {
+        Ok(text) => text,
+        // The sample may end partway through a character
+        Err(e) if e.error_len().is_none() => str::from_utf8(&data[..e.valid_up_to()]).unwrap(),
+        Err(_) => return Some("application/octet-stream"),
+    };
+    if text
+        .chars()
+        .any(|c| c.is_control() && !matches!(c, '\t' | '\n' | '\r' | '\x0c' | '\x1b'))
+    {
+        return Some("application/octet-stream");
+    }
+    let start = text
+        .trim_start()
+        .chars()
+        .take(14)
+        .collect::<String>()
+        .to_ascii_lowercase();
+    Some(if start.starts_with("<?xml") {
+        "application/xml"
+    } else if start.starts_with("<!doctype html") || start.starts_with("<html") {
+        "text/html"
+    } else {
+        "text/plain"
+    })
+}
@@ -0,0 +1,194 @@
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::mem;
+use std::sync::Arc;
+
+use object_store::path::Path as StorePath;
+use object_store::{MultipartUpload, ObjectStore, PutPayload};
+use tokio::runtime::Runtime;
+
+use crate::chunks::{ChunkedReader, FetchRange};
+
+/// A reader over an object in an object store such as S3, GCS or Azure Blob
+/// Storage, fetching it in chunks with ranged gets as it is read.
+///
+/// Like `HttpReader`, this is meant for `Archive::entries_with_seek`, which
+/// only fetches the parts of the archive holding headers and the entries
+/// which are read. Each reader drives the store on a runtime of its own, so
+/// it must not be used from within an async task.
+pub struct StoreReader {
+    inner: ChunkedReader<StoreSource>,
+}
+
+struct StoreSource {
+    runtime: Runtime,
+    store: Arc<dyn ObjectStore>,
+    path: StorePath,
+}
+
+impl StoreReader {
+    /// Opens the object at `path` in `store`, looking up its size.
+    pub fn open(store: Arc<dyn ObjectStore>, path: StorePath) -> io::Result<StoreReader> {
+        let runtime = runtime()?;
+        let meta = runtime.block_on(store.head(&path))?;
+        let source = StoreSource {
+            runtime,
+            store,
+            path,
+        };
+        Ok(StoreReader {
+            inner: ChunkedReader::new(source, meta.size),
+        })
+    }
+
+    /// Returns the size of the object.
+    pub fn len(&self) -> u64 {
+        self.inner.len()
+    }
+
+    /// Returns whether the object is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Sets how much of the object is fetched by each request, 1 MiB by
+    /// default. Changing this empties the cache.
+    pub fn set_chunk_size(&mut self, size: u64) {
+        self.inner.set_chunk_size(size);
+    }
+
+    /// Sets how many chunks are kept after they have been read, 16 by
+    /// default.
+    pub fn set_cache_chunks(&mut self, chunks: usize) {
+        self.inner.set_cache_chunks(chunks);
+    }
+}
+
+impl FetchRange for StoreSource {
+    fn fetch(&mut self, first: u64, len: u64) -> io::Result<Vec<u8>> {
+        let range = first..first + len;
+        let data = self
+            .runtime
+            .block_on(self.store.get_range(&self.path, range))?;
+        Ok(data.into())
+    }
+}
+
+impl Read for StoreReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf)
+    }
+}
+
+impl Seek for StoreReader {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.inner.seek(pos)
+    }
+}
+
+/// A writer which uploads everything written through it to an object in an
+/// object store, in parts with a multipart upload.
+///
+/// The object only appears once `finish` has been called; dropping the
+/// writer instead aborts the upload. Objects smaller than a part are
+/// uploaded with a single put. As with `StoreReader`, the writer must not be
+/// used from within an async task.
+pub struct StoreWriter {
+    runtime: Runtime,
+    store: Arc<dyn ObjectStore>,
+    path: StorePath,
+    part_size: usize,
+    buf: Vec<u8>,
+    upload: Option<Box<dyn MultipartUpload>>,
+}
+
+impl StoreWriter {
+    /// Creates a writer uploading to `path` in `store`.
+    ///
+    /// Nothing is uploaded until a whole part has been written.
+    pub fn new(store: Arc<dyn ObjectStore>, path: StorePath) -> io::Result<StoreWriter> {
+        Ok(StoreWriter {
+            runtime: runtime()?,
+            store,
+            path,
+            part_size: 10 << 20,
+            buf: Vec::new(),
+            upload: None,
+        })
+    }
+
+    /// Sets the size of each part uploaded, 10 MiB by default.
+    ///
+    /// Stores have a minimum part size, 5 MiB for S3, which every part but
+    /// the last must reach.
+    pub fn set_part_size(&mut self, size: usize) {
+        self.part_size = size.max(1);
+    }
+
+    /// Uploads whatever hasn't been uploaded yet and completes the upload.
+    pub fn finish(mut self) -> io::Result<()> {
+        let data = mem::take(&mut self.buf);
+        match self.upload.take() {
+            None => {
+                let payload = PutPayload::from(data);
+                self.runtime.block_on(self.store.put(&self.path, payload))?;
+            }
+            Some(mut upload) => {
+                if !data.is_empty() {
+                    self.runtime
+                        .block_on(upload.put_part(PutPayload::from(data)))?;
+                }
+                self.runtime.block_on(upload.complete())?;
+            }
+        }
+        Ok(())
+    }
+
+    fn upload_part(&mut self) -> io::Result<()> {
+        let rest = self.buf.split_off(self.part_size);
+        let part = mem::replace(&mut self.buf, rest);
+        let upload = match &mut self.upload {
+            Some(upload) => upload,
+            None => {
+                let upload = self
+                    .runtime
+                    .block_on(self.store.put_multipart(&self.path))?;
+                self.upload.insert(upload)
+            }
+        };
+        self.runtime
+            .block_on(upload.put_part(PutPayload::from(part)))?;
+        Ok(())
+    }
+}
+
+impl Write for StoreWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buf.extend_from_slice(buf);
+        // A part is only uploaded once there's more to follow it, so that
+        // the last part is never empty
+        while self.buf.len() > self.part_size {
+            self.upload_part()?;
+        }
+        Ok(buf.len())
+    }
+
+    // Parts can't be smaller than the part size, so there's nothing to do
+    // until `finish`
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for StoreWriter {
+    fn drop(&mut self) {
+        if let Some(mut upload) = self.upload.take() {
+            let _ = self.runtime.block_on(upload.abort());
+        }
+    }
+}
+
+fn runtime() -> io::Result<Runtime> {
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+}
@@ -0,0 +1,88 @@
+use std::io::{self, Write};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// A token bucket limiting how many bytes a second are read or written,
+/// sleeping whenever more have gone through than the rate allows.
+pub(crate) struct RateLimiter {
+    bytes_per_sec: f64,
+    // Bytes which can go through before sleeping; negative once over the
+    // rate, until slept off
+    available: f64,
+    last: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(bytes_per_sec: u64) -> RateLimiter {
+        RateLimiter {
+            bytes_per_sec: bytes_per_sec.max(1) as f64,
+            available: 0.0,
+            last: Instant::now(),
+        }
+    }
+
+    /// Accounts for `n` bytes going through, first sleeping for as long as
+    /// the rate calls for.
+    pub fn consume(&mut self, n: usize) {
+        let now = Instant::now();
+        // Bursts after a pause are limited to a quarter of a second's worth
+        let burst = self.bytes_per_sec / 4.0;
+        let refilled = now.duration_since(self.last).as_secs_f64() * self.bytes_per_sec;
+        self.available = (self.available + refilled).min(burst) - n as f64;
+        self.last = now;
+        if self.available < 0.0 {
+            thread::sleep(Duration::from_secs_f64(
+                -self.available / self.bytes_per_sec,
+            ));
+            self.available = 0.0;
+            self.last = Instant::now();
+        }
+    }
+}
+
+/// A writer passing everything through to `inner`, at no more than the rate
+/// `limiter` allows, if there is one, and adding up the bytes written in
+/// `written`.
+pub(crate) struct Throttled<'a, W: ?Sized> {
+    inner: &'a mut W,
+    limiter: Option<&'a mut RateLimiter>,
+    written: &'a mut u64,
+}
+
+impl<'a, W: ?Sized> Throttled<'a, W> {
+    pub fn new(
+        inner: &'a mut W,
+        limiter: &'a mut Option<RateLimiter>,
+        written: &'a mut u64,
+    ) -> Throttled<'a, W> {
+        Throttled {
+            inner,
+            limiter: limiter.as_mut(),
+            written,
+        }
+    }
+}
+
+impl<W: ?Sized + Write> Write for Throttled<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        if let Some(limiter) = &mut self.limiter {
+            limiter.consume(n);
+        }
+        *self.written += n as u64;
+        Ok(n)
+    }
+
+    fn write_vectored(&mut self, bufs: &[io::IoSlice<'_>]) -> io::Result<usize> {
+        let n = self.inner.write_vectored(bufs)?;
+        if let Some(limiter) = &mut self.limiter {
+            limiter.consume(n);
+        }
+        *self.written += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
@@ -0,0 +1,235 @@
+use std::collections::BTreeMap;
+use std::io::{self, Read, Write};
+use std::path::{Component, Path, PathBuf};
+
+use crate::error::TarError;
+use crate::{other, Builder, EntryType, Header};
+
+/// A tree of files, directories and symlinks put together in memory, to be
+/// written out as an archive in one go with `append_to`.
+///
+/// Entries are archived sorted by path, each after its parent directory,
+/// whatever order they were added in. Parent directories which weren't added
+/// are archived too, with default metadata. Everything is owned by root and
+/// has a modification time of zero unless set otherwise, so the same tree
+/// always makes the same archive.
+///
+/// # Examples
+///
+/// ```
+/// use std::io::{self, Read};
+/// use tar::{Builder, VirtualTreeBuilder};
+///
+/// let mut tree = VirtualTreeBuilder::new();
+/// tree.file("bin/hello", "#!/bin/sh\necho hello\n").mode(0o755);
+/// tree.file_from_reader("data/zeroes", 1024, io::repeat(0).take(1024));
+/// tree.symlink("latest", "bin/hello");
+///
+/// let mut ar = Builder::new(Vec::new());
+/// tree.append_to(&mut ar).unwrap();
+/// let data = ar.into_inner().unwrap();
+/// ```
+#[derive(Default)]
+pub struct VirtualTreeBuilder<'a> {
+    entries: BTreeMap<PathBuf, VirtualEntry<'a>>,
+}
+
+/// An entry added to a `VirtualTreeBuilder`, whose metadata can be set
+/// before the tree is archived.
+pub struct VirtualEntry<'a> {
+    contents: Contents<'a>,
+    mode: u32,
+    uid: u64,
+    gid: u64,
+    mtime: u64,
+}
+
+enum Contents<'a> {
+    Directory,
+    Bytes(Vec<u8>),
+    Reader(u64, Box<dyn Read + 'a>),
+    Symlink(PathBuf),
+}
+
+impl<'a> VirtualTreeBuilder<'a> {
+    /// Creates an empty tree.
+    pub fn new() -> VirtualTreeBuilder<'a> {
+        VirtualTreeBuilder::default()
+    }
+
+    /// Adds a directory, with mode `0o755` unless set otherwise.
+    pub fn dir<P: AsRef<Path>>(&mut self, path: P) -> &mut VirtualEntry<'a> {
+        self.insert(path.as_ref(), Contents::Directory, 0o755)
+    }
+
+    /// Adds a file holding `contents`, with mode `0o644` unless set
+    /// otherwise.
+    pub fn file<P: AsRef<Path>, C: Into<Vec<u8>>>(
+        &mut self,
+        path: P,
+        contents: C,
+    ) -> &mut VirtualEntry<'a> {
+        self.insert(path.as_ref(), Contents::Bytes(contents.into()), 0o644)
+    }
+
+    /// Adds a file of `size` bytes, which are read from `reader` as the tree
+    /// is archived, with mode `0o644` unless set otherwise.
+    ///
+    /// Archiving the tree fails if `reader` ends before `size` bytes have
+    /// been read from it, and anything after them isn't read.
+    pub fn file_from_reader<P: AsRef<Path>, R: Read + 'a>(
+        &mut self,
+        path: P,
+        size: u64,
+        reader: R,
+    ) -> &mut VirtualEntry<'a> {
+        let contents = Contents::Reader(size, Box::new(reader));
+        self.insert(path.as_ref(), contents, 0o644)
+    }
+
+    /// Adds a symlink pointing at `target`, with mode `0o777` unless set
+    /// otherwise.
+    pub fn symlink<P: AsRef<Path>, T: AsRef<Path>>(
+        &mut self,
+        path: P,
+        target: T,
+    ) -> &mut VirtualEntry<'a> {
+        let contents = Contents::Symlink(target.as_ref().to_path_buf());
+        self.insert(path.as_ref(), contents, 0o777)
+    }
+
+    /// Adds an entry, replacing any already at `path`.
+    fn insert(&mut self, path: &Path, contents: Contents<'a>, mode: u32) -> &mut VirtualEntry<'a> {
+        let entry = VirtualEntry {
+            contents,
+            mode,
+            uid: 0,
+            gid: 0,
+            mtime: 0,
+        };
+        let path = path.components().collect::<PathBuf>();
+        self.entries.insert(path.clone(), entry);
+        self.entries.get_mut(&path).unwrap()
+    }
+
+    /// Appends every entry in the tree to `ar`, along with any parent
+    /// directories which weren't added.
+    ///
+    /// Paths must be relative and have no `..` components. Like the other
+    /// ways of adding entries, this doesn't call `Builder::finish`.
+    pub fn append_to<W: Write>(mut self, ar: &mut Builder<W>) -> io::Result<()> {
+        let mut parents = Vec::new();
+        for path in self.entries.keys() {
+            if path
+                .components()
+                .any(|part| !matches!(part, Component::Normal(_)))
+            {
+                return Err(other(&format!(
+                    "`{}` isn't a relative path without `..` components",
+                    path.display()
+                )));
+            }
+            parents.extend(
+                path.ancestors()
+                    .skip(1)
+                    .filter(|parent| {
+                        !parent.as_os_str().is_empty() && !self.entries.contains_key(*parent)
+                    })
+                    .map(Path::to_path_buf),
+            );
+        }
+        for parent in parents {
+            self.dir(parent);
+        }
+        for (path, entry) in self.entries {
+            entry
+                .append(ar, &path)
+                .map_err(|e| TarError::new(format!("failed to append `{}`", path.display()), e))?;
+        }
+        Ok(())
+    }
+}
+
+impl VirtualEntry<'_> {
+    /// Sets the permission bits, replacing the default for the kind of
+    /// entry.
+    pub fn mode(&mut self, mode: u32) -> &mut Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Sets the owner's user ID, 0 by default.
+    pub fn uid(&mut self, uid: u64) -> &mut Self {
+        self.uid = uid;
+        self
+    }
+
+    /// Sets the owner's group ID, 0 by default.
+    pub fn gid(&mut self, gid: u64) -> &mut Self {
+        self.gid = gid;
+        self
+    }
+
+    /// Sets the modification time, in seconds since the Unix epoch, 0 by
+    /// default.
+    pub fn mtime(&mut self, mtime: u64) -> &mut Self {
+        self.mtime = mtime;
+        self
+    }
+
+    fn append<W: Write>(self, ar: &mut Builder<W>, path: &Path) -> io::Result<()> {
+        let mut header = Header::new_gnu();
+        header.set_mode(self.mode);
+        header.set_uid(self.uid);
+        header.set_gid(self.gid);
+        header.set_mtime(self.mtime);
+        match self.contents {
+            Contents::Directory => {
+                header.set_entry_type(EntryType::Directory);
+                header.set_size(0);
+                ar.append_data(&mut header, path, io::empty())
+            }
+            Contents::Bytes(data) => {
+                header.set_entry_type(EntryType::Regular);
+                header.set_size(data.len() as u64);
+                ar.append_data(&mut header, path, &data[..])
+            }
+            Contents::Reader(size, reader) => {
+                header.set_entry_type(EntryType::Regular);
+                header.set_size(size);
+                let reader = Exactly {
+                    inner: reader.take(size),
+                    remaining: size,
+                };
+                ar.append_data(&mut header, path, reader)
+            }
+            Contents::Symlink(target) => {
+                header.set_entry_type(EntryType::Symlink);
+                header.set_size(0);
+                ar.append_link(&mut header, path, target)
+            }
+        }
+    }
+}
+
+/// A reader failing if `inner` ends before `remaining` bytes have been read,
+/// rather than leaving an archive whose entry is shorter than its header
+/// says.
+struct Exactly<R> {
+    inner: R,
+    remaining: u64,
+}
+
+impl<R: Read> Read for Exactly<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        if n == 0 && self.remaining > 0 && !buf.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!("reader ended {} bytes short", self.remaining),
+            ));
+        }
+        self.remaining -= n as u64;
+        Ok(n)
+    }
+}
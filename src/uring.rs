@@ -0,0 +1,177 @@
+use std::collections::{HashSet, VecDeque};
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::mem::ManuallyDrop;
+use std::os::unix::prelude::*;
+use std::path::{Path, PathBuf};
+
+use filetime::FileTime;
+use tokio::task::JoinHandle;
+use tokio_uring::buf::IoBuf;
+
+use crate::error::TarError;
+use crate::{other, Entry};
+
+/// Files at most this large are written through io_uring. Larger ones are
+/// unpacked as usual rather than read into memory whole.
+const MAX_FILE_SIZE: u64 = 1 << 20;
+
+/// How many files are written at once.
+const MAX_IN_FLIGHT: usize = 64;
+
+/// A regular file read out of the archive, with everything needed to write
+/// it out without the entry.
+pub(crate) struct PendingFile {
+    pub path: PathBuf,
+    pub data: Vec<u8>,
+    pub mode: Option<u32>,
+    pub atime: Option<FileTime>,
+    pub mtime: Option<FileTime>,
+    pub replace: bool,
+}
+
+/// Unpacks `entries` into `dst` as `Archive::unpack_uring` describes.
+pub(crate) fn unpack<'a>(
+    entries: impl Iterator<Item = io::Result<Entry<'a, io::Empty>>>,
+    dst: &Path,
+) -> io::Result<()> {
+    let runtime = tokio_uring::Runtime::new(&tokio_uring::builder())?;
+    runtime.block_on(unpack_async(entries, dst))
+}
+
+async fn unpack_async<'a>(
+    entries: impl Iterator<Item = io::Result<Entry<'a, io::Empty>>>,
+    dst: &Path,
+) -> io::Result<()> {
+    if dst.symlink_metadata().is_err() {
+        fs::create_dir_all(dst)
+            .map_err(|e| TarError::new(format!("failed to create `{}`", dst.display()), e))?;
+    }
+    let dst = &dst.canonicalize().unwrap_or(dst.to_path_buf());
+
+    // Directories are delayed until the end, as in `Archive::unpack`
+    let mut directories = Vec::new();
+    let mut in_flight = InFlight::default();
+    for entry in entries {
+        let mut file = entry.map_err(|e| TarError::new("failed to iterate over archive", e))?;
+        let kind = file.header().entry_type();
+        if kind.is_dir() {
+            file.prepare_dir_in(dst)?;
+            directories.push(file);
+            continue;
+        }
+
+        // Anything which might depend on a file still being written, such as
+        // a link to it or a later copy of it, waits for it to be finished
+        let small_file = kind.is_file() && !kind.is_gnu_sparse() && file.size() <= MAX_FILE_SIZE;
+        let key = file.destination_in(dst)?;
+        if !small_file
+            || key
+                .as_ref()
+                .is_some_and(|key| in_flight.paths.contains(key))
+        {
+            in_flight.finish_all().await?;
+        }
+        if !small_file {
+            file.unpack_in(dst)?;
+            continue;
+        }
+        if let Ok(pending) = file.pending_file_in(dst)? {
+            in_flight.push(key.unwrap_or_default(), pending).await?;
+        }
+    }
+    in_flight.finish_all().await?;
+
+    directories.sort_by(|a, b| b.path_bytes().cmp(&a.path_bytes()));
+    for mut dir in directories {
+        dir.unpack_in(dst)?;
+    }
+    Ok(())
+}
+
+/// The files being written, oldest first.
+#[derive(Default)]
+struct InFlight {
+    tasks: VecDeque<(PathBuf, JoinHandle<io::Result<()>>)>,
+    paths: HashSet<PathBuf>,
+}
+
+impl InFlight {
+    async fn push(&mut self, key: PathBuf, file: PendingFile) -> io::Result<()> {
+        if self.tasks.len() >= MAX_IN_FLIGHT {
+            self.finish_oldest().await?;
+        }
+        self.paths.insert(key.clone());
+        self.tasks
+            .push_back((key, tokio_uring::spawn(write_file(file))));
+        Ok(())
+    }
+
+    async fn finish_oldest(&mut self) -> io::Result<()> {
+        if let Some((key, task)) = self.tasks.pop_front() {
+            self.paths.remove(&key);
+            task.await.map_err(|e| other(&e.to_string()))??;
+        }
+        Ok(())
+    }
+
+    async fn finish_all(&mut self) -> io::Result<()> {
+        while !self.tasks.is_empty() {
+            self.finish_oldest().await?;
+        }
+        Ok(())
+    }
+}
+
+async fn write_file(file: PendingFile) -> io::Result<()> {
+    let path = file.path.clone();
+    _write_file(file)
+        .await
+        .map_err(|e| TarError::new(format!("failed to unpack `{}`", path.display()), e).into())
+}
+
+async fn _write_file(file: PendingFile) -> io::Result<()> {
+    // Like unpacking normally, a new file is always created rather than
+    // writing through whatever is already there
+    let f = match open(&file.path).await {
+        Err(err) if err.kind() == ErrorKind::AlreadyExists && file.replace => {
+            match fs::remove_file(&file.path) {
+                Ok(()) => open(&file.path).await?,
+                Err(e) if e.kind() == ErrorKind::NotFound => open(&file.path).await?,
+                Err(e) => return Err(e),
+            }
+        }
+        f => f?,
+    };
+
+    let mut data = file.data;
+    let mut written = 0;
+    while written < data.len() {
+        let (result, slice) = f.write_at(data.slice(written..), written as u64).await;
+        data = slice.into_inner();
+        match result? {
+            0 => return Err(ErrorKind::WriteZero.into()),
+            n => written += n,
+        }
+    }
+
+    {
+        // The descriptor stays owned by `f`, and is only borrowed here
+        let std = ManuallyDrop::new(unsafe { fs::File::from_raw_fd(f.as_raw_fd()) });
+        if file.atime.is_some() || file.mtime.is_some() {
+            filetime::set_file_handle_times(&std, file.atime, file.mtime)?;
+        }
+        if let Some(mode) = file.mode {
+            std.set_permissions(fs::Permissions::from_mode(mode))?;
+        }
+    }
+    f.close().await
+}
+
+async fn open(path: &Path) -> io::Result<tokio_uring::fs::File> {
+    tokio_uring::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(path)
+        .await
+}
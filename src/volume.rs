@@ -0,0 +1,161 @@
+use std::io::{self, Read, Write};
+
+use crate::header::BLOCK_SIZE;
+
+/// A writer which spreads an archive across several volumes of a fixed size.
+///
+/// Volumes are opened on demand by a closure given the index of the volume,
+/// starting at 0. Each volume except the last is filled up to exactly the
+/// volume size, which must be a multiple of 512 bytes so that the blocks of an
+/// uncompressed archive, and so its headers, are never split across volumes.
+///
+/// # Examples
+///
+/// ```no_run
+/// use std::fs::File;
+/// use tar::{Builder, MultiVolumeWriter};
+///
+/// let volumes = MultiVolumeWriter::new(1 << 20, |i| File::create(format!("foo.tar.{:03}", i)))
+///     .unwrap();
+/// let mut ar = Builder::new(volumes);
+/// ar.append_path("bar.txt").unwrap();
+/// ar.into_inner().unwrap().finish().unwrap();
+/// ```
+pub struct MultiVolumeWriter<W, F> {
+    open: F,
+    volume_size: u64,
+    volumes: usize,
+    current: Option<(W, u64)>,
+}
+
+impl<W: Write, F: FnMut(usize) -> io::Result<W>> MultiVolumeWriter<W, F> {
+    /// Creates a writer producing volumes of `volume_size` bytes, each opened
+    /// by calling `open` with its index.
+    ///
+    /// Returns an error if `volume_size` isn't a positive multiple of 512.
+    pub fn new(volume_size: u64, open: F) -> io::Result<MultiVolumeWriter<W, F>> {
+        if volume_size == 0 || !volume_size.is_multiple_of(BLOCK_SIZE) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "volume size must be a positive multiple of 512 bytes",
+            ));
+        }
+        Ok(MultiVolumeWriter {
+            open,
+            volume_size,
+            volumes: 0,
+            current: None,
+        })
+    }
+
+    /// Returns the number of volumes opened so far.
+    pub fn volumes(&self) -> usize {
+        self.volumes
+    }
+
+    /// Flushes the last volume, returning it.
+    pub fn finish(mut self) -> io::Result<Option<W>> {
+        self.flush()?;
+        Ok(self.current.take().map(|(volume, _)| volume))
+    }
+}
+
+impl<W: Write, F: FnMut(usize) -> io::Result<W>> Write for MultiVolumeWriter<W, F> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let full = match &self.current {
+            Some((_, len)) => *len == self.volume_size,
+            None => true,
+        };
+        if full {
+            if let Some((mut volume, _)) = self.current.take() {
+                volume.flush()?;
+            }
+            let volume = (self.open)(self.volumes)?;
+            self.volumes += 1;
+            self.current = Some((volume, 0));
+        }
+        let (volume, len) = self.current.as_mut().unwrap();
+        let room = (self.volume_size - *len).min(buf.len() as u64) as usize;
+        let n = volume.write(&buf[..room])?;
+        *len += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.current {
+            Some((volume, _)) => volume.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+/// A reader presenting the volumes written by a `MultiVolumeWriter` as one
+/// archive.
+///
+/// Volumes are opened on demand by a closure given the index of the volume,
+/// starting at 0, which returns `None` once there are no more volumes.
+///
+/// # Examples
+///
+/// ```no_run
+/// use std::fs::File;
+/// use std::path::Path;
+/// use tar::{Archive, MultiVolumeReader};
+///
+/// let volumes = MultiVolumeReader::new(|i| {
+///     let path = format!("foo.tar.{:03}", i);
+///     if Path::new(&path).exists() {
+///         File::open(path).map(Some)
+///     } else {
+///         Ok(None)
+///     }
+/// });
+/// let mut ar = Archive::new(volumes);
+/// ar.unpack("foo").unwrap();
+/// ```
+pub struct MultiVolumeReader<R, F> {
+    open: F,
+    volumes: usize,
+    current: Option<R>,
+    done: bool,
+}
+
+impl<R: Read, F: FnMut(usize) -> io::Result<Option<R>>> MultiVolumeReader<R, F> {
+    /// Creates a reader over the volumes returned by calling `open` with each
+    /// index in turn.
+    pub fn new(open: F) -> MultiVolumeReader<R, F> {
+        MultiVolumeReader {
+            open,
+            volumes: 0,
+            current: None,
+            done: false,
+        }
+    }
+
+    /// Returns the number of volumes opened so far.
+    pub fn volumes(&self) -> usize {
+        self.volumes
+    }
+}
+
+impl<R: Read, F: FnMut(usize) -> io::Result<Option<R>>> Read for MultiVolumeReader<R, F> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while !self.done {
+            if let Some(volume) = &mut self.current {
+                let n = volume.read(buf)?;
+                if n > 0 || buf.is_empty() {
+                    return Ok(n);
+                }
+            }
+            self.current = (self.open)(self.volumes)?;
+            match self.current {
+                Some(_) => self.volumes += 1,
+                None => self.done = true,
+            }
+        }
+        Ok(0)
+    }
+}
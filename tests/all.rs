@@ -1,17 +1,40 @@
+// These tests predate lints added in newer toolchains.
+#![allow(
+    clippy::manual_inspect,
+    clippy::manual_repeat_n,
+    clippy::manual_str_repeat,
+    clippy::needless_borrows_for_generic_args,
+    clippy::option_map_unit_fn,
+    clippy::unnecessary_mut_passed
+)]
+
 extern crate filetime;
 extern crate tar;
 extern crate tempfile;
 #[cfg(all(unix, feature = "xattr"))]
 extern crate xattr;
 
+use std::cell::RefCell;
+use std::collections::HashMap;
 use std::fs::{self, File};
 use std::io::prelude::*;
-use std::io::{self, BufWriter, Cursor};
+use std::io::{self, BufWriter, Cursor, SeekFrom};
 use std::iter::repeat;
 use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
 
 use filetime::FileTime;
-use tar::{Archive, Builder, Entries, Entry, EntryType, Header, HeaderMode};
+use tar::{
+    Archive, ArchiveFormat, ArchiveMetadata, Builder, CaseCollisionPolicy, Difference,
+    DumpDirEntry, Entries, Entry, EntryType, FileSnapshot, FileSystem, Header, HeaderMode,
+    MergePolicy, MultiVolumeReader, MultiVolumeWriter, OsFileSystem, OverwritePolicy, OwnerMap,
+    ParseProfile, ProgressObserver, Salvaged, Snapshot, SymlinkFallback, SyncPolicy,
+    VirtualTreeBuilder,
+};
+#[cfg(feature = "sha2")]
+use tar::{DigestAlgorithm, ManifestFormat, ManifestMismatch};
 use tempfile::{Builder as TempBuilder, TempDir};
 
 macro_rules! t {
@@ -267,6 +290,7 @@ fn reading_entries_with_seek() {
 struct LoggingReader<R> {
     inner: R,
     read_bytes: u64,
+    reads: u64,
 }
 
 impl<R> LoggingReader<R> {
@@ -274,6 +298,7 @@ impl<R> LoggingReader<R> {
         LoggingReader {
             inner: reader,
             read_bytes: 0,
+            reads: 0,
         }
     }
 }
@@ -282,6 +307,7 @@ impl<T: Read> Read for LoggingReader<T> {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
         self.inner.read(buf).map(|i| {
             self.read_bytes += i as u64;
+            self.reads += 1;
             i
         })
     }
@@ -311,6 +337,40 @@ fn skipping_entries_with_seek() {
     assert!(seekable_reader.read_bytes < reader.read_bytes);
 }
 
+#[test]
+fn reading_with_buffer() {
+    fn contents(ar: &mut Archive<&mut LoggingReader<Cursor<Vec<u8>>>>, seek: bool) -> Vec<Vec<u8>> {
+        let entries = if seek {
+            t!(ar.entries_with_seek())
+        } else {
+            t!(ar.entries())
+        };
+        entries
+            .enumerate()
+            .map(|(i, entry)| {
+                // Only every other entry is read, so the rest are skipped
+                let mut entry = t!(entry);
+                let mut data = Vec::new();
+                if i % 2 == 0 {
+                    t!(entry.read_to_end(&mut data));
+                }
+                data
+            })
+            .collect()
+    }
+
+    for seek in [false, true] {
+        let mut reader = LoggingReader::new(Cursor::new(tar!("reading_files.tar").to_vec()));
+        let expected = contents(&mut Archive::new(&mut reader), seek);
+
+        let mut buffered = LoggingReader::new(Cursor::new(tar!("reading_files.tar").to_vec()));
+        let mut ar = Archive::new(&mut buffered);
+        ar.set_read_buffer_size(64 * 1024);
+        assert_eq!(contents(&mut ar, seek), expected);
+        assert!(buffered.reads < reader.reads);
+    }
+}
+
 fn check_dirtree(td: &TempDir) {
     let dir_a = td.path().join("a");
     let dir_b = td.path().join("a/b");
@@ -363,6 +423,38 @@ fn extracting_duplicate_file_succeed() {
     t!(ar.unpack(td.path()));
 }
 
+#[test]
+fn extracting_duplicate_file_policies() {
+    let mut ar = Builder::new(Vec::new());
+    for (name, mtime) in [("old", 1_000), ("new", 2_000_000_000)] {
+        let mut header = Header::new_gnu();
+        header.set_size(7);
+        header.set_mode(0o644);
+        header.set_mtime(mtime);
+        t!(ar.append_data(&mut header, name, &b"archive"[..]));
+    }
+    let data = t!(ar.into_inner());
+
+    let unpack = |policy| {
+        let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
+        t!(fs::write(td.path().join("old"), b"disk"));
+        t!(fs::write(td.path().join("new"), b"disk"));
+        let mut ar = Archive::new(&data[..]);
+        ar.set_overwrite_policy(policy);
+        t!(ar.unpack(td.path()));
+        [
+            t!(fs::read(td.path().join("old"))),
+            t!(fs::read(td.path().join("new"))),
+        ]
+    };
+    assert_eq!(unpack(OverwritePolicy::Skip), [b"disk", b"disk"]);
+    assert_eq!(
+        unpack(OverwritePolicy::KeepNewer),
+        [&b"disk"[..], b"archive"]
+    );
+    assert_eq!(unpack(OverwritePolicy::Overwrite), [b"archive", b"archive"]);
+}
+
 #[test]
 #[cfg(unix)]
 fn extracting_duplicate_link_fail() {
@@ -432,678 +524,3471 @@ fn no_xattrs() {
 }
 
 #[test]
-fn writing_and_extracting_directories() {
-    let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
+#[cfg(all(unix, feature = "xattr"))]
+fn append_xattrs_roundtrip() {
+    // If /tmp is a tmpfs, xattr will fail
+    let td = t!(TempBuilder::new().prefix("tar-rs").tempdir_in("/var/tmp"));
+    let src = td.path().join("src");
+    t!(fs::create_dir_all(src.join("dir")));
+    t!(fs::write(src.join("dir/file"), b"contents"));
+    t!(xattr::set(src.join("dir"), "user.kind", b"directory"));
+    t!(xattr::set(src.join("dir/file"), "user.kind", b"file"));
 
     let mut ar = Builder::new(Vec::new());
-    let tmppath = td.path().join("tmpfile");
-    t!(t!(File::create(&tmppath)).write_all(b"c"));
-    t!(ar.append_dir("a", "."));
-    t!(ar.append_dir("a/b", "."));
-    t!(ar.append_file("a/c", &mut t!(File::open(&tmppath))));
-    t!(ar.finish());
+    ar.xattrs(true);
+    t!(ar.append_dir_all("dir", src.join("dir")));
+    let data = t!(ar.into_inner());
 
-    let rdr = Cursor::new(t!(ar.into_inner()));
-    let mut ar = Archive::new(rdr);
-    t!(ar.unpack(td.path()));
-    check_dirtree(&td);
+    let dst = td.path().join("dst");
+    let mut ar = Archive::new(&data[..]);
+    ar.set_unpack_xattrs(true);
+    t!(ar.unpack(&dst));
+    assert_eq!(
+        t!(xattr::get(dst.join("dir"), "user.kind")).as_deref(),
+        Some(&b"directory"[..])
+    );
+    assert_eq!(
+        t!(xattr::get(dst.join("dir/file"), "user.kind")).as_deref(),
+        Some(&b"file"[..])
+    );
+
+    // Nothing is stored unless asked for
+    let mut ar = Builder::new(Vec::new());
+    t!(ar.append_path_with_name(src.join("dir/file"), "file"));
+    let data = t!(ar.into_inner());
+    let mut ar = Archive::new(&data[..]);
+    let mut entries = t!(ar.entries());
+    let mut entry = t!(entries.next().unwrap());
+    assert!(t!(entry.pax_extensions()).is_none());
 }
 
 #[test]
-fn writing_and_extracting_directories_complex_permissions() {
-    let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
+#[cfg(all(unix, feature = "xattr"))]
+fn unpack_selinux() {
+    let td = t!(TempBuilder::new().prefix("tar-rs").tempdir_in("/var/tmp"));
+    let context = &b"system_u:object_r:tmp_t:s0"[..];
 
-    // Archive with complex permissions which would fail to unpack if one attempted to do so
-    // without reordering of entries.
     let mut ar = Builder::new(Vec::new());
-    let tmppath = td.path().join("tmpfile");
-    t!(t!(File::create(&tmppath)).write_all(b"c"));
-
-    // Root dir with very stringent permissions
-    let data: &[u8] = &[];
+    t!(ar.append_pax_extensions([
+        ("SCHILY.xattr.user.kind", &b"file"[..]),
+        ("RHT.security.selinux", context),
+    ]));
     let mut header = Header::new_gnu();
-    header.set_mode(0o555);
-    header.set_entry_type(EntryType::Directory);
-    t!(header.set_path("a"));
     header.set_size(0);
-    header.set_cksum();
-    t!(ar.append(&header, data));
+    header.set_mode(0o644);
+    t!(ar.append_data(&mut header, "file", io::empty()));
+    let data = t!(ar.into_inner());
 
-    // Nested dir
-    header.set_mode(0o777);
-    header.set_entry_type(EntryType::Directory);
-    t!(header.set_path("a/b"));
-    header.set_cksum();
-    t!(ar.append(&header, data));
+    // Extended attributes alone leave the context out
+    let dst = td.path().join("xattrs");
+    let mut ar = Archive::new(&data[..]);
+    ar.set_unpack_xattrs(true);
+    t!(ar.unpack(&dst));
+    assert!(t!(xattr::get(dst.join("file"), "user.kind")).is_some());
+    assert_ne!(
+        t!(xattr::get(dst.join("file"), "security.selinux")).as_deref(),
+        Some(context)
+    );
 
-    // Nested file.
-    t!(ar.append_file("a/c", &mut t!(File::open(&tmppath))));
-    t!(ar.finish());
+    let dst = td.path().join("selinux");
+    let mut ar = Archive::new(&data[..]);
+    ar.set_unpack_selinux(true);
+    t!(ar.unpack(&dst));
+    assert!(t!(xattr::get(dst.join("file"), "user.kind")).is_none());
+    assert_eq!(
+        t!(xattr::get(dst.join("file"), "security.selinux")).as_deref(),
+        Some(context)
+    );
+}
 
-    let rdr = Cursor::new(t!(ar.into_inner()));
-    let mut ar = Archive::new(rdr);
-    ar.unpack(td.path()).unwrap();
-    check_dirtree(&td);
+#[test]
+fn skip_apple_double() {
+    let mut ar = Builder::new(Vec::new());
+    for path in ["dir/", "dir/._file", "dir/file", "._dir", "dir/._"] {
+        let mut header = Header::new_gnu();
+        header.set_size(0);
+        header.set_mode(0o644);
+        if path.ends_with('/') {
+            header.set_entry_type(EntryType::Directory);
+        }
+        t!(ar.append_data(&mut header, path, io::empty()));
+    }
+    let data = t!(ar.into_inner());
+
+    let mut ar = Archive::new(&data[..]);
+    ar.set_skip_apple_double(true);
+    let paths = t!(ar.entries())
+        .map(|e| t!(t!(e).path()).into_owned())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        paths,
+        [Path::new("dir"), Path::new("dir/file"), Path::new("dir/._")]
+    );
+
+    let mut ar = Archive::new(&data[..]);
+    assert_eq!(t!(ar.entries()).count(), 5);
 }
 
 #[test]
-fn writing_directories_recursively() {
-    let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
+#[cfg(windows)]
+fn windows_attributes_roundtrip() {
+    use std::os::windows::fs::MetadataExt;
 
-    let base_dir = td.path().join("base");
-    t!(fs::create_dir(&base_dir));
-    t!(t!(File::create(base_dir.join("file1"))).write_all(b"file1"));
-    let sub_dir = base_dir.join("sub");
-    t!(fs::create_dir(&sub_dir));
-    t!(t!(File::create(sub_dir.join("file2"))).write_all(b"file2"));
+    const FILE_ATTRIBUTE_HIDDEN: u32 = 0x2;
+
+    let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
+    let src = td.path().join("hidden");
+    t!(fs::write(&src, b"contents"));
+    let status = t!(std::process::Command::new("attrib")
+        .arg("+h")
+        .arg(&src)
+        .status());
+    assert!(status.success());
 
     let mut ar = Builder::new(Vec::new());
-    t!(ar.append_dir_all("foobar", base_dir));
+    ar.windows_attributes(true);
+    t!(ar.append_path_with_name(&src, "hidden"));
     let data = t!(ar.into_inner());
 
-    let mut ar = Archive::new(Cursor::new(data));
-    t!(ar.unpack(td.path()));
-    let base_dir = td.path().join("foobar");
-    assert!(fs::metadata(&base_dir).map(|m| m.is_dir()).unwrap_or(false));
-    let file1_path = base_dir.join("file1");
-    assert!(fs::metadata(&file1_path)
-        .map(|m| m.is_file())
-        .unwrap_or(false));
-    let sub_dir = base_dir.join("sub");
-    assert!(fs::metadata(&sub_dir).map(|m| m.is_dir()).unwrap_or(false));
-    let file2_path = sub_dir.join("file2");
-    assert!(fs::metadata(&file2_path)
-        .map(|m| m.is_file())
-        .unwrap_or(false));
+    let dst = td.path().join("dst");
+    let mut ar = Archive::new(&data[..]);
+    ar.set_unpack_windows_attributes(true);
+    t!(ar.unpack(&dst));
+    let attributes = t!(fs::metadata(dst.join("hidden"))).file_attributes();
+    assert_ne!(attributes & FILE_ATTRIBUTE_HIDDEN, 0);
 }
 
 #[test]
-fn append_dir_all_blank_dest() {
+fn extended_times_roundtrip() {
     let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
-
-    let base_dir = td.path().join("base");
-    t!(fs::create_dir(&base_dir));
-    t!(t!(File::create(base_dir.join("file1"))).write_all(b"file1"));
-    let sub_dir = base_dir.join("sub");
-    t!(fs::create_dir(&sub_dir));
-    t!(t!(File::create(sub_dir.join("file2"))).write_all(b"file2"));
+    let src = td.path().join("file");
+    t!(fs::write(&src, b"contents"));
+    let atime = FileTime::from_unix_time(1_000_000_000, 123_456_789);
+    let mtime = FileTime::from_unix_time(1_200_000_000, 0);
+    t!(filetime::set_file_times(&src, atime, mtime));
 
     let mut ar = Builder::new(Vec::new());
-    t!(ar.append_dir_all("", base_dir));
+    ar.extended_times(true);
+    t!(ar.append_path_with_name(&src, "file"));
     let data = t!(ar.into_inner());
 
-    let mut ar = Archive::new(Cursor::new(data));
-    t!(ar.unpack(td.path()));
-    let base_dir = td.path();
-    assert!(fs::metadata(&base_dir).map(|m| m.is_dir()).unwrap_or(false));
-    let file1_path = base_dir.join("file1");
-    assert!(fs::metadata(&file1_path)
-        .map(|m| m.is_file())
-        .unwrap_or(false));
-    let sub_dir = base_dir.join("sub");
-    assert!(fs::metadata(&sub_dir).map(|m| m.is_dir()).unwrap_or(false));
-    let file2_path = sub_dir.join("file2");
-    assert!(fs::metadata(&file2_path)
-        .map(|m| m.is_file())
-        .unwrap_or(false));
+    let mut ar = Archive::new(&data[..]);
+    let mut entry = t!(t!(ar.entries()).next().unwrap());
+    let expected = std::time::UNIX_EPOCH + std::time::Duration::new(1_000_000_000, 123_456_789);
+    assert_eq!(t!(entry.atime()), Some(expected));
+    #[cfg(unix)]
+    assert!(t!(entry.ctime()).is_some());
+
+    let dst = td.path().join("dst");
+    let mut ar = Archive::new(&data[..]);
+    ar.set_preserve_atime(true);
+    t!(ar.unpack(&dst));
+    let meta = t!(fs::metadata(dst.join("file")));
+    assert_eq!(FileTime::from_last_access_time(&meta), atime);
+    assert_eq!(FileTime::from_last_modification_time(&meta), mtime);
+
+    // Without the records, entries have no times beyond the header's
+    let mut ar = Builder::new(Vec::new());
+    t!(ar.append_path_with_name(&src, "file"));
+    let data = t!(ar.into_inner());
+    let mut ar = Archive::new(&data[..]);
+    let mut entry = t!(t!(ar.entries()).next().unwrap());
+    assert_eq!(t!(entry.atime()), None);
+    assert_eq!(t!(entry.ctime()), None);
 }
 
 #[test]
-fn append_dir_all_does_not_work_on_non_directory() {
-    let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
-    let path = td.path().join("test");
-    t!(t!(File::create(&path)).write_all(b"test"));
+fn pax_owner_names() {
+    let long = "a-user-name-too-long-for-the-header";
+    let mut ar = Builder::new(Vec::new());
+    let mut header = Header::new_ustar();
+    header.set_size(0);
+    header.set_mode(0o644);
+    t!(header.set_username("short"));
+    t!(header.set_groupname("staff"));
+    let records = [("uname", long.as_bytes())];
+    t!(ar.append_pax_records(&mut header, "file", records, io::empty()));
+    let data = t!(ar.into_inner());
 
+    let mut ar = Archive::new(&data[..]);
+    let entry = t!(t!(ar.entries()).next().unwrap());
+    assert_eq!(t!(entry.username()), Some(long));
+    assert_eq!(t!(entry.groupname()), Some("staff"));
+
+    // Names are only stored when asked for
+    let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
+    let src = td.path().join("file");
+    t!(fs::write(&src, b"contents"));
     let mut ar = Builder::new(Vec::new());
-    let result = ar.append_dir_all("test", path);
-    assert!(result.is_err());
+    t!(ar.append_path_with_name(&src, "file"));
+    let data = t!(ar.into_inner());
+    let mut ar = Archive::new(&data[..]);
+    let entry = t!(t!(ar.entries()).next().unwrap());
+    assert_eq!(t!(entry.username()), Some(""));
 }
 
 #[test]
-fn extracting_duplicate_dirs() {
-    let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
-    let rdr = Cursor::new(tar!("duplicate_dirs.tar"));
-    let mut ar = Archive::new(rdr);
-    t!(ar.unpack(td.path()));
+fn long_names_with_reused_buffers() {
+    // Names shrink from one entry to the next, so any stale bytes left in a
+    // reused buffer would show up in a later name.
+    let names = (0..6)
+        .rev()
+        .map(|i| format!("{}/{}", "dir".repeat(40 + 20 * i), i))
+        .collect::<Vec<_>>();
+    let mut ar = Builder::new(Vec::new());
+    for (i, name) in names.iter().enumerate() {
+        let contents = name.to_uppercase();
+        let mut header = Header::new_gnu();
+        header.set_size(contents.len() as u64);
+        header.set_mode(0o644);
+        if i % 2 == 0 {
+            t!(ar.append_data(&mut header, name, contents.as_bytes()));
+        } else {
+            let records = [("path", name.as_bytes()), ("comment", name.as_bytes())];
+            t!(ar.append_pax_records(&mut header, "short", records, contents.as_bytes()));
+        }
+    }
+    let data = t!(ar.into_inner());
 
-    let some_dir = td.path().join("some_dir");
-    assert!(fs::metadata(&some_dir).map(|m| m.is_dir()).unwrap_or(false));
+    let mut ar = Archive::new(&data[..]);
+    let mut seen = 0;
+    for (entry, name) in t!(ar.entries()).zip(&names) {
+        let mut entry = t!(entry);
+        assert_eq!(&*entry.path_bytes(), name.as_bytes());
+        let mut contents = String::new();
+        t!(entry.read_to_string(&mut contents));
+        assert_eq!(contents, name.to_uppercase());
+        seen += 1;
+    }
+    assert_eq!(seen, names.len());
 }
 
 #[test]
-fn unpack_old_style_bsd_dir() {
+fn nanosecond_mtime_roundtrip() {
     let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
+    let src = td.path().join("file");
+    t!(fs::write(&src, b"contents"));
+    let mtime = FileTime::from_unix_time(1_200_000_000, 987_654_321);
+    t!(filetime::set_file_mtime(&src, mtime));
 
     let mut ar = Builder::new(Vec::new());
+    ar.extended_times(true);
+    t!(ar.append_path_with_name(&src, "file"));
+    let data = t!(ar.into_inner());
 
-    let mut header = Header::new_old();
-    header.set_entry_type(EntryType::Regular);
-    t!(header.set_path("testdir/"));
-    header.set_size(0);
-    header.set_cksum();
-    t!(ar.append(&header, &mut io::empty()));
+    let mut ar = Archive::new(&data[..]);
+    let mut entry = t!(t!(ar.entries()).next().unwrap());
+    assert_eq!(t!(entry.header().mtime()), 1_200_000_000);
+    let expected = std::time::UNIX_EPOCH + std::time::Duration::new(1_200_000_000, 987_654_321);
+    assert_eq!(t!(entry.mtime()), expected);
 
-    // Extracting
-    let rdr = Cursor::new(t!(ar.into_inner()));
-    let mut ar = Archive::new(rdr);
-    t!(ar.unpack(td.path()));
+    let dst = td.path().join("dst");
+    let mut ar = Archive::new(&data[..]);
+    t!(ar.unpack(&dst));
+    let meta = t!(fs::metadata(dst.join("file")));
+    assert_eq!(FileTime::from_last_modification_time(&meta), mtime);
+}
 
-    // Iterating
-    let rdr = Cursor::new(ar.into_inner().into_inner());
-    let mut ar = Archive::new(rdr);
-    assert!(t!(ar.entries()).all(|fr| fr.is_ok()));
+#[test]
+fn directory_mtime_restored() {
+    let mut ar = Builder::new(Vec::new());
+    for (path, kind, mtime) in [
+        ("dir", EntryType::Directory, 1_000_000_000),
+        ("dir/sub", EntryType::Directory, 1_100_000_000),
+        ("dir/sub/file", EntryType::Regular, 1_200_000_000),
+        ("dir/file", EntryType::Regular, 1_300_000_000),
+    ] {
+        let mut header = Header::new_gnu();
+        header.set_entry_type(kind);
+        header.set_size(0);
+        header.set_mode(0o755);
+        header.set_mtime(mtime);
+        t!(ar.append_data(&mut header, path, io::empty()));
+    }
+    let data = t!(ar.into_inner());
 
-    assert!(td.path().join("testdir").is_dir());
+    // The directories come before what is written into them
+    let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
+    t!(Archive::new(&data[..]).unpack(td.path()));
+    for (path, mtime) in [("dir", 1_000_000_000), ("dir/sub", 1_100_000_000)] {
+        let meta = t!(fs::metadata(td.path().join(path)));
+        assert_eq!(
+            FileTime::from_last_modification_time(&meta),
+            FileTime::from_unix_time(mtime, 0),
+            "{}",
+            path
+        );
+    }
 }
 
 #[test]
-fn handling_incorrect_file_size() {
+fn atomic_unpack() {
     let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
+    t!(fs::write(td.path().join("file"), b"old"));
 
     let mut ar = Builder::new(Vec::new());
+    let mut header = Header::new_gnu();
+    header.set_size(8192);
+    header.set_mode(0o644);
+    t!(ar.append_data(&mut header, "file", &[b'x'; 8192][..]));
+    let data = t!(ar.into_inner());
 
-    let path = td.path().join("tmpfile");
-    t!(File::create(&path));
-    let mut file = t!(File::open(&path));
-    let mut header = Header::new_old();
-    t!(header.set_path("somepath"));
-    header.set_metadata(&t!(file.metadata()));
-    header.set_size(2048); // past the end of file null blocks
-    header.set_cksum();
-    t!(ar.append(&header, &mut file));
-
-    // Extracting
-    let rdr = Cursor::new(t!(ar.into_inner()));
-    let mut ar = Archive::new(rdr);
+    // A truncated archive leaves the existing file alone, and nothing behind
+    let mut ar = Archive::new(&data[..4096]);
+    ar.set_atomic_unpack(true);
     assert!(ar.unpack(td.path()).is_err());
+    assert_eq!(t!(fs::read(td.path().join("file"))), b"old");
+    assert_eq!(t!(fs::read_dir(td.path())).count(), 1);
 
-    // Iterating
-    let rdr = Cursor::new(ar.into_inner().into_inner());
-    let mut ar = Archive::new(rdr);
-    assert!(t!(ar.entries()).any(|fr| fr.is_err()));
+    let mut ar = Archive::new(&data[..]);
+    ar.set_atomic_unpack(true);
+    t!(ar.unpack(td.path()));
+    assert_eq!(t!(fs::read(td.path().join("file"))), [b'x'; 8192]);
+    assert_eq!(t!(fs::read_dir(td.path())).count(), 1);
+
+    let mut ar = Archive::new(&data[..]);
+    ar.set_atomic_unpack(true);
+    ar.set_overwrite_policy(OverwritePolicy::Error);
+    assert!(ar.unpack(td.path()).is_err());
 }
 
 #[test]
-fn extracting_malicious_tarball() {
+#[cfg(feature = "mmap")]
+fn from_mmap() {
     let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
+    let path = td.path().join("a.tar");
+    let mut ar = Builder::new(Vec::new());
+    for (name, data) in [("a", &b"first"[..]), ("b", &[b'x'; 1000][..])] {
+        let mut header = Header::new_gnu();
+        header.set_size(data.len() as u64);
+        header.set_cksum();
+        t!(ar.append_data(&mut header, name, data));
+    }
+    t!(fs::write(&path, t!(ar.into_inner())));
 
-    let mut evil_tar = Vec::new();
+    let mut ar = t!(Archive::from_mmap(&path));
+    let mut entries = t!(ar.entries_with_seek());
+    let a = t!(entries.next().unwrap());
+    assert_eq!(a.mapped_data(), Some(&b"first"[..]));
+    let mut b = t!(entries.next().unwrap());
+    let mut s = Vec::new();
+    t!(b.read_to_end(&mut s));
+    assert_eq!(s, [b'x'; 1000]);
+    assert_eq!(b.mapped_data(), Some(&s[..]));
+    assert!(entries.next().is_none());
 
-    {
-        let mut a = Builder::new(&mut evil_tar);
-        let mut append = |path: &str| {
-            let mut header = Header::new_gnu();
-            assert!(header.set_path(path).is_err(), "was ok: {:?}", path);
-            {
-                let h = header.as_gnu_mut().unwrap();
-                for (a, b) in h.name.iter_mut().zip(path.as_bytes()) {
-                    *a = *b;
-                }
-            }
-            header.set_size(1);
-            header.set_cksum();
-            t!(a.append(&header, io::repeat(1).take(1)));
-        };
-        append("/tmp/abs_evil.txt");
-        // std parse `//` as UNC path, see rust-lang/rust#100833
-        append(
-            #[cfg(not(windows))]
-            "//tmp/abs_evil2.txt",
-            #[cfg(windows)]
-            "C://tmp/abs_evil2.txt",
-        );
-        append("///tmp/abs_evil3.txt");
-        append("/./tmp/abs_evil4.txt");
-        append(
-            #[cfg(not(windows))]
-            "//./tmp/abs_evil5.txt",
-            #[cfg(windows)]
-            "C://./tmp/abs_evil5.txt",
-        );
-        append("///./tmp/abs_evil6.txt");
-        append("/../tmp/rel_evil.txt");
-        append("../rel_evil2.txt");
-        append("./../rel_evil3.txt");
-        append("some/../../rel_evil4.txt");
-        append("");
-        append("././//./..");
-        append("..");
-        append("/////////..");
-        append("/////////");
+    let mut ar = t!(Archive::from_mmap(&path));
+    t!(ar.unpack(td.path().join("out")));
+    assert_eq!(t!(fs::read(td.path().join("out/a"))), b"first");
+
+    let mut ar = Archive::new(File::open(&path).unwrap());
+    assert_eq!(t!(t!(ar.entries()).next().unwrap()).mapped_data(), None);
+}
+
+#[test]
+#[cfg(all(feature = "bytes", feature = "sha2"))]
+fn read_all_bytes() {
+    let mut ar = Builder::new(Vec::new());
+    for (name, data) in [("a", &b"first"[..]), ("b", &[b'x'; 1000][..])] {
+        let mut header = Header::new_gnu();
+        header.set_size(data.len() as u64);
+        header.set_cksum();
+        t!(ar.append_data(&mut header, name, data));
     }
+    let data = bytes::Bytes::from(t!(ar.into_inner()));
+    let within = |b: &[u8]| data.as_ptr_range().contains(&b.as_ptr());
 
-    let mut ar = Archive::new(&evil_tar[..]);
-    t!(ar.unpack(td.path()));
+    let mut ar = Archive::from_bytes(data.clone());
+    ar.set_digest_algorithm(Some(DigestAlgorithm::Sha256));
+    let mut entries = t!(ar.entries_with_seek());
+    let mut a = t!(entries.next().unwrap());
+    assert_eq!(a.mapped_data(), Some(&b"first"[..]));
+    let contents = t!(a.read_all_bytes());
+    assert_eq!(contents, &b"first"[..]);
+    assert!(within(&contents));
+    assert_eq!(
+        a.digest().unwrap().to_hex(),
+        tar::Digest::of(DigestAlgorithm::Sha256, b"first").to_hex()
+    );
+    let mut b = t!(entries.next().unwrap());
+    let mut start = [0; 10];
+    t!(b.read_exact(&mut start));
+    let rest = t!(b.read_all_bytes());
+    assert_eq!(rest.len(), 990);
+    assert!(within(&rest));
+    assert_eq!(t!(b.read_all_bytes()), &b""[..]);
+    assert!(entries.next().is_none());
 
-    assert!(fs::metadata("/tmp/abs_evil.txt").is_err());
-    assert!(fs::metadata("/tmp/abs_evil.txt2").is_err());
-    assert!(fs::metadata("/tmp/abs_evil.txt3").is_err());
-    assert!(fs::metadata("/tmp/abs_evil.txt4").is_err());
-    assert!(fs::metadata("/tmp/abs_evil.txt5").is_err());
-    assert!(fs::metadata("/tmp/abs_evil.txt6").is_err());
-    assert!(fs::metadata("/tmp/rel_evil.txt").is_err());
-    assert!(fs::metadata("/tmp/rel_evil.txt").is_err());
-    assert!(fs::metadata(td.path().join("../tmp/rel_evil.txt")).is_err());
-    assert!(fs::metadata(td.path().join("../rel_evil2.txt")).is_err());
-    assert!(fs::metadata(td.path().join("../rel_evil3.txt")).is_err());
-    assert!(fs::metadata(td.path().join("../rel_evil4.txt")).is_err());
+    let mut ar = Archive::new(&data[..]);
+    let mut a = t!(t!(ar.entries()).next().unwrap());
+    let contents = t!(a.read_all_bytes());
+    assert_eq!(contents, &b"first"[..]);
+    assert!(!within(&contents));
+}
 
-    // The `some` subdirectory should not be created because the only
-    // filename that references this has '..'.
-    assert!(fs::metadata(td.path().join("some")).is_err());
+#[test]
+fn reader_at() {
+    let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
+    let path = td.path().join("a.tar");
+    let mut ar = Builder::new(Vec::new());
+    let contents = (0..8u8)
+        .map(|i| vec![b'a' + i; 1000 * (i as usize + 1)])
+        .collect::<Vec<_>>();
+    for (i, data) in contents.iter().enumerate() {
+        let mut header = Header::new_gnu();
+        header.set_size(data.len() as u64);
+        header.set_cksum();
+        t!(ar.append_data(&mut header, i.to_string(), &data[..]));
+    }
+    t!(fs::write(&path, t!(ar.into_inner())));
+
+    let file = Arc::new(t!(File::open(&path)));
+    let mut ar = Archive::new(&*file);
+    let mut readers = t!(ar.entries_with_seek())
+        .map(|entry| t!(t!(entry).reader_at(file.clone())))
+        .collect::<Vec<_>>();
+    assert_eq!(readers.len(), 8);
+
+    // Interleaved reads don't disturb each other
+    let mut first = [0; 10];
+    let mut second = [0; 10];
+    t!(readers[0].read_exact(&mut first));
+    t!(readers[1].read_exact(&mut second));
+    t!(readers[0].read_exact(&mut first));
+    assert_eq!(first, [b'a'; 10]);
+    assert_eq!(second, [b'b'; 10]);
+    t!(readers[0].seek(SeekFrom::Start(0)));
+    t!(readers[1].seek(SeekFrom::Current(-10)));
+
+    let threads = readers
+        .into_iter()
+        .map(|mut reader| {
+            thread::spawn(move || {
+                let mut data = Vec::new();
+                reader.read_to_end(&mut data).map(|_| data)
+            })
+        })
+        .collect::<Vec<_>>();
+    for (thread, expected) in threads.into_iter().zip(&contents) {
+        assert_eq!(&t!(thread.join().unwrap()), expected);
+    }
 
-    // The `tmp` subdirectory should be created and within this
-    // subdirectory, there should be files named `abs_evil.txt` through
-    // `abs_evil6.txt`.
-    assert!(fs::metadata(td.path().join("tmp"))
-        .map(|m| m.is_dir())
-        .unwrap_or(false));
-    assert!(fs::metadata(td.path().join("tmp/abs_evil.txt"))
-        .map(|m| m.is_file())
-        .unwrap_or(false));
-    assert!(fs::metadata(td.path().join("tmp/abs_evil2.txt"))
-        .map(|m| m.is_file())
-        .unwrap_or(false));
-    assert!(fs::metadata(td.path().join("tmp/abs_evil3.txt"))
-        .map(|m| m.is_file())
-        .unwrap_or(false));
-    assert!(fs::metadata(td.path().join("tmp/abs_evil4.txt"))
-        .map(|m| m.is_file())
-        .unwrap_or(false));
-    assert!(fs::metadata(td.path().join("tmp/abs_evil5.txt"))
-        .map(|m| m.is_file())
-        .unwrap_or(false));
-    assert!(fs::metadata(td.path().join("tmp/abs_evil6.txt"))
-        .map(|m| m.is_file())
-        .unwrap_or(false));
+    let data = t!(fs::read(&path));
+    let mut ar = Archive::new(&data[..]);
+    let entry = t!(t!(ar.entries()).nth(2).unwrap());
+    let mut s = Vec::new();
+    t!(t!(entry.reader_at(&data[..])).read_to_end(&mut s));
+    assert_eq!(s, contents[2]);
+}
+
+/// Serves `data` over HTTP on a local port, answering range requests, and
+/// returns its URL and a count of the requests made.
+#[cfg(feature = "http")]
+fn serve_ranges(data: Vec<u8>) -> (String, Arc<std::sync::atomic::AtomicUsize>) {
+    use std::io::BufRead;
+    use std::net::TcpListener;
+    use std::sync::atomic::Ordering;
+
+    let listener = t!(TcpListener::bind("127.0.0.1:0"));
+    let url = format!("http://{}/a.tar", t!(listener.local_addr()));
+    let requests = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+    let count = requests.clone();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = t!(stream);
+            let mut range = None;
+            for line in io::BufReader::new(&stream).lines() {
+                let line = t!(line);
+                if line.is_empty() {
+                    break;
+                }
+                if let Some(r) = line.strip_prefix("Range: bytes=") {
+                    let (first, last) = r.split_once('-').unwrap();
+                    range = Some((t!(first.parse::<usize>()), t!(last.parse::<usize>())));
+                }
+            }
+            count.fetch_add(1, Ordering::SeqCst);
+            let (first, last) = range.unwrap();
+            let last = last.min(data.len() - 1);
+            let body = &data[first..=last];
+            t!(write!(
+                stream,
+                "HTTP/1.1 206 Partial Content\r\nContent-Range: bytes {}-{}/{}\r\n\
+                 Content-Length: {}\r\nConnection: close\r\n\r\n",
+                first,
+                last,
+                data.len(),
+                body.len()
+            ));
+            t!(stream.write_all(body));
+        }
+    });
+    (url, requests)
 }
 
 #[test]
-fn octal_spaces() {
-    let rdr = Cursor::new(tar!("spaces.tar"));
-    let mut ar = Archive::new(rdr);
+#[cfg(feature = "http")]
+fn http_reader() {
+    use std::sync::atomic::Ordering;
 
-    let entry = ar.entries().unwrap().next().unwrap().unwrap();
-    assert_eq!(entry.header().mode().unwrap() & 0o777, 0o777);
-    assert_eq!(entry.header().uid().unwrap(), 0);
-    assert_eq!(entry.header().gid().unwrap(), 0);
-    assert_eq!(entry.header().size().unwrap(), 2);
-    assert_eq!(entry.header().mtime().unwrap(), 0o12440016664);
-    assert_eq!(entry.header().cksum().unwrap(), 0o4253);
+    let mut ar = Builder::new(Vec::new());
+    for i in 0..20u8 {
+        let mut header = Header::new_gnu();
+        header.set_size(10_000);
+        header.set_cksum();
+        t!(ar.append_data(&mut header, i.to_string(), &[b'a' + i; 10_000][..]));
+    }
+    let data = t!(ar.into_inner());
+    let (url, requests) = serve_ranges(data.clone());
+
+    let mut reader = t!(tar::HttpReader::open(&url));
+    assert_eq!(reader.len(), data.len() as u64);
+    reader.set_chunk_size(1024);
+    let mut ar = Archive::new(reader);
+    let mut found = false;
+    for entry in t!(ar.entries_with_seek()) {
+        let mut entry = t!(entry);
+        if &*entry.path_bytes() == b"7" {
+            let mut s = Vec::new();
+            t!(entry.read_to_end(&mut s));
+            assert_eq!(s, [b'h'; 10_000]);
+            found = true;
+        }
+    }
+    assert!(found);
+    // Only the chunks holding headers and the one entry read were fetched,
+    // out of over 200
+    let fetched = requests.load(Ordering::SeqCst);
+    assert!(fetched < 40, "{} requests", fetched);
+
+    let mut reader = t!(tar::HttpReader::open(&url));
+    let mut s = Vec::new();
+    t!(reader.read_to_end(&mut s));
+    assert_eq!(s, data);
 }
 
 #[test]
-fn extracting_malformed_tar_null_blocks() {
+#[cfg(feature = "store")]
+fn object_store() {
+    use object_store::memory::InMemory;
+    use object_store::path::Path as StorePath;
+    use tar::{StoreReader, StoreWriter};
+
+    let store = Arc::new(InMemory::new());
+    let path = StorePath::from("archives/a.tar");
+    let mut writer = t!(StoreWriter::new(store.clone(), path.clone()));
+    writer.set_part_size(4096);
+    let mut ar = Builder::new(writer);
+    for i in 0..10u8 {
+        let mut header = Header::new_gnu();
+        header.set_size(3000);
+        header.set_cksum();
+        t!(ar.append_data(&mut header, i.to_string(), &[b'a' + i; 3000][..]));
+    }
+    t!(t!(ar.into_inner()).finish());
+
+    let mut reader = t!(StoreReader::open(store.clone(), path.clone()));
+    assert_eq!(reader.len(), 10 * (512 + 3072) + 1024);
+    reader.set_chunk_size(1000);
+    let mut ar = Archive::new(reader);
+    for (i, entry) in t!(ar.entries_with_seek()).enumerate() {
+        let mut entry = t!(entry);
+        let mut s = Vec::new();
+        t!(entry.read_to_end(&mut s));
+        assert_eq!(s, [b'a' + i as u8; 3000]);
+    }
+
+    // Dropping a writer leaves nothing behind
+    let path = StorePath::from("archives/b.tar");
+    let mut writer = t!(StoreWriter::new(store.clone(), path.clone()));
+    writer.set_part_size(4096);
+    t!(writer.write_all(&[0; 10000]));
+    drop(writer);
+    assert!(StoreReader::open(store, path).is_err());
+}
+
+#[test]
+#[cfg(all(target_os = "linux", feature = "uring"))]
+fn unpack_uring() {
+    use std::os::unix::prelude::*;
+
+    let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
+    let big = vec![b'x'; 2 << 20];
+    let mut ar = Builder::new(Vec::new());
+    let mut header = Header::new_gnu();
+    header.set_entry_type(EntryType::Directory);
+    header.set_size(0);
+    header.set_mode(0o755);
+    t!(ar.append_data(&mut header, "dir", io::empty()));
+    for i in 0..200 {
+        let data = format!("file {}", i);
+        let mut header = Header::new_gnu();
+        header.set_size(data.len() as u64);
+        header.set_mode(0o640);
+        header.set_mtime(1_000_000 + i);
+        t!(ar.append_data(&mut header, format!("dir/{}", i), data.as_bytes()));
+    }
+    let mut header = Header::new_gnu();
+    header.set_size(big.len() as u64);
+    header.set_mode(0o644);
+    t!(ar.append_data(&mut header, "big", &big[..]));
+    // A later copy of a file replaces it, even while it is being written
+    let mut header = Header::new_gnu();
+    header.set_size(5);
+    header.set_mode(0o644);
+    t!(ar.append_data(&mut header, "dir/7", &b"again"[..]));
+    let mut header = Header::new_gnu();
+    header.set_entry_type(EntryType::Link);
+    header.set_size(0);
+    t!(ar.append_link(&mut header, "link", "dir/3"));
+    let data = t!(ar.into_inner());
+
+    let mut ar = Archive::new(&data[..]);
+    t!(ar.unpack_uring(td.path()));
+    for i in (0..200).filter(|&i| i != 7) {
+        let path = td.path().join(format!("dir/{}", i));
+        assert_eq!(t!(fs::read_to_string(&path)), format!("file {}", i));
+        let meta = t!(fs::metadata(&path));
+        assert_eq!(meta.permissions().mode() & 0o777, 0o640);
+        assert_eq!(
+            FileTime::from_last_modification_time(&meta).unix_seconds(),
+            1_000_000 + i as i64
+        );
+    }
+    assert_eq!(t!(fs::read(td.path().join("dir/7"))), b"again");
+    assert_eq!(t!(fs::read(td.path().join("big"))), big);
+    assert_eq!(t!(fs::read(td.path().join("link"))), b"file 3");
+}
+
+/// A reader which cancels `token` once more than `after` bytes have been read.
+struct CancellingReader<R> {
+    inner: R,
+    read: usize,
+    after: usize,
+    token: tar::CancellationToken,
+}
+
+impl<R: Read> Read for CancellingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.read += n;
+        if self.read > self.after {
+            self.token.cancel();
+        }
+        Ok(n)
+    }
+}
+
+#[test]
+fn unpack_with_cancel() {
     let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
+    let mut ar = Builder::new(Vec::new());
+    for (name, size) in [("a", 100), ("big", 1 << 20), ("c", 100)] {
+        let mut header = Header::new_gnu();
+        header.set_size(size as u64);
+        header.set_cksum();
+        t!(ar.append_data(&mut header, name, &vec![b'x'; size][..]));
+    }
+    let data = t!(ar.into_inner());
+
+    // Cancelled partway through `big`
+    let token = tar::CancellationToken::new();
+    let mut ar = Archive::new(CancellingReader {
+        inner: &data[..],
+        read: 0,
+        after: 200 * 1024,
+        token: token.clone(),
+    });
+    let err = ar.unpack_with_cancel(td.path(), &token).unwrap_err();
+    assert!(err.get_ref().unwrap().is::<tar::Cancelled>(), "{}", err);
+    assert_eq!(t!(fs::read(td.path().join("a"))), [b'x'; 100]);
+    assert!(!td.path().join("big").exists());
+    assert!(!td.path().join("c").exists());
+
+    let token = tar::CancellationToken::new();
+    let mut ar = Archive::new(&data[..]);
+    t!(ar.unpack_with_cancel(td.path(), &token));
+    assert_eq!(t!(fs::metadata(td.path().join("big"))).len(), 1 << 20);
+    assert!(td.path().join("c").exists());
+}
 
+#[test]
+fn timeouts() {
+    let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
     let mut ar = Builder::new(Vec::new());
+    for name in ["a", "b"] {
+        let mut header = Header::new_gnu();
+        header.set_size(100);
+        header.set_cksum();
+        t!(ar.append_data(&mut header, name, &[b'x'; 100][..]));
+    }
+    let data = t!(ar.into_inner());
 
-    let path1 = td.path().join("tmpfile1");
-    let path2 = td.path().join("tmpfile2");
-    t!(File::create(&path1));
-    t!(File::create(&path2));
-    t!(ar.append_file("tmpfile1", &mut t!(File::open(&path1))));
-    let mut data = t!(ar.into_inner());
-    let amt = data.len();
-    data.truncate(amt - 512);
-    let mut ar = Builder::new(data);
-    t!(ar.append_file("tmpfile2", &mut t!(File::open(&path2))));
-    t!(ar.finish());
+    let mut ar = Archive::new(&data[..]);
+    ar.set_timeout(Some(Duration::ZERO));
+    let err = ar.unpack(td.path()).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+    assert!(!td.path().join("a").exists());
+
+    let mut ar = Archive::new(&data[..]);
+    ar.set_entry_timeout(Some(Duration::ZERO));
+    let mut entry = t!(t!(ar.entries()).next().unwrap());
+    let err = entry.read_to_end(&mut Vec::new()).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+
+    let mut ar = Archive::new(&data[..]);
+    ar.set_timeout(Some(Duration::from_secs(60)));
+    ar.set_entry_timeout(Some(Duration::from_secs(60)));
+    t!(ar.unpack(td.path()));
+    assert_eq!(t!(fs::read(td.path().join("b"))), [b'x'; 100]);
+}
 
+#[test]
+fn max_bytes_per_sec() {
+    let contents = [b'x'; 64 * 1024];
+    let start = Instant::now();
+    let mut ar = Builder::new(Vec::new());
+    ar.max_bytes_per_sec(Some(512 * 1024));
+    let mut header = Header::new_gnu();
+    header.set_size(contents.len() as u64);
+    header.set_cksum();
+    t!(ar.append_data(&mut header, "a", &contents[..]));
     let data = t!(ar.into_inner());
+    assert!(start.elapsed() >= Duration::from_millis(50));
+
+    let start = Instant::now();
     let mut ar = Archive::new(&data[..]);
-    assert!(ar.unpack(td.path()).is_ok());
+    ar.set_max_bytes_per_sec(Some(512 * 1024));
+    let mut entry = t!(t!(ar.entries()).next().unwrap());
+    let mut read = Vec::new();
+    t!(entry.read_to_end(&mut read));
+    assert!(start.elapsed() >= Duration::from_millis(50));
+    assert_eq!(read, contents);
+}
+
+#[derive(Clone, Default)]
+struct Events(Arc<Mutex<Vec<String>>>);
+
+impl ProgressObserver for Events {
+    fn entry_started(&mut self, path: &Path, size: u64) {
+        let event = format!("start {} {}", path.display(), size);
+        self.0.lock().unwrap().push(event);
+    }
+
+    fn bytes_processed(&mut self, bytes: u64) {
+        self.0.lock().unwrap().push(format!("{}", bytes));
+    }
+
+    fn entry_finished(&mut self, path: &Path) {
+        let event = format!("finish {}", path.display());
+        self.0.lock().unwrap().push(event);
+    }
+
+    fn archive_finished(&mut self) {
+        self.0.lock().unwrap().push("done".to_string());
+    }
 }
 
 #[test]
-fn empty_filename() {
+fn progress_observer() {
     let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
-    let rdr = Cursor::new(tar!("empty_filename.tar"));
-    let mut ar = Archive::new(rdr);
-    assert!(ar.unpack(td.path()).is_ok());
+    t!(fs::create_dir(td.path().join("src")));
+    t!(fs::write(td.path().join("src/a"), [b'x'; 100]));
+
+    let events = Events::default();
+    let mut ar = Builder::new(Vec::new());
+    ar.progress_observer(events.clone());
+    t!(ar.append_dir_all("d", td.path().join("src")));
+    let data = t!(ar.into_inner());
+    assert_eq!(
+        *events.0.lock().unwrap(),
+        [
+            "start d/ 0",
+            "0",
+            "finish d/",
+            "start d/a 100",
+            "100",
+            "finish d/a",
+            "done"
+        ]
+    );
+
+    let events = Events::default();
+    let mut ar = Archive::new(&data[..]);
+    ar.set_progress_observer(events.clone());
+    t!(ar.unpack(td.path().join("dst")));
+    assert_eq!(
+        *events.0.lock().unwrap(),
+        [
+            "start d/a 100",
+            "100",
+            "finish d/a",
+            "start d/ 0",
+            "finish d/",
+            "done"
+        ]
+    );
+}
+
+#[cfg(feature = "tracing")]
+#[test]
+fn tracing_spans() {
+    use tracing::span::{Attributes, Id, Record};
+    use tracing::{Event, Metadata, Subscriber};
+
+    #[derive(Default)]
+    struct Spans(Mutex<Vec<&'static str>>);
+
+    impl Subscriber for Spans {
+        fn enabled(&self, _: &Metadata<'_>) -> bool {
+            true
+        }
+        fn new_span(&self, span: &Attributes<'_>) -> Id {
+            let mut spans = self.0.lock().unwrap();
+            spans.push(span.metadata().name());
+            Id::from_u64(spans.len() as u64)
+        }
+        fn record(&self, _: &Id, _: &Record<'_>) {}
+        fn record_follows_from(&self, _: &Id, _: &Id) {}
+        fn event(&self, _: &Event<'_>) {}
+        fn enter(&self, _: &Id) {}
+        fn exit(&self, _: &Id) {}
+    }
+
+    let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
+    let spans = Arc::new(Spans::default());
+    tracing::subscriber::with_default(spans.clone(), || {
+        let mut ar = Builder::new(Vec::new());
+        let mut header = Header::new_gnu();
+        header.set_size(3);
+        t!(ar.append_data(&mut header, "a", &b"foo"[..]));
+        let data = t!(ar.into_inner());
+        t!(Archive::new(&data[..]).unpack(td.path()));
+    });
+    assert_eq!(
+        *spans.0.lock().unwrap(),
+        ["append_entry", "unpack", "unpack_entry"]
+    );
+}
+
+#[test]
+#[cfg(unix)]
+fn unpack_into() {
+    #[derive(Default)]
+    struct MemoryFileSystem {
+        files: HashMap<PathBuf, Vec<u8>>,
+        log: Vec<String>,
+    }
+
+    impl FileSystem for MemoryFileSystem {
+        fn create_dir(&mut self, path: &Path) -> io::Result<()> {
+            self.log.push(format!("dir {}", path.display()));
+            Ok(())
+        }
+
+        fn create_file(&mut self, path: &Path) -> io::Result<Box<dyn Write + '_>> {
+            self.log.push(format!("file {}", path.display()));
+            Ok(Box::new(self.files.entry(path.to_path_buf()).or_default()))
+        }
+
+        fn symlink(&mut self, target: &Path, path: &Path) -> io::Result<()> {
+            let event = format!("symlink {} {}", path.display(), target.display());
+            self.log.push(event);
+            Ok(())
+        }
+
+        fn hard_link(&mut self, target: &Path, path: &Path) -> io::Result<()> {
+            let event = format!("link {} {}", path.display(), target.display());
+            self.log.push(event);
+            Ok(())
+        }
+
+        fn set_metadata(&mut self, path: &Path, header: &Header) -> io::Result<()> {
+            let event = format!("mode {} {:o}", path.display(), header.mode()?);
+            self.log.push(event);
+            Ok(())
+        }
+    }
+
+    let mut ar = Builder::new(Vec::new());
+    let mut header = Header::new_gnu();
+    header.set_size(3);
+    header.set_mode(0o644);
+    t!(ar.append_data(&mut header, "a/b.txt", &b"foo"[..]));
+    let mut header = Header::new_gnu();
+    header.set_entry_type(EntryType::Directory);
+    header.set_size(0);
+    header.set_mode(0o755);
+    t!(ar.append_data(&mut header, "a", io::empty()));
+    let mut header = Header::new_gnu();
+    header.set_entry_type(EntryType::Symlink);
+    header.set_size(0);
+    header.set_mode(0o777);
+    t!(ar.append_link(&mut header, "a/s", "../.."));
+    let mut header = Header::new_gnu();
+    header.set_entry_type(EntryType::Link);
+    header.set_size(0);
+    t!(ar.append_link(&mut header, "a/h", "a/b.txt"));
+    let mut header = Header::new_gnu();
+    header.as_gnu_mut().unwrap().name[..7].copy_from_slice(b"../evil");
+    header.set_size(0);
+    header.set_cksum();
+    t!(ar.append(&header, io::empty()));
+    let mut header = Header::new_gnu();
+    header.set_size(3);
+    header.set_mode(0o644);
+    t!(ar.append_data(&mut header, "a/s/escaped", &b"bar"[..]));
+    let data = t!(ar.into_inner());
+
+    let mut memory = MemoryFileSystem::default();
+    t!(Archive::new(&data[..]).unpack_into(&mut memory));
+    assert_eq!(
+        memory.log,
+        [
+            "dir a",
+            "file a/b.txt",
+            "mode a/b.txt 644",
+            "symlink a/s ../..",
+            "mode a/s 777",
+            "link a/h a/b.txt",
+            "dir a/s",
+            "file a/s/escaped",
+            "mode a/s/escaped 644",
+            "mode a 755",
+        ]
+    );
+    assert_eq!(memory.files[Path::new("a/b.txt")], b"foo");
+
+    // On disk, the symlink is replaced by a directory rather than followed
+    let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
+    let dst = td.path().join("x/y");
+    t!(Archive::new(&data[..]).unpack_into(&mut OsFileSystem::new(&dst)));
+    assert!(!td.path().join("escaped").exists());
+    assert_eq!(t!(fs::read(dst.join("a/s/escaped"))), b"bar");
+    assert_eq!(t!(fs::read(dst.join("a/h"))), b"foo");
+}
+
+#[test]
+fn unpack_with_sinks() {
+    let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
+    let mut ar = Builder::new(Vec::new());
+    let mut header = Header::new_gnu();
+    header.set_entry_type(EntryType::Directory);
+    header.set_size(0);
+    t!(ar.append_data(&mut header, "d", io::empty()));
+    for (path, contents) in [("d/a.txt", "foo"), ("d/b.bin", "bar"), ("c.txt", "baz")] {
+        let mut header = Header::new_gnu();
+        header.set_size(contents.len() as u64);
+        t!(ar.append_data(&mut header, path, contents.as_bytes()));
+    }
+    let data = t!(ar.into_inner());
+
+    let mut seen = Vec::new();
+    t!(Archive::new(&data[..]).unpack_with_sinks(|entry| {
+        let path = t!(entry.path()).into_owned();
+        seen.push(path.display().to_string());
+        if path.extension()? != "txt" {
+            return None;
+        }
+        Some(t!(File::create(td.path().join(path.file_name()?))))
+    }));
+    assert_eq!(seen, ["d", "d/a.txt", "d/b.bin", "c.txt"]);
+    assert_eq!(t!(fs::read(td.path().join("a.txt"))), b"foo");
+    assert_eq!(t!(fs::read(td.path().join("c.txt"))), b"baz");
+    assert!(!td.path().join("b.bin").exists());
+}
+
+#[test]
+fn virtual_tree() {
+    let mut tree = VirtualTreeBuilder::new();
+    tree.file_from_reader("b/c/data", 4, &b"datamore"[..]);
+    tree.symlink("b/link", "c/data");
+    tree.file("a.sh", "#!/bin/sh\n")
+        .mode(0o755)
+        .mtime(1_000_000);
+    tree.dir("b").uid(1000).gid(1000);
+    let mut ar = Builder::new(Vec::new());
+    t!(tree.append_to(&mut ar));
+    let data = t!(ar.into_inner());
+
+    let mut archive = Archive::new(&data[..]);
+    let entries: Vec<_> = t!(archive.entries())
+        .map(|entry| {
+            let mut entry = t!(entry);
+            let header = entry.header();
+            let summary = format!(
+                "{} {:?} {:o} {} {} {}",
+                t!(entry.path()).display(),
+                header.entry_type(),
+                t!(header.mode()),
+                t!(header.uid()),
+                t!(header.mtime()),
+                t!(entry.link_name()).map_or(String::new(), |t| t.display().to_string()),
+            );
+            let mut contents = String::new();
+            t!(entry.read_to_string(&mut contents));
+            (summary, contents)
+        })
+        .collect();
+    let expected = [
+        ("a.sh Regular 755 0 1000000 ", "#!/bin/sh\n"),
+        ("b Directory 755 1000 0 ", ""),
+        ("b/c Directory 755 0 0 ", ""),
+        ("b/c/data Regular 644 0 0 ", "data"),
+        ("b/link Symlink 777 0 0 c/data", ""),
+    ];
+    let entries: Vec<_> = entries.iter().map(|(s, c)| (&s[..], &c[..])).collect();
+    assert_eq!(entries, expected);
+
+    let mut tree = VirtualTreeBuilder::new();
+    tree.file_from_reader("short", 8, &b"data"[..]);
+    assert!(tree.append_to(&mut Builder::new(Vec::new())).is_err());
+    let mut tree = VirtualTreeBuilder::new();
+    tree.file("../escaped", "");
+    assert!(tree.append_to(&mut Builder::new(Vec::new())).is_err());
+}
+
+#[test]
+#[cfg(feature = "sha2")]
+fn unpack_resumable() {
+    let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
+    let journal = td.path().join("journal");
+    let dst = td.path().join("out");
+
+    let mut ar = Builder::new(Vec::new());
+    for (path, size) in [("a", 512), ("b", 8192)] {
+        let mut header = Header::new_gnu();
+        header.set_size(size as u64);
+        header.set_mode(0o644);
+        t!(ar.append_data(&mut header, path, &vec![b'x'; size][..]));
+    }
+    let data = t!(ar.into_inner());
+
+    for digest in [None, Some(DigestAlgorithm::Sha256)] {
+        let _ = fs::remove_dir_all(&dst);
+
+        // Interrupted partway through `b`, with `a` recorded as finished
+        let mut ar = Archive::new(&data[..4096]);
+        ar.set_digest_algorithm(digest);
+        assert!(ar.unpack_resumable(&dst, &journal).is_err());
+        assert!(journal.exists());
+
+        // Changing `a` without changing its size is only noticed by its digest
+        t!(fs::write(dst.join("a"), [b'y'; 512]));
+        let mut ar = Archive::new(&data[..]);
+        ar.set_digest_algorithm(digest);
+        let report = t!(ar.unpack_resumable(&dst, &journal));
+        assert_eq!(report.entries(), 2);
+        assert!(!journal.exists());
+        let a = if digest.is_some() { b'x' } else { b'y' };
+        assert_eq!(t!(fs::read(dst.join("a"))), [a; 512]);
+        assert_eq!(t!(fs::read(dst.join("b"))), [b'x'; 8192]);
+    }
+}
+
+#[test]
+fn sync_on_unpack() {
+    let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
+    let mut ar = Builder::new(Vec::new());
+    for path in ["a/b/file", "a/other", "top"] {
+        let mut header = Header::new_gnu();
+        header.set_size(4);
+        header.set_mode(0o644);
+        t!(ar.append_data(&mut header, path, &b"data"[..]));
+    }
+    let data = t!(ar.into_inner());
+
+    for (dir, policy) in [
+        ("data", SyncPolicy::Data),
+        ("dirs", SyncPolicy::DataAndDirs),
+    ] {
+        let mut ar = Archive::new(&data[..]);
+        ar.set_sync_on_unpack(policy);
+        ar.set_atomic_unpack(true);
+        t!(ar.unpack(td.path().join(dir)));
+        assert_eq!(t!(fs::read(td.path().join(dir).join("a/b/file"))), b"data");
+        assert_eq!(t!(fs::read(td.path().join(dir).join("top"))), b"data");
+    }
+}
+
+#[test]
+fn preallocate_on_unpack() {
+    let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
+    let big = (0..1 << 20).map(|i| i as u8).collect::<Vec<_>>();
+    let mut ar = Builder::new(Vec::new());
+    for (path, data) in [("small", &b"data"[..]), ("big", &big[..])] {
+        let mut header = Header::new_gnu();
+        header.set_size(data.len() as u64);
+        header.set_mode(0o644);
+        t!(ar.append_data(&mut header, path, data));
+    }
+    let data = t!(ar.into_inner());
+
+    let mut ar = Archive::new(&data[..]);
+    ar.set_preallocate_threshold(Some(4096));
+    t!(ar.unpack(td.path()));
+    assert_eq!(t!(fs::read(td.path().join("small"))), b"data");
+    assert_eq!(t!(fs::read(td.path().join("big"))), big);
+
+    // Sparse files keep their holes, and their length
+    let mut ar = Archive::new(Cursor::new(tar!("sparse.tar")));
+    ar.set_preallocate_threshold(Some(1));
+    t!(ar.unpack(td.path()));
+    let s = t!(fs::read(td.path().join("sparse_end.txt")));
+    assert!(s[..s.len() - 9].iter().all(|&b| b == 0));
+    assert_eq!(&s[s.len() - 9..], b"test_end\n");
+}
+
+#[test]
+fn writing_and_extracting_directories() {
+    let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
+
+    let mut ar = Builder::new(Vec::new());
+    let tmppath = td.path().join("tmpfile");
+    t!(t!(File::create(&tmppath)).write_all(b"c"));
+    t!(ar.append_dir("a", "."));
+    t!(ar.append_dir("a/b", "."));
+    t!(ar.append_file("a/c", &mut t!(File::open(&tmppath))));
+    t!(ar.finish());
+
+    let rdr = Cursor::new(t!(ar.into_inner()));
+    let mut ar = Archive::new(rdr);
+    t!(ar.unpack(td.path()));
+    check_dirtree(&td);
+}
+
+#[test]
+fn writing_and_extracting_directories_complex_permissions() {
+    let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
+
+    // Archive with complex permissions which would fail to unpack if one attempted to do so
+    // without reordering of entries.
+    let mut ar = Builder::new(Vec::new());
+    let tmppath = td.path().join("tmpfile");
+    t!(t!(File::create(&tmppath)).write_all(b"c"));
+
+    // Root dir with very stringent permissions
+    let data: &[u8] = &[];
+    let mut header = Header::new_gnu();
+    header.set_mode(0o555);
+    header.set_entry_type(EntryType::Directory);
+    t!(header.set_path("a"));
+    header.set_size(0);
+    header.set_cksum();
+    t!(ar.append(&header, data));
+
+    // Nested dir
+    header.set_mode(0o777);
+    header.set_entry_type(EntryType::Directory);
+    t!(header.set_path("a/b"));
+    header.set_cksum();
+    t!(ar.append(&header, data));
+
+    // Nested file.
+    t!(ar.append_file("a/c", &mut t!(File::open(&tmppath))));
+    t!(ar.finish());
+
+    let rdr = Cursor::new(t!(ar.into_inner()));
+    let mut ar = Archive::new(rdr);
+    ar.unpack(td.path()).unwrap();
+    check_dirtree(&td);
+}
+
+#[test]
+fn writing_directories_recursively() {
+    let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
+
+    let base_dir = td.path().join("base");
+    t!(fs::create_dir(&base_dir));
+    t!(t!(File::create(base_dir.join("file1"))).write_all(b"file1"));
+    let sub_dir = base_dir.join("sub");
+    t!(fs::create_dir(&sub_dir));
+    t!(t!(File::create(sub_dir.join("file2"))).write_all(b"file2"));
+
+    let mut ar = Builder::new(Vec::new());
+    t!(ar.append_dir_all("foobar", base_dir));
+    let data = t!(ar.into_inner());
+
+    let mut ar = Archive::new(Cursor::new(data));
+    t!(ar.unpack(td.path()));
+    let base_dir = td.path().join("foobar");
+    assert!(fs::metadata(&base_dir).map(|m| m.is_dir()).unwrap_or(false));
+    let file1_path = base_dir.join("file1");
+    assert!(fs::metadata(&file1_path)
+        .map(|m| m.is_file())
+        .unwrap_or(false));
+    let sub_dir = base_dir.join("sub");
+    assert!(fs::metadata(&sub_dir).map(|m| m.is_dir()).unwrap_or(false));
+    let file2_path = sub_dir.join("file2");
+    assert!(fs::metadata(&file2_path)
+        .map(|m| m.is_file())
+        .unwrap_or(false));
+}
+
+#[test]
+fn append_dir_all_blank_dest() {
+    let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
+
+    let base_dir = td.path().join("base");
+    t!(fs::create_dir(&base_dir));
+    t!(t!(File::create(base_dir.join("file1"))).write_all(b"file1"));
+    let sub_dir = base_dir.join("sub");
+    t!(fs::create_dir(&sub_dir));
+    t!(t!(File::create(sub_dir.join("file2"))).write_all(b"file2"));
+
+    let mut ar = Builder::new(Vec::new());
+    t!(ar.append_dir_all("", base_dir));
+    let data = t!(ar.into_inner());
+
+    let mut ar = Archive::new(Cursor::new(data));
+    t!(ar.unpack(td.path()));
+    let base_dir = td.path();
+    assert!(fs::metadata(&base_dir).map(|m| m.is_dir()).unwrap_or(false));
+    let file1_path = base_dir.join("file1");
+    assert!(fs::metadata(&file1_path)
+        .map(|m| m.is_file())
+        .unwrap_or(false));
+    let sub_dir = base_dir.join("sub");
+    assert!(fs::metadata(&sub_dir).map(|m| m.is_dir()).unwrap_or(false));
+    let file2_path = sub_dir.join("file2");
+    assert!(fs::metadata(&file2_path)
+        .map(|m| m.is_file())
+        .unwrap_or(false));
+}
+
+#[test]
+fn append_dir_all_does_not_work_on_non_directory() {
+    let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
+    let path = td.path().join("test");
+    t!(t!(File::create(&path)).write_all(b"test"));
+
+    let mut ar = Builder::new(Vec::new());
+    let result = ar.append_dir_all("test", path);
+    assert!(result.is_err());
+}
+
+#[test]
+fn extracting_duplicate_dirs() {
+    let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
+    let rdr = Cursor::new(tar!("duplicate_dirs.tar"));
+    let mut ar = Archive::new(rdr);
+    t!(ar.unpack(td.path()));
+
+    let some_dir = td.path().join("some_dir");
+    assert!(fs::metadata(&some_dir).map(|m| m.is_dir()).unwrap_or(false));
+}
+
+#[test]
+fn unpack_old_style_bsd_dir() {
+    let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
+
+    let mut ar = Builder::new(Vec::new());
+
+    let mut header = Header::new_old();
+    header.set_entry_type(EntryType::Regular);
+    t!(header.set_path("testdir/"));
+    header.set_size(0);
+    header.set_cksum();
+    t!(ar.append(&header, &mut io::empty()));
+
+    // Extracting
+    let rdr = Cursor::new(t!(ar.into_inner()));
+    let mut ar = Archive::new(rdr);
+    t!(ar.unpack(td.path()));
+
+    // Iterating
+    let rdr = Cursor::new(ar.into_inner().into_inner());
+    let mut ar = Archive::new(rdr);
+    assert!(t!(ar.entries()).all(|fr| fr.is_ok()));
+
+    assert!(td.path().join("testdir").is_dir());
+}
+
+#[test]
+fn handling_incorrect_file_size() {
+    let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
+
+    let mut ar = Builder::new(Vec::new());
+
+    let path = td.path().join("tmpfile");
+    t!(File::create(&path));
+    let mut file = t!(File::open(&path));
+    let mut header = Header::new_old();
+    t!(header.set_path("somepath"));
+    header.set_metadata(&t!(file.metadata()));
+    header.set_size(2048); // past the end of file null blocks
+    header.set_cksum();
+    t!(ar.append(&header, &mut file));
+
+    // Extracting
+    let rdr = Cursor::new(t!(ar.into_inner()));
+    let mut ar = Archive::new(rdr);
+    assert!(ar.unpack(td.path()).is_err());
+
+    // Iterating
+    let rdr = Cursor::new(ar.into_inner().into_inner());
+    let mut ar = Archive::new(rdr);
+    assert!(t!(ar.entries()).any(|fr| fr.is_err()));
+}
+
+#[test]
+fn extracting_malicious_tarball() {
+    let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
+
+    let mut evil_tar = Vec::new();
+
+    {
+        let mut a = Builder::new(&mut evil_tar);
+        let mut append = |path: &str| {
+            let mut header = Header::new_gnu();
+            assert!(header.set_path(path).is_err(), "was ok: {:?}", path);
+            {
+                let h = header.as_gnu_mut().unwrap();
+                for (a, b) in h.name.iter_mut().zip(path.as_bytes()) {
+                    *a = *b;
+                }
+            }
+            header.set_size(1);
+            header.set_cksum();
+            t!(a.append(&header, io::repeat(1).take(1)));
+        };
+        append("/tmp/abs_evil.txt");
+        // std parse `//` as UNC path, see rust-lang/rust#100833
+        append(
+            #[cfg(not(windows))]
+            "//tmp/abs_evil2.txt",
+            #[cfg(windows)]
+            "C://tmp/abs_evil2.txt",
+        );
+        append("///tmp/abs_evil3.txt");
+        append("/./tmp/abs_evil4.txt");
+        append(
+            #[cfg(not(windows))]
+            "//./tmp/abs_evil5.txt",
+            #[cfg(windows)]
+            "C://./tmp/abs_evil5.txt",
+        );
+        append("///./tmp/abs_evil6.txt");
+        append("/../tmp/rel_evil.txt");
+        append("../rel_evil2.txt");
+        append("./../rel_evil3.txt");
+        append("some/../../rel_evil4.txt");
+        append("");
+        append("././//./..");
+        append("..");
+        append("/////////..");
+        append("/////////");
+    }
+
+    let mut ar = Archive::new(&evil_tar[..]);
+    t!(ar.unpack(td.path()));
+
+    assert!(fs::metadata("/tmp/abs_evil.txt").is_err());
+    assert!(fs::metadata("/tmp/abs_evil.txt2").is_err());
+    assert!(fs::metadata("/tmp/abs_evil.txt3").is_err());
+    assert!(fs::metadata("/tmp/abs_evil.txt4").is_err());
+    assert!(fs::metadata("/tmp/abs_evil.txt5").is_err());
+    assert!(fs::metadata("/tmp/abs_evil.txt6").is_err());
+    assert!(fs::metadata("/tmp/rel_evil.txt").is_err());
+    assert!(fs::metadata("/tmp/rel_evil.txt").is_err());
+    assert!(fs::metadata(td.path().join("../tmp/rel_evil.txt")).is_err());
+    assert!(fs::metadata(td.path().join("../rel_evil2.txt")).is_err());
+    assert!(fs::metadata(td.path().join("../rel_evil3.txt")).is_err());
+    assert!(fs::metadata(td.path().join("../rel_evil4.txt")).is_err());
+
+    // The `some` subdirectory should not be created because the only
+    // filename that references this has '..'.
+    assert!(fs::metadata(td.path().join("some")).is_err());
+
+    // The `tmp` subdirectory should be created and within this
+    // subdirectory, there should be files named `abs_evil.txt` through
+    // `abs_evil6.txt`.
+    assert!(fs::metadata(td.path().join("tmp"))
+        .map(|m| m.is_dir())
+        .unwrap_or(false));
+    assert!(fs::metadata(td.path().join("tmp/abs_evil.txt"))
+        .map(|m| m.is_file())
+        .unwrap_or(false));
+    assert!(fs::metadata(td.path().join("tmp/abs_evil2.txt"))
+        .map(|m| m.is_file())
+        .unwrap_or(false));
+    assert!(fs::metadata(td.path().join("tmp/abs_evil3.txt"))
+        .map(|m| m.is_file())
+        .unwrap_or(false));
+    assert!(fs::metadata(td.path().join("tmp/abs_evil4.txt"))
+        .map(|m| m.is_file())
+        .unwrap_or(false));
+    assert!(fs::metadata(td.path().join("tmp/abs_evil5.txt"))
+        .map(|m| m.is_file())
+        .unwrap_or(false));
+    assert!(fs::metadata(td.path().join("tmp/abs_evil6.txt"))
+        .map(|m| m.is_file())
+        .unwrap_or(false));
+}
+
+#[test]
+fn octal_spaces() {
+    let rdr = Cursor::new(tar!("spaces.tar"));
+    let mut ar = Archive::new(rdr);
+
+    let entry = ar.entries().unwrap().next().unwrap().unwrap();
+    assert_eq!(entry.header().mode().unwrap() & 0o777, 0o777);
+    assert_eq!(entry.header().uid().unwrap(), 0);
+    assert_eq!(entry.header().gid().unwrap(), 0);
+    assert_eq!(entry.header().size().unwrap(), 2);
+    assert_eq!(entry.header().mtime().unwrap(), 0o12440016664);
+    assert_eq!(entry.header().cksum().unwrap(), 0o4253);
+}
+
+#[test]
+fn extracting_malformed_tar_null_blocks() {
+    let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
+
+    let mut ar = Builder::new(Vec::new());
+
+    let path1 = td.path().join("tmpfile1");
+    let path2 = td.path().join("tmpfile2");
+    t!(File::create(&path1));
+    t!(File::create(&path2));
+    t!(ar.append_file("tmpfile1", &mut t!(File::open(&path1))));
+    let mut data = t!(ar.into_inner());
+    let amt = data.len();
+    data.truncate(amt - 512);
+    let mut ar = Builder::new(data);
+    t!(ar.append_file("tmpfile2", &mut t!(File::open(&path2))));
+    t!(ar.finish());
+
+    let data = t!(ar.into_inner());
+    let mut ar = Archive::new(&data[..]);
+    assert!(ar.unpack(td.path()).is_ok());
+}
+
+#[test]
+fn empty_filename() {
+    let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
+    let rdr = Cursor::new(tar!("empty_filename.tar"));
+    let mut ar = Archive::new(rdr);
+    assert!(ar.unpack(td.path()).is_ok());
+}
+
+#[test]
+fn file_times() {
+    let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
+    let rdr = Cursor::new(tar!("file_times.tar"));
+    let mut ar = Archive::new(rdr);
+    t!(ar.unpack(td.path()));
+
+    let meta = fs::metadata(td.path().join("a")).unwrap();
+    let mtime = FileTime::from_last_modification_time(&meta);
+    let atime = FileTime::from_last_access_time(&meta);
+    assert_eq!(mtime.unix_seconds(), 1000000000);
+    assert_eq!(mtime.nanoseconds(), 0);
+    assert_eq!(atime.unix_seconds(), 1000000000);
+    assert_eq!(atime.nanoseconds(), 0);
+}
+
+#[test]
+fn zero_file_times() {
+    let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
+
+    let mut ar = Builder::new(Vec::new());
+    ar.mode(HeaderMode::Deterministic);
+    let path = td.path().join("tmpfile");
+    t!(File::create(&path));
+    t!(ar.append_path_with_name(&path, "a"));
+
+    let data = t!(ar.into_inner());
+    let mut ar = Archive::new(&data[..]);
+    assert!(ar.unpack(td.path()).is_ok());
+
+    let meta = fs::metadata(td.path().join("a")).unwrap();
+    let mtime = FileTime::from_last_modification_time(&meta);
+    let atime = FileTime::from_last_access_time(&meta);
+    assert!(mtime.unix_seconds() != 0);
+    assert!(atime.unix_seconds() != 0);
+}
+
+#[test]
+fn backslash_treated_well() {
+    // Insert a file into an archive with a backslash
+    let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
+    let mut ar = Builder::new(Vec::<u8>::new());
+    t!(ar.append_dir("foo\\bar", td.path()));
+    let mut ar = Archive::new(Cursor::new(t!(ar.into_inner())));
+    let f = t!(t!(ar.entries()).next().unwrap());
+    if cfg!(unix) {
+        assert_eq!(t!(f.header().path()).to_str(), Some("foo\\bar"));
+    } else {
+        assert_eq!(t!(f.header().path()).to_str(), Some("foo/bar"));
+    }
+
+    // Unpack an archive with a backslash in the name
+    let mut ar = Builder::new(Vec::<u8>::new());
+    let mut header = Header::new_gnu();
+    header.set_metadata(&t!(fs::metadata(td.path())));
+    header.set_size(0);
+    for (a, b) in header.as_old_mut().name.iter_mut().zip(b"foo\\bar\x00") {
+        *a = *b;
+    }
+    header.set_cksum();
+    t!(ar.append(&header, &mut io::empty()));
+    let data = t!(ar.into_inner());
+    let mut ar = Archive::new(&data[..]);
+    let f = t!(t!(ar.entries()).next().unwrap());
+    assert_eq!(t!(f.header().path()).to_str(), Some("foo\\bar"));
+
+    let mut ar = Archive::new(&data[..]);
+    t!(ar.unpack(td.path()));
+    assert!(fs::metadata(td.path().join("foo\\bar")).is_ok());
+}
+
+#[test]
+#[cfg(unix)]
+fn set_mask() {
+    use ::std::os::unix::fs::PermissionsExt;
+    let mut ar = tar::Builder::new(Vec::new());
+
+    let mut header = tar::Header::new_gnu();
+    header.set_size(0);
+    header.set_entry_type(tar::EntryType::Regular);
+    t!(header.set_path("foo"));
+    header.set_mode(0o777);
+    header.set_cksum();
+    t!(ar.append(&header, &[][..]));
+
+    let mut header = tar::Header::new_gnu();
+    header.set_size(0);
+    header.set_entry_type(tar::EntryType::Regular);
+    t!(header.set_path("bar"));
+    header.set_mode(0o421);
+    header.set_cksum();
+    t!(ar.append(&header, &[][..]));
+
+    let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
+    let bytes = t!(ar.into_inner());
+    let mut ar = tar::Archive::new(&bytes[..]);
+    ar.set_mask(0o211);
+    t!(ar.unpack(td.path()));
+
+    let md = t!(fs::metadata(td.path().join("foo")));
+    assert_eq!(md.permissions().mode(), 0o100566);
+    let md = t!(fs::metadata(td.path().join("bar")));
+    assert_eq!(md.permissions().mode(), 0o100420);
+}
+
+#[test]
+#[cfg(unix)]
+fn set_apply_umask() {
+    use ::std::os::unix::fs::PermissionsExt;
+    let mut ar = tar::Builder::new(Vec::new());
+    let mut header = tar::Header::new_gnu();
+    header.set_size(0);
+    header.set_mode(0o666);
+    t!(ar.append_data(&mut header, "foo", &[][..]));
+    let bytes = t!(ar.into_inner());
+
+    // A file created here has the umask applied to the same mode
+    let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
+    t!(fs::File::create(td.path().join("created")));
+    let expected = t!(fs::metadata(td.path().join("created")))
+        .permissions()
+        .mode();
+
+    let mut ar = tar::Archive::new(&bytes[..]);
+    ar.set_apply_umask(true);
+    t!(ar.unpack(td.path().join("umask")));
+    let md = t!(fs::metadata(td.path().join("umask/foo")));
+    assert_eq!(md.permissions().mode(), expected);
+
+    let mut ar = tar::Archive::new(&bytes[..]);
+    ar.set_apply_umask(true);
+    ar.set_preserve_permissions(true);
+    t!(ar.unpack(td.path().join("preserved")));
+    let md = t!(fs::metadata(td.path().join("preserved/foo")));
+    assert_eq!(md.permissions().mode(), 0o100666);
+}
+
+#[test]
+#[cfg(unix)]
+fn read_only_directory_unpacked_again() {
+    use ::std::os::unix::fs::PermissionsExt;
+    let mut ar = Builder::new(Vec::new());
+    let mut header = Header::new_gnu();
+    header.set_entry_type(EntryType::Directory);
+    header.set_size(0);
+    header.set_mode(0o555);
+    t!(ar.append_data(&mut header, "ro", io::empty()));
+    let mut header = Header::new_gnu();
+    header.set_size(1);
+    header.set_mode(0o644);
+    t!(ar.append_data(&mut header, "ro/file", &b"x"[..]));
+    let data = t!(ar.into_inner());
+
+    let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
+    let mode = || t!(fs::metadata(td.path().join("ro"))).permissions().mode() & 0o777;
+    t!(Archive::new(&data[..]).unpack(td.path()));
+    assert_eq!(mode(), 0o555);
+
+    // The directory is made writable before its contents are unpacked
+    let mut ar = Archive::new(&data[..]);
+    let mut entry = t!(t!(ar.entries()).next().unwrap());
+    t!(entry.prepare_dir_in(td.path()));
+    assert_eq!(mode(), 0o755);
+
+    t!(Archive::new(&data[..]).unpack(td.path()));
+    assert_eq!(mode(), 0o555);
+    t!(fs::set_permissions(
+        td.path().join("ro"),
+        fs::Permissions::from_mode(0o755)
+    ));
+}
+
+#[test]
+#[cfg(unix)]
+fn read_only_directory_through_symlink() {
+    use ::std::os::unix::fs::PermissionsExt;
+    let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
+    let victim = td.path().join("victim");
+    t!(fs::create_dir_all(victim.join("sub")));
+    t!(fs::set_permissions(
+        victim.join("sub"),
+        fs::Permissions::from_mode(0o500)
+    ));
+    let dst = td.path().join("dst");
+    t!(fs::create_dir(&dst));
+    t!(std::os::unix::fs::symlink(&victim, dst.join("link")));
+
+    let mut ar = Builder::new(Vec::new());
+    let mut header = Header::new_gnu();
+    header.set_entry_type(EntryType::Directory);
+    header.set_size(0);
+    header.set_mode(0o755);
+    t!(ar.append_data(&mut header, "link/sub", io::empty()));
+    let data = t!(ar.into_inner());
+
+    assert!(Archive::new(&data[..]).unpack(&dst).is_err());
+    let mode = t!(fs::metadata(victim.join("sub"))).permissions().mode();
+    assert_eq!(mode & 0o777, 0o500);
+    t!(fs::set_permissions(
+        victim.join("sub"),
+        fs::Permissions::from_mode(0o755)
+    ));
+}
+
+#[cfg(unix)]
+#[test]
+fn nul_bytes_in_path() {
+    use std::ffi::OsStr;
+    use std::os::unix::prelude::*;
+
+    let nul_path = OsStr::from_bytes(b"foo\0");
+    let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
+    let mut ar = Builder::new(Vec::<u8>::new());
+    let err = ar.append_dir(nul_path, td.path()).unwrap_err();
+    assert!(err.to_string().contains("contains a nul byte"));
+}
+
+#[test]
+fn links() {
+    let mut ar = Archive::new(Cursor::new(tar!("link.tar")));
+    let mut entries = t!(ar.entries());
+    let link = t!(entries.next().unwrap());
+    assert_eq!(
+        t!(link.header().link_name()).as_ref().map(|p| &**p),
+        Some(Path::new("file"))
+    );
+    let other = t!(entries.next().unwrap());
+    assert!(t!(other.header().link_name()).is_none());
+}
+
+#[test]
+#[cfg(unix)] // making symlinks on windows is hard
+fn unpack_links() {
+    let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
+    let mut ar = Archive::new(Cursor::new(tar!("link.tar")));
+    t!(ar.unpack(td.path()));
+
+    let md = t!(fs::symlink_metadata(td.path().join("lnk")));
+    assert!(md.file_type().is_symlink());
+
+    let mtime = FileTime::from_last_modification_time(&md);
+    assert_eq!(mtime.unix_seconds(), 1448291033);
+
+    assert_eq!(
+        &*t!(fs::read_link(td.path().join("lnk"))),
+        Path::new("file")
+    );
+    t!(File::open(td.path().join("lnk")));
+}
+
+#[test]
+fn unpack_path_transform() {
+    let mut ar = Builder::new(Vec::new());
+    for name in ["old/a", "skip/b"] {
+        let mut header = Header::new_gnu();
+        header.set_size(1);
+        header.set_mode(0o644);
+        t!(ar.append_data(&mut header, name, &b"x"[..]));
+    }
+    let data = t!(ar.into_inner());
+
+    let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
+    let mut ar = Archive::new(&data[..]);
+    ar.set_path_transform(|path| {
+        let rest = path.strip_prefix("old").ok()?;
+        Some(Path::new("new").join(rest))
+    });
+    t!(ar.unpack(td.path()));
+
+    assert!(td.path().join("new/a").exists());
+    assert!(!td.path().join("old").exists());
+    assert!(!td.path().join("skip").exists());
+}
+
+#[test]
+fn append_to_existing_archive() {
+    let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
+    let path = td.path().join("test.tar");
+    let a = td.path().join("a");
+    let b = td.path().join("b");
+    t!(fs::write(&a, b"a"));
+    t!(fs::write(&b, b"b"));
+
+    let mut ar = Builder::new(t!(File::create(&path)));
+    t!(ar.append_path_with_name(&a, "a"));
+    t!(ar.finish());
+    drop(ar);
+
+    let file = t!(fs::OpenOptions::new().read(true).write(true).open(&path));
+    let mut ar = t!(Builder::append_to(file));
+    t!(ar.append_path_with_name(&b, "b"));
+    assert!(!t!(ar.update_path_with_name(&a, "a")));
+    t!(ar.finish());
+    drop(ar);
+
+    let mut ar = Archive::new(t!(File::open(&path)));
+    let names = t!(ar.entries())
+        .map(|e| t!(t!(e).path()).into_owned())
+        .collect::<Vec<_>>();
+    assert_eq!(names, [Path::new("a"), Path::new("b")]);
+}
+
+#[test]
+fn rewrite_without_members() {
+    let mut ar = Builder::new(Vec::new());
+    let long_name = "d".repeat(200);
+    for name in ["a", long_name.as_str(), "b"] {
+        let mut header = Header::new_gnu();
+        header.set_size(name.len() as u64);
+        t!(ar.append_data(&mut header, name, name.as_bytes()));
+    }
+    let data = t!(ar.into_inner());
+
+    let mut ar = Archive::new(Cursor::new(data));
+    let mut out = Vec::new();
+    let removed = t!(ar.rewrite(&mut out, |e| &*e.path_bytes() != b"a"));
+    assert_eq!(removed, 1);
+
+    let mut ar = Archive::new(&out[..]);
+    let mut names = Vec::new();
+    for entry in t!(ar.entries()) {
+        let mut entry = t!(entry);
+        let name = String::from_utf8(entry.path_bytes().into_owned()).unwrap();
+        let mut contents = String::new();
+        t!(entry.read_to_string(&mut contents));
+        assert_eq!(name, contents);
+        names.push(name);
+    }
+    assert_eq!(names, [long_name.as_str(), "b"]);
+}
+
+#[test]
+fn rewrite_keeps_global_pax() {
+    let mut ar = Builder::new(Vec::new());
+    t!(ar.set_global_pax([("comment", &b"abc"[..])]));
+    let mut header = Header::new_ustar();
+    header.set_size(0);
+    t!(ar.append_data(&mut header, "a", &[][..]));
+    t!(ar.append_data(&mut header, "b", &[][..]));
+    let data = t!(ar.into_inner());
+
+    let mut ar = Archive::new(Cursor::new(data));
+    let mut out = Vec::new();
+    let removed = t!(ar.rewrite(&mut out, |e| &*e.path_bytes() != b"a"));
+    assert_eq!(removed, 1);
+
+    let mut ar = Archive::new(&out[..]);
+    let mut records = Vec::new();
+    for entry in t!(ar.entries()) {
+        let mut entry = t!(entry);
+        let mut line = t!(entry.path()).display().to_string();
+        for (key, value) in t!(entry.pax_records()) {
+            line += &format!(" {}={}", key, String::from_utf8_lossy(value));
+        }
+        records.push(line);
+    }
+    assert_eq!(records, ["b comment=abc"]);
+}
+
+#[test]
+fn compare_entries_to_files() {
+    let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
+    let same = td.path().join("same");
+    let changed = td.path().join("changed");
+    t!(fs::write(&same, b"same"));
+    t!(fs::write(&changed, b"before"));
+
+    let mut ar = Builder::new(Vec::new());
+    t!(ar.append_path_with_name(&same, "same"));
+    t!(ar.append_path_with_name(&changed, "changed"));
+    t!(ar.append_path_with_name(&same, "missing"));
+    let data = t!(ar.into_inner());
+
+    t!(fs::write(&changed, b"after!"));
+    t!(filetime::set_file_mtime(
+        &changed,
+        FileTime::from_unix_time(1_000_000_000, 0)
+    ));
+
+    let mut ar = Archive::new(&data[..]);
+    let differences = t!(ar.entries())
+        .map(|e| {
+            let mut e = t!(e);
+            let path = td.path().join(t!(e.path()));
+            t!(e.compare_to(path))
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        differences,
+        [
+            vec![],
+            vec![Difference::Contents, Difference::Mtime],
+            vec![Difference::Missing],
+        ]
+    );
+}
+
+#[test]
+fn multi_volume_round_trip() {
+    let volumes = RefCell::new(Vec::new());
+    let open = |_| {
+        volumes.borrow_mut().push(Vec::new());
+        Ok(VolumeSink(&volumes))
+    };
+    let mut ar = Builder::new(t!(MultiVolumeWriter::new(1024, open)));
+    for name in ["a", "b", "c"] {
+        let mut header = Header::new_gnu();
+        header.set_size(600);
+        t!(ar.append_data(&mut header, name, &[name.as_bytes()[0]; 600][..]));
+    }
+    t!(t!(ar.into_inner()).finish());
+
+    let volumes = volumes.into_inner();
+    assert_eq!(volumes.len(), 6);
+    assert!(volumes[..5].iter().all(|v| v.len() == 1024));
+    assert_eq!(volumes[5].len(), 512);
+
+    let mut volumes = volumes.into_iter();
+    let mut ar = Archive::new(MultiVolumeReader::new(|_| {
+        Ok(volumes.next().map(Cursor::new))
+    }));
+    for (entry, name) in t!(ar.entries()).zip(["a", "b", "c"]) {
+        let mut entry = t!(entry);
+        assert_eq!(&*entry.path_bytes(), name.as_bytes());
+        let mut data = Vec::new();
+        t!(entry.read_to_end(&mut data));
+        assert_eq!(data, [name.as_bytes()[0]; 600]);
+    }
+}
+
+struct VolumeSink<'a>(&'a RefCell<Vec<Vec<u8>>>);
+
+impl Write for VolumeSink<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0
+            .borrow_mut()
+            .last_mut()
+            .unwrap()
+            .extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+#[cfg(all(feature = "sha2", feature = "blake3"))]
+fn entry_digests() {
+    let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
+
+    let mut ar = Archive::new(Cursor::new(tar!("sparse.tar")));
+    ar.set_digest_algorithm(Some(DigestAlgorithm::Sha256));
+    let report = t!(ar.unpack_with_report(td.path()));
+    assert!(report.entries() > 0);
+    assert!(!report.digests().is_empty());
+    for (path, digest) in report.digests() {
+        let data = t!(fs::read(td.path().join(path)));
+        assert_eq!(*digest, tar::Digest::of(DigestAlgorithm::Sha256, &data));
+    }
+
+    let mut builder = Builder::new(Vec::new());
+    let mut header = Header::new_gnu();
+    header.set_size(6);
+    t!(builder.append_data(&mut header, "a", &b"hello\n"[..]));
+    let data = t!(builder.into_inner());
+
+    let mut ar = Archive::new(&data[..]);
+    ar.set_digest_algorithm(Some(DigestAlgorithm::Sha256));
+    let report = t!(ar.unpack_with_report(td.path()));
+    assert_eq!(
+        report.digest("a").unwrap().to_hex(),
+        "5891b5b522d5df086d0ff0b110fbd9d21bb4fc7163af34d08286a2e846f6be03"
+    );
+
+    let mut ar = Archive::new(&data[..]);
+    ar.set_digest_algorithm(Some(DigestAlgorithm::Blake3));
+    let mut entry = t!(t!(ar.entries()).next().unwrap());
+    assert!(entry.digest().is_none());
+    t!(io::copy(&mut entry, &mut io::sink()));
+    let digest = entry.digest().unwrap();
+    assert_eq!(digest.algorithm(), DigestAlgorithm::Blake3);
+    assert_eq!(digest, tar::Digest::of(DigestAlgorithm::Blake3, b"hello\n"));
+}
+
+#[test]
+#[cfg(feature = "sha2")]
+fn write_manifests() {
+    let mut builder = Builder::new(Vec::new());
+    let mut header = Header::new_gnu();
+    header.set_entry_type(EntryType::Directory);
+    header.set_mode(0o755);
+    header.set_mtime(1000);
+    header.set_size(0);
+    t!(builder.append_data(&mut header, "./dir/", io::empty()));
+    let mut header = Header::new_gnu();
+    header.set_size(6);
+    header.set_mode(0o644);
+    header.set_mtime(1000);
+    t!(builder.append_data(&mut header, "./dir/a b", &b"hello\n"[..]));
+    let mut header = Header::new_gnu();
+    header.set_entry_type(EntryType::Link);
+    header.set_mode(0o644);
+    header.set_mtime(1000);
+    header.set_size(0);
+    t!(builder.append_link(&mut header, "dir/hard", "./dir/a b"));
+    let mut header = Header::new_gnu();
+    header.set_entry_type(EntryType::Symlink);
+    header.set_mode(0o777);
+    header.set_mtime(1000);
+    header.set_size(0);
+    t!(builder.append_link(&mut header, "dir/sym", "a b"));
+    let data = t!(builder.into_inner());
+
+    let hello = "5891b5b522d5df086d0ff0b110fbd9d21bb4fc7163af34d08286a2e846f6be03";
+    let mut mtree = Vec::new();
+    t!(Archive::new(&data[..]).write_manifest(ManifestFormat::Mtree, &mut mtree));
+    assert_eq!(
+        String::from_utf8(mtree).unwrap(),
+        format!(
+            "#mtree\n\
+             ./dir type=dir mode=0755 time=1000.000000000\n\
+             ./dir/a\\040b type=file mode=0644 time=1000.000000000 size=6 sha256digest={0}\n\
+             ./dir/hard type=file mode=0644 time=1000.000000000 size=6 sha256digest={0}\n\
+             ./dir/sym type=link mode=0777 time=1000.000000000 link=a\\040b\n",
+            hello
+        )
+    );
+
+    let mut sums = Vec::new();
+    t!(Archive::new(&data[..]).write_manifest(ManifestFormat::Sha256Sums, &mut sums));
+    assert_eq!(
+        String::from_utf8(sums).unwrap(),
+        format!("{0}  dir/a b\n{0}  dir/hard\n", hello)
+    );
+}
+
+#[test]
+#[cfg(feature = "sha2")]
+fn verify_manifests() {
+    let mut builder = Builder::new(Vec::new());
+    for (name, data) in [("a", &b"hello\n"[..]), ("b", b"bye\n")] {
+        let mut header = Header::new_gnu();
+        header.set_size(data.len() as u64);
+        header.set_mode(0o644);
+        header.set_mtime(1000);
+        t!(builder.append_data(&mut header, name, data));
+    }
+    let data = t!(builder.into_inner());
+
+    for format in [ManifestFormat::Mtree, ManifestFormat::Sha256Sums] {
+        let mut manifest = Vec::new();
+        t!(Archive::new(&data[..]).write_manifest(format, &mut manifest));
+        assert_eq!(
+            t!(Archive::new(&data[..]).verify_manifest(&manifest[..])),
+            []
+        );
+    }
+
+    let manifest = "#mtree
+/set type=file mode=0644
+a size=6 sha256digest=0000000000000000000000000000000000000000000000000000000000000000
+b mode=0600 size=3
+c
+";
+    let mismatches = t!(Archive::new(&data[..]).verify_manifest(manifest.as_bytes()));
+    assert_eq!(
+        mismatches,
+        [
+            ManifestMismatch::Differs("a".into(), Difference::Contents),
+            ManifestMismatch::Differs("b".into(), Difference::Size),
+            ManifestMismatch::Differs("b".into(), Difference::Mode),
+            ManifestMismatch::Missing("c".into()),
+        ]
+    );
+
+    let sums = "5891b5b522d5df086d0ff0b110fbd9d21bb4fc7163af34d08286a2e846f6be03  ./a\n";
+    let mismatches = t!(Archive::new(&data[..]).verify_manifest(sums.as_bytes()));
+    assert_eq!(mismatches, [ManifestMismatch::Unlisted("b".into())]);
+}
+
+#[test]
+#[cfg(feature = "sign")]
+fn sign_and_verify() {
+    use tar::{PublicKey, SecretKey, Signature, SigningWriter};
+
+    let key = t!(SecretKey::generate());
+    let key = t!(SecretKey::from_minisign(&key.to_minisign()));
+    let public = t!(PublicKey::from_minisign(&key.public_key().to_minisign()));
+
+    let mut builder = Builder::new(SigningWriter::new(Vec::new()));
+    let mut header = Header::new_gnu();
+    header.set_size(6);
+    t!(builder.append_data(&mut header, "a", &b"hello\n"[..]));
+    let (data, signature) = t!(t!(builder.into_inner()).finish(&key, "file:a.tar"));
+    let signature = t!(Signature::from_minisign(&signature.to_minisign()));
+    assert_eq!(signature.trusted_comment(), "file:a.tar");
+    assert_eq!(signature, t!(key.sign(&data[..], "file:a.tar")));
+
+    let mut ar = Archive::new(Cursor::new(&data));
+    t!(ar.verify_signature(&public, &signature));
+    assert_eq!(t!(ar.entries()).count(), 1);
+
+    let mut tampered = data.clone();
+    tampered[600] ^= 1;
+    let mut ar = Archive::new(Cursor::new(tampered));
+    let err = ar.verify_signature(&public, &signature).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+    let other = t!(SecretKey::generate()).public_key();
+    assert!(other.verify(&data[..], &signature).is_err());
+}
+
+#[test]
+#[cfg(feature = "encrypt")]
+fn encrypt_and_decrypt() {
+    use tar::{Identity, Recipient};
+
+    let identities = [Identity::generate()];
+    let recipient: Recipient = t!(identities[0].to_public().to_string().parse());
+
+    let mut builder = t!(Builder::with_encryption(Vec::new(), &[recipient]));
+    let mut header = Header::new_gnu();
+    header.set_size(6);
+    t!(builder.append_data(&mut header, "a", &b"hello\n"[..]));
+    let data = t!(t!(builder.into_inner()).finish());
+    assert!(data.starts_with(b"age-encryption.org/v1\n"));
+    assert!(!data.windows(6).any(|w| w == b"hello\n"));
+
+    let mut ar = t!(Archive::with_decryption(&data[..], &identities));
+    let mut entries = t!(ar.entries());
+    let mut entry = t!(entries.next().unwrap());
+    let mut contents = String::new();
+    t!(entry.read_to_string(&mut contents));
+    assert_eq!(contents, "hello\n");
+    assert!(entries.next().is_none());
+
+    assert!(Archive::with_decryption(&data[..], &[Identity::generate()]).is_err());
+
+    let mut tampered = data.clone();
+    let last = tampered.len() - 1;
+    tampered[last] ^= 1;
+    let mut ar = t!(Archive::with_decryption(&tampered[..], &identities));
+    assert!(t!(ar.entries()).any(|e| e.is_err()));
+}
+
+#[test]
+fn unpack_case_collisions() {
+    let mut ar = Builder::new(Vec::new());
+    for (path, contents) in [
+        ("Foo/a", "1"),
+        ("foo/b", "2"),
+        ("README", "3"),
+        ("readme", "4"),
+        ("README", "5"),
+    ] {
+        let mut header = Header::new_gnu();
+        header.set_size(1);
+        header.set_mode(0o644);
+        t!(ar.append_data(&mut header, path, contents.as_bytes()));
+    }
+    let mut header = Header::new_gnu();
+    header.set_entry_type(EntryType::Link);
+    header.set_size(0);
+    t!(ar.append_link(&mut header, "link", "foo/b"));
+    let data = t!(ar.into_inner());
+
+    let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
+    let mut ar = Archive::new(&data[..]);
+    ar.set_case_collision_policy(CaseCollisionPolicy::Error);
+    let err = ar.unpack(td.path().join("error")).unwrap_err();
+    assert!(err.to_string().contains("differs only in case"), "{}", err);
+
+    let dst = td.path().join("rename");
+    let mut ar = Archive::new(&data[..]);
+    ar.set_case_collision_policy(CaseCollisionPolicy::Rename);
+    t!(ar.unpack(&dst));
+    for (path, contents) in [
+        ("Foo/a", "1"),
+        ("foo.~1~/b", "2"),
+        ("README", "5"),
+        ("readme.~1~", "4"),
+        ("link", "2"),
+    ] {
+        assert_eq!(t!(fs::read_to_string(dst.join(path))), contents, "{}", path);
+    }
+    assert!(!dst.join("foo").exists());
+}
+
+#[test]
+fn unpack_prefix() {
+    let mut ar = Builder::new(Vec::new());
+    for path in [
+        "./project/docs/index.html",
+        "project/docs/api/a.html",
+        "project/src/main.rs",
+        "project/docs-old/x",
+    ] {
+        let mut header = Header::new_gnu();
+        header.set_size(2);
+        header.set_mode(0o644);
+        t!(ar.append_data(&mut header, path, &b"hi"[..]));
+    }
+    let data = t!(ar.into_inner());
+
+    let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
+    let mut ar = Archive::new(&data[..]);
+    t!(ar.unpack_prefix(td.path(), "project/docs/"));
+    assert_eq!(t!(fs::read(td.path().join("index.html"))), b"hi");
+    assert_eq!(t!(fs::read(td.path().join("api/a.html"))), b"hi");
+    assert_eq!(t!(fs::read_dir(td.path())).count(), 2);
+}
+
+#[test]
+#[cfg(unix)]
+fn unpack_devices() {
+    use std::os::unix::fs::FileTypeExt;
+
+    let mut ar = Builder::new(Vec::new());
+    for path in ["with", "without"] {
+        let mut header = Header::new_gnu();
+        header.set_entry_type(EntryType::Fifo);
+        header.set_size(0);
+        header.set_mode(0o640);
+        t!(ar.append_data(&mut header, path, io::empty()));
+    }
+    let data = t!(ar.into_inner());
+
+    let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
+    let mut ar = Archive::new(&data[..]);
+    for (i, entry) in t!(ar.entries()).enumerate() {
+        let mut entry = t!(entry);
+        entry.set_unpack_devices(i == 0);
+        t!(entry.unpack_in(td.path()));
+    }
+    let with = t!(fs::symlink_metadata(td.path().join("with")));
+    assert!(with.file_type().is_fifo());
+    let without = t!(fs::symlink_metadata(td.path().join("without")));
+    assert!(without.is_file());
+}
+
+#[test]
+fn unpack_windows_names() {
+    let mut ar = Builder::new(Vec::new());
+    for path in [
+        "a:b?.txt",
+        "CON",
+        "dir./nul.txt",
+        "trail. ",
+        "com1.tar.gz",
+        "console",
+    ] {
+        let mut header = Header::new_gnu();
+        header.set_size(2);
+        header.set_mode(0o644);
+        t!(ar.append_data(&mut header, path, &b"hi"[..]));
+    }
+    let mut header = Header::new_gnu();
+    header.set_entry_type(EntryType::Link);
+    header.set_size(0);
+    t!(ar.append_link(&mut header, "link", "a:b?.txt"));
+    let data = t!(ar.into_inner());
+
+    let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
+    let mut ar = Archive::new(&data[..]);
+    ar.set_windows_names(true);
+    t!(ar.unpack(td.path()));
+    for path in [
+        "a_b_.txt",
+        "CON_",
+        "dir/nul_.txt",
+        "trail",
+        "com1_.tar.gz",
+        "console",
+        "link",
+    ] {
+        assert_eq!(t!(fs::read(td.path().join(path))), b"hi", "{}", path);
+    }
+}
+
+#[test]
+#[cfg(feature = "unicode-normalization")]
+fn normalize_paths() {
+    use tar::NormalizationForm;
+
+    let nfd = "cafe\u{301}/re\u{301}sume\u{301}.txt";
+    let nfc = "caf\u{e9}/r\u{e9}sum\u{e9}.txt";
+
+    // Decomposed names, as from macOS, are composed when added
+    let mut ar = Builder::new(Vec::new());
+    ar.normalize_paths(Some(NormalizationForm::Nfc));
+    let mut header = Header::new_gnu();
+    header.set_size(2);
+    header.set_mode(0o644);
+    t!(ar.append_data(&mut header, nfd, &b"hi"[..]));
+    let data = t!(ar.into_inner());
+    let mut ar = Archive::new(&data[..]);
+    let entry = t!(t!(ar.entries()).next().unwrap());
+    assert_eq!(&*t!(entry.path()), Path::new(nfc));
+
+    // and when unpacked, along with the targets of links
+    let mut ar = Builder::new(Vec::new());
+    let mut header = Header::new_gnu();
+    header.set_size(2);
+    header.set_mode(0o644);
+    t!(ar.append_data(&mut header, nfd, &b"hi"[..]));
+    let mut header = Header::new_gnu();
+    header.set_entry_type(EntryType::Link);
+    header.set_size(0);
+    t!(ar.append_link(&mut header, "link", nfd));
+    let data = t!(ar.into_inner());
+
+    let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
+    let mut ar = Archive::new(&data[..]);
+    ar.set_normalize_paths(Some(NormalizationForm::Nfc));
+    t!(ar.unpack(td.path()));
+    assert_eq!(t!(fs::read(td.path().join(nfc))), b"hi");
+    assert_eq!(t!(fs::read(td.path().join("link"))), b"hi");
+    assert_eq!(t!(fs::read_dir(td.path())).count(), 2);
+}
+
+#[test]
+#[cfg(windows)]
+fn non_utf8_path_policy() {
+    use tar::NonUtf8PathPolicy;
+
+    let mut ar = Builder::new(Vec::new());
+    for name in [&b"caf\xe9.txt"[..], b"ok.txt"] {
+        let mut header = Header::new_gnu();
+        header.as_old_mut().name[..name.len()].copy_from_slice(name);
+        header.set_size(2);
+        header.set_mode(0o644);
+        header.set_cksum();
+        t!(ar.append(&header, &b"hi"[..]));
+    }
+    let data = t!(ar.into_inner());
+
+    let mut ar = Archive::new(&data[..]);
+    assert!(t!(t!(ar.entries()).next().unwrap()).path().is_err());
+
+    let mut ar = Archive::new(&data[..]);
+    ar.set_non_utf8_path_policy(NonUtf8PathPolicy::LossyReplace);
+    let entry = t!(t!(ar.entries()).next().unwrap());
+    assert_eq!(&*t!(entry.path()), Path::new("caf\u{fffd}.txt"));
+
+    let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
+    let mut ar = Archive::new(&data[..]);
+    ar.set_non_utf8_path_policy(NonUtf8PathPolicy::PercentEncode);
+    t!(ar.unpack(td.path()));
+    assert_eq!(t!(fs::read(td.path().join("caf%E9.txt"))), b"hi");
+
+    let mut ar = Archive::new(&data[..]);
+    ar.set_non_utf8_path_policy(NonUtf8PathPolicy::Skip);
+    let paths = t!(ar.entries())
+        .map(|entry| t!(t!(entry).path()).into_owned())
+        .collect::<Vec<_>>();
+    assert_eq!(paths, [Path::new("ok.txt")]);
+}
+
+#[test]
+fn unpack_symlink_fallback() {
+    let mut ar = Builder::new(Vec::new());
+    let mut header = Header::new_gnu();
+    header.set_size(2);
+    header.set_mode(0o644);
+    t!(ar.append_data(&mut header, "target/f", &b"hi"[..]));
+    let mut header = Header::new_gnu();
+    header.set_entry_type(EntryType::Symlink);
+    header.set_size(0);
+    t!(ar.append_link(&mut header, "link", "target"));
+    let data = t!(ar.into_inner());
+
+    // A directory in the way of the symlink makes creating it fail.
+    let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
+    for fallback in [
+        SymlinkFallback::Error,
+        SymlinkFallback::Skip,
+        SymlinkFallback::CopyTarget,
+    ] {
+        let dst = td.path().join(format!("{:?}", fallback));
+        t!(fs::create_dir_all(dst.join("link/in-the-way")));
+        let mut ar = Archive::new(&data[..]);
+        ar.set_symlink_fallback(fallback);
+        let result = ar.unpack(&dst);
+        assert_eq!(result.is_err(), fallback == SymlinkFallback::Error);
+        assert!(dst.join("link/in-the-way").is_dir());
+        assert_eq!(
+            dst.join("link/f").exists(),
+            fallback == SymlinkFallback::CopyTarget
+        );
+    }
+}
+
+#[test]
+#[cfg(unix)]
+fn detect_hardlinks() {
+    use std::os::unix::fs::MetadataExt;
+
+    let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
+    let src = td.path().join("src");
+    t!(fs::create_dir(&src));
+    t!(fs::write(src.join("a"), "shared"));
+    t!(fs::hard_link(src.join("a"), src.join("b")));
+    t!(fs::write(src.join("c"), "shared"));
+
+    let mut ar = Builder::new(Vec::new());
+    ar.detect_hardlinks(true);
+    t!(ar.append_dir_all("", &src));
+    t!(ar.append_path_with_name(src.join("b"), "again"));
+    let data = t!(ar.into_inner());
+
+    let mut ar = Archive::new(&data[..]);
+    let mut links = Vec::new();
+    let mut first = None;
+    for entry in t!(ar.entries()) {
+        let entry = t!(entry);
+        let path = t!(entry.path()).into_owned();
+        match entry.header().entry_type() {
+            EntryType::Link => {
+                assert_eq!(entry.header().entry_size().unwrap(), 0);
+                links.push((path, t!(entry.link_name()).unwrap().into_owned()));
+            }
+            _ if path == Path::new("a") || path == Path::new("b") => first = Some(path),
+            _ => {}
+        }
+    }
+    let first = first.unwrap();
+    let mut others = ["a", "b", "again"]
+        .iter()
+        .map(PathBuf::from)
+        .filter(|p| *p != first)
+        .map(|p| (p, first.clone()))
+        .collect::<Vec<_>>();
+    links.sort();
+    others.sort();
+    assert_eq!(links, others);
+
+    let dst = td.path().join("dst");
+    t!(Archive::new(&data[..]).unpack(&dst));
+    let ino = t!(fs::metadata(dst.join("a"))).ino();
+    assert_eq!(t!(fs::metadata(dst.join("again"))).ino(), ino);
+    assert_ne!(t!(fs::metadata(dst.join("c"))).ino(), ino);
+    assert_eq!(t!(fs::read_to_string(dst.join("b"))), "shared");
+}
+
+#[test]
+#[cfg(feature = "sha2")]
+fn deduplicate_contents() {
+    let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
+    t!(fs::write(td.path().join("a"), "same"));
+    t!(fs::write(td.path().join("b"), "same"));
+    t!(fs::write(td.path().join("c"), "different"));
+    t!(fs::write(td.path().join("d"), ""));
+    t!(fs::write(td.path().join("e"), ""));
+
+    let mut ar = Builder::new(Vec::new());
+    ar.deduplicate(Some(DigestAlgorithm::Sha256));
+    for name in ["a", "b", "c", "d", "e"] {
+        t!(ar.append_path_with_name(td.path().join(name), name));
+    }
+    let data = t!(ar.into_inner());
+
+    let mut ar = Archive::new(&data[..]);
+    let kinds = t!(ar.entries())
+        .map(|e| {
+            let e = t!(e);
+            let link = t!(e.link_name()).map(|l| l.into_owned());
+            (e.header().entry_type(), link)
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        kinds,
+        [
+            (EntryType::Regular, None),
+            (EntryType::Link, Some(PathBuf::from("a"))),
+            (EntryType::Regular, None),
+            (EntryType::Regular, None),
+            (EntryType::Regular, None),
+        ]
+    );
+
+    let dst = td.path().join("dst");
+    t!(Archive::new(&data[..]).unpack(&dst));
+    assert_eq!(t!(fs::read_to_string(dst.join("b"))), "same");
+}
+
+#[test]
+fn unpack_strip_components() {
+    let mut ar = Builder::new(Vec::new());
+    let mut header = Header::new_gnu();
+    header.set_entry_type(EntryType::Directory);
+    header.set_size(0);
+    header.set_mode(0o755);
+    t!(ar.append_data(&mut header, "top", io::empty()));
+    let mut header = Header::new_gnu();
+    header.set_size(2);
+    header.set_mode(0o644);
+    t!(ar.append_data(&mut header, "top/sub/a", &b"hi"[..]));
+    let mut header = Header::new_gnu();
+    header.set_entry_type(EntryType::Link);
+    header.set_size(0);
+    header.set_mode(0o644);
+    t!(header.set_link_name("top/sub/a"));
+    t!(ar.append_data(&mut header, "top/b", io::empty()));
+    let data = t!(ar.into_inner());
+
+    let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
+    let mut ar = Archive::new(&data[..]);
+    ar.set_strip_components(1);
+    t!(ar.unpack(td.path()));
+
+    assert!(!td.path().join("top").exists());
+    assert_eq!(t!(fs::read(td.path().join("sub/a"))), b"hi");
+    assert_eq!(t!(fs::read(td.path().join("b"))), b"hi");
+}
+
+#[test]
+fn destination_in() {
+    let mut ar = Builder::new(Vec::new());
+    for path in ["top/sub/a", "top/old/b", "top"] {
+        let mut header = Header::new_gnu();
+        header.set_size(0);
+        header.set_mode(0o644);
+        t!(ar.append_data(&mut header, path, io::empty()));
+    }
+    let mut header = Header::new_gnu();
+    header.as_gnu_mut().unwrap().name[..11].copy_from_slice(b"top/../evil");
+    header.set_size(0);
+    header.set_cksum();
+    t!(ar.append(&header, io::empty()));
+    let data = t!(ar.into_inner());
+
+    let dst = Path::new("dst");
+    let mut ar = Archive::new(&data[..]);
+    ar.set_path_transform(|path| {
+        let path = path.to_str()?;
+        Some(PathBuf::from(path.replace("/old/", "/new/")))
+    });
+    ar.set_strip_components(1);
+    let destinations = t!(ar.entries())
+        .map(|entry| t!(t!(entry).destination_in(dst)))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        destinations,
+        [
+            Some(dst.join("sub/a")),
+            Some(dst.join("new/b")),
+            Some(dst.to_path_buf()),
+            None,
+        ]
+    );
+}
+
+#[test]
+fn pax_size() {
+    let mut ar = Archive::new(tar!("pax_size.tar"));
+    let mut entries = t!(ar.entries());
+    let mut entry = t!(entries.next().unwrap());
+    let mut attributes = t!(entry.pax_extensions()).unwrap();
+
+    let _first = t!(attributes.next().unwrap());
+    let _second = t!(attributes.next().unwrap());
+    let _third = t!(attributes.next().unwrap());
+    let fourth = t!(attributes.next().unwrap());
+    assert!(attributes.next().is_none());
+
+    assert_eq!(fourth.key(), Ok("size"));
+    assert_eq!(fourth.value(), Ok("4"));
+
+    assert_eq!(entry.header().size().unwrap(), 0);
+    assert_eq!(entry.size(), 4);
+}
+
+#[test]
+fn pax_simple() {
+    let mut ar = Archive::new(tar!("pax.tar"));
+    let mut entries = t!(ar.entries());
+
+    let mut first = t!(entries.next().unwrap());
+    let mut attributes = t!(first.pax_extensions()).unwrap();
+    let first = t!(attributes.next().unwrap());
+    let second = t!(attributes.next().unwrap());
+    let third = t!(attributes.next().unwrap());
+    assert!(attributes.next().is_none());
+
+    assert_eq!(first.key(), Ok("mtime"));
+    assert_eq!(first.value(), Ok("1453146164.953123768"));
+    assert_eq!(second.key(), Ok("atime"));
+    assert_eq!(second.value(), Ok("1453251915.24892486"));
+    assert_eq!(third.key(), Ok("ctime"));
+    assert_eq!(third.value(), Ok("1453146164.953123768"));
+}
+
+#[test]
+fn pax_simple_write() {
+    let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
+    let pax_path = td.path().join("pax.tar");
+    let file: File = t!(File::create(&pax_path));
+    let mut ar: Builder<BufWriter<File>> = Builder::new(BufWriter::new(file));
+
+    let pax_extensions = [
+        ("arbitrary_pax_key", b"arbitrary_pax_value".as_slice()),
+        ("SCHILY.xattr.security.selinux", b"foo_t"),
+    ];
+
+    t!(ar.append_pax_extensions(pax_extensions));
+    t!(ar.append_file("test2", &mut t!(File::open(&pax_path))));
+    t!(ar.finish());
+    drop(ar);
+
+    let mut archive_opened = Archive::new(t!(File::open(pax_path)));
+    let mut entries = t!(archive_opened.entries());
+    let mut f: Entry<File> = t!(entries.next().unwrap());
+    let pax_headers = t!(f.pax_extensions());
+
+    assert!(pax_headers.is_some(), "pax_headers is None");
+    let mut pax_headers = pax_headers.unwrap();
+    let pax_arbitrary = t!(pax_headers.next().unwrap());
+    assert_eq!(pax_arbitrary.key(), Ok("arbitrary_pax_key"));
+    assert_eq!(pax_arbitrary.value(), Ok("arbitrary_pax_value"));
+    let xattr = t!(pax_headers.next().unwrap());
+    assert_eq!(xattr.key().unwrap(), pax_extensions[1].0);
+    assert_eq!(xattr.value_bytes(), pax_extensions[1].1);
+
+    assert!(entries.next().is_none());
+}
+
+#[test]
+fn pax_records() {
+    let mut ar = Builder::new(Vec::new());
+    let mut header = Header::new_ustar();
+    header.set_size(4);
+    header.set_mode(0o644);
+    let records = [
+        ("mtime", &b"1700000000.5"[..]),
+        ("size", b"4"),
+        ("path", b"long/name"),
+        ("SCHILY.xattr.user.origin", b"web"),
+    ];
+    t!(ar.append_pax_records(&mut header, "short", records, &b"data"[..]));
+    let data = t!(ar.into_inner());
+
+    let mut ar = Archive::new(&data[..]);
+    let mut entry = t!(t!(ar.entries()).next().unwrap());
+    assert_eq!(t!(entry.pax_records()).collect::<Vec<_>>(), records);
+    assert_eq!(
+        t!(entry.pax_mtime()),
+        Some(std::time::UNIX_EPOCH + Duration::new(1_700_000_000, 500_000_000))
+    );
+    assert_eq!(t!(entry.pax_size()), Some(4));
+    assert_eq!(t!(entry.pax_path()), Some(PathBuf::from("long/name")));
+    assert_eq!(t!(entry.pax_linkpath()), None);
+    assert_eq!(
+        t!(entry.pax_xattrs()).collect::<Vec<_>>(),
+        [("user.origin", &b"web"[..])]
+    );
+    assert_eq!(&*t!(entry.path()), Path::new("long/name"));
+}
+
+#[test]
+fn global_pax_records() {
+    let mut ar = Builder::new(Vec::new());
+    t!(ar.set_global_pax([("comment", &b"abc123"[..]), ("uname", b"git")]));
+    let mut header = Header::new_ustar();
+    header.set_size(0);
+    t!(ar.append_data(&mut header, "a", &[][..]));
+    t!(ar.append_pax_records(&mut header, "b", [("comment", &b"own"[..])], &[][..]));
+    t!(ar.set_global_pax([("uname", &b""[..])]));
+    t!(ar.append_data(&mut header, "c", &[][..]));
+    let data = t!(ar.into_inner());
+
+    let mut ar = Archive::new(&data[..]);
+    let mut records = Vec::new();
+    for entry in t!(ar.entries()) {
+        let mut entry = t!(entry);
+        let mut line = t!(entry.path()).display().to_string();
+        for (key, value) in t!(entry.pax_records()) {
+            line += &format!(" {}={}", key, String::from_utf8_lossy(value));
+        }
+        records.push(line);
+    }
+    assert_eq!(
+        records,
+        [
+            "a comment=abc123 uname=git",
+            "b uname=git comment=own",
+            "c comment=abc123"
+        ]
+    );
+}
+
+#[test]
+fn archive_metadata() {
+    let mut metadata = ArchiveMetadata::new();
+    metadata.set_comment("nightly build");
+    metadata.set_revision("v1.2.0-3-g4b825dc");
+    metadata.set_tool("tar-rs tests");
+    metadata.set("EXAMPLE.channel", "beta");
+
+    let mut ar = Builder::new(Vec::new());
+    t!(ar.append_archive_metadata(&metadata));
+    let mut header = Header::new_ustar();
+    header.set_size(0);
+    t!(ar.append_data(&mut header, "a", &[][..]));
+    let data = t!(ar.into_inner());
+
+    let mut ar = Archive::new(&data[..]);
+    let mut entries = t!(ar.entries());
+    assert!(entries.archive_metadata().is_empty());
+    let entry = t!(entries.next().unwrap());
+    assert_eq!(&*t!(entry.path()), Path::new("a"));
+    let read = entries.archive_metadata();
+    assert_eq!(read, metadata);
+    assert_eq!(read.comment(), Some("nightly build"));
+    assert_eq!(read.revision(), Some("v1.2.0-3-g4b825dc"));
+    assert_eq!(read.tool(), Some("tar-rs tests"));
+    assert_eq!(read.get("EXAMPLE.channel"), Some("beta"));
+}
+
+#[test]
+fn pax_path() {
+    let mut ar = Archive::new(tar!("pax2.tar"));
+    let mut entries = t!(ar.entries());
+
+    let first = t!(entries.next().unwrap());
+    assert!(first.path().unwrap().ends_with("aaaaaaaaaaaaaaa"));
+}
+
+#[test]
+fn pax_linkpath() {
+    let mut ar = Archive::new(tar!("pax2.tar"));
+    let mut links = t!(ar.entries()).skip(3).take(2);
+
+    let long_symlink = t!(links.next().unwrap());
+    let link_name = long_symlink.link_name().unwrap().unwrap();
+    assert!(link_name.to_str().unwrap().len() > 99);
+    assert!(link_name.ends_with("bbbbbbbbbbbbbbb"));
+
+    let long_hardlink = t!(links.next().unwrap());
+    let link_name = long_hardlink.link_name().unwrap().unwrap();
+    assert!(link_name.to_str().unwrap().len() > 99);
+    assert!(link_name.ends_with("ccccccccccccccc"));
+}
+
+#[test]
+fn long_name_trailing_nul() {
+    let mut b = Builder::new(Vec::<u8>::new());
+
+    let mut h = Header::new_gnu();
+    t!(h.set_path("././@LongLink"));
+    h.set_size(4);
+    h.set_entry_type(EntryType::new(b'L'));
+    h.set_cksum();
+    t!(b.append(&h, "foo\0".as_bytes()));
+    let mut h = Header::new_gnu();
+
+    t!(h.set_path("bar"));
+    h.set_size(6);
+    h.set_entry_type(EntryType::file());
+    h.set_cksum();
+    t!(b.append(&h, "foobar".as_bytes()));
+
+    let contents = t!(b.into_inner());
+    let mut a = Archive::new(&contents[..]);
+
+    let e = t!(t!(a.entries()).next().unwrap());
+    assert_eq!(&*e.path_bytes(), b"foo");
+}
+
+#[test]
+fn long_linkname_trailing_nul() {
+    let mut b = Builder::new(Vec::<u8>::new());
+
+    let mut h = Header::new_gnu();
+    t!(h.set_path("././@LongLink"));
+    h.set_size(4);
+    h.set_entry_type(EntryType::new(b'K'));
+    h.set_cksum();
+    t!(b.append(&h, "foo\0".as_bytes()));
+    let mut h = Header::new_gnu();
+
+    t!(h.set_path("bar"));
+    h.set_size(6);
+    h.set_entry_type(EntryType::file());
+    h.set_cksum();
+    t!(b.append(&h, "foobar".as_bytes()));
+
+    let contents = t!(b.into_inner());
+    let mut a = Archive::new(&contents[..]);
+
+    let e = t!(t!(a.entries()).next().unwrap());
+    assert_eq!(&*e.link_name_bytes().unwrap(), b"foo");
+}
+
+#[test]
+fn long_linkname_gnu() {
+    for t in [tar::EntryType::Symlink, tar::EntryType::Link] {
+        let mut b = Builder::new(Vec::<u8>::new());
+        let mut h = Header::new_gnu();
+        h.set_entry_type(t);
+        h.set_size(0);
+        let path = "usr/lib/.build-id/05/159ed904e45ff5100f7acd3d3b99fa7e27e34f";
+        let target = "../../../../usr/lib64/qt5/plugins/wayland-graphics-integration-server/libqt-wayland-compositor-xcomposite-egl.so";
+        t!(b.append_link(&mut h, path, target));
+
+        let contents = t!(b.into_inner());
+        let mut a = Archive::new(&contents[..]);
+
+        let e = &t!(t!(a.entries()).next().unwrap());
+        assert_eq!(e.header().entry_type(), t);
+        assert_eq!(e.path().unwrap().to_str().unwrap(), path);
+        assert_eq!(e.link_name().unwrap().unwrap().to_str().unwrap(), target);
+    }
 }
 
 #[test]
-fn file_times() {
-    let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
-    let rdr = Cursor::new(tar!("file_times.tar"));
-    let mut ar = Archive::new(rdr);
-    t!(ar.unpack(td.path()));
+fn linkname_literal() {
+    for t in [tar::EntryType::Symlink, tar::EntryType::Link] {
+        let mut b = Builder::new(Vec::<u8>::new());
+        let mut h = Header::new_gnu();
+        h.set_entry_type(t);
+        h.set_size(0);
+        let path = "usr/lib/systemd/systemd-sysv-install";
+        let target = "../../..//sbin/chkconfig";
+        h.set_link_name_literal(target).unwrap();
+        t!(b.append_data(&mut h, path, std::io::empty()));
 
-    let meta = fs::metadata(td.path().join("a")).unwrap();
-    let mtime = FileTime::from_last_modification_time(&meta);
-    let atime = FileTime::from_last_access_time(&meta);
-    assert_eq!(mtime.unix_seconds(), 1000000000);
-    assert_eq!(mtime.nanoseconds(), 0);
-    assert_eq!(atime.unix_seconds(), 1000000000);
-    assert_eq!(atime.nanoseconds(), 0);
+        let contents = t!(b.into_inner());
+        let mut a = Archive::new(&contents[..]);
+
+        let e = &t!(t!(a.entries()).next().unwrap());
+        assert_eq!(e.header().entry_type(), t);
+        assert_eq!(e.path().unwrap().to_str().unwrap(), path);
+        assert_eq!(e.link_name().unwrap().unwrap().to_str().unwrap(), target);
+    }
 }
 
 #[test]
-fn zero_file_times() {
-    let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
+fn append_entry() {
+    fn summarize(data: &[u8]) -> Vec<(String, Vec<u8>, Vec<u8>)> {
+        let mut ar = Archive::new(data);
+        t!(ar.entries())
+            .map(|e| {
+                let mut e = t!(e);
+                let names = format!("{:?} {:?}", t!(e.path()), t!(e.link_name()));
+                let mut contents = Vec::new();
+                t!(e.read_to_end(&mut contents));
+                (names, e.header().as_bytes().to_vec(), contents)
+            })
+            .collect()
+    }
+
+    for src in [
+        tar!("sparse.tar"),
+        tar!("sparse-1.tar"),
+        tar!("pax.tar"),
+        tar!("7z_long_path.tar"),
+        tar!("link.tar"),
+    ] {
+        let mut ar = Builder::new(Vec::new());
+        for entry in t!(Archive::new(src).entries()) {
+            t!(ar.append_entry(&mut t!(entry)));
+        }
+        let copy = t!(ar.into_inner());
+        assert_eq!(summarize(&copy), summarize(src));
+    }
 
+    // A sparse file with more blocks than its header has room for, so that
+    // they are listed in extended headers too
+    let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
+    let path = td.path().join("sparse");
+    let mut file = t!(File::create(&path));
+    for i in 0..6 {
+        t!(file.seek(SeekFrom::Start(i * 0x20_000)));
+        t!(file.write_all(b"data"));
+    }
     let mut ar = Builder::new(Vec::new());
-    ar.mode(HeaderMode::Deterministic);
-    let path = td.path().join("tmpfile");
-    t!(File::create(&path));
-    t!(ar.append_path_with_name(&path, "a"));
+    t!(ar.append_path_with_name(&path, "sparse"));
+    let src = t!(ar.into_inner());
+    let mut ar = Builder::new(Vec::new());
+    t!(ar.append_entry(&mut t!(t!(Archive::new(&src[..]).entries())
+        .next()
+        .unwrap())));
+    assert_eq!(t!(ar.into_inner()), src);
+
+    let mut src = Archive::new(tar!("reading_files.tar"));
+    let mut entry = t!(t!(src.entries()).next().unwrap());
+    t!(entry.read_exact(&mut [0; 1]));
+    assert!(Builder::new(Vec::new()).append_entry(&mut entry).is_err());
+}
 
-    let data = t!(ar.into_inner());
-    let mut ar = Archive::new(&data[..]);
-    assert!(ar.unpack(td.path()).is_ok());
+#[test]
+fn append_entry_pax_size_and_globals() {
+    // Large members are written with a zero size in the header and their
+    // real size in a PAX record
+    let mut ar = Builder::new(Vec::new());
+    t!(ar.set_global_pax([("comment", &b"abc"[..])]));
+    let mut header = Header::new_ustar();
+    header.set_size(0);
+    header.set_mode(0o644);
+    t!(ar.append_pax_records(&mut header, "big", [("size", &b"5"[..])], &b"hello"[..]));
+    let src = t!(ar.into_inner());
 
-    let meta = fs::metadata(td.path().join("a")).unwrap();
-    let mtime = FileTime::from_last_modification_time(&meta);
-    let atime = FileTime::from_last_access_time(&meta);
-    assert!(mtime.unix_seconds() != 0);
-    assert!(atime.unix_seconds() != 0);
+    let mut ar = Builder::new(Vec::new());
+    for entry in t!(Archive::new(&src[..]).entries()) {
+        t!(ar.append_entry(&mut t!(entry)));
+    }
+    let copy = t!(ar.into_inner());
+
+    let mut ar = Archive::new(&copy[..]);
+    let mut entry = t!(t!(ar.entries()).next().unwrap());
+    let records = t!(entry.pax_records())
+        .map(|(key, value)| format!("{}={}", key, String::from_utf8_lossy(value)))
+        .collect::<Vec<_>>();
+    assert_eq!(records, ["size=5"]);
+    let mut contents = String::new();
+    t!(entry.read_to_string(&mut contents));
+    assert_eq!(contents, "hello");
 }
 
 #[test]
-fn backslash_treated_well() {
-    // Insert a file into an archive with a backslash
-    let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
-    let mut ar = Builder::new(Vec::<u8>::new());
-    t!(ar.append_dir("foo\\bar", td.path()));
-    let mut ar = Archive::new(Cursor::new(t!(ar.into_inner())));
-    let f = t!(t!(ar.entries()).next().unwrap());
-    if cfg!(unix) {
-        assert_eq!(t!(f.header().path()).to_str(), Some("foo\\bar"));
-    } else {
-        assert_eq!(t!(f.header().path()).to_str(), Some("foo/bar"));
+fn parse_profiles() {
+    fn read(data: &[u8], profile: ParseProfile) -> io::Result<Vec<u32>> {
+        let mut ar = Archive::new(data);
+        ar.set_parse_profile(profile);
+        ar.entries()?.map(|e| e?.header().mode()).collect()
     }
 
-    // Unpack an archive with a backslash in the name
-    let mut ar = Builder::new(Vec::<u8>::new());
+    let mut ar = Builder::new(Vec::new());
     let mut header = Header::new_gnu();
-    header.set_metadata(&t!(fs::metadata(td.path())));
-    header.set_size(0);
-    for (a, b) in header.as_old_mut().name.iter_mut().zip(b"foo\\bar\x00") {
-        *a = *b;
-    }
-    header.set_cksum();
-    t!(ar.append(&header, &mut io::empty()));
+    header.set_size(3);
+    header.set_mode(0o644);
+    header.set_uid(0);
+    header.set_gid(0);
+    t!(ar.append_data(&mut header, "a", &b"foo"[..]));
     let data = t!(ar.into_inner());
-    let mut ar = Archive::new(&data[..]);
-    let f = t!(t!(ar.entries()).next().unwrap());
-    assert_eq!(t!(f.header().path()).to_str(), Some("foo\\bar"));
-
-    let mut ar = Archive::new(&data[..]);
-    t!(ar.unpack(td.path()));
-    assert!(fs::metadata(td.path().join("foo\\bar")).is_ok());
-}
+    for profile in [
+        ParseProfile::Strict,
+        ParseProfile::GnuCompatible,
+        ParseProfile::Permissive,
+    ] {
+        assert_eq!(t!(read(&data, profile)), [0o644]);
+    }
 
-#[test]
-#[cfg(unix)]
-fn set_mask() {
-    use ::std::os::unix::fs::PermissionsExt;
-    let mut ar = tar::Builder::new(Vec::new());
+    // Without the end-of-archive marker
+    let truncated = &data[..1024];
+    assert!(read(truncated, ParseProfile::Strict).is_err());
+    assert_eq!(t!(read(truncated, ParseProfile::GnuCompatible)), [0o644]);
 
-    let mut header = tar::Header::new_gnu();
-    header.set_size(0);
-    header.set_entry_type(tar::EntryType::Regular);
-    t!(header.set_path("foo"));
-    header.set_mode(0o777);
+    // Space-padded fields are fine, but junk after the digits isn't
+    let mut header = Header::from_byte_slice(&data[..512]).clone();
+    header.as_old_mut().mode = *b" 00644 \0";
     header.set_cksum();
-    t!(ar.append(&header, &[][..]));
-
-    let mut header = tar::Header::new_gnu();
-    header.set_size(0);
-    header.set_entry_type(tar::EntryType::Regular);
-    t!(header.set_path("bar"));
-    header.set_mode(0o421);
+    let mut spaced = data.clone();
+    spaced[..512].copy_from_slice(header.as_bytes());
+    assert_eq!(t!(read(&spaced, ParseProfile::Strict)), [0o644]);
+    header.as_old_mut().mode = *b"644x\0\0\0\0";
     header.set_cksum();
-    t!(ar.append(&header, &[][..]));
-
-    let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
-    let bytes = t!(ar.into_inner());
-    let mut ar = tar::Archive::new(&bytes[..]);
-    ar.set_mask(0o211);
-    t!(ar.unpack(td.path()));
-
-    let md = t!(fs::metadata(td.path().join("foo")));
-    assert_eq!(md.permissions().mode(), 0o100566);
-    let md = t!(fs::metadata(td.path().join("bar")));
-    assert_eq!(md.permissions().mode(), 0o100420);
+    let mut junk = data.clone();
+    junk[..512].copy_from_slice(header.as_bytes());
+    assert!(read(&junk, ParseProfile::Strict).is_err());
+    assert!(read(&junk, ParseProfile::GnuCompatible).is_err());
+    assert_eq!(t!(read(&junk, ParseProfile::Permissive)), [0o644]);
 }
 
-#[cfg(unix)]
 #[test]
-fn nul_bytes_in_path() {
-    use std::ffi::OsStr;
-    use std::os::unix::prelude::*;
+fn signed_checksum() {
+    fn read(data: &[u8], profile: ParseProfile) -> io::Result<String> {
+        let mut ar = Archive::new(data);
+        ar.set_parse_profile(profile);
+        let entry = ar.entries()?.next().unwrap()?;
+        Ok(entry.path()?.display().to_string())
+    }
 
-    let nul_path = OsStr::from_bytes(b"foo\0");
-    let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
-    let mut ar = Builder::new(Vec::<u8>::new());
-    let err = ar.append_dir(nul_path, td.path()).unwrap_err();
-    assert!(err.to_string().contains("contains a nul byte"));
-}
+    let mut ar = Builder::new(Vec::new());
+    let mut header = Header::new_gnu();
+    header.set_size(0);
+    header.set_mode(0o644);
+    t!(ar.append_data(&mut header, "caf\u{e9}", io::empty()));
+    let mut data = t!(ar.into_inner());
 
-#[test]
-fn links() {
-    let mut ar = Archive::new(Cursor::new(tar!("link.tar")));
-    let mut entries = t!(ar.entries());
-    let link = t!(entries.next().unwrap());
-    assert_eq!(
-        t!(link.header().link_name()).as_ref().map(|p| &**p),
-        Some(Path::new("file"))
-    );
-    let other = t!(entries.next().unwrap());
-    assert!(t!(other.header().link_name()).is_none());
+    // Sum the bytes as signed, with the checksum field as spaces
+    let signed: i64 = data[..512]
+        .iter()
+        .enumerate()
+        .map(|(i, &b)| {
+            if (148..156).contains(&i) {
+                32
+            } else {
+                i64::from(b as i8)
+            }
+        })
+        .sum();
+    data[148..156].copy_from_slice(format!("{:06o}\0 ", signed).as_bytes());
+    assert_eq!(t!(read(&data, ParseProfile::GnuCompatible)), "caf\u{e9}");
+    assert_eq!(t!(read(&data, ParseProfile::Permissive)), "caf\u{e9}");
+    assert!(read(&data, ParseProfile::Strict).is_err());
+
+    data[148..156].copy_from_slice(format!("{:06o}\0 ", signed + 1).as_bytes());
+    assert!(read(&data, ParseProfile::GnuCompatible).is_err());
 }
 
 #[test]
-#[cfg(unix)] // making symlinks on windows is hard
-fn unpack_links() {
-    let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
-    let mut ar = Archive::new(Cursor::new(tar!("link.tar")));
-    t!(ar.unpack(td.path()));
+fn lenient_eof() {
+    fn read(data: &[u8], profile: ParseProfile) -> io::Result<Vec<Vec<u8>>> {
+        let mut ar = Archive::new(data);
+        ar.set_parse_profile(profile);
+        ar.entries()?
+            .map(|e| {
+                let mut contents = Vec::new();
+                e?.read_to_end(&mut contents)?;
+                Ok(contents)
+            })
+            .collect()
+    }
 
-    let md = t!(fs::symlink_metadata(td.path().join("lnk")));
-    assert!(md.file_type().is_symlink());
+    let mut ar = Builder::new(Vec::new());
+    let mut header = Header::new_gnu();
+    header.set_size(3);
+    header.set_mode(0o644);
+    header.set_uid(0);
+    header.set_gid(0);
+    t!(ar.append_data(&mut header, "a", &b"foo"[..]));
+    let data = t!(ar.into_inner());
 
-    let mtime = FileTime::from_last_modification_time(&md);
-    assert_eq!(mtime.unix_seconds(), 1448291033);
+    // Cut off in the padding and partway through the end-of-archive marker
+    for len in [515, 700, 1100] {
+        let truncated = &data[..len];
+        assert_eq!(t!(read(truncated, ParseProfile::Permissive)), [b"foo"]);
+        assert!(read(truncated, ParseProfile::GnuCompatible).is_err());
+    }
 
-    assert_eq!(
-        &*t!(fs::read_link(td.path().join("lnk"))),
-        Path::new("file")
-    );
-    t!(File::open(td.path().join("lnk")));
+    // Cut off in the contents themselves
+    assert!(read(&data[..514], ParseProfile::Permissive).is_err());
 }
 
 #[test]
-fn pax_size() {
-    let mut ar = Archive::new(tar!("pax_size.tar"));
-    let mut entries = t!(ar.entries());
-    let mut entry = t!(entries.next().unwrap());
-    let mut attributes = t!(entry.pax_extensions()).unwrap();
+fn entries_salvage() {
+    fn salvage(data: &[u8]) -> Vec<String> {
+        let mut ar = Archive::new(data);
+        let items = t!(ar.entries_salvage())
+            .map(|item| match t!(item) {
+                Salvaged::Entry(e) => t!(e.path()).display().to_string(),
+                Salvaged::Skipped(range) => format!("{:?}", range),
+            })
+            .collect();
+        items
+    }
 
-    let _first = t!(attributes.next().unwrap());
-    let _second = t!(attributes.next().unwrap());
-    let _third = t!(attributes.next().unwrap());
-    let fourth = t!(attributes.next().unwrap());
-    assert!(attributes.next().is_none());
+    let mut ar = Builder::new(Vec::new());
+    for name in ["a", "b", "c"] {
+        let mut header = Header::new_gnu();
+        header.set_size(3);
+        header.set_mode(0o644);
+        header.set_uid(0);
+        header.set_gid(0);
+        t!(ar.append_data(&mut header, name, &b"foo"[..]));
+    }
+    let mut data = t!(ar.into_inner());
+    assert_eq!(salvage(&data), ["a", "b", "c"]);
+
+    // Damage to a header in the middle passes over it and its contents
+    data[1024] = b'x';
+    assert!(Archive::new(&data[..])
+        .entries()
+        .unwrap()
+        .nth(1)
+        .unwrap()
+        .is_err());
+    assert_eq!(salvage(&data), ["a", "1024..2048", "c"]);
+
+    // Damage running up to the end of the archive
+    data[2048] = b'x';
+    let end = format!("1024..{}", data.len());
+    assert_eq!(salvage(&data), ["a", end.as_str()]);
+}
 
-    assert_eq!(fourth.key(), Ok("size"));
-    assert_eq!(fourth.value(), Ok("4"));
+#[test]
+fn repair() {
+    let mut ar = Builder::new(Vec::new());
+    for (name, size) in [("a", 3), ("b", 600)] {
+        let mut header = Header::new_gnu();
+        header.set_size(size);
+        header.set_mode(0o644);
+        header.set_uid(0);
+        header.set_gid(0);
+        t!(ar.append_data(&mut header, name, &vec![b'x'; size as usize][..]));
+    }
+    let data = t!(ar.into_inner());
 
-    assert_eq!(entry.header().size().unwrap(), 0);
-    assert_eq!(entry.size(), 4);
+    // An intact archive is copied as it is
+    let mut repaired = Vec::new();
+    let report = t!(Archive::new(Cursor::new(&data)).repair(&mut repaired));
+    assert!(!report.repaired());
+    assert_eq!(report.entries(), 2);
+    assert_eq!(repaired, data);
+
+    let mut damaged = data[..1024 + 512 + 100].to_vec();
+    damaged[148] = b'7';
+    damaged[600] = 1;
+    let mut repaired = Vec::new();
+    let report = t!(Archive::new(Cursor::new(&damaged)).repair(&mut repaired));
+    assert_eq!(report.entries(), 2);
+    assert_eq!(report.fixed_checksums(), 1);
+    assert_eq!(report.fixed_padding(), 1);
+    assert!(report.truncated());
+    assert!(report.added_trailer());
+
+    let mut ar = Archive::new(&repaired[..]);
+    ar.set_parse_profile(ParseProfile::Strict);
+    let sizes = t!(ar.entries())
+        .map(|e| {
+            let mut contents = Vec::new();
+            t!(t!(e).read_to_end(&mut contents));
+            contents.len()
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(sizes, [3, 100]);
 }
 
 #[test]
-fn pax_simple() {
-    let mut ar = Archive::new(tar!("pax.tar"));
-    let mut entries = t!(ar.entries());
+fn blocking_factor() {
+    let mut ar = Builder::new(Cursor::new(Vec::new()));
+    ar.blocking_factor(20);
+    let mut header = Header::new_gnu();
+    header.set_size(3);
+    header.set_mode(0o644);
+    header.set_uid(0);
+    header.set_gid(0);
+    t!(ar.append_data(&mut header, "a", &b"foo"[..]));
+    let data = t!(ar.into_inner()).into_inner();
+    assert_eq!(data.len(), 10240);
 
-    let mut first = t!(entries.next().unwrap());
-    let mut attributes = t!(first.pax_extensions()).unwrap();
-    let first = t!(attributes.next().unwrap());
-    let second = t!(attributes.next().unwrap());
-    let third = t!(attributes.next().unwrap());
-    assert!(attributes.next().is_none());
+    // Record padding is read past like the end-of-archive marker
+    let mut ar = Archive::new(&data[..]);
+    ar.set_parse_profile(ParseProfile::Strict);
+    assert_eq!(t!(ar.entries()).count(), 1);
 
-    assert_eq!(first.key(), Ok("mtime"));
-    assert_eq!(first.value(), Ok("1453146164.953123768"));
-    assert_eq!(second.key(), Ok("atime"));
-    assert_eq!(second.value(), Ok("1453251915.24892486"));
-    assert_eq!(third.key(), Ok("ctime"));
-    assert_eq!(third.value(), Ok("1453146164.953123768"));
+    // Entries written with `append_writer` count towards the records too
+    let mut ar = Builder::new(Cursor::new(Vec::new()));
+    ar.blocking_factor(4);
+    let mut header = Header::new_gnu();
+    header.set_mode(0o644);
+    let mut entry = t!(ar.append_writer(&mut header, "b".repeat(200)));
+    t!(entry.write_all(&[1; 600]));
+    t!(entry.finish());
+    let data = t!(ar.into_inner()).into_inner();
+    assert_eq!(data.len(), 4096);
+
+    // And so do PAX extended headers, global or not
+    let mut ar = Builder::new(Cursor::new(Vec::new()));
+    ar.blocking_factor(20);
+    t!(ar.set_global_pax([("comment", &b"abc"[..])]));
+    let mut header = Header::new_ustar();
+    header.set_size(0);
+    header.set_mode(0o644);
+    t!(ar.append_data(&mut header, "c", io::empty()));
+    let records = [("mtime", &b"1700000000.5"[..])];
+    t!(ar.append_pax_records(&mut header, "d", records, io::empty()));
+    let data = t!(ar.into_inner()).into_inner();
+    assert_eq!(data.len(), 10240);
 }
 
 #[test]
-fn pax_simple_write() {
-    let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
-    let pax_path = td.path().join("pax.tar");
-    let file: File = t!(File::create(&pax_path));
-    let mut ar: Builder<BufWriter<File>> = Builder::new(BufWriter::new(file));
-
-    let pax_extensions = [
-        ("arbitrary_pax_key", b"arbitrary_pax_value".as_slice()),
-        ("SCHILY.xattr.security.selinux", b"foo_t"),
-    ];
-
-    t!(ar.append_pax_extensions(pax_extensions));
-    t!(ar.append_file("test2", &mut t!(File::open(&pax_path))));
-    t!(ar.finish());
-    drop(ar);
+fn incremental_backup() {
+    fn listing(data: &[u8]) -> Vec<(String, Vec<DumpDirEntry>)> {
+        let mut ar = Archive::new(data);
+        let items = t!(ar.entries())
+            .map(|e| {
+                let mut e = t!(e);
+                let path = t!(e.path()).display().to_string();
+                if e.header().entry_type().is_gnu_dumpdir() {
+                    (path, t!(e.dumpdir()))
+                } else {
+                    (path, Vec::new())
+                }
+            })
+            .collect();
+        items
+    }
 
-    let mut archive_opened = Archive::new(t!(File::open(pax_path)));
-    let mut entries = t!(archive_opened.entries());
-    let mut f: Entry<File> = t!(entries.next().unwrap());
-    let pax_headers = t!(f.pax_extensions());
+    let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
+    let src = td.path().join("src");
+    t!(fs::create_dir_all(src.join("sub")));
+    t!(fs::write(src.join("a.txt"), "a"));
+    t!(fs::write(src.join("b.txt"), "b"));
+    t!(fs::write(src.join("sub/c.txt"), "c"));
 
-    assert!(pax_headers.is_some(), "pax_headers is None");
-    let mut pax_headers = pax_headers.unwrap();
-    let pax_arbitrary = t!(pax_headers.next().unwrap());
-    assert_eq!(pax_arbitrary.key(), Ok("arbitrary_pax_key"));
-    assert_eq!(pax_arbitrary.value(), Ok("arbitrary_pax_value"));
-    let xattr = t!(pax_headers.next().unwrap());
-    assert_eq!(xattr.key().unwrap(), pax_extensions[1].0);
-    assert_eq!(xattr.value_bytes(), pax_extensions[1].1);
+    let mut snapshot = Snapshot::new();
+    let mut ar = Builder::new(Vec::new());
+    t!(ar.append_dir_incremental("src", &src, &mut snapshot));
+    let full = t!(ar.into_inner());
+    let name = |name: &str| PathBuf::from(name);
+    assert_eq!(
+        listing(&full),
+        [
+            (
+                "src".to_string(),
+                vec![
+                    DumpDirEntry::Archived(name("a.txt")),
+                    DumpDirEntry::Archived(name("b.txt")),
+                    DumpDirEntry::Directory(name("sub")),
+                ]
+            ),
+            ("src/a.txt".to_string(), vec![]),
+            ("src/b.txt".to_string(), vec![]),
+            (
+                "src/sub".to_string(),
+                vec![DumpDirEntry::Archived(name("c.txt"))]
+            ),
+            ("src/sub/c.txt".to_string(), vec![]),
+        ]
+    );
 
-    assert!(entries.next().is_none());
-}
+    // The snapshot survives a round trip through a snapshot file
+    let mut file = Vec::new();
+    t!(snapshot.write(&mut file));
+    let mut snapshot = t!(Snapshot::read(&file[..]));
+    assert_eq!(
+        snapshot.directories().collect::<Vec<_>>(),
+        [Path::new("src"), Path::new("src/sub")]
+    );
 
-#[test]
-fn pax_path() {
-    let mut ar = Archive::new(tar!("pax2.tar"));
-    let mut entries = t!(ar.entries());
+    // Timestamps may be coarser than the clock, so date the change ahead
+    t!(fs::remove_file(src.join("b.txt")));
+    t!(fs::write(src.join("a.txt"), "aa"));
+    let later = FileTime::from_unix_time(FileTime::now().unix_seconds() + 60, 0);
+    t!(filetime::set_file_mtime(src.join("a.txt"), later));
+    let mut ar = Builder::new(Vec::new());
+    t!(ar.append_dir_incremental("src", &src, &mut snapshot));
+    let changes = t!(ar.into_inner());
+    assert_eq!(
+        listing(&changes),
+        [
+            (
+                "src".to_string(),
+                vec![
+                    DumpDirEntry::Archived(name("a.txt")),
+                    DumpDirEntry::Directory(name("sub")),
+                ]
+            ),
+            ("src/a.txt".to_string(), vec![]),
+            (
+                "src/sub".to_string(),
+                vec![DumpDirEntry::Unchanged(name("c.txt"))]
+            ),
+        ]
+    );
 
-    let first = t!(entries.next().unwrap());
-    assert!(first.path().unwrap().ends_with("aaaaaaaaaaaaaaa"));
+    let dst = td.path().join("dst");
+    t!(Archive::new(&full[..]).unpack(&dst));
+    let mut ar = Archive::new(&changes[..]);
+    ar.set_incremental(true);
+    t!(ar.unpack(&dst));
+    assert_eq!(t!(fs::read_to_string(dst.join("src/a.txt"))), "aa");
+    assert!(!dst.join("src/b.txt").exists());
+    assert_eq!(t!(fs::read_to_string(dst.join("src/sub/c.txt"))), "c");
 }
 
 #[test]
-fn pax_linkpath() {
-    let mut ar = Archive::new(tar!("pax2.tar"));
-    let mut links = t!(ar.entries()).skip(3).take(2);
-
-    let long_symlink = t!(links.next().unwrap());
-    let link_name = long_symlink.link_name().unwrap().unwrap();
-    assert!(link_name.to_str().unwrap().len() > 99);
-    assert!(link_name.ends_with("bbbbbbbbbbbbbbb"));
+#[cfg(unix)]
+fn incremental_prune_through_symlink() {
+    let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
+    let victim = td.path().join("victim");
+    t!(fs::create_dir(&victim));
+    t!(fs::write(victim.join("important"), "x"));
+    let dst = td.path().join("dst");
+    t!(fs::create_dir(&dst));
+    t!(std::os::unix::fs::symlink(&victim, dst.join("link")));
+
+    let listing = b"Ykeep\0\0";
+    let mut ar = Builder::new(Vec::new());
+    let mut header = Header::new_gnu();
+    header.set_entry_type(EntryType::GNUDumpDir);
+    header.set_size(listing.len() as u64);
+    header.set_mode(0o755);
+    t!(ar.append_data(&mut header, "link/", &listing[..]));
+    let data = t!(ar.into_inner());
 
-    let long_hardlink = t!(links.next().unwrap());
-    let link_name = long_hardlink.link_name().unwrap().unwrap();
-    assert!(link_name.to_str().unwrap().len() > 99);
-    assert!(link_name.ends_with("ccccccccccccccc"));
+    let mut ar = Archive::new(&data[..]);
+    ar.set_incremental(true);
+    let _ = ar.unpack(&dst);
+    assert!(victim.join("important").exists());
 }
 
 #[test]
-fn long_name_trailing_nul() {
-    let mut b = Builder::new(Vec::<u8>::new());
+fn append_incremental() {
+    fn paths(data: &[u8]) -> Vec<String> {
+        let mut ar = Archive::new(data);
+        let items = t!(ar.entries())
+            .map(|e| t!(t!(e).path()).display().to_string())
+            .collect();
+        items
+    }
 
-    let mut h = Header::new_gnu();
-    t!(h.set_path("././@LongLink"));
-    h.set_size(4);
-    h.set_entry_type(EntryType::new(b'L'));
-    h.set_cksum();
-    t!(b.append(&h, "foo\0".as_bytes()));
-    let mut h = Header::new_gnu();
+    let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
+    let src = td.path().join("src");
+    t!(fs::create_dir_all(src.join("sub")));
+    t!(fs::write(src.join("a.txt"), "a"));
+    t!(fs::write(src.join("b.txt"), "b"));
+    t!(fs::write(src.join("sub/c.txt"), "c"));
 
-    t!(h.set_path("bar"));
-    h.set_size(6);
-    h.set_entry_type(EntryType::file());
-    h.set_cksum();
-    t!(b.append(&h, "foobar".as_bytes()));
+    let mut ar = Builder::new(Vec::new());
+    let snapshot = t!(ar.append_incremental("src", &src, &FileSnapshot::new()));
+    let full = t!(ar.into_inner());
+    assert_eq!(
+        paths(&full),
+        ["src", "src/a.txt", "src/b.txt", "src/sub", "src/sub/c.txt"]
+    );
+    assert_eq!(snapshot.len(), 5);
 
-    let contents = t!(b.into_inner());
-    let mut a = Archive::new(&contents[..]);
+    // The snapshot survives a round trip through a state file
+    let mut file = Vec::new();
+    t!(snapshot.write(&mut file));
+    let snapshot2 = t!(FileSnapshot::read(&file[..]));
+    assert_eq!(snapshot2, snapshot);
 
-    let e = t!(t!(a.entries()).next().unwrap());
-    assert_eq!(&*e.path_bytes(), b"foo");
+    // Nothing changed, so nothing is archived
+    let mut ar = Builder::new(Vec::new());
+    let unchanged = t!(ar.append_incremental("src", &src, &snapshot2));
+    assert!(paths(&t!(ar.into_inner())).is_empty());
+    assert_eq!(unchanged, snapshot);
+
+    t!(fs::write(src.join("a.txt"), "aa"));
+    t!(fs::write(src.join("sub/d.txt"), "d"));
+    let mut ar = Builder::new(Vec::new());
+    let snapshot = t!(ar.append_incremental("src", &src, &snapshot2));
+    let changes = paths(&t!(ar.into_inner()));
+    assert!(changes.contains(&"src/a.txt".to_string()));
+    assert!(changes.contains(&"src/sub/d.txt".to_string()));
+    assert!(!changes.contains(&"src/b.txt".to_string()));
+    assert!(!changes.contains(&"src/sub/c.txt".to_string()));
+    assert!(snapshot
+        .paths()
+        .any(|path| path == Path::new("src/sub/d.txt")));
 }
 
 #[test]
-fn long_linkname_trailing_nul() {
-    let mut b = Builder::new(Vec::<u8>::new());
-
-    let mut h = Header::new_gnu();
-    t!(h.set_path("././@LongLink"));
-    h.set_size(4);
-    h.set_entry_type(EntryType::new(b'K'));
-    h.set_cksum();
-    t!(b.append(&h, "foo\0".as_bytes()));
-    let mut h = Header::new_gnu();
+fn append_merged() {
+    fn layer(files: &[(&str, u64, &str)]) -> io::Cursor<Vec<u8>> {
+        let mut ar = Builder::new(Vec::new());
+        for (path, mtime, data) in files {
+            let mut header = Header::new_gnu();
+            header.set_size(data.len() as u64);
+            header.set_mtime(*mtime);
+            t!(ar.append_data(&mut header, path, data.as_bytes()));
+        }
+        io::Cursor::new(t!(ar.into_inner()))
+    }
+    fn merge(layers: &mut [io::Cursor<Vec<u8>>], policy: MergePolicy) -> Vec<(String, String)> {
+        let mut ar = Builder::new(Vec::new());
+        t!(ar.append_merged(layers, policy));
+        let data = t!(ar.into_inner());
+        let mut ar = Archive::new(&data[..]);
+        let items = t!(ar.entries())
+            .map(|e| {
+                let mut e = t!(e);
+                let mut s = String::new();
+                t!(e.read_to_string(&mut s));
+                (t!(e.path()).display().to_string(), s)
+            })
+            .collect();
+        items
+    }
+    let pair = |path: &str, data: &str| (path.to_string(), data.to_string());
 
-    t!(h.set_path("bar"));
-    h.set_size(6);
-    h.set_entry_type(EntryType::file());
-    h.set_cksum();
-    t!(b.append(&h, "foobar".as_bytes()));
+    let mut layers = [
+        layer(&[("a", 20, "a1"), ("b", 10, "b1")]),
+        layer(&[("b", 5, "b2"), ("c", 10, "c2"), ("a", 10, "a2")]),
+    ];
+    assert_eq!(
+        merge(&mut layers, MergePolicy::KeepFirst),
+        [pair("a", "a1"), pair("b", "b1"), pair("c", "c2")]
+    );
+    assert_eq!(
+        merge(&mut layers, MergePolicy::KeepLast),
+        [pair("b", "b2"), pair("c", "c2"), pair("a", "a2")]
+    );
+    assert_eq!(
+        merge(&mut layers, MergePolicy::KeepNewestMtime),
+        [pair("a", "a1"), pair("b", "b1"), pair("c", "c2")]
+    );
 
-    let contents = t!(b.into_inner());
-    let mut a = Archive::new(&contents[..]);
+    let mut ar = Builder::new(Vec::new());
+    let err = ar
+        .append_merged(&mut layers, MergePolicy::Error)
+        .unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+    assert_eq!(t!(ar.into_inner()).len(), 1024);
 
-    let e = t!(t!(a.entries()).next().unwrap());
-    assert_eq!(&*e.link_name_bytes().unwrap(), b"foo");
+    let mut layers = [layer(&[("a", 0, "a")]), layer(&[("b", 0, "b")])];
+    let mut ar = Builder::new(Vec::new());
+    assert_eq!(t!(ar.append_merged(&mut layers, MergePolicy::Error)), 0);
 }
 
 #[test]
-fn long_linkname_gnu() {
-    for t in [tar::EntryType::Symlink, tar::EntryType::Link] {
-        let mut b = Builder::new(Vec::<u8>::new());
-        let mut h = Header::new_gnu();
-        h.set_entry_type(t);
-        h.set_size(0);
-        let path = "usr/lib/.build-id/05/159ed904e45ff5100f7acd3d3b99fa7e27e34f";
-        let target = "../../../../usr/lib64/qt5/plugins/wayland-graphics-integration-server/libqt-wayland-compositor-xcomposite-egl.so";
-        t!(b.append_link(&mut h, path, target));
+fn content_type() {
+    let mut ar = Builder::new(Vec::new());
+    let mut tar = vec![0; 512];
+    tar[257..263].copy_from_slice(b"ustar\0");
+    let files: [(&str, &[u8]); 6] = [
+        ("image.png", b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR"),
+        ("notes.txt", "caf\u{e9}\n".as_bytes()),
+        ("page.html", b"\n<!DOCTYPE html>\n<html>"),
+        ("blob", b"\x01\x02\x03\x00"),
+        ("inner.tar", &tar),
+        ("empty", b""),
+    ];
+    for (path, data) in files {
+        let mut header = Header::new_gnu();
+        header.set_size(data.len() as u64);
+        t!(ar.append_data(&mut header, path, data));
+    }
+    let mut header = Header::new_gnu();
+    header.set_entry_type(EntryType::Directory);
+    header.set_size(0);
+    t!(ar.append_data(&mut header, "dir", io::empty()));
+    let data = t!(ar.into_inner());
 
-        let contents = t!(b.into_inner());
-        let mut a = Archive::new(&contents[..]);
+    let mut ar = Archive::new(&data[..]);
+    let types: Vec<_> = t!(ar.entries()).map(|e| t!(t!(e).content_type())).collect();
+    assert_eq!(
+        types,
+        [
+            Some("image/png"),
+            Some("text/plain"),
+            Some("text/html"),
+            Some("application/octet-stream"),
+            Some("application/x-tar"),
+            None,
+            None,
+        ]
+    );
+}
 
-        let e = &t!(t!(a.entries()).next().unwrap());
-        assert_eq!(e.header().entry_type(), t);
-        assert_eq!(e.path().unwrap().to_str().unwrap(), path);
-        assert_eq!(e.link_name().unwrap().unwrap().to_str().unwrap(), target);
+#[test]
+fn detect_format() {
+    for (data, format) in [
+        (tar!("spaces.tar"), ArchiveFormat::V7),
+        (tar!("simple.tar"), ArchiveFormat::Gnu),
+        (tar!("7z_long_path.tar"), ArchiveFormat::Gnu),
+        (tar!("pax.tar"), ArchiveFormat::Pax),
+    ] {
+        assert_eq!(t!(Archive::new(data).detect_format()), Some(format));
     }
+    assert_eq!(t!(Archive::new(&[0; 1024][..]).detect_format()), None);
+
+    let mut ar = Builder::new(Vec::new());
+    let mut header = Header::new_ustar();
+    header.set_size(0);
+    t!(ar.append_data(&mut header, "ustar", io::empty()));
+    let mut header = Header::new_ustar();
+    header.as_ustar_mut().unwrap().magic = *b"star\0\0";
+    header.set_size(0);
+    t!(ar.append_data(&mut header, "unknown", io::empty()));
+    let data = t!(ar.into_inner());
+    let mut ar = Archive::new(&data[..]);
+    let formats: Vec<_> = t!(ar.entries()).map(|e| t!(e).format()).collect();
+    let unknown = ArchiveFormat::Unknown {
+        magic: *b"star\0\0",
+        version: *b"00",
+    };
+    assert_eq!(formats, [ArchiveFormat::Ustar, unknown]);
+    assert_eq!(t!(Archive::new(&data[..]).detect_format()), Some(unknown));
 }
 
 #[test]
-fn linkname_literal() {
-    for t in [tar::EntryType::Symlink, tar::EntryType::Link] {
-        let mut b = Builder::new(Vec::<u8>::new());
-        let mut h = Header::new_gnu();
-        h.set_entry_type(t);
-        h.set_size(0);
-        let path = "usr/lib/systemd/systemd-sysv-install";
-        let target = "../../..//sbin/chkconfig";
-        h.set_link_name_literal(target).unwrap();
-        t!(b.append_data(&mut h, path, std::io::empty()));
+fn list() {
+    let mut ar = Builder::new(Vec::new());
+    let long = "long/".repeat(40) + "name";
+    let mut header = Header::new_gnu();
+    header.set_size(3);
+    header.set_mode(0o600);
+    header.set_uid(7);
+    header.set_gid(8);
+    header.set_mtime(1_000);
+    t!(ar.append_data(&mut header, &long, &b"foo"[..]));
+    let mut header = Header::new_gnu();
+    header.set_entry_type(EntryType::Symlink);
+    header.set_size(0);
+    header.set_mode(0o777);
+    header.set_uid(0);
+    header.set_gid(0);
+    t!(ar.append_link(&mut header, "link", &long));
+    let data = t!(ar.into_inner());
 
-        let contents = t!(b.into_inner());
-        let mut a = Archive::new(&contents[..]);
+    let list = t!(Archive::new(&data[..]).list());
+    assert_eq!(list.len(), 2);
+    assert_eq!(list[0].path, Path::new(&long));
+    assert_eq!(
+        (list[0].size, list[0].mode, list[0].uid, list[0].mtime),
+        (3, 0o600, 7, 1_000)
+    );
+    assert_eq!(list[0].entry_type, EntryType::Regular);
+    assert_eq!(list[0].link_name, None);
+    assert_eq!(list[1].path, Path::new("link"));
+    assert_eq!(list[1].entry_type, EntryType::Symlink);
+    assert_eq!(list[1].link_name.as_deref(), Some(Path::new(&long)));
+}
 
-        let e = &t!(t!(a.entries()).next().unwrap());
-        assert_eq!(e.header().entry_type(), t);
-        assert_eq!(e.path().unwrap().to_str().unwrap(), path);
-        assert_eq!(e.link_name().unwrap().unwrap().to_str().unwrap(), target);
-    }
+#[test]
+#[cfg(feature = "serde")]
+fn entry_metadata_serde() {
+    use tar::EntryMetadata;
+
+    let mut ar = Archive::new(tar!("link.tar"));
+    let metadata: Vec<_> = t!(ar.entries())
+        .map(|e| t!(EntryMetadata::from_entry(&t!(e))))
+        .collect();
+    assert!(metadata.iter().any(|m| m.link_name.is_some()));
+
+    let json = t!(serde_json::to_string(&metadata));
+    let first = &metadata[0];
+    assert!(json.contains(&format!(r#""path":{:?}"#, first.path.to_str().unwrap())));
+    assert!(json.contains(&format!(r#""entry_type":"{:?}""#, first.entry_type)));
+    let parsed: Vec<EntryMetadata> = t!(serde_json::from_str(&json));
+    assert_eq!(parsed, metadata);
 }
 
 #[test]
@@ -1145,6 +4030,37 @@ fn append_writer() {
     assert!(r.iter().all(|b| *b == b'x'));
 }
 
+#[test]
+fn append_stream() {
+    let mut b = Builder::new(Vec::new());
+    b.spool_threshold(512);
+
+    let mut h = Header::new_gnu();
+    h.set_uid(42);
+    t!(b.append_stream(&mut h, "small", &b"foobar"[..]));
+    let mut h = Header::new_gnu();
+    h.set_uid(43);
+    t!(b.append_stream(&mut h, "exact", io::repeat(b'y').take(512)));
+    let mut h = Header::new_gnu();
+    h.set_uid(44);
+    t!(b.append_stream(&mut h, "spooled", io::repeat(b'x').take(1500)));
+
+    let contents = t!(b.into_inner());
+    let mut ar = Archive::new(&contents[..]);
+    let entries: Vec<_> = t!(ar.entries())
+        .map(|e| {
+            let mut e = t!(e);
+            let mut r = Vec::new();
+            t!(e.read_to_end(&mut r));
+            (t!(e.header().uid()), t!(e.header().size()), r)
+        })
+        .collect();
+    assert_eq!(entries.len(), 3);
+    assert_eq!(entries[0], (42, 6, b"foobar".to_vec()));
+    assert_eq!(entries[1], (43, 512, vec![b'y'; 512]));
+    assert_eq!(entries[2], (44, 1500, vec![b'x'; 1500]));
+}
+
 #[test]
 fn encoded_long_name_has_trailing_nul() {
     let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
@@ -1259,6 +4175,26 @@ fn extract_sparse() {
     assert!(s[0x2fa0 + 6..0x4000].chars().all(|x| x == '\u{0}'));
 }
 
+#[test]
+fn unpack_sparse_zeros() {
+    let mut contents = vec![0; 1 << 20];
+    contents[..5].copy_from_slice(b"start");
+    contents[0x80000..0x80003].copy_from_slice(b"mid");
+    let mut ar = Builder::new(Vec::new());
+    let mut header = Header::new_gnu();
+    header.set_size(contents.len() as u64);
+    header.set_mode(0o644);
+    t!(ar.append_data(&mut header, "image", &contents[..]));
+    let data = t!(ar.into_inner());
+
+    let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
+    let mut ar = Archive::new(&data[..]);
+    ar.set_unpack_sparse(true);
+    t!(ar.unpack(td.path()));
+    // The trailing zeros are skipped too, so the length must still be right
+    assert_eq!(t!(fs::read(td.path().join("image"))), contents);
+}
+
 #[test]
 fn large_sparse() {
     let rdr = Cursor::new(tar!("sparse-large.tar"));
@@ -1443,6 +4379,101 @@ fn append_path_symlink() {
     assert!(entries.next().is_none());
 }
 
+#[test]
+fn vectored_writes() {
+    #[derive(Default)]
+    struct VectoredWriter {
+        data: Vec<u8>,
+        writes: usize,
+    }
+
+    impl Write for VectoredWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.writes += 1;
+            self.data.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn write_vectored(&mut self, bufs: &[io::IoSlice<'_>]) -> io::Result<usize> {
+            self.writes += 1;
+            bufs.iter().for_each(|buf| self.data.extend_from_slice(buf));
+            Ok(bufs.iter().map(|buf| buf.len()).sum())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
+    t!(fs::write(td.path().join("file"), b"from a file"));
+
+    // Each small member takes a single write
+    let mut ar = Builder::new(VectoredWriter::default());
+    let mut header = Header::new_gnu();
+    header.set_size(5);
+    header.set_cksum();
+    t!(ar.append_data(&mut header, "data", &b"hello"[..]));
+    assert_eq!(ar.get_ref().writes, 1);
+    t!(ar.append_path_with_name(td.path().join("file"), "file"));
+    assert_eq!(ar.get_ref().writes, 2);
+    let big = vec![b'x'; 100_000];
+    let mut header = Header::new_gnu();
+    header.set_size(big.len() as u64);
+    header.set_cksum();
+    t!(ar.append_data(&mut header, "big", &big[..]));
+
+    let data = t!(ar.into_inner()).data;
+    let mut ar = Archive::new(&data[..]);
+    let contents = t!(ar.entries())
+        .map(|entry| {
+            let mut entry = t!(entry);
+            let mut data = Vec::new();
+            t!(entry.read_to_end(&mut data));
+            (t!(entry.path()).into_owned(), data)
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        contents,
+        [
+            (PathBuf::from("data"), b"hello".to_vec()),
+            (PathBuf::from("file"), b"from a file".to_vec()),
+            (PathBuf::from("big"), big),
+        ]
+    );
+}
+
+#[test]
+#[cfg(unix)]
+fn append_with_follow_override() {
+    use std::os::unix::fs::symlink;
+
+    let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
+    t!(fs::create_dir(td.path().join("dir")));
+    t!(fs::write(td.path().join("dir/file"), b"contents"));
+    t!(symlink("file", td.path().join("dir/link")));
+
+    let mut ar = Builder::new(Vec::new());
+    ar.follow_symlinks(true);
+    t!(ar.append_path_with_follow(td.path().join("dir/link"), "kept", false));
+    t!(ar.append_dir_all_with_follow("tree", td.path().join("dir"), false));
+    ar.follow_symlinks(false);
+    t!(ar.append_path_with_follow(td.path().join("dir/link"), "followed", true));
+
+    let rd = Cursor::new(t!(ar.into_inner()));
+    let mut ar = Archive::new(rd);
+    let kinds = t!(ar.entries())
+        .map(|e| {
+            let e = t!(e);
+            (t!(e.path()).into_owned(), e.header().entry_type())
+        })
+        .collect::<std::collections::HashMap<_, _>>();
+    assert_eq!(kinds[Path::new("kept")], EntryType::Symlink);
+    assert_eq!(kinds[Path::new("tree/link")], EntryType::Symlink);
+    assert_eq!(kinds[Path::new("tree/file")], EntryType::Regular);
+    assert_eq!(kinds[Path::new("followed")], EntryType::Regular);
+}
+
 #[test]
 fn name_with_slash_doesnt_fool_long_link_and_bsd_compat() {
     let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
@@ -1718,6 +4749,108 @@ fn ownership_preserving() {
     }
 }
 
+#[test]
+#[cfg(unix)]
+fn owner_map() {
+    use std::os::unix::prelude::*;
+
+    let mut ar = Builder::new(Vec::new());
+    for (path, uid, gid, user) in [
+        ("by-id", 580800000, 580800000, "nobody-here"),
+        ("by-name", 580800001, 580800001, "olduser"),
+        ("unmapped", 580800002, 580800002, "nobody-here"),
+    ] {
+        let mut header = Header::new_gnu();
+        header.set_uid(uid);
+        header.set_gid(gid);
+        t!(header.set_username(user));
+        header.set_size(0);
+        header.set_mode(0o644);
+        t!(ar.append_data(&mut header, path, io::empty()));
+    }
+    let data = t!(ar.into_inner());
+
+    let mut map = OwnerMap::new();
+    t!(map.read_users("# comment\n+580800000 +580800010\nolduser x:580800011\n".as_bytes()));
+    map.map_gid(580800001, 580800012);
+    assert!(map.read_groups("+1".as_bytes()).is_err());
+    assert!(map.read_groups("+x +1".as_bytes()).is_err());
+
+    let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
+    let mut ar = Archive::new(&data[..]);
+    ar.set_preserve_ownerships(true);
+    ar.set_owner_map(Some(map));
+    if unsafe { libc::getuid() } != 0 {
+        // Ownerships can't be restored without root permissions
+        assert!(ar.unpack(td.path()).is_err());
+        return;
+    }
+    t!(ar.unpack(td.path()));
+    let meta = t!(fs::metadata(td.path().join("by-id")));
+    assert_eq!((meta.uid(), meta.gid()), (580800010, 580800000));
+    let meta = t!(fs::metadata(td.path().join("by-name")));
+    assert_eq!((meta.uid(), meta.gid()), (580800011, 580800012));
+    let meta = t!(fs::metadata(td.path().join("unmapped")));
+    assert_eq!((meta.uid(), meta.gid()), (580800002, 580800002));
+}
+
+#[test]
+#[cfg(unix)]
+fn owner_by_name() {
+    use std::os::unix::prelude::*;
+
+    let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
+    let src = td.path().join("src");
+    t!(fs::write(&src, b"contents"));
+
+    // Find out the names of the current user and group
+    let mut ar = Builder::new(Vec::new());
+    ar.owner_names(true);
+    t!(ar.append_path_with_name(&src, "file"));
+    let data = t!(ar.into_inner());
+    let mut ar = Archive::new(&data[..]);
+    let entry = t!(t!(ar.entries()).next().unwrap());
+    let user = t!(entry.username()).unwrap().to_owned();
+    let group = t!(entry.groupname()).unwrap().to_owned();
+    if user.is_empty() || group.is_empty() {
+        // No passwd or group entries to look the names up in
+        return;
+    }
+
+    let mut ar = Builder::new(Vec::new());
+    let mut header = Header::new_gnu();
+    header.set_uid(580800020);
+    header.set_gid(580800020);
+    t!(header.set_username(&user));
+    t!(header.set_groupname(&group));
+    header.set_size(0);
+    header.set_mode(0o644);
+    t!(ar.append_data(&mut header, "file", io::empty()));
+    let data = t!(ar.into_inner());
+
+    let dst = td.path().join("by-name");
+    let mut ar = Archive::new(&data[..]);
+    ar.set_preserve_ownerships(true);
+    ar.set_numeric_owner(false);
+    t!(ar.unpack(&dst));
+    let meta = t!(fs::metadata(dst.join("file")));
+    let expected = t!(fs::metadata(&src));
+    assert_eq!((meta.uid(), meta.gid()), (expected.uid(), expected.gid()));
+
+    let dst = td.path().join("by-id");
+    let mut ar = Archive::new(&data[..]);
+    ar.set_preserve_ownerships(true);
+    ar.set_numeric_owner(true);
+    if unsafe { libc::getuid() } != 0 {
+        // Ownerships can't be restored without root permissions
+        assert!(ar.unpack(&dst).is_err());
+        return;
+    }
+    t!(ar.unpack(&dst));
+    let meta = t!(fs::metadata(dst.join("file")));
+    assert_eq!((meta.uid(), meta.gid()), (580800020, 580800020));
+}
+
 #[test]
 #[cfg(unix)]
 fn pax_and_gnu_uid_gid() {
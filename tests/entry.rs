@@ -30,6 +30,7 @@ fn absolute_symlink() {
 
     let bytes = t!(ar.into_inner());
     let mut ar = tar::Archive::new(&bytes[..]);
+    ar.set_allow_external_symlinks(true);
 
     let td = t!(Builder::new().prefix("tar").tempdir());
     t!(ar.unpack(td.path()));
@@ -120,6 +121,7 @@ fn absolute_link_deref_error() {
 
     let bytes = t!(ar.into_inner());
     let mut ar = tar::Archive::new(&bytes[..]);
+    ar.set_allow_external_symlinks(true);
 
     let td = t!(Builder::new().prefix("tar").tempdir());
     assert!(ar.unpack(td.path()).is_err());
@@ -148,6 +150,7 @@ fn relative_link_deref_error() {
 
     let bytes = t!(ar.into_inner());
     let mut ar = tar::Archive::new(&bytes[..]);
+    ar.set_allow_external_symlinks(true);
 
     let td = t!(Builder::new().prefix("tar").tempdir());
     assert!(ar.unpack(td.path()).is_err());
@@ -302,6 +305,7 @@ fn parent_paths_error() {
 
     let bytes = t!(ar.into_inner());
     let mut ar = tar::Archive::new(&bytes[..]);
+    ar.set_allow_external_symlinks(true);
 
     let td = t!(Builder::new().prefix("tar").tempdir());
     assert!(ar.unpack(td.path()).is_err());
@@ -396,6 +400,7 @@ fn modify_symlink_just_created() {
 
     let bytes = t!(ar.into_inner());
     let mut ar = tar::Archive::new(&bytes[..]);
+    ar.set_allow_external_symlinks(true);
 
     let td = t!(Builder::new().prefix("tar").tempdir());
 
@@ -409,3 +414,81 @@ fn modify_symlink_just_created() {
     t!(t!(File::open(&test)).read_to_end(&mut contents));
     assert_eq!(contents.len(), 0);
 }
+
+#[test]
+fn external_symlink_error() {
+    for target in ["/bar", "..", "a/../../bar", "inner/../bar"] {
+        let mut ar = tar::Builder::new(Vec::new());
+
+        // `inner` leads back to the root, so `inner/..` escapes it even
+        // though it doesn't look like it.
+        let mut header = tar::Header::new_gnu();
+        header.set_size(0);
+        header.set_entry_type(tar::EntryType::Symlink);
+        t!(header.set_path("inner"));
+        t!(header.set_link_name("."));
+        header.set_cksum();
+        t!(ar.append(&header, &[][..]));
+
+        let mut header = tar::Header::new_gnu();
+        header.set_size(0);
+        header.set_entry_type(tar::EntryType::Symlink);
+        t!(header.set_path("foo"));
+        t!(header.set_link_name(target));
+        header.set_cksum();
+        t!(ar.append(&header, &[][..]));
+
+        let bytes = t!(ar.into_inner());
+        let mut ar = tar::Archive::new(&bytes[..]);
+
+        let td = t!(Builder::new().prefix("tar").tempdir());
+        assert!(ar.unpack(td.path()).is_err(), "{}", target);
+        assert!(td.path().join("foo").symlink_metadata().is_err());
+    }
+}
+
+#[test]
+fn external_symlink_copy() {
+    let td = t!(Builder::new().prefix("tar").tempdir());
+    let outside = td.path().join("outside");
+    t!(std::fs::write(&outside, "hello"));
+
+    let mut ar = tar::Builder::new(Vec::new());
+
+    let mut header = tar::Header::new_gnu();
+    header.set_size(0);
+    header.set_entry_type(tar::EntryType::Symlink);
+    t!(header.set_path("abs"));
+    t!(header.set_link_name(&outside));
+    header.set_cksum();
+    t!(ar.append(&header, &[][..]));
+
+    let mut header = tar::Header::new_gnu();
+    header.set_size(0);
+    header.set_entry_type(tar::EntryType::Symlink);
+    t!(header.set_path("rel"));
+    t!(header.set_link_name("../outside"));
+    header.set_cksum();
+    t!(ar.append(&header, &[][..]));
+
+    let mut header = tar::Header::new_gnu();
+    header.set_size(0);
+    header.set_entry_type(tar::EntryType::Symlink);
+    t!(header.set_path("inside"));
+    t!(header.set_link_name("rel"));
+    header.set_cksum();
+    t!(ar.append(&header, &[][..]));
+
+    let bytes = t!(ar.into_inner());
+    let mut ar = tar::Archive::new(&bytes[..]);
+    ar.set_copy_external_symlinks(true);
+
+    let dir = td.path().join("dir");
+    t!(ar.unpack(&dir));
+    for name in ["abs", "rel"] {
+        let path = dir.join(name);
+        assert!(t!(path.symlink_metadata()).is_file());
+        assert_eq!(t!(std::fs::read_to_string(path)), "hello");
+    }
+    assert_eq!(t!(dir.join("inside").read_link()).to_str(), Some("rel"));
+}
@@ -5,7 +5,7 @@ use std::{iter, mem, thread, time};
 
 use tempfile::Builder;
 
-use tar::{GnuHeader, Header, HeaderMode};
+use tar::{EntryType, GnuHeader, Header, HeaderBuilder, HeaderMode};
 
 #[test]
 fn default_gnu() {
@@ -34,6 +34,23 @@ fn goto_ustar() {
     assert!(h.as_ustar_mut().is_some());
 }
 
+#[test]
+fn cksum() {
+    // Bytes as large as possible can't overflow the sum
+    for fill in [0u8, 0x7f, 0xff] {
+        let mut h = Header::new_old();
+        h.as_mut_bytes().fill(fill);
+        h.set_cksum();
+        let sum = h.as_bytes()[..148]
+            .iter()
+            .chain(&h.as_bytes()[156..])
+            .map(|&b| b as u32)
+            .sum::<u32>()
+            + 8 * 32;
+        assert_eq!(t!(h.cksum()), sum);
+    }
+}
+
 #[test]
 fn link_name() {
     let mut h = Header::new_gnu();
@@ -106,6 +123,12 @@ fn dev_major_minor() {
     assert_eq!(t!(h.device_major()), Some(1));
     assert_eq!(t!(h.device_minor()), Some(2));
 
+    t!(h.set_device_major(u32::MAX));
+    t!(h.set_device_minor(0o7777777));
+    assert_eq!(t!(h.device_major()), Some(u32::MAX));
+    assert_eq!(t!(h.device_minor()), Some(0o7777777));
+    assert_eq!(h.as_ustar().unwrap().dev_minor, *b"7777777\0");
+
     h.as_ustar_mut().unwrap().dev_minor[0] = 0x7f;
     h.as_ustar_mut().unwrap().dev_major[0] = 0x7f;
     assert!(h.device_major().is_err());
@@ -254,3 +277,54 @@ fn byte_slice_conversion() {
     let b_conv: &[u8] = Header::from_byte_slice(h.as_bytes()).as_bytes();
     assert_eq!(b, b_conv);
 }
+
+#[test]
+fn header_builder() {
+    let h = HeaderBuilder::new().path("a/b").size(3).build().unwrap();
+    assert!(h.as_ustar().is_some());
+    assert_eq!(h.path().unwrap().to_str(), Some("a/b"));
+    assert_eq!(h.size().unwrap(), 3);
+    assert_eq!(h.mode().unwrap(), 0o644);
+    assert_eq!(h.cksum().unwrap(), {
+        let mut copy = h.clone();
+        copy.set_cksum();
+        copy.cksum().unwrap()
+    });
+
+    let h = HeaderBuilder::new()
+        .entry_type(EntryType::Directory)
+        .path("d")
+        .owner(1 << 30, 7)
+        .build()
+        .unwrap();
+    assert!(h.as_gnu().is_some());
+    assert_eq!(h.uid().unwrap(), 1 << 30);
+    assert_eq!(h.mode().unwrap(), 0o755);
+
+    // Split between the name and prefix of a UStar header
+    let long = "dir/".repeat(30) + "file";
+    let h = HeaderBuilder::new().path(&long).build().unwrap();
+    assert!(h.as_ustar().is_some());
+    assert_eq!(h.path().unwrap().to_str(), Some(&long[..]));
+
+    // Too long for any header, so only appended with PAX extensions
+    let longer = "x".repeat(300);
+    let builder = HeaderBuilder::new()
+        .entry_type(EntryType::Symlink)
+        .path(&longer)
+        .link_name(&longer)
+        .mtime(1 << 40);
+    assert!(builder.build().is_err());
+    let mut ar = tar::Builder::new(Vec::new());
+    builder.append_to(&mut ar, io::empty()).unwrap();
+    let data = ar.into_inner().unwrap();
+    let mut ar = tar::Archive::new(&data[..]);
+    let entry = ar.entries().unwrap().next().unwrap().unwrap();
+    assert_eq!(entry.path().unwrap().to_str(), Some(&longer[..]));
+    assert_eq!(
+        entry.link_name().unwrap().unwrap().to_str(),
+        Some(&longer[..])
+    );
+    assert_eq!(entry.header().entry_type(), EntryType::Symlink);
+    assert_eq!(entry.header().mtime().unwrap(), 1 << 40);
+}